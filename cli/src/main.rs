@@ -14,6 +14,7 @@ use libhyperchain::service::client::Client;
 use libhyperchain::wallet::Wallet;
 use libhyperchain::wallet::private_wallet::PrivateWallet;
 use libhyperchain::transaction::TransactionVariant;
+use libhyperchain::address::{encode_address, parse_address};
 use clap::{App, Arg, SubCommand, ArgMatches};
 use std::path::PathBuf;
 use std::fs::File;
@@ -32,13 +33,20 @@ fn balance(mut client: Client, options: &ArgMatches) -> Result<(), Box<dyn Error
 
     let wallet = wallet_or_error.unwrap();
     let address = wallet.get_address().data().to_vec();
-    match client.send(Command::Balance(address))?
+    let command = match options.value_of("height")
+    {
+        Some(height) => Command::BalanceAtHeight(address, height.parse::<u64>()?),
+        None => Command::Balance(address),
+    };
+
+    match client.send(command)?
     {
         Response::WalletStatus(status) =>
         {
-            println!("Address: {}", wallet.get_address());
+            println!("Address: {}", encode_address(&wallet.get_address()));
             println!("Balance: {}", status.balance)
         },
+        Response::Failed => println!("Error: No block at that height"),
         _ => {},
     }
     Ok(())
@@ -76,7 +84,7 @@ fn parse_outputs<'a, I>(to_addresses: I, amounts: &mut I)
     for to_address_str in to_addresses
     {
         let amount_str = amounts.nth(0).unwrap();
-        let to = base_62::decode(to_address_str)?;
+        let to = parse_address(to_address_str)?.data().to_vec();
         let amount = amount_str.parse::<f32>()?;
         outputs.push((to, amount));
     }
@@ -159,12 +167,12 @@ fn transaction_info(mut client: Client, options: &ArgMatches)
                     println!("Transfer:");
                     for input in &transfer.header.inputs 
                     {
-                        println!("From: {}", input.get_address());
+                        println!("From: {}", encode_address(&input.get_address()));
                         println!("Amount: {}", input.amount);
                     }
                     for output in &transfer.header.content.outputs
                     {
-                        println!("To: {}", output.to);
+                        println!("To: {}", encode_address(&output.to));
                         println!("Amount: {}", output.amount);
                     }
                     println!("Fee: {}", transfer.header.content.fee);
@@ -175,7 +183,7 @@ fn transaction_info(mut client: Client, options: &ArgMatches)
                     println!("Page:");
                     for input in &page.header.inputs 
                     {
-                        println!("From: {}", input.get_address());
+                        println!("From: {}", encode_address(&input.get_address()));
                         println!("Amount: {}", input.amount);
                     }
                     println!("Length: {} bytes", page.header.content.data_length);
@@ -189,6 +197,7 @@ fn transaction_info(mut client: Client, options: &ArgMatches)
                 None => println!("In block: Pending"),
             }
         },
+        Response::NotFound => println!("Transaction not found"),
         _ => println!("Error"),
     }
     Ok(())
@@ -199,13 +208,35 @@ fn new_wallet(options: &ArgMatches) -> Result<(), Box<dyn Error>>
     let output = options.value_of("output").unwrap();
 
     println!("Creating new wallet...");
-    let wallet = PrivateWallet::new()?;
+    let wallet = if options.is_present("ed25519") {
+        PrivateWallet::new_ed25519()
+    } else {
+        PrivateWallet::new()?
+    };
     wallet.write_to_file(&PathBuf::from(output))?;
     println!("Done!");
 
     Ok(())
 }
 
+fn inspect_wallet(options: &ArgMatches) -> Result<(), Box<dyn Error>>
+{
+    let wallet_path = options.value_of("wallet").unwrap();
+    match PrivateWallet::inspect(&PathBuf::from(wallet_path))
+    {
+        Ok(inspection) =>
+        {
+            println!("Address: {}", encode_address(&inspection.address));
+            println!("Key bits: {}", inspection.key_bits);
+            println!("Status: {}", inspection.status);
+        },
+
+        Err(err) => println!("Error: {}", err),
+    }
+
+    Ok(())
+}
+
 fn shutdown(mut client: Client) -> Result<(), Box<dyn Error>>
 {
     match client.send(Command::Exit)?
@@ -232,7 +263,12 @@ fn main() -> Result<(), Box<dyn Error>>
                 .long("wallet")
                 .takes_value(true)
                 .required(true)
-                .help("Path to wallet file")))
+                .help("Path to wallet file"))
+            .arg(Arg::with_name("height")
+                .long("height")
+                .takes_value(true)
+                .required(false)
+                .help("Show the balance as of this block instead of the top")))
 
         .subcommand(SubCommand::with_name("send")
             .about("Sent coins to someone")
@@ -301,7 +337,19 @@ fn main() -> Result<(), Box<dyn Error>>
                  .long("output")
                  .takes_value(true)
                  .required(true)
-                 .help("Output path of new wallet")))
+                 .help("Output path of new wallet"))
+            .arg(Arg::with_name("ed25519")
+                 .long("ed25519")
+                 .help("Use an Ed25519 key, for chains using that signature scheme")))
+
+        .subcommand(SubCommand::with_name("inspect-wallet")
+            .about("Check a wallet file is valid and display its address")
+            .arg(Arg::with_name("wallet")
+                 .short("w")
+                 .long("wallet")
+                 .takes_value(true)
+                 .required(true)
+                 .help("Path to wallet file")))
 
         .subcommand(SubCommand::with_name("shutdown")
             .about("Shutdown service"))
@@ -311,6 +359,7 @@ fn main() -> Result<(), Box<dyn Error>>
     match matches.subcommand_name()
     {
         Some("new-wallet") => return new_wallet(matches.subcommand().1.unwrap()),
+        Some("inspect-wallet") => return inspect_wallet(matches.subcommand().1.unwrap()),
         Some(&_) | None => {},
     }
 
@@ -143,7 +143,8 @@ pub async fn transaction_handler(request: HttpRequest) -> impl Responder
             HttpResponse::Ok().body(body)
         },
 
-        _ => HttpResponse::Ok().body("Transaction not found"),
+        Response::NotFound => HttpResponse::Ok().body("Transaction not found"),
+        _ => HttpResponse::Ok().body("Failed to fetch transaction"),
     }
 }
 
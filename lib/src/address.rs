@@ -0,0 +1,289 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use crate::error::ErrorMessage;
+use crate::hash::Hash;
+use crate::config::HASH_LEN;
+
+use std::error::Error;
+
+pub const ADDRESS_PREFIX: &str = "hc";
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+const CHECKSUM_LEN: usize = 6;
+
+pub trait AddressFormat
+{
+
+    fn encode(&self, address: &Hash) -> String;
+    fn decode(&self, address: &str) -> Result<Hash, Box<dyn Error>>;
+
+}
+
+// Raw base62 of the hash, as used internally
+pub struct Base62Format;
+
+// Bech32 with a human readable prefix, and a checksum to catch typos
+pub struct Bech32Format
+{
+    prefix: String,
+}
+
+impl Bech32Format
+{
+
+    pub fn new(prefix: &str) -> Self
+    {
+        Self
+        {
+            prefix: prefix.to_lowercase(),
+        }
+    }
+
+}
+
+impl Default for Bech32Format
+{
+
+    fn default() -> Self
+    {
+        Self::new(ADDRESS_PREFIX)
+    }
+
+}
+
+fn hash_from_bytes(bytes: &[u8]) -> Result<Hash, Box<dyn Error>>
+{
+    if bytes.len() != HASH_LEN {
+        return Err(ErrorMessage::new(&format!("Address must be {} bytes, got {}", HASH_LEN, bytes.len())));
+    }
+
+    Ok(Hash::from(bytes))
+}
+
+impl AddressFormat for Base62Format
+{
+
+    fn encode(&self, address: &Hash) -> String
+    {
+        base_62::encode(address.data())
+    }
+
+    fn decode(&self, address: &str) -> Result<Hash, Box<dyn Error>>
+    {
+        hash_from_bytes(&base_62::decode(address)?)
+    }
+
+}
+
+impl AddressFormat for Bech32Format
+{
+
+    fn encode(&self, address: &Hash) -> String
+    {
+        encode(&self.prefix, address.data())
+    }
+
+    fn decode(&self, address: &str) -> Result<Hash, Box<dyn Error>>
+    {
+        hash_from_bytes(&decode(&self.prefix, address)?)
+    }
+
+}
+
+fn polymod(values: &[u8]) -> u32
+{
+    let mut checksum = 1u32;
+    for value in values
+    {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ *value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate()
+        {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+
+    checksum
+}
+
+fn expand_prefix(prefix: &str) -> Vec<u8>
+{
+    let mut expanded = prefix.bytes().map(|x| x >> 5).collect::<Vec<_>>();
+    expanded.push(0);
+    expanded.extend(prefix.bytes().map(|x| x & 31));
+    expanded
+}
+
+fn create_checksum(prefix: &str, data: &[u8]) -> Vec<u8>
+{
+    let mut values = expand_prefix(prefix);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0u8; CHECKSUM_LEN]);
+
+    let checksum = polymod(&values) ^ 1;
+    (0..CHECKSUM_LEN)
+        .map(|i| ((checksum >> (5 * (CHECKSUM_LEN - 1 - i))) & 31) as u8)
+        .collect()
+}
+
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool)
+    -> Result<Vec<u8>, Box<dyn Error>>
+{
+    let mut accumulator = 0u32;
+    let mut bits = 0u32;
+    let mut result = Vec::new();
+    let max_value = (1u32 << to) - 1;
+    for value in data
+    {
+        accumulator = (accumulator << from) | *value as u32;
+        bits += from;
+        while bits >= to
+        {
+            bits -= to;
+            result.push(((accumulator >> bits) & max_value) as u8);
+        }
+    }
+
+    if pad
+    {
+        if bits > 0 {
+            result.push(((accumulator << (to - bits)) & max_value) as u8);
+        }
+    }
+    else if bits >= from || ((accumulator << (to - bits)) & max_value) != 0
+    {
+        return Err(ErrorMessage::new("Invalid padding in address"));
+    }
+
+    Ok(result)
+}
+
+pub fn encode(prefix: &str, data: &[u8]) -> String
+{
+    // NOTE: Converting up to 5 bits with padding can't fail
+    let mut values = convert_bits(data, 8, 5, true).unwrap();
+    values.extend(create_checksum(prefix, &values));
+
+    let mut encoded = prefix.to_owned();
+    encoded.push('1');
+    encoded.extend(values.iter().map(|x| CHARSET[*x as usize] as char));
+    encoded
+}
+
+pub fn decode(prefix: &str, encoded: &str) -> Result<Vec<u8>, Box<dyn Error>>
+{
+    let is_mixed_case =
+        encoded.chars().any(|x| x.is_ascii_lowercase()) &&
+        encoded.chars().any(|x| x.is_ascii_uppercase());
+    if is_mixed_case {
+        return Err(ErrorMessage::new("Address can't be mixed case"));
+    }
+
+    let encoded = encoded.to_lowercase();
+    let separator = match encoded.rfind('1')
+    {
+        Some(separator) => separator,
+        None => return Err(ErrorMessage::new("Address is missing a separator")),
+    };
+
+    let (found_prefix, data_part) = (&encoded[..separator], &encoded[separator + 1..]);
+    if found_prefix != prefix
+    {
+        return Err(ErrorMessage::new(&format!(
+            "Expected address prefix '{}', got '{}'", prefix, found_prefix)));
+    }
+
+    if data_part.len() < CHECKSUM_LEN {
+        return Err(ErrorMessage::new("Address is too short"));
+    }
+
+    let mut values = Vec::new();
+    for c in data_part.bytes()
+    {
+        match CHARSET.iter().position(|x| *x == c)
+        {
+            Some(value) => values.push(value as u8),
+            None => return Err(ErrorMessage::new(&format!("Invalid character '{}' in address", c as char))),
+        }
+    }
+
+    let mut checked = expand_prefix(prefix);
+    checked.extend_from_slice(&values);
+    if polymod(&checked) != 1 {
+        return Err(ErrorMessage::new("Address checksum does not match"));
+    }
+
+    values.truncate(values.len() - CHECKSUM_LEN);
+    convert_bits(&values, 5, 8, false)
+}
+
+pub fn encode_address(address: &Hash) -> String
+{
+    Bech32Format::default().encode(address)
+}
+
+// Accept both formats, so older base62 addresses still work
+pub fn parse_address(address: &str) -> Result<Hash, Box<dyn Error>>
+{
+    let bech32_start = format!("{}1", ADDRESS_PREFIX);
+    if address.to_lowercase().starts_with(&bech32_start) {
+        Bech32Format::default().decode(address)
+    } else {
+        Base62Format.decode(address)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::wallet::Wallet;
+    use crate::wallet::private_wallet::PrivateWallet;
+
+    #[test]
+    fn test_bech32()
+    {
+        // Reference vectors from BIP-173
+        assert_eq!(decode("a", "A12UEL5L").unwrap(), Vec::<u8>::new());
+        assert_eq!(encode("a", &[]), "a12uel5l");
+        assert!(decode("a", "a12uel5m").is_err());
+        assert!(decode("a", "A12uEL5L").is_err());
+
+        let data = (0..=255).collect::<Vec<u8>>();
+        assert_eq!(decode("test", &encode("test", &data)).unwrap(), data);
+    }
+
+    #[test]
+    fn test_address_format()
+    {
+        let address = PrivateWallet::open_temp(0).unwrap().get_address();
+        let encoded = encode_address(&address);
+        assert!(encoded.starts_with("hc1"));
+        assert_eq!(Bech32Format::default().decode(&encoded).unwrap(), address);
+        assert_eq!(parse_address(&encoded).unwrap(), address);
+        assert_eq!(parse_address(&encoded.to_uppercase()).unwrap(), address);
+        assert_eq!(parse_address(&address.to_string()).unwrap(), address);
+
+        // Any single corrupted character is caught by the checksum
+        for i in 3..encoded.len()
+        {
+            let mut corrupted = encoded.clone().into_bytes();
+            corrupted[i] = if corrupted[i] == b'q' { b'p' } else { b'q' };
+            let corrupted = String::from_utf8(corrupted).unwrap();
+            assert!(parse_address(&corrupted).is_err(), "{}", corrupted);
+        }
+
+        // And addresses for something else are rejected
+        assert!(Bech32Format::new("xx").decode(&encoded).is_err());
+    }
+
+}
+
@@ -19,6 +19,11 @@ pub struct BlockBuilder<'a, W>
     raward_to: &'a W,
     transfers: Vec<Transaction<Transfer>>,
     pages: Vec<Transaction<Page>>,
+
+    // Total size of the added transactions, and the largest block 
+    // size to allow, if any.
+    transactions_size: usize,
+    max_size: Option<usize>,
 }
 
 impl<'a, W> BlockBuilder<'a, W>
@@ -32,21 +37,75 @@ impl<'a, W> BlockBuilder<'a, W>
             raward_to,
             transfers: Vec::new(),
             pages: Vec::new(),
+            transactions_size: 0,
+            max_size: None,
+        }
+    }
+
+    pub fn with_max_size(mut self, max_size: usize) -> Self
+    {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    pub fn size_bytes(&self) -> Result<usize, Box<dyn Error>>
+    {
+        let empty_size = Block::empty_size_bytes(self.raward_to.get_address())?;
+        Ok(empty_size + self.transactions_size)
+    }
+
+    fn has_room_for(&self, size: usize) -> Result<bool, Box<dyn Error>>
+    {
+        match self.max_size
+        {
+            Some(max_size) => Ok(self.size_bytes()? + size <= max_size),
+            None => Ok(true),
         }
     }
 
     pub fn add_transfer(mut self, transfer: Transaction<Transfer>) -> Self
     {
+        // NOTE: A transaction that can't be serialized will fail when building
+        self.transactions_size += transfer.size_bytes().unwrap_or(0);
         self.transfers.push(transfer);
         self
     }
 
     pub fn add_page(mut self, page: Transaction<Page>) -> Self
     {
+        self.transactions_size += page.size_bytes().unwrap_or(0);
         self.pages.push(page);
         self
     }
 
+    // Add the transfer only if the block stays within the max size. 
+    // Returns if it was added.
+    pub fn try_add_transfer(&mut self, transfer: &Transaction<Transfer>) 
+        -> Result<bool, Box<dyn Error>>
+    {
+        let size = transfer.size_bytes()?;
+        if !self.has_room_for(size)? {
+            return Ok(false);
+        }
+
+        self.transactions_size += size;
+        self.transfers.push(transfer.clone());
+        Ok(true)
+    }
+
+    pub fn try_add_page(&mut self, page: &Transaction<Page>) 
+        -> Result<bool, Box<dyn Error>>
+    {
+        let size = page.size_bytes()?;
+        if !self.has_room_for(size)? {
+            return Ok(false);
+        }
+
+        self.transactions_size += size;
+        self.pages.push(page.clone());
+        Ok(true)
+    }
+
     pub fn build(self, chain: &mut BlockChain) -> Result<Block, Box<dyn Error>>
     {
         Block::new(chain, self.raward_to, 
@@ -55,3 +114,51 @@ impl<'a, W> BlockBuilder<'a, W>
 
 }
 
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::wallet::private_wallet::PrivateWallet;
+    use crate::miner;
+
+    #[test]
+    fn test_block_size()
+    {
+        let mut chain = BlockChain::open_temp();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let block = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        chain.add(&block).unwrap();
+
+        let transfers = (0..5)
+            .map(|_| chain.new_transfer(vec![(&wallet, 1.25)], 
+                vec![(other.get_address(), 1.0)], 0.25).unwrap())
+            .collect::<Vec<_>>();
+        let transfer_size = transfers[0].size_bytes().unwrap();
+
+        // Sizes of the parts add up to the whole block
+        let mut builder = BlockBuilder::new(&wallet);
+        for transfer in &transfers {
+            builder = builder.add_transfer(transfer.clone());
+        }
+        let expected_size = builder.size_bytes().unwrap();
+        let block = builder.build(&mut chain).unwrap();
+        assert_eq!(block.size_bytes().unwrap(), expected_size);
+        assert_eq!(expected_size, Block::empty_size_bytes(wallet.get_address()).unwrap() + 5 * transfer_size);
+
+        // Stop adding before going over the limit
+        let max_size = Block::empty_size_bytes(wallet.get_address()).unwrap() + 3 * transfer_size + 1;
+        let mut builder = BlockBuilder::new(&wallet).with_max_size(max_size);
+        let added = transfers.iter()
+            .filter(|transfer| builder.try_add_transfer(transfer).unwrap())
+            .count();
+        assert_eq!(added, 3);
+
+        let block = builder.build(&mut chain).unwrap();
+        assert_eq!(block.transfers.len(), 3);
+        assert!(block.size_bytes().unwrap() <= max_size);
+    }
+
+}
+
@@ -9,9 +9,9 @@ pub mod target;
 pub mod builder;
 mod transactions;
 use target::{calculate_target, Target};
-use transactions::merkle_root_for_transactions;
-use crate::transaction::Transaction;
-use crate::transaction::transfer::Transfer;
+use transactions::{merkle_root_for_transactions, sort_canonically};
+use crate::transaction::{Transaction, TransactionHeader};
+use crate::transaction::transfer::{Transfer, TransferBuilder};
 use crate::transaction::page::Page;
 use crate::chain::BlockChain;
 use crate::wallet::Wallet;
@@ -23,28 +23,71 @@ use serde::{Serialize, Deserialize};
 use std::time::SystemTime;
 use std::error::Error;
 use bincode;
+use std::collections::HashMap;
 
 pub fn current_timestamp() -> u128
 {
     SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis()
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub fn block_reward(_block_id: u64) -> f32
+{
+    // TODO: do real reward calc
+    10.0
+}
+
+pub fn new_coinbase(block_id: u64, raward_to: Hash, reward: f32, 
+                    premine: &[(Hash, f32)]) 
+    -> Transaction<Transfer>
+{
+    // NOTE: The coinbase has no inputs, it just creates the reward. 
+    //       The block id is used to give each one a unique hash.
+    let mut builder = TransferBuilder::new(block_id as u32, 0.0)
+        .add_output(raward_to, reward);
+    for (address, amount) in premine {
+        builder = builder.add_output(*address, *amount);
+    }
+    let content = builder.build();
+
+    Transaction::new(TransactionHeader
+    {
+        content,
+        inputs: Vec::new(),
+        valid_until: None,
+    }, HashMap::new())
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 pub struct BlockHeader
 {
     pub prev_hash: Hash,
     pub block_id: u64,
     pub timestamp: u128,
     pub raward_to: Hash,
+    pub reward: f32,
     pub target: Target,
     pub transaction_merkle_root: Hash,
     pub pow: u64, // TODO: This should be a correct size
 }
 
+impl BlockHeader
+{
+
+    pub fn hash(&self) -> Result<Hash, Box<dyn Error>>
+    {
+        let mut hasher = Sha256::default();
+        let bytes = bincode::serialize(self)?;
+        hasher.update(&bytes);
+        Ok(Hash::from(&hasher.clone().finalize()))
+    }
+
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Clone)]
 pub struct Block
 {
     pub header: BlockHeader,
+    pub coinbase: Transaction<Transfer>,
     pub pages: Vec<Transaction<Page>>,
     pub transfers: Vec<Transaction<Transfer>>,
 }
@@ -79,7 +122,7 @@ impl Block
         -> Result<Self, Box<dyn Error>>
     {
         let (sample_start, sample_end) = chain.take_sample();
-        let target = calculate_target(sample_start, sample_end);
+        let target = calculate_target(chain.config(), sample_start, sample_end);
         let (prev_block_id, prev_hash) =
             match chain.top()
             {
@@ -95,7 +138,12 @@ impl Block
             };
 
         let timestamp = current_timestamp();
-        let transaction_merkle_root = merkle_root_for_transactions(&transfers, &pages)?;
+        let reward = block_reward(block_id);
+        let premine = if block_id == 0 { &chain.config().premine[..] } else { &[] };
+        let coinbase = new_coinbase(block_id, raward_to.get_address(), reward, premine);
+        let transfers = sort_canonically(transfers)?;
+        let pages = sort_canonically(pages)?;
+        let transaction_merkle_root = merkle_root_for_transactions(&coinbase, &transfers, &pages)?;
         Ok(Block
         {
             header: BlockHeader
@@ -104,28 +152,70 @@ impl Block
                 block_id,
                 timestamp,
                 raward_to: raward_to.get_address(),
+                reward,
                 target,
                 transaction_merkle_root,
                 pow: 0,
             },
 
+            coinbase,
             pages,
             transfers,
         })
     }
 
+    // NOTE: Every field of an empty block is a fixed size, so this is 
+    //       the same no matter where in the chain it goes.
+    pub fn empty_size_bytes(raward_to: Hash) -> Result<usize, Box<dyn Error>>
+    {
+        let empty = Block
+        {
+            header: BlockHeader
+            {
+                prev_hash: Hash::empty(),
+                block_id: 0,
+                timestamp: 0,
+                raward_to,
+                reward: 0.0,
+                target: Default::default(),
+                transaction_merkle_root: Hash::empty(),
+                pow: 0,
+            },
+
+            coinbase: new_coinbase(0, raward_to, 0.0, &[]),
+            pages: Vec::new(),
+            transfers: Vec::new(),
+        };
+
+        empty.size_bytes()
+    }
+
+    pub fn size_bytes(&self) -> Result<usize, Box<dyn Error>>
+    {
+        Ok(bincode::serialize(self)?.len())
+    }
+
     pub fn calculate_reward(&self) -> f32
     {
-        // TODO: do real reward calc
-        10.0
+        block_reward(self.header.block_id)
     }
 
     pub fn hash(&self) -> Result<Hash, Box<dyn Error>>
     {
-        let mut hasher = Sha256::default();
-        let bytes = bincode::serialize(&self.header)?;
-        hasher.update(&bytes);
-        Ok(Hash::from(&hasher.clone().finalize()))
+        self.header.hash()
+    }
+
+    // NOTE: The header commits to every transaction through the merkle 
+    //       root, so comparing headers tells blocks apart without walking 
+    //       their transactions, or hashing anything.
+    pub fn is_same_block(&self, other: &Block) -> bool
+    {
+        self.header == other.header
+    }
+
+    pub fn calculate_merkle_root(&self) -> Result<Hash, Box<dyn Error>>
+    {
+        merkle_root_for_transactions(&self.coinbase, &self.transfers, &self.pages)
     }
 
 }
@@ -6,7 +6,7 @@
 
 use super::HASH_LEN;
 use super::Block;
-use crate::config::{BLOCK_SAMPLE_SIZE, BLOCK_TIME};
+use crate::config::{ChainConfig, RetargetMode};
 
 const TARGET_LEN: usize = 4;
 const MIN_TARGET: [u8; TARGET_LEN] = [0x00, 0xFF, 0xFF, 0x20];
@@ -36,14 +36,27 @@ pub fn difficulty(target: &Target) -> f64
     coefficent_diff * exponent_diff.exp2()
 }
 
-pub fn hash_rate(diff: f64, time: u64) -> f64
+// Whether the target can be represented as a hash, and met at all
+pub fn is_target_satisfiable(target: &Target) -> bool
 {
-    (diff * 256.0 * BLOCK_SAMPLE_SIZE as f64) / time as f64
+    index(target) as usize >= TARGET_LEN - 1 && coefficent(target) != 0
 }
 
-fn diff_for_hash_rate(hash_rate: f64) -> f64
+// Whether the expected number of hashes to meet the target is more 
+// then the pow field can hold.
+pub fn needs_extra_nonce(target: &Target) -> bool
 {
-    (hash_rate * BLOCK_TIME as f64) / 256.0
+    difficulty(target) * 256.0 > u64::MAX as f64
+}
+
+pub fn hash_rate(diff: f64, block_count: u64, time: u64) -> f64
+{
+    (diff * 256.0 * block_count as f64) / time as f64
+}
+
+fn diff_for_hash_rate(hash_rate: f64, block_time: u64) -> f64
+{
+    (hash_rate * block_time as f64) / 256.0
 }
 
 fn compact_from_difficulty(diff: f64) -> Target
@@ -74,35 +87,105 @@ pub fn hash_from_target(compact: &Target) -> [u8; HASH_LEN]
     target
 }
 
-pub fn calculate_target(sample_start_or_none: Option<Block>, 
-                        sample_end_or_none: Option<Block>) -> Target
+// Make the target `factor` times easier to meet, without going 
+// below the min difficulty
+fn scale_target(target: &Target, factor: f64) -> Target
 {
-    // If we do not have enough data for a sample, use the min target
-    if sample_start_or_none.is_none() || sample_end_or_none.is_none() {
+    let mut co = coefficent(target) as f64 * factor;
+    let mut id = index(target);
+    while co > 0xFFFFFF as f64 && id < index(&MIN_TARGET)
+    {
+        co /= 256.0;
+        id += 1;
+    }
+
+    // NOTE: Keep as much precision in the coefficent as we can
+    while co < 0x8000 as f64 && id as usize > TARGET_LEN - 1
+    {
+        co *= 256.0;
+        id -= 1;
+    }
+
+    if id == index(&MIN_TARGET) && co > coefficent(&MIN_TARGET) as f64 {
         return MIN_TARGET;
     }
 
+    let co = co as u32;
+    [
+        (co >> 16) as u8,
+        (co >> 8) as u8,
+        co as u8,
+        id as u8,
+    ]
+}
+
+fn window_target(config: &ChainConfig, sample_start: Block, sample_end: Block) -> Target
+{
     // We're within the sample range, so keep the last target
-    let sample_end = sample_end_or_none.unwrap();
-    if sample_end.header.block_id % BLOCK_SAMPLE_SIZE != 0 {
+    if sample_end.header.block_id % config.block_sample_size != 0 {
         return sample_end.header.target;
     }
 
     // Calculate new target with sample
-    let sample_start = sample_start_or_none.unwrap();
     let sample_time = sample_end.header.timestamp - sample_start.header.timestamp;
     let curr_diff = difficulty(&sample_end.header.target);
-    let curr_hash_rate = hash_rate(curr_diff, sample_time as u64);
+    let curr_hash_rate = hash_rate(curr_diff, config.block_sample_size, sample_time as u64);
 
-    let new_diff = diff_for_hash_rate(curr_hash_rate);
+    let new_diff = diff_for_hash_rate(curr_hash_rate, config.block_time);
     compact_from_difficulty(new_diff)
 }
 
+fn ema_target(config: &ChainConfig, last_block: Block, top: Block) -> Target
+{
+    // NOTE: The current target was set to be on time, so the decayed average 
+    //       only moves a fraction of the way towards the latest block time
+    let block_time = top.header.timestamp.saturating_sub(last_block.header.timestamp) as f64;
+    let expected_time = config.block_time as f64;
+    let smoothing = std::cmp::max(config.block_sample_size, 1) as f64;
+    let average_time = expected_time + (block_time - expected_time) / smoothing;
+
+    scale_target(&top.header.target, average_time.max(1.0) / expected_time)
+}
+
+fn apply_difficulty_floor(config: &ChainConfig, target: Target) -> Target
+{
+    match config.min_difficulty
+    {
+        Some(min_difficulty) if difficulty(&target) < min_difficulty => 
+            compact_from_difficulty(min_difficulty),
+
+        _ => target,
+    }
+}
+
+pub fn calculate_target(config: &ChainConfig,
+                        sample_start_or_none: Option<Block>, 
+                        sample_end_or_none: Option<Block>) -> Target
+{
+    // If we do not have enough data for a sample, use the min target
+    if sample_start_or_none.is_none() || sample_end_or_none.is_none() {
+        return apply_difficulty_floor(config, MIN_TARGET);
+    }
+
+    let sample_start = sample_start_or_none.unwrap();
+    let sample_end = sample_end_or_none.unwrap();
+    let target = match config.retarget_mode
+    {
+        RetargetMode::Window => window_target(config, sample_start, sample_end),
+        RetargetMode::Ema => ema_target(config, sample_start, sample_end),
+    };
+
+    apply_difficulty_floor(config, target)
+}
+
 #[cfg(test)]
 mod tests
 {
 
     use super::*;
+    use crate::config::{BLOCK_SAMPLE_SIZE, BLOCK_TIME};
+    use crate::chain::BlockChain;
+    use crate::wallet::private_wallet::PrivateWallet;
 
     #[test]
     fn test_target_calc()
@@ -138,10 +221,177 @@ mod tests
         assert_eq!(difficulty(&[0x00, 0xEE, 0xEE, 0x10]), 364588250272434060000000000000000000000.0);
         assert_eq!(compact_from_difficulty(364588250272434406000000000000000000000.0), [0x00, 0xEE, 0xED, 0x10]);
 
-        assert_eq!(hash_rate(1.0, BLOCK_SAMPLE_SIZE), 256.0);
-        assert_eq!(hash_rate(difficulty(&[0x00, 0xFF, 0xFF, 0x1F]), BLOCK_SAMPLE_SIZE), 65536.0);
+        assert_eq!(hash_rate(1.0, BLOCK_SAMPLE_SIZE, BLOCK_SAMPLE_SIZE), 256.0);
+        assert_eq!(is_target_satisfiable(&MIN_TARGET), true);
+        assert_eq!(is_target_satisfiable(&[0x00, 0x00, 0x00, 0x20]), false);
+        assert_eq!(is_target_satisfiable(&[0x00, 0xFF, 0xFF, 0x02]), false);
+        assert_eq!(needs_extra_nonce(&MIN_TARGET), false);
+        assert_eq!(needs_extra_nonce(&[0x00, 0xFF, 0xFF, 0x03]), true);
+        assert_eq!(hash_rate(difficulty(&[0x00, 0xFF, 0xFF, 0x1F]), BLOCK_SAMPLE_SIZE, BLOCK_SAMPLE_SIZE), 65536.0);
+
+        assert_eq!(diff_for_hash_rate(256.0, BLOCK_TIME), BLOCK_TIME as f64);
+    }
+
+    #[test]
+    fn test_target_block_time()
+    {
+        let config = ChainConfig
+        {
+            block_time: 1000,
+            block_sample_size: 10,
+            branch_prune_interval: None,
+            ..ChainConfig::default()
+        };
+
+        let mut chain = BlockChain::open_temp_with_config(config.clone());
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+
+        // Ten blocks, one second apart
+        let mut sample_start = Block::new_blank(&mut chain, &wallet).unwrap();
+        sample_start.header.timestamp = 0;
+        let mut sample_end = sample_start.clone();
+        sample_end.header.block_id = 10;
+        sample_end.header.timestamp = 10 * 1000;
+
+        // Blocks are on cadence for the short block time, so keep the same difficulty
+        let target = calculate_target(&config, Some(sample_start.clone()), Some(sample_end.clone()));
+        assert_eq!(target, MIN_TARGET);
+
+        // But much faster then the default block time, so the difficulty should go up
+        let default_config = ChainConfig
+        {
+            block_sample_size: 10,
+            ..ChainConfig::default()
+        };
+        let target = calculate_target(&default_config, Some(sample_start), Some(sample_end));
+        assert!(difficulty(&target) > 1.0);
+    }
+
+    // Mine a block at each hash rate (hashes per ms), taking exactly the 
+    // expected time, and return the difficulty of each block.
+    fn simulate_difficulty(config: &ChainConfig, hash_rates: &[f64]) -> Vec<f64>
+    {
+        let mut chain = BlockChain::open_temp_with_config(config.clone());
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let template = Block::new_blank(&mut chain, &wallet).unwrap();
+
+        let span = config.sample_span() as usize;
+        let mut blocks: Vec<Block> = Vec::new();
+        for (block_id, rate) in hash_rates.iter().enumerate()
+        {
+            let sample_start = 
+                if blocks.len() > span {
+                    Some(blocks[blocks.len() - span - 1].clone())
+                } else {
+                    None
+                };
+
+            let target = calculate_target(config, sample_start, blocks.last().cloned());
+            let time = (difficulty(&target) * 256.0 / rate) as u128;
+            let last_timestamp = blocks.last().map(|block| block.header.timestamp).unwrap_or(0);
+
+            let mut block = template.clone();
+            block.header.block_id = block_id as u64;
+            block.header.timestamp = last_timestamp + time;
+            block.header.target = target;
+            blocks.push(block);
+        }
+
+        blocks.iter()
+            .map(|block| difficulty(&block.header.target))
+            .collect()
+    }
+
+    fn largest_step(diffs: &[f64]) -> f64
+    {
+        diffs.windows(2)
+            .map(|pair| (pair[1] / pair[0]).max(pair[0] / pair[1]))
+            .fold(1.0, f64::max)
+    }
+
+    #[test]
+    fn test_target_ema()
+    {
+        let window_config = ChainConfig
+        {
+            block_time: 1000,
+            block_sample_size: 10,
+            ..ChainConfig::default()
+        };
+        let ema_config = ChainConfig
+        {
+            retarget_mode: RetargetMode::Ema,
+            ..window_config.clone()
+        };
+
+        // Enough hash rate for a difficulty of 256, then 320
+        let steady_rate = 256.0 * 256.0 / 1000.0;
+        let mut hash_rates = vec![steady_rate; 500];
+        hash_rates.extend(vec![steady_rate * 1.25; 500]);
+
+        let is_close = |diff: f64, expected: f64| (diff / expected - 1.0).abs() < 0.05;
+        for config in [&window_config, &ema_config]
+        {
+            let diffs = simulate_difficulty(config, &hash_rates);
+            assert!(is_close(diffs[499], 256.0), "{:?}: {}", config.retarget_mode, diffs[499]);
+            assert!(is_close(diffs[999], 320.0), "{:?}: {}", config.retarget_mode, diffs[999]);
+        }
+
+        // The window jumps straight to the new difficulty, where EMA 
+        // gets there in smaller steps
+        let window_diffs = simulate_difficulty(&window_config, &hash_rates);
+        let ema_diffs = simulate_difficulty(&ema_config, &hash_rates);
+        assert!(largest_step(&window_diffs[500..]) > 1.2);
+        assert!(largest_step(&ema_diffs[500..]) < 1.05);
+    }
+
+    #[test]
+    fn test_difficulty_floor()
+    {
+        let config = ChainConfig
+        {
+            block_time: 1000,
+            block_sample_size: 10,
+            ..ChainConfig::default()
+        };
+        let floor_config = ChainConfig
+        {
+            min_difficulty: Some(256.0),
+            ..config.clone()
+        };
+        let floor = compact_from_difficulty(256.0);
+
+        let mut chain = BlockChain::open_temp_with_config(config.clone());
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+
+        // Ten blocks at the floor, taking 256 times longer then they should
+        let mut sample_start = Block::new_blank(&mut chain, &wallet).unwrap();
+        sample_start.header.timestamp = 0;
+        sample_start.header.target = floor;
+        let mut sample_end = sample_start.clone();
+        sample_end.header.block_id = 10;
+        sample_end.header.timestamp = 256 * 10 * 1000;
+
+        let target = calculate_target(&config, Some(sample_start.clone()), Some(sample_end.clone()));
+        assert_eq!(target, MIN_TARGET);
+
+        // Would be trivially easy, so it's held at the floor instead
+        let target = calculate_target(&floor_config, Some(sample_start), Some(sample_end));
+        assert_eq!(target, floor);
+        assert_eq!(difficulty(&target), 256.0);
+        assert_eq!(calculate_target(&floor_config, None, None), floor);
 
-        assert_eq!(diff_for_hash_rate(256.0), BLOCK_TIME as f64);
+        // Neither retarget mode goes below it, however little hash rate there is
+        let ema_config = ChainConfig
+        {
+            retarget_mode: RetargetMode::Ema,
+            ..floor_config.clone()
+        };
+        for config in [&floor_config, &ema_config]
+        {
+            let diffs = simulate_difficulty(config, &vec![0.01; 100]);
+            assert!(diffs.iter().all(|diff| *diff >= 256.0), "{:?}: {:?}", config.retarget_mode, diffs);
+        }
     }
 
 }
@@ -5,22 +5,24 @@
  */
 
 use super::Block;
-use crate::wallet::WalletStatus;
+use crate::wallet::{WalletStatus, WalletStatusDelta};
 use crate::transaction::Transaction;
 use crate::transaction::transfer::Transfer;
 use crate::transaction::page::Page;
-use crate::transaction::TransactionVariant;
+use crate::transaction::{TransactionVariant, TransactionContent};
 use crate::merkle_tree::calculate_merkle_root;
 use crate::hash::Hash;
 
+use serde::Serialize;
 use std::collections::HashSet;
 use std::error::Error;
 
-pub fn merkle_root_for_transactions(transfers: &Vec<Transaction<Transfer>>,
+pub fn merkle_root_for_transactions(coinbase: &Transaction<Transfer>,
+                                    transfers: &Vec<Transaction<Transfer>>,
                                     pages: &Vec<Transaction<Page>>)
     -> Result<Hash, Box<dyn Error>>
 {
-    let mut hashes = Vec::new();
+    let mut hashes = vec![coinbase.hash()?];
     for transfer in transfers {
         hashes.push(transfer.hash()?);
     }
@@ -31,6 +33,35 @@ pub fn merkle_root_for_transactions(transfers: &Vec<Transaction<Transfer>>,
     Ok(calculate_merkle_root(&hashes))
 }
 
+// NOTE: Ordered by id first, so each wallet's transactions are applied 
+//       in the order they were made, then by hash to break ties.
+fn canonical_keys<C>(transactions: &[Transaction<C>]) -> Result<Vec<(u32, Hash)>, Box<dyn Error>>
+    where C: TransactionContent + Serialize
+{
+    transactions
+        .iter()
+        .map(|transaction| Ok((transaction.get_id(), transaction.hash()?)))
+        .collect()
+}
+
+pub fn sort_canonically<C>(transactions: Vec<Transaction<C>>) 
+    -> Result<Vec<Transaction<C>>, Box<dyn Error>>
+    where C: TransactionContent + Serialize
+{
+    let keys = canonical_keys(&transactions)?;
+    let mut keyed = keys.into_iter().zip(transactions).collect::<Vec<_>>();
+    keyed.sort_by_key(|(key, _)| *key);
+    Ok(keyed.into_iter().map(|(_, transaction)| transaction).collect())
+}
+
+pub fn is_canonically_ordered<C>(transactions: &[Transaction<C>]) 
+    -> Result<bool, Box<dyn Error>>
+    where C: TransactionContent + Serialize
+{
+    let keys = canonical_keys(transactions)?;
+    Ok(keys.windows(2).all(|pair| pair[0] <= pair[1]))
+}
+
 impl Block
 {
 
@@ -38,6 +69,9 @@ impl Block
     {
         let mut addresses_in_use = HashSet::<Hash>::new();
         addresses_in_use.insert(self.header.raward_to);
+        for output in &self.coinbase.header.content.outputs {
+            addresses_in_use.insert(output.to);
+        }
         
         for transaction in &self.transfers
         {
@@ -59,12 +93,40 @@ impl Block
         addresses_in_use.into_iter().collect::<Vec<_>>()
     }
 
-    pub fn update_wallet_status(&self, address: &Hash, mut status: WalletStatus) 
-        -> Result<WalletStatus, Box<dyn Error>>
+    pub fn get_from_addresses(&self) -> Vec<Hash>
     {
-        if &self.header.raward_to == address {
-            status.balance += self.calculate_reward()
+        let mut from_addresses = HashSet::<Hash>::new();
+        for transfer in &self.transfers {
+            from_addresses.extend(transfer.get_from_addresses());
         }
+        for page in &self.pages {
+            from_addresses.extend(page.get_from_addresses());
+        }
+
+        from_addresses.into_iter().collect::<Vec<_>>()
+    }
+
+    pub fn total_fees(&self) -> f32
+    {
+        let transfer_fees = self.transfers.iter()
+            .fold(0.0, |acc, x| acc + x.header.content.get_fee());
+        let page_fees = self.pages.iter()
+            .fold(0.0, |acc, x| acc + x.header.content.get_fee());
+        transfer_fees + page_fees
+    }
+
+    pub fn fees_burned(&self, fee_burn_ratio: f32) -> f32
+    {
+        self.total_fees() * fee_burn_ratio
+    }
+
+    pub fn update_wallet_status(&self, address: &Hash, mut status: WalletStatus,
+                                fee_burn_ratio: f32) 
+        -> Result<WalletStatus, Box<dyn Error>>
+    {
+        // NOTE: The coinbase is checked against `calculate_reward` 
+        //       during validation. Fees are credited by each transaction.
+        status = self.coinbase.update_wallet_status(address, status, false)?;
 
         for transfer in &self.transfers
         {
@@ -78,12 +140,31 @@ impl Block
             status = page.update_wallet_status(address, status, is_block_winner)?;
         }
 
+        // NOTE: The winner is credited the full fee above, so take back 
+        //       the part that's burned
+        if &self.header.raward_to == address {
+            status.balance -= self.fees_burned(fee_burn_ratio);
+        }
+
         Ok(status)
     }
 
+    // NOTE: Ids only have to increase within the block, so it can be 
+    //       replayed onto an empty wallet without knowing what came before
+    pub fn wallet_delta(&self, address: &Hash, fee_burn_ratio: f32)
+        -> Result<WalletStatusDelta, Box<dyn Error>>
+    {
+        let status = self.update_wallet_status(address, WalletStatus::default(), fee_burn_ratio)?;
+        Ok(WalletStatusDelta
+        {
+            balance: status.balance,
+            max_id: if status.max_id > 0 { Some(status.max_id) } else { None },
+        })
+    }
+
     pub fn transactions(&self) -> Vec<TransactionVariant>
     {
-        let mut transactions = Vec::new();
+        let mut transactions = vec![TransactionVariant::Transfer(self.coinbase.clone())];
         for transfer in &self.transfers {
             transactions.push(TransactionVariant::Transfer(transfer.clone()));
         }
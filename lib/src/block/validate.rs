@@ -5,11 +5,16 @@
  */
 
 use super::{Block, Hash, current_timestamp};
-use super::target::{calculate_target, hash_from_target};
-use super::transactions::merkle_root_for_transactions;
-use crate::transaction::TransactionValidationResult;
+use super::target::{calculate_target, hash_from_target, is_target_satisfiable};
+use super::transactions::is_canonically_ordered;
+use crate::transaction::{Transaction, TransactionContent, TransactionValidationResult};
+use crate::chain::BlockChain;
+use crate::wallet::WalletStatus;
+use crate::config::ChainConfig;
 
 use rsa::BigUint;
+use serde::Serialize;
+use std::collections::HashSet;
 use std::error::Error;
 
 #[derive(Debug, PartialEq)]
@@ -22,8 +27,13 @@ pub enum BlockValidationResult
     POW,
     Target,
     MerkleRoot,
+    Reward,
     Transaction(TransactionValidationResult),
     Balance(Hash),
+    PageUpdateInterval(Hash),
+    PageChunkSize,
+    DuplicateTransaction,
+    TransactionOrder,
 }
 
 impl std::fmt::Display for BlockValidationResult
@@ -40,27 +50,72 @@ impl std::fmt::Display for BlockValidationResult
             BlockValidationResult::POW => write!(f, "No valid proof or work"),
             BlockValidationResult::Target => write!(f, "Incorrect target value"),
             BlockValidationResult::MerkleRoot => write!(f, "Incorrect merkle root"),
+            BlockValidationResult::Reward => write!(f, "Claimed reward does not match block reward"),
             BlockValidationResult::Transaction(result) => write!(f, "{}", result),
             BlockValidationResult::Balance(_) => write!(f, "Insufficient balance"),
+            BlockValidationResult::PageUpdateInterval(_) => write!(f, "Site was updated too recently"),
+            BlockValidationResult::PageChunkSize => write!(f, "Page uses a different chunk size"),
+            BlockValidationResult::DuplicateTransaction => write!(f, "Transaction id has already been used"),
+            BlockValidationResult::TransactionOrder => write!(f, "Transactions are not in canonical order"),
         }
     }
 
 }
 
+fn spend_keys<C>(transactions: &[Transaction<C>]) -> Vec<(Hash, u32)>
+    where C: TransactionContent + Serialize
+{
+    transactions
+        .iter()
+        .flat_map(|transaction|
+        {
+            let id = transaction.get_id();
+            transaction.get_from_addresses()
+                .into_iter()
+                .map(move |address| (address, id))
+        })
+        .collect()
+}
+
 impl Block
 {
 
-    fn validate_transactions(&self) 
+    pub fn validate_merkle_root(&self) 
+        -> Result<BlockValidationResult, Box<dyn Error>>
+    {
+        if self.calculate_merkle_root()? != self.header.transaction_merkle_root {
+            Ok(BlockValidationResult::MerkleRoot)
+        } else {
+            Ok(BlockValidationResult::Ok)
+        }
+    }
+
+    fn validate_transactions(&self, config: &ChainConfig) 
         -> Result<BlockValidationResult, Box<dyn Error>>
     {
-        let merkle_root = merkle_root_for_transactions(&self.transfers, &self.pages)?;
-        if merkle_root != self.header.transaction_merkle_root {
-            return Ok(BlockValidationResult::MerkleRoot);
+        let is_expired = 
+            self.transfers.iter().any(|x| x.is_expired(self.header.timestamp)) ||
+            self.pages.iter().any(|x| x.is_expired(self.header.timestamp));
+        if is_expired {
+            return Ok(BlockValidationResult::Transaction(TransactionValidationResult::Expired));
         }
 
         for transfer in &self.transfers
         {
-            match transfer.validate_content()?
+            match transfer.validate_content(config.signature_scheme)?
+            {
+                TransactionValidationResult::Ok => {},
+                result => return Ok(BlockValidationResult::Transaction(result)),
+            }
+        }
+
+        for page in &self.pages
+        {
+            if page.header.content.chunk_size as usize != config.page_chunk_size {
+                return Ok(BlockValidationResult::PageChunkSize);
+            }
+
+            match page.validate_content(config.signature_scheme)?
             {
                 TransactionValidationResult::Ok => {},
                 result => return Ok(BlockValidationResult::Transaction(result)),
@@ -70,6 +125,40 @@ impl Block
         Ok(BlockValidationResult::Ok)
     }
 
+    // NOTE: So every miner includes the same transactions in the same order
+    pub fn validate_transaction_order(&self) 
+        -> Result<BlockValidationResult, Box<dyn Error>>
+    {
+        if is_canonically_ordered(&self.transfers)? && is_canonically_ordered(&self.pages)? {
+            Ok(BlockValidationResult::Ok)
+        } else {
+            Ok(BlockValidationResult::TransactionOrder)
+        }
+    }
+
+    // NOTE: Each wallet may only spend with a given id once
+    pub fn validate_duplicates(&self) -> BlockValidationResult
+    {
+        let mut keys = spend_keys(&self.transfers);
+        keys.extend(spend_keys(&self.pages));
+
+        let mut seen = HashSet::new();
+        if keys.into_iter().all(|key| seen.insert(key)) {
+            BlockValidationResult::Ok
+        } else {
+            BlockValidationResult::DuplicateTransaction
+        }
+    }
+
+    // Whether this block spends from `address` with an id it's already used
+    pub fn replays_from(&self, address: &Hash, status: &WalletStatus) -> bool
+    {
+        spend_keys(&self.transfers)
+            .into_iter()
+            .chain(spend_keys(&self.pages))
+            .any(|(from, id)| &from == address && id <= status.max_id)
+    }
+
     pub fn validate_next(&self, prev: &Block) 
         -> Result<BlockValidationResult, Box<dyn Error>>
     {
@@ -95,6 +184,10 @@ impl Block
     pub fn validate_pow(&self) 
         -> Result<BlockValidationResult, Box<dyn Error>>
     {
+        if !is_target_satisfiable(&self.header.target) {
+            return Ok(BlockValidationResult::Target);
+        }
+
         let hash = self.hash()?;
         let hash_num = BigUint::from_bytes_be(hash.data());
         let target_num = BigUint::from_bytes_be(&hash_from_target(&self.header.target));
@@ -106,18 +199,62 @@ impl Block
     }
 
     pub fn validate_target(&self, 
+                           config: &ChainConfig,
                            start_sample: Option<Block>, 
                            end_sample: Option<Block>) 
         -> BlockValidationResult
     {
-        if self.header.target == calculate_target(start_sample, end_sample) {
+        if self.header.target == calculate_target(config, start_sample, end_sample) {
             BlockValidationResult::Ok
         } else {
             BlockValidationResult::Target
         }
     }
 
+    pub fn validate_reward(&self) -> BlockValidationResult
+    {
+        self.validate_reward_with_premine(&[])
+    }
+
+    // NOTE: The premine is paid by the outputs following the reward
+    pub fn validate_reward_with_premine(&self, premine: &[(Hash, f32)]) 
+        -> BlockValidationResult
+    {
+        let coinbase = &self.coinbase;
+        if !coinbase.header.inputs.is_empty() || !coinbase.signatures.is_empty() || 
+            coinbase.header.content.fee != 0.0 || coinbase.header.valid_until.is_some()
+        {
+            return BlockValidationResult::Reward;
+        }
+
+        let outputs = &coinbase.header.content.outputs;
+        if outputs.iter().any(|x| x.amount < 0.0) || outputs.len() < premine.len() {
+            return BlockValidationResult::Reward;
+        }
+
+        let (reward_outputs, premine_outputs) = outputs.split_at(outputs.len() - premine.len());
+        if reward_outputs.iter().any(|x| x.to != self.header.raward_to) {
+            return BlockValidationResult::Reward;
+        }
+
+        let pays_premine = premine_outputs
+            .iter()
+            .zip(premine)
+            .all(|(output, (address, amount))| &output.to == address && output.amount == *amount);
+        if !pays_premine {
+            return BlockValidationResult::Reward;
+        }
+
+        let total_output = reward_outputs.iter().fold(0.0, |acc, x| acc + x.amount);
+        if total_output == self.calculate_reward() && self.header.reward == total_output {
+            BlockValidationResult::Ok
+        } else {
+            BlockValidationResult::Reward
+        }
+    }
+
     pub fn validate_content(&self,
+                            config: &ChainConfig,
                             start_sample: Option<Block>, 
                             end_sample: Option<Block>) 
         -> Result<BlockValidationResult, Box<dyn Error>>
@@ -127,18 +264,52 @@ impl Block
             BlockValidationResult::Ok => {},
             err => return Ok(err),
         }
-        match self.validate_target(start_sample, end_sample)
+        match self.validate_target(config, start_sample, end_sample)
+        {
+            BlockValidationResult::Ok => {},
+            err => return Ok(err),
+        }
+        match self.validate_merkle_root()?
+        {
+            BlockValidationResult::Ok => {},
+            err => return Ok(err),
+        }
+        let premine = if self.header.block_id == 0 { &config.premine[..] } else { &[] };
+        match self.validate_reward_with_premine(premine)
+        {
+            BlockValidationResult::Ok => {},
+            err => return Ok(err),
+        }
+        match self.validate_transactions(config)?
         {
             BlockValidationResult::Ok => {},
             err => return Ok(err),
         }
-        match self.validate_transactions()?
+        match self.validate_duplicates()
         {
             BlockValidationResult::Ok => {},
             err => return Ok(err),
         }
 
-        Ok(BlockValidationResult::Ok)
+        if self.header.block_id < config.transaction_order_height {
+            return Ok(BlockValidationResult::Ok);
+        }
+        self.validate_transaction_order()
+    }
+
+    // Run every check against the chain, returning the first failure. The
+    // `branch` holds any blocks below this one that aren't in the chain yet.
+    //
+    // NOTE: Checks are done in the order placement (block id, previous hash 
+    //       and timestamp), proof of work, target, merkle root, reward, 
+    //       transaction signatures, duplicates, transaction order, page 
+    //       intervals then balances.
+    pub fn full_validate(&self, chain: &mut BlockChain, branch: &[Block])
+        -> Result<BlockValidationResult, Box<dyn Error>>
+    {
+        let mut blocks = branch.to_vec();
+        blocks.push(self.clone());
+        chain.validate_branch(&blocks)
     }
 
 }
@@ -149,11 +320,15 @@ mod tests
 
     use super::*;
     use super::super::builder::BlockBuilder;
+    use super::super::transactions::merkle_root_for_transactions;
     use crate::transaction::transfer::TransferBuilder;
     use crate::transaction::builder::TransactionBuilder;
-    use crate::chain::BlockChain;
+    use crate::transaction::TransactionVariant;
+    use crate::chain::{BlockChain, BlockChainAddResult};
     use crate::wallet::{WalletStatus, Wallet};
+    use crate::wallet::public_wallet::WalletValidationResult;
     use crate::wallet::private_wallet::PrivateWallet;
+    use crate::hash::Signature;
     use crate::miner;
 
     #[test]
@@ -176,23 +351,24 @@ mod tests
             .expect("Can create block");
 
         assert_ne!(block.validate_pow().unwrap(), BlockValidationResult::Ok);
-        assert_eq!(block.validate_target(None, None), BlockValidationResult::Ok);
-        assert_ne!(block.validate_content(None, None).unwrap(), BlockValidationResult::Ok);
+        let config = ChainConfig::default();
+        assert_eq!(block.validate_target(&config, None, None), BlockValidationResult::Ok);
+        assert_ne!(block.validate_content(&config, None, None).unwrap(), BlockValidationResult::Ok);
 
         block = miner::mine_block(block);
         assert_eq!(block.validate_pow().unwrap(), BlockValidationResult::Ok);
-        assert_eq!(block.validate_content(None, None).unwrap(), BlockValidationResult::Ok);
+        assert_eq!(block.validate_content(&config, None, None).unwrap(), BlockValidationResult::Ok);
 
         {
             let mut wallet_status = WalletStatus::default();
-            wallet_status = block.update_wallet_status(&wallet.get_address(), wallet_status).unwrap();
+            wallet_status = block.update_wallet_status(&wallet.get_address(), wallet_status, 0.0).unwrap();
             assert_eq!(wallet_status.balance, block.calculate_reward() - 4.0);
             assert_eq!(wallet_status.max_id, 1);
         }
 
         {
             let mut wallet_status = WalletStatus::default();
-            wallet_status = block.update_wallet_status(&other.get_address(), wallet_status).unwrap();
+            wallet_status = block.update_wallet_status(&other.get_address(), wallet_status, 0.0).unwrap();
             assert_eq!(wallet_status.balance, 4.0);
             assert_eq!(wallet_status.max_id, 0);
         }
@@ -203,5 +379,345 @@ mod tests
         assert_eq!(addresses_used.contains(&other.get_address()), true);
     }
 
+    #[test]
+    fn test_block_verify_next()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut chain = BlockChain::open_temp();
+
+        let block_a = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Ok);
+        let block_b = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(chain.add(&block_b).unwrap(), BlockChainAddResult::Ok);
+
+        let block_c = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(block_c.validate_next(&block_b).unwrap(), BlockValidationResult::Ok);
+
+        // Skipped block id
+        let mut skipped = block_c.clone();
+        skipped.header.block_id += 1;
+        skipped = miner::mine_block(skipped);
+        assert_eq!(skipped.validate_next(&block_b).unwrap(), BlockValidationResult::NotNextBlock);
+        assert_eq!(chain.validate_branch(&[skipped]).unwrap(), BlockValidationResult::NotNextBlock);
+
+        // Mismatched previous hash
+        let mut wrong_prev = block_c.clone();
+        wrong_prev.header.prev_hash = block_a.hash().unwrap();
+        wrong_prev = miner::mine_block(wrong_prev);
+        assert_eq!(wrong_prev.validate_next(&block_b).unwrap(), BlockValidationResult::PrevHash);
+        assert_eq!(chain.add(&wrong_prev).unwrap(), 
+                   BlockChainAddResult::Invalid(BlockValidationResult::PrevHash));
+
+        assert_eq!(chain.add(&block_c).unwrap(), BlockChainAddResult::Ok);
+    }
+
+    #[test]
+    fn test_block_verify_reward()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut chain = BlockChain::open_temp();
+
+        let block_a = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(block_a.header.reward, block_a.calculate_reward());
+        assert_eq!(block_a.validate_reward(), BlockValidationResult::Ok);
+        assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Ok);
+
+        let mut over_claimed = Block::new_blank(&mut chain, &wallet).unwrap();
+        over_claimed.header.reward += 100.0;
+        over_claimed = miner::mine_block(over_claimed);
+
+        let config = ChainConfig::default();
+        assert_eq!(over_claimed.validate_reward(), BlockValidationResult::Reward);
+        assert_eq!(over_claimed.validate_content(&config, None, None).unwrap(), 
+                   BlockValidationResult::Reward);
+        assert_eq!(chain.add(&over_claimed).unwrap(), 
+                   BlockChainAddResult::Invalid(BlockValidationResult::Reward));
+
+        let status = chain.get_wallet_status(&wallet.get_address());
+        assert_eq!(status.balance, block_a.calculate_reward());
+    }
+
+    #[test]
+    fn test_block_coinbase()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut chain = BlockChain::open_temp();
+
+        let block = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        let transactions = block.transactions();
+        assert_eq!(transactions.len(), 1);
+        match &transactions[0]
+        {
+            TransactionVariant::Transfer(coinbase) =>
+            {
+                assert_eq!(coinbase.header.inputs.len(), 0);
+                assert_eq!(coinbase.header.content.outputs.len(), 1);
+                assert_eq!(coinbase.header.content.outputs[0].to, wallet.get_address());
+                assert_eq!(coinbase.header.content.outputs[0].amount, block.calculate_reward());
+            },
+            _ => panic!("Expected coinbase transfer"),
+        }
+        assert_eq!(block.validate_reward(), BlockValidationResult::Ok);
+        assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+
+        // Changing the coinbase without updating the merkle root
+        let mut tampered = Block::new_blank(&mut chain, &wallet).unwrap();
+        tampered.coinbase.header.content.outputs[0].to = other.get_address();
+        tampered = miner::mine_block(tampered);
+        assert_eq!(chain.add(&tampered).unwrap(), 
+                   BlockChainAddResult::Invalid(BlockValidationResult::MerkleRoot));
+
+        // Paying the reward to someone other then `raward_to`
+        let mut wrong_address = Block::new_blank(&mut chain, &wallet).unwrap();
+        wrong_address.coinbase.header.content.outputs[0].to = other.get_address();
+        wrong_address.header.transaction_merkle_root = merkle_root_for_transactions(
+            &wrong_address.coinbase, &wrong_address.transfers, &wrong_address.pages).unwrap();
+        wrong_address = miner::mine_block(wrong_address);
+        assert_eq!(wrong_address.validate_reward(), BlockValidationResult::Reward);
+        assert_eq!(chain.add(&wrong_address).unwrap(), 
+                   BlockChainAddResult::Invalid(BlockValidationResult::Reward));
+
+        // Claiming the wrong amount
+        let mut wrong_amount = Block::new_blank(&mut chain, &wallet).unwrap();
+        wrong_amount.coinbase.header.content.outputs[0].amount += 1.0;
+        wrong_amount.header.transaction_merkle_root = merkle_root_for_transactions(
+            &wrong_amount.coinbase, &wrong_amount.transfers, &wrong_amount.pages).unwrap();
+        wrong_amount = miner::mine_block(wrong_amount);
+        assert_eq!(wrong_amount.validate_reward(), BlockValidationResult::Reward);
+        assert_eq!(chain.add(&wrong_amount).unwrap(), 
+                   BlockChainAddResult::Invalid(BlockValidationResult::Reward));
+        assert_eq!(chain.get_wallet_status(&wallet.get_address()).balance, block.calculate_reward());
+    }
+
+    #[test]
+    fn test_block_merkle_root()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut chain = BlockChain::open_temp();
+
+        let transfer = |amount: f32|
+            TransactionBuilder::new(
+                TransferBuilder::new(1, 0.25)
+                    .add_output(other.get_address(), amount)
+                    .build())
+                .add_input(&wallet, amount + 0.25)
+                .build().unwrap();
+
+        let block = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_transfer(transfer(1.0))
+            .build(&mut chain)
+            .unwrap());
+
+        let config = ChainConfig::default();
+        assert_eq!(block.validate_content(&config, None, None).unwrap(), BlockValidationResult::Ok);
+
+        // Swapping a transaction leaves the header, and so the block hash, unchanged
+        let mut tampered = block.clone();
+        tampered.transfers[0] = transfer(4.0);
+        assert_eq!(tampered.hash().unwrap(), block.hash().unwrap());
+        assert_eq!(tampered.validate_pow().unwrap(), BlockValidationResult::Ok);
+        assert_eq!(tampered.validate_content(&config, None, None).unwrap(), 
+                   BlockValidationResult::MerkleRoot);
+        assert_eq!(chain.add(&tampered).unwrap(), 
+                   BlockChainAddResult::Invalid(BlockValidationResult::MerkleRoot));
+    }
+
+    #[test]
+    fn test_block_identity()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut chain = BlockChain::open_temp();
+
+        let transfer = TransactionBuilder::new(
+            TransferBuilder::new(1, 0.25)
+                .add_output(other.get_address(), 1.0)
+                .build())
+            .add_input(&wallet, 1.25)
+            .build().unwrap();
+
+        let block = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_transfer(transfer)
+            .build(&mut chain)
+            .unwrap());
+        assert!(block.is_same_block(&block.clone()));
+
+        // Only the header is looked at, the transactions are left to the merkle root
+        let mut without_transfers = block.clone();
+        without_transfers.transfers.clear();
+        assert!(without_transfers != block);
+        assert!(without_transfers.is_same_block(&block));
+
+        let mut other_pow = block.clone();
+        other_pow.header.pow += 1;
+        assert!(!other_pow.is_same_block(&block));
+    }
+
+    #[test]
+    fn test_full_validate()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut chain = BlockChain::open_temp();
+
+        let block_a = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Ok);
+
+        let transfer = |id: u32, amount: f32|
+            TransactionBuilder::new(
+                TransferBuilder::new(id, 0.25)
+                    .add_output(other.get_address(), amount)
+                    .build())
+                .add_input(&wallet, amount + 0.25)
+                .build().unwrap();
+        let remine = |mut block: Block|
+        {
+            block.header.transaction_merkle_root = merkle_root_for_transactions(
+                &block.coinbase, &block.transfers, &block.pages).unwrap();
+            miner::mine_block(block)
+        };
+
+        let valid = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_transfer(transfer(1, 1.0))
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(valid.full_validate(&mut chain, &[]).unwrap(), BlockValidationResult::Ok);
+
+        // Placed on top of a block at the same height
+        assert_eq!(valid.full_validate(&mut chain, std::slice::from_ref(&valid)).unwrap(), 
+                   BlockValidationResult::NotNextBlock);
+
+        let mut unmined = valid.clone();
+        while unmined.validate_pow().unwrap() == BlockValidationResult::Ok {
+            unmined.header.pow += 1;
+        }
+        assert_eq!(unmined.full_validate(&mut chain, &[]).unwrap(), BlockValidationResult::POW);
+
+        let mut wrong_target = valid.clone();
+        wrong_target.header.target = [0x00, 0xFF, 0xFE, 0x20];
+        wrong_target = miner::mine_block(wrong_target);
+        assert_eq!(wrong_target.full_validate(&mut chain, &[]).unwrap(), BlockValidationResult::Target);
+
+        let mut future = valid.clone();
+        future.header.timestamp += 60 * 60 * 1000;
+        future = miner::mine_block(future);
+        assert_eq!(future.full_validate(&mut chain, &[]).unwrap(), BlockValidationResult::Timestamp);
+
+        let mut wrong_merkle_root = valid.clone();
+        wrong_merkle_root.transfers[0] = transfer(1, 2.0);
+        assert_eq!(wrong_merkle_root.full_validate(&mut chain, &[]).unwrap(), 
+                   BlockValidationResult::MerkleRoot);
+
+        let mut wrong_reward = valid.clone();
+        wrong_reward.coinbase.header.content.outputs[0].amount += 1.0;
+        wrong_reward = remine(wrong_reward);
+        assert_eq!(wrong_reward.full_validate(&mut chain, &[]).unwrap(), BlockValidationResult::Reward);
+
+        let mut forged = valid.clone();
+        for signature in forged.transfers[0].signatures.values_mut() {
+            *signature = Signature::empty();
+        }
+        forged = remine(forged);
+        assert_eq!(forged.full_validate(&mut chain, &[]).unwrap(), 
+                   BlockValidationResult::Transaction(TransactionValidationResult::Wallet(
+                       WalletValidationResult::Signature)));
+
+        let mut duplicated = valid.clone();
+        duplicated.transfers.push(transfer(1, 2.0));
+        duplicated = remine(duplicated);
+        assert_eq!(duplicated.full_validate(&mut chain, &[]).unwrap(), 
+                   BlockValidationResult::DuplicateTransaction);
+
+        let mut overdrawn = valid.clone();
+        overdrawn.transfers[0] = transfer(1, 1000.0);
+        overdrawn = remine(overdrawn);
+        assert_eq!(overdrawn.full_validate(&mut chain, &[]).unwrap(), 
+                   BlockValidationResult::Balance(wallet.get_address()));
+
+        // Replaying a transaction that's already in the chain
+        assert_eq!(chain.add(&valid).unwrap(), BlockChainAddResult::Ok);
+        let replayed = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_transfer(transfer(1, 1.0))
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(replayed.full_validate(&mut chain, &[]).unwrap(), 
+                   BlockValidationResult::DuplicateTransaction);
+        assert_eq!(chain.add(&replayed).unwrap(), 
+                   BlockChainAddResult::Invalid(BlockValidationResult::DuplicateTransaction));
+    }
+
+    #[test]
+    fn test_transaction_order()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut chain = BlockChain::open_temp();
+
+        let block_a = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Ok);
+
+        let transfer = |from: &PrivateWallet, to: &PrivateWallet, id: u32, amount: f32|
+            TransactionBuilder::new(
+                TransferBuilder::new(id, 0.25)
+                    .add_output(to.get_address(), amount)
+                    .build())
+                .add_input(from, amount + 0.25)
+                .build().unwrap();
+        let remine = |mut block: Block|
+        {
+            block.header.transaction_merkle_root = merkle_root_for_transactions(
+                &block.coinbase, &block.transfers, &block.pages).unwrap();
+            miner::mine_block(block)
+        };
+
+        // Added out of order, but built in canonical order
+        let second = transfer(&wallet, &other, 2, 1.0);
+        let first = transfer(&wallet, &other, 1, 2.0);
+        let block = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_transfer(second.clone())
+            .add_transfer(first.clone())
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(block.transfers, vec![first.clone(), second.clone()]);
+        assert_eq!(block.validate_transaction_order().unwrap(), BlockValidationResult::Ok);
+        assert_eq!(block.full_validate(&mut chain, &[]).unwrap(), BlockValidationResult::Ok);
+
+        let mut out_of_order = block.clone();
+        out_of_order.transfers.reverse();
+        out_of_order = remine(out_of_order);
+        assert_eq!(out_of_order.full_validate(&mut chain, &[]).unwrap(), 
+                   BlockValidationResult::TransactionOrder);
+        assert_eq!(chain.add(&out_of_order).unwrap(), 
+                   BlockChainAddResult::Invalid(BlockValidationResult::TransactionOrder));
+
+        // Ties between ids are broken by hash
+        let config = ChainConfig::default();
+        let mut tied = BlockBuilder::new(&wallet)
+            .add_transfer(first.clone())
+            .add_transfer(transfer(&other, &wallet, 1, 0.5))
+            .build(&mut chain)
+            .unwrap();
+        assert!(tied.transfers[0].hash().unwrap() < tied.transfers[1].hash().unwrap());
+        tied.transfers.reverse();
+        tied = remine(tied);
+        assert_eq!(tied.validate_content(&config, None, None).unwrap(), 
+                   BlockValidationResult::TransactionOrder);
+
+        // Blocks below the activation height may be in any order
+        let before_activation = ChainConfig
+        {
+            transaction_order_height: block.header.block_id + 1,
+            ..ChainConfig::default()
+        };
+        assert_eq!(out_of_order.validate_content(&before_activation, None, None).unwrap(), 
+                   BlockValidationResult::Ok);
+        assert_eq!(tied.validate_content(&before_activation, None, None).unwrap(), 
+                   BlockValidationResult::Ok);
+
+        assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+    }
+
 }
 
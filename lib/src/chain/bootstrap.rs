@@ -0,0 +1,278 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use super::{BlockChain, BlockChainAddResult};
+use crate::block::Block;
+use crate::error::ErrorMessage;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::error::Error;
+
+// NOTE: A corrupt length could otherwise ask for any amount of memory
+const MAX_BOOTSTRAP_BLOCK_SIZE: u64 = 100 * 1000 * 1000; // 100MB
+
+// How much of a bootstrap file was imported. If it stopped early, this
+// has the byte offset of the block it stopped at, and why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockImportReport
+{
+    pub blocks_imported: u64,
+    pub stopped_at: Option<(u64, String)>,
+}
+
+fn write_bootstrap_block<W: Write>(writer: &mut W, block: &Block)
+    -> Result<(), Box<dyn Error>>
+{
+    let bytes = bincode::serialize(block)?;
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(&bytes)?;
+    Ok(())
+}
+
+// Read the next block, and the number of bytes it took up, or
+// none if the file ends cleanly before it.
+//
+// NOTE: Each block is stored as its serialized length, a little
+//       endian u64, followed by the block serialized with bincode.
+fn read_bootstrap_block<R: Read>(reader: &mut R)
+    -> Result<Option<(Block, u64)>, Box<dyn Error>>
+{
+    let mut length_bytes = Vec::new();
+    reader.by_ref().take(8).read_to_end(&mut length_bytes)?;
+    if length_bytes.is_empty() {
+        return Ok(None);
+    }
+    if length_bytes.len() < 8 {
+        return Err(ErrorMessage::new("File ends part way through a block length"));
+    }
+
+    let mut length_array = [0u8; 8];
+    length_array.copy_from_slice(&length_bytes);
+    let length = u64::from_le_bytes(length_array);
+    if length > MAX_BOOTSTRAP_BLOCK_SIZE {
+        return Err(ErrorMessage::new(&format!("Block length of {} bytes is too large", length)));
+    }
+
+    let mut buffer = vec![0u8; length as usize];
+    if reader.read_exact(&mut buffer).is_err() {
+        return Err(ErrorMessage::new("File ends part way through a block"));
+    }
+
+    let block = bincode::deserialize::<Block>(&buffer)?;
+    Ok(Some((block, 8 + length)))
+}
+
+impl BlockChain
+{
+
+    // Write the blocks from `from` to `to`, inclusive, to a bootstrap 
+    // file. Blocks are read and written one at a time.
+    pub fn export_to_file(&self, path: &PathBuf, from: u64, to: u64)
+        -> Result<(), Box<dyn Error>>
+    {
+        if from > to || to >= self.blocks.next_top() {
+            return Err(ErrorMessage::new(&format!("No blocks from {} to {}", from, to)));
+        }
+
+        // NOTE: Pruning removes the lowest blocks first
+        if self.is_pruned(from) {
+            return Err(ErrorMessage::new(&format!("Block {} has been pruned", from)));
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        for block_id in from..=to {
+            write_bootstrap_block(&mut writer, &self.block_or_error(block_id)?)?;
+        }
+        writer.flush()?;
+
+        info!("Exported blocks {} to {} to '{}'", from, to, path.display());
+        Ok(())
+    }
+
+    // Add each block from a bootstrap file, in order. Blocks we already
+    // have are skipped, and the import stops at the first block that's
+    // corrupt or can't be added, keeping everything before it.
+    pub fn import_from_file(&mut self, path: &PathBuf)
+        -> Result<BlockImportReport, Box<dyn Error>>
+    {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut blocks_imported = 0;
+        let mut offset = 0;
+        let mut stopped_at = None;
+        loop
+        {
+            let (block, size) = match read_bootstrap_block(&mut reader)
+            {
+                Ok(Some(block_and_size)) => block_and_size,
+                Ok(None) => break,
+                Err(err) =>
+                {
+                    stopped_at = Some((offset, err.to_string()));
+                    break;
+                },
+            };
+
+            match self.add(&block)?
+            {
+                BlockChainAddResult::Ok => blocks_imported += 1,
+                BlockChainAddResult::Duplicate => {},
+                result =>
+                {
+                    stopped_at = Some((offset, format!("Could not add block {}: {}",
+                        block.header.block_id, result)));
+                    break;
+                },
+            }
+            offset += size;
+        }
+
+        match &stopped_at
+        {
+            Some((offset, reason)) =>
+                warn!("Stopped importing '{}' at byte {}: {}", path.display(), offset, reason),
+            None =>
+                info!("Imported {} block(s) from '{}'", blocks_imported, path.display()),
+        }
+
+        Ok(BlockImportReport
+        {
+            blocks_imported,
+            stopped_at,
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::config::ChainConfig;
+    use crate::wallet::private_wallet::PrivateWallet;
+    use crate::miner;
+
+    fn write_bootstrap(path: &PathBuf, blocks: &[Block])
+    {
+        let mut file = File::create(path).unwrap();
+        for block in blocks {
+            write_bootstrap_block(&mut file, block).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_import_from_file()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let config = ChainConfig
+        {
+            block_time: 1,
+            block_sample_size: 2,
+            ..ChainConfig::default()
+        };
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut chain = BlockChain::open_temp_with_config(config.clone());
+        for _ in 0..5
+        {
+            let block = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+            assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+        }
+
+        let blocks = (0..5)
+            .map(|id| chain.block(id).unwrap())
+            .collect::<Vec<_>>();
+        let time = crate::block::current_timestamp();
+        let path = std::env::temp_dir().join(format!("{}.bootstrap", time));
+        write_bootstrap(&path, &blocks);
+
+        // Reproduces the same chain
+        let mut imported = BlockChain::open_temp_with_config(config.clone());
+        let report = imported.import_from_file(&path).unwrap();
+        assert_eq!(report, BlockImportReport { blocks_imported: 5, stopped_at: None });
+        assert_eq!(imported.top(), chain.top());
+
+        // Importing again skips the blocks we already have
+        let report = imported.import_from_file(&path).unwrap();
+        assert_eq!(report, BlockImportReport { blocks_imported: 0, stopped_at: None });
+
+        // A truncated file imports up to the last whole block
+        let bytes = std::fs::read(&path).unwrap();
+        let offset_of_last = bytes.len() as u64 - 8 - bincode::serialize(&blocks[4]).unwrap().len() as u64;
+        std::fs::write(&path, &bytes[..bytes.len() - 10]).unwrap();
+
+        let mut truncated = BlockChain::open_temp_with_config(config.clone());
+        let report = truncated.import_from_file(&path).unwrap();
+        assert_eq!(report.blocks_imported, 4);
+        assert_eq!(report.stopped_at.map(|(offset, _)| offset), Some(offset_of_last));
+        assert_eq!(truncated.top(), Some(blocks[3].clone()));
+
+        // As does one with a block that isn't valid
+        let mut tampered = blocks.clone();
+        tampered[2].coinbase.header.content.outputs[0].amount += 1.0;
+        write_bootstrap(&path, &tampered);
+
+        let mut corrupt = BlockChain::open_temp_with_config(config);
+        let report = corrupt.import_from_file(&path).unwrap();
+        assert_eq!(report.blocks_imported, 2);
+        assert!(report.stopped_at.unwrap().1.contains("Could not add block 2"));
+        assert_eq!(corrupt.top(), Some(blocks[1].clone()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_export_to_file()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let config = ChainConfig
+        {
+            block_time: 1,
+            block_sample_size: 2,
+            ..ChainConfig::default()
+        };
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut chain = BlockChain::open_temp_with_config(config.clone());
+        for _ in 0..6
+        {
+            let block = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+            assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+        }
+
+        let time = crate::block::current_timestamp();
+        let path = std::env::temp_dir().join(format!("{}.export", time));
+        assert!(chain.export_to_file(&path, 0, 6).is_err());
+        assert!(chain.export_to_file(&path, 3, 2).is_err());
+
+        // The whole chain
+        chain.export_to_file(&path, 0, 5).unwrap();
+        let mut imported = BlockChain::open_temp_with_config(config.clone());
+        let report = imported.import_from_file(&path).unwrap();
+        assert_eq!(report, BlockImportReport { blocks_imported: 6, stopped_at: None });
+        assert_eq!(imported.top(), chain.top());
+
+        // Or just a range of it, on top of the blocks before it
+        let mut partial = BlockChain::open_temp_with_config(config);
+        for block_id in 0..2 {
+            assert_eq!(partial.add(&chain.block(block_id).unwrap()).unwrap(), BlockChainAddResult::Ok);
+        }
+
+        chain.export_to_file(&path, 2, 4).unwrap();
+        let report = partial.import_from_file(&path).unwrap();
+        assert_eq!(report, BlockImportReport { blocks_imported: 3, stopped_at: None });
+        for block_id in 0..5 {
+            assert_eq!(partial.block(block_id), chain.block(block_id));
+        }
+        assert_eq!(partial.top(), chain.block(4));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+}
+
@@ -4,7 +4,7 @@
  * SPDX-License-Identifier: BSD-2-Clause
  */
 
-use super::{BlockChain, BLOCK_SAMPLE_SIZE};
+use super::BlockChain;
 use super::{BlockValidationResult, BlockChainAddResult};
 use crate::block::Block;
 use crate::wallet::WalletStatus;
@@ -12,6 +12,7 @@ use crate::hash::Hash;
 
 use std::error::Error;
 use std::collections::HashMap;
+use std::sync::mpsc::{Receiver, channel};
 
 #[derive(Debug, PartialEq)]
 pub enum BlockChainCanMergeResult
@@ -20,92 +21,212 @@ pub enum BlockChainCanMergeResult
     Empty,
     Above,
     Short,
+    TooDeep,
     Invalid(BlockValidationResult),
 }
 
+// Describes the blocks swapped out when a branch replaces the top of the chain
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReorgEvent
+{
+    pub common_ancestor: Option<Block>,
+    pub old_top: Block,
+    pub new_top: Block,
+    pub disconnected: Vec<Block>,
+    pub connected: Vec<Block>,
+}
+
 impl BlockChain
 {
 
     fn take_sample_of_branch_at(&mut self, branch: &[Block], block_id: u64) 
         -> (Option<Block>, Option<Block>)
     {
-        assert_eq!(branch.is_empty(), false);
-
-        if block_id < BLOCK_SAMPLE_SIZE {
+        let sample_size = self.config.sample_span();
+        if block_id < sample_size {
             return (None, None);
         }
 
-        let branch_start = branch.first().unwrap();
-        let mut block_at = |block_id: u64| -> Option<Block>
+        let sample_start = self.block_in_branch_or_chain(branch, block_id - sample_size);
+        let sample_end = self.block_in_branch_or_chain(branch, block_id);
+        (sample_start, sample_end)
+    }
+
+    fn block_in_branch_or_chain(&mut self, branch: &[Block], block_id: u64) 
+        -> Option<Block>
+    {
+        let bottom_or_none = branch.first();
+        if bottom_or_none.is_some() && block_id >= bottom_or_none.unwrap().header.block_id 
         {
-            if block_id >= branch_start.header.block_id 
+            let index = block_id - bottom_or_none.unwrap().header.block_id;
+            return branch.get(index as usize).cloned();
+        }
+
+        self.block(block_id)
+    }
+
+    pub fn immature_reward(&mut self, address: &Hash, block_id: u64, branch: &[Block]) 
+        -> f32
+    {
+        let maturity = self.config.reward_maturity.unwrap_or(0);
+        let start = (block_id + 1).saturating_sub(maturity);
+
+        let mut immature = 0.0;
+        for reward_block_id in start..=block_id
+        {
+            // NOTE: Blocks not yet mined have no reward
+            let block_or_none = self.block_in_branch_or_chain(branch, reward_block_id);
+            if block_or_none.is_none() {
+                continue;
+            }
+
+            let coinbase = block_or_none.unwrap().coinbase;
+            for output in &coinbase.header.content.outputs
             {
-                match branch.get((block_id - branch_start.header.block_id) as usize)
-                {
-                    Some(block) => Some(block.clone()),
-                    None => None,
+                if &output.to == address {
+                    immature += output.amount;
                 }
-            } 
-            else 
-            {
-                self.block(block_id)
             }
-        };
+        }
 
-        let sample_start = block_at(block_id - BLOCK_SAMPLE_SIZE);
-        let sample_end = block_at(block_id);
-        (sample_start, sample_end)
+        immature
     }
 
-    pub fn validate_branch(&mut self, branch: &[Block])
-        -> Result<BlockValidationResult, Box<dyn Error>>
+    pub fn last_page_update_below(&mut self, site: &Hash, below_block_id: u64, 
+                                  interval: u64) 
+        -> Option<u64>
     {
-        let bottom = branch.first().unwrap();
-        let last_block_id = 
-            if bottom.header.block_id == 0 {
-                0
-            } else {
-                bottom.header.block_id - 1
-            };
+        // NOTE: Only updates that would block `below_block_id` are found
+        let start = (below_block_id + 1).saturating_sub(interval);
+        for block_id in (start..below_block_id).rev()
+        {
+            let metadata = self.metadata.get(block_id).unwrap();
+            if metadata.page_updates.contains_key(site) {
+                return Some(block_id);
+            }
+        }
 
-        let mut last_block_or_none = self.block(last_block_id);
-        let mut wallets = HashMap::<Hash, WalletStatus>::new();
-        for block in branch
+        None
+    }
+
+    pub fn validate_page_update_interval(&mut self, block: &Block, bottom_id: u64,
+                                         page_updates: &mut HashMap<Hash, u64>)
+        -> Option<BlockValidationResult>
+    {
+        let interval = self.config.page_update_interval?;
+        for page in &block.pages
         {
-            for address in block.get_addresses_used()
+            // NOTE: Updates within the branch replace those in the chain
+            let site = page.header.content.site;
+            let last_update = match page_updates.get(&site)
             {
-                if !wallets.contains_key(&address) 
-                {
-                    let status = self.get_wallet_status_up_to_block(last_block_id, &address);
-                    wallets.insert(address, status);
-                }
+                Some(block_id) => Some(*block_id),
+                None => self.last_page_update_below(&site, bottom_id, interval),
+            };
 
-                let status = wallets.get_mut(&address).unwrap();
-                let new_status = block.update_wallet_status(&address, status.clone())?;
-                if new_status.balance < 0.0 {
-                    return Ok(BlockValidationResult::Balance(address));
-                }
-                *status = new_status;
+            if last_update.is_some() && block.header.block_id - last_update.unwrap() < interval {
+                return Some(BlockValidationResult::PageUpdateInterval(site));
             }
+            page_updates.insert(site, block.header.block_id);
+        }
 
-            if last_block_or_none.is_some()
-            {
-                let last_block = last_block_or_none.unwrap();
+        None
+    }
 
-                // FIXME: Validate transactions in this case
-                let (sample_start, sample_end) = self.take_sample_of_branch_at(branch, last_block.header.block_id);
-                match block.validate_next(&last_block)?
+    // Validate a block following `last_block_or_none` in a branch from 
+    // `bottom_id`. The wallets and page updates changed by the blocks of 
+    // the branch so far are kept in `wallets` and `page_updates`, 
+    // anything else is read from the chain below it.
+    pub fn validate_block_in_branch(&mut self, block: &Block, last_block_or_none: Option<&Block>,
+                                    branch: &[Block], bottom_id: u64,
+                                    wallets: &mut HashMap<Hash, WalletStatus>,
+                                    page_updates: &mut HashMap<Hash, u64>)
+        -> Result<BlockValidationResult, Box<dyn Error>>
+    {
+        let (sample_start, sample_end) = match last_block_or_none
+        {
+            Some(last_block) =>
+            {
+                match block.validate_next(last_block)?
                 {
                     BlockValidationResult::Ok => {},
                     result => return Ok(result),
                 }
 
-                match block.validate_content(sample_start, sample_end)?
+                self.take_sample_of_branch_at(branch, last_block.header.block_id)
+            },
+
+            None => (None, None),
+        };
+
+        match block.validate_content(&self.config, sample_start, sample_end)?
+        {
+            BlockValidationResult::Ok => {},
+            result => return Ok(result),
+        }
+
+        if let Some(result) = self.validate_page_update_interval(block, bottom_id, page_updates) {
+            return Ok(result);
+        }
+
+        for address in block.get_addresses_used()
+        {
+            if !wallets.contains_key(&address) 
+            {
+                let status = match bottom_id.checked_sub(1)
                 {
-                    BlockValidationResult::Ok => {},
-                    result => return Ok(result),
+                    Some(last_block_id) => self.get_wallet_status_up_to_block(last_block_id, &address),
+                    None => WalletStatus::default(),
+                };
+                wallets.insert(address, status);
+            }
+
+            let status = wallets.get_mut(&address).unwrap().clone();
+            if block.replays_from(&address, &status) {
+                return Ok(BlockValidationResult::DuplicateTransaction);
+            }
+
+            let new_status = block.update_wallet_status(&address, status, self.config().fee_burn_ratio)?;
+            if new_status.balance < 0.0 {
+                return Ok(BlockValidationResult::Balance(address));
+            }
+
+            // NOTE: Any spending must leave the immature rewards untouched
+            if block.get_from_addresses().contains(&address)
+            {
+                let immature = self.immature_reward(&address, block.header.block_id, branch);
+                if new_status.balance < immature {
+                    return Ok(BlockValidationResult::Balance(address));
                 }
             }
+            wallets.insert(address, new_status);
+        }
+
+        Ok(BlockValidationResult::Ok)
+    }
+
+    pub fn validate_branch(&mut self, branch: &[Block])
+        -> Result<BlockValidationResult, Box<dyn Error>>
+    {
+        // NOTE: A branch starting at the genesis block has no parent
+        let bottom = branch.first().unwrap();
+        let last_block_id_or_none = bottom.header.block_id.checked_sub(1);
+
+        let mut last_block_or_none = last_block_id_or_none.and_then(|id| self.block(id));
+        if last_block_id_or_none.is_some() && last_block_or_none.is_none() {
+            return Ok(BlockValidationResult::NotNextBlock);
+        }
+
+        let mut wallets = HashMap::<Hash, WalletStatus>::new();
+        let mut page_updates = HashMap::<Hash, u64>::new();
+        for block in branch
+        {
+            match self.validate_block_in_branch(block, last_block_or_none.as_ref(), branch, 
+                bottom.header.block_id, &mut wallets, &mut page_updates)?
+            {
+                BlockValidationResult::Ok => {},
+                result => return Ok(result),
+            }
 
             last_block_or_none = Some( block.clone() );
         }
@@ -113,6 +234,17 @@ impl BlockChain
         Ok(BlockValidationResult::Ok)
     }
 
+    // Whether a branch from `block_id` would replace no more of our 
+    // blocks then the max reorg depth
+    pub fn is_within_reorg_depth(&self, block_id: u64) -> bool
+    {
+        match self.config.max_reorg_depth
+        {
+            Some(max_depth) => self.blocks.next_top().saturating_sub(block_id) <= max_depth,
+            None => true,
+        }
+    }
+
     pub fn can_merge_branch(&mut self, branch: &[Block]) 
         -> Result<BlockChainCanMergeResult, Box<dyn Error>>
     {
@@ -126,12 +258,27 @@ impl BlockChain
             return Ok(BlockChainCanMergeResult::Above);
         }
 
+        if !self.is_within_reorg_depth(bottom.header.block_id) {
+            return Ok(BlockChainCanMergeResult::TooDeep);
+        }
+
         // Not longer then the current branch
         let top = branch.last().unwrap();
-        if top.header.block_id < self.blocks.next_top() as u64 {
+        let next_top = self.blocks.next_top() as u64;
+        if top.header.block_id + 1 < next_top {
             return Ok(BlockChainCanMergeResult::Short);
         }
 
+        // NOTE: Branches of equal height are broken by the lowest top 
+        //       block hash, so every node ends up following the same one.
+        if top.header.block_id + 1 == next_top
+        {
+            let our_top = self.top_ref().unwrap();
+            if top.hash()? >= our_top.hash()? {
+                return Ok(BlockChainCanMergeResult::Short);
+            }
+        }
+
         // Validate branch
         match self.validate_branch(branch)?
         {
@@ -140,17 +287,59 @@ impl BlockChain
         }
     }
 
+    pub fn subscribe_reorgs(&mut self) -> Receiver<ReorgEvent>
+    {
+        let (sender, receiver) = channel();
+        self.reorg_subscribers.push(sender);
+        receiver
+    }
+
+    fn publish_reorg(&mut self, event: ReorgEvent)
+    {
+        // NOTE: Subscribers are removed once their receiver is dropped
+        self.reorg_subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
     pub fn merge_branch(&mut self, branch: Vec<Block>)
     {
         assert_eq!(self.can_merge_branch(&branch).unwrap(), BlockChainCanMergeResult::Ok);
 
-        let bottom = branch.first().unwrap();
-        self.metadata.truncate(bottom.header.block_id);
-        self.blocks.truncate(bottom.header.block_id);
+        let bottom_id = branch.first().unwrap().header.block_id;
+        let old_top = self.top();
+        let disconnected = (bottom_id..self.blocks.next_top())
+            .filter_map(|block_id| self.block(block_id))
+            .collect::<Vec<_>>();
+        let common_ancestor = match bottom_id
+        {
+            0 => None,
+            _ => self.block(bottom_id - 1),
+        };
+
+        self.invalidate_wallet_status_cache(bottom_id);
+        self.metadata.truncate(bottom_id);
+        self.headers.truncate(bottom_id);
+        self.unindex_headers_from(bottom_id);
+        self.blocks.truncate(bottom_id);
+        self.load_top();
+
+        for block in &branch {
+            assert_eq!(self.add(block).unwrap(), BlockChainAddResult::Ok);
+        }
 
-        for block in branch {
-            assert_eq!(self.add(&block).unwrap(), BlockChainAddResult::Ok);
+        // NOTE: A branch that only extends the chain doesn't replace anything
+        if disconnected.is_empty() {
+            return;
         }
+
+        info!("Reorganized {} block(s) from {}", disconnected.len(), bottom_id);
+        self.publish_reorg(ReorgEvent
+        {
+            common_ancestor,
+            old_top: old_top.unwrap(),
+            new_top: branch.last().unwrap().clone(),
+            disconnected,
+            connected: branch,
+        });
     }
 
 }
@@ -0,0 +1,145 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+// Layouts of the types kept in storage before they were changed, so
+// chunks written by older versions can still be read.
+
+use super::metadata::{BlockMetadata, PageMetadata};
+use crate::block::{Block, BlockHeader, new_coinbase, block_reward};
+use crate::block::target::Target;
+use crate::transaction::{Transaction, TransactionHeader, TransactionContent, Input};
+use crate::transaction::transfer::Transfer;
+use crate::transaction::page::Page;
+use crate::wallet::WalletStatus;
+use crate::hash::{Hash, Signature};
+
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BlockHeaderV1
+{
+    pub prev_hash: Hash,
+    pub block_id: u64,
+    pub timestamp: u128,
+    pub raward_to: Hash,
+    pub target: Target,
+    pub transaction_merkle_root: Hash,
+    pub pow: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TransactionHeaderV1<C>
+{
+    pub content: C,
+    pub inputs: Vec<Input>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TransactionV1<C>
+{
+    pub header: TransactionHeaderV1<C>,
+    pub signatures: HashMap<Hash, Signature>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PageV1
+{
+    pub id: u32,
+    pub site: Hash,
+
+    pub data_hashes: Vec<Hash>,
+    pub data_length: u32,
+    pub fee: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BlockV1
+{
+    pub header: BlockHeaderV1,
+    pub pages: Vec<TransactionV1<PageV1>>,
+    pub transfers: Vec<TransactionV1<Transfer>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BlockMetadataV1
+{
+    pub wallets: HashMap<Hash, WalletStatus>,
+    pub page_updates: HashMap<Hash, PageMetadata>,
+}
+
+// NOTE: The reward was implied by the block id before it was part of
+//       the header. As the header layout has changed, a migrated
+//       header no longer hashes to the `prev_hash` of the block after
+//       it, so these are only for reading back what was stored.
+impl From<BlockHeaderV1> for BlockHeader
+{
+    fn from(header: BlockHeaderV1) -> Self
+    {
+        Self
+        {
+            prev_hash: header.prev_hash,
+            block_id: header.block_id,
+            timestamp: header.timestamp,
+            raward_to: header.raward_to,
+            reward: block_reward(header.block_id),
+            target: header.target,
+            transaction_merkle_root: header.transaction_merkle_root,
+            pow: header.pow,
+        }
+    }
+}
+
+impl From<PageV1> for Page
+{
+    fn from(page: PageV1) -> Self
+    {
+        Page::new(page.id, page.site, page.data_hashes, page.data_length, page.fee)
+    }
+}
+
+fn migrate_transaction<A, B>(transaction: TransactionV1<A>) -> Transaction<B>
+    where A: Into<B>, B: TransactionContent + Serialize
+{
+    Transaction::new(TransactionHeader
+    {
+        content: transaction.header.content.into(),
+        inputs: transaction.header.inputs,
+        valid_until: None,
+    }, transaction.signatures)
+}
+
+impl From<BlockV1> for Block
+{
+    fn from(block: BlockV1) -> Self
+    {
+        // NOTE: The reward was paid without a coinbase transaction, so
+        //       make the one it would have had.
+        let header = BlockHeader::from(block.header);
+        let coinbase = new_coinbase(header.block_id, header.raward_to, header.reward, &[]);
+        Self
+        {
+            header,
+            coinbase,
+            pages: block.pages.into_iter().map(migrate_transaction).collect(),
+            transfers: block.transfers.into_iter().map(migrate_transaction).collect(),
+        }
+    }
+}
+
+impl From<BlockMetadataV1> for BlockMetadata
+{
+    fn from(metadata: BlockMetadataV1) -> Self
+    {
+        Self
+        {
+            wallets: metadata.wallets,
+            page_updates: metadata.page_updates,
+            fees_burned: 0.0,
+        }
+    }
+}
+
@@ -0,0 +1,222 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use super::BlockChain;
+use crate::block::BlockHeader;
+use crate::error::ErrorMessage;
+use crate::hash::Hash;
+
+use std::error::Error;
+use std::collections::HashMap;
+
+const DENSE_LOCATOR_LENGTH: usize = 10;
+
+// NOTE: Enough to reach the genesis block of any realistic chain, 
+//       while keeping what a peer can make us look up small.
+pub const MAX_LOCATOR_LENGTH: usize = 32;
+
+impl BlockChain
+{
+
+    fn header_or_error(&self, block_id: u64) -> Result<BlockHeader, Box<dyn Error>>
+    {
+        match self.header(block_id)
+        {
+            Some(header) => Ok(header),
+            None => Err(ErrorMessage::new(&format!("No header for block {}", block_id))),
+        }
+    }
+
+    // Hashes of our blocks from the top down. The first few are consecutive,
+    // then the gap doubles each time, always ending with the genesis block.
+    pub fn block_locator(&self) -> Result<Vec<Hash>, Box<dyn Error>>
+    {
+        let mut block_id = match self.top_ref()
+        {
+            Some(top) => top.header.block_id,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut locator = Vec::new();
+        let mut step = 1;
+        loop
+        {
+            // NOTE: Always end with the genesis block, even if that means 
+            //       skipping further then the step
+            if locator.len() == MAX_LOCATOR_LENGTH - 1 {
+                block_id = 0;
+            }
+
+            locator.push(self.header_or_error(block_id)?.hash()?);
+            if block_id == 0 {
+                break;
+            }
+
+            if locator.len() >= DENSE_LOCATOR_LENGTH {
+                step *= 2;
+            }
+            block_id = block_id.saturating_sub(step);
+        }
+
+        Ok(locator)
+    }
+
+    pub(super) fn index_header(&mut self, header: &BlockHeader) -> Result<(), Box<dyn Error>>
+    {
+        if let Some(index) = self.header_index.get_mut().unwrap() {
+            index.insert(header.hash()?, header.block_id);
+        }
+        Ok(())
+    }
+
+    pub(super) fn unindex_headers_from(&mut self, block_id: u64)
+    {
+        if let Some(index) = self.header_index.get_mut().unwrap() {
+            index.retain(|_, indexed_id| *indexed_id < block_id);
+        }
+    }
+
+    // The id of our block with this hash, if we have it
+    pub fn block_id_of(&self, hash: &Hash) -> Result<Option<u64>, Box<dyn Error>>
+    {
+        let mut index_or_none = self.header_index.lock().unwrap();
+        if index_or_none.is_none()
+        {
+            let mut index = HashMap::new();
+            for block_id in 0..self.headers.next_top() 
+            {
+                if let Some(header) = self.headers.get(block_id) {
+                    index.insert(header.hash()?, block_id);
+                }
+            }
+            *index_or_none = Some(index);
+        }
+
+        Ok(index_or_none.as_ref().unwrap().get(hash).cloned())
+    }
+
+    // The highest of our blocks found in the locator, if any
+    pub fn find_locator_fork(&self, locator: &[Hash]) -> Result<Option<u64>, Box<dyn Error>>
+    {
+        if locator.len() > MAX_LOCATOR_LENGTH 
+        {
+            return Err(ErrorMessage::new(&format!("Locator has {} hashes, the max is {}", 
+                locator.len(), MAX_LOCATOR_LENGTH)));
+        }
+
+        let mut fork = None;
+        for hash in locator {
+            fork = std::cmp::max(fork, self.block_id_of(hash)?);
+        }
+        Ok(fork)
+    }
+
+    // Headers of up to `max_count` blocks after the fork with the locator,
+    // or from the genesis block if we have no blocks in common.
+    pub fn headers_after_locator(&self, locator: &[Hash], max_count: usize)
+        -> Result<Vec<BlockHeader>, Box<dyn Error>>
+    {
+        let start = match self.find_locator_fork(locator)?
+        {
+            Some(fork) => fork + 1,
+            None => 0,
+        };
+
+        let next_top = self.blocks.next_top();
+        let end = std::cmp::min(start.saturating_add(max_count as u64), next_top);
+        (start..end)
+            .map(|block_id| self.header_or_error(block_id))
+            .collect()
+    }
+
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::chain::BlockChainAddResult;
+    use crate::block::Block;
+    use crate::wallet::private_wallet::PrivateWallet;
+    use crate::miner;
+
+    fn mine_blocks(chain: &mut BlockChain, wallet: &PrivateWallet, count: usize)
+        -> Vec<Block>
+    {
+        (0..count)
+            .map(|_|
+            {
+                let block = miner::mine_block(Block::new_blank(chain, wallet).unwrap());
+                assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+                block
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_block_locator()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut chain = BlockChain::open_temp();
+        assert_eq!(chain.block_locator().unwrap(), Vec::<Hash>::new());
+
+        let blocks = mine_blocks(&mut chain, &wallet, 100);
+        let locator = chain.block_locator().unwrap();
+
+        // Dense near the top, then exponentially sparser down to the genesis
+        let hash_of = |block_id: usize| blocks[block_id].hash().unwrap();
+        let expected_ids = [99, 98, 97, 96, 95, 94, 93, 92, 91, 90, 88, 84, 76, 60, 28, 0];
+        assert_eq!(locator, expected_ids.iter().map(|id| hash_of(*id)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_find_locator_fork()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut chain_a = BlockChain::open_temp();
+        let mut chain_b = BlockChain::open_temp();
+
+        for block in mine_blocks(&mut chain_a, &wallet, 30) {
+            assert_eq!(chain_b.add(&block).unwrap(), BlockChainAddResult::Ok);
+        }
+
+        // The chains diverge after block 29
+        mine_blocks(&mut chain_a, &wallet, 5);
+        let b_blocks = mine_blocks(&mut chain_b, &other, 40);
+
+        // A single locator finds the common ancestor
+        let locator = chain_a.block_locator().unwrap();
+        assert_eq!(chain_b.find_locator_fork(&locator).unwrap(), Some(29));
+
+        let headers = chain_b.headers_after_locator(&locator, 10).unwrap();
+        assert_eq!(headers.len(), 10);
+        assert_eq!(headers[0], b_blocks[0].header);
+        assert_eq!(headers[0].prev_hash, chain_a.header(29).unwrap().hash().unwrap());
+        assert_eq!(chain_b.headers_after_locator(&locator, 100).unwrap().len(), 40);
+
+        // Blocks added after the index is built are found too
+        let new_block = mine_blocks(&mut chain_b, &other, 1).remove(0);
+        assert_eq!(chain_b.block_id_of(&new_block.hash().unwrap()).unwrap(), Some(70));
+        assert_eq!(chain_b.block_id_of(&chain_a.top().unwrap().hash().unwrap()).unwrap(), None);
+
+        // A peer can't make us look up any number of hashes
+        let long_locator = vec![Hash::empty(); MAX_LOCATOR_LENGTH + 1];
+        assert!(chain_b.find_locator_fork(&long_locator).is_err());
+
+        // Nothing in common, so start from the genesis block
+        let unrelated = BlockChain::open_temp();
+        assert_eq!(unrelated.find_locator_fork(&locator).unwrap(), None);
+        let mut chain_c = BlockChain::open_temp();
+        let c_blocks = mine_blocks(&mut chain_c, &other, 3);
+        assert_eq!(chain_c.find_locator_fork(&locator).unwrap(), None);
+        let headers = chain_c.headers_after_locator(&locator, 10).unwrap();
+        assert_eq!(headers, c_blocks.iter().map(|x| x.header.clone()).collect::<Vec<_>>());
+    }
+
+}
+
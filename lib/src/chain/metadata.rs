@@ -23,6 +23,7 @@ pub struct BlockMetadata
 {
     pub wallets: HashMap<Hash, WalletStatus>,
     pub page_updates: HashMap<Hash, PageMetadata>,
+    pub fees_burned: f32,
 }
 
 impl BlockChain
@@ -32,11 +33,12 @@ impl BlockChain
     {
         // NOTE: We assume the block is valid at this point
 
+        let fee_burn_ratio = self.config().fee_burn_ratio;
         let mut wallets = HashMap::new();
         for address in block.get_addresses_used() 
         {
             let mut status = self.get_wallet_status(&address);
-            status = block.update_wallet_status(&address, status).unwrap();
+            status = block.update_wallet_status(&address, status, fee_burn_ratio).unwrap();
             wallets.insert(address, status);
         }
 
@@ -54,6 +56,7 @@ impl BlockChain
         {
             wallets,
             page_updates,
+            fees_burned: block.fees_burned(fee_burn_ratio),
         }
     }
 
@@ -9,25 +9,55 @@ mod transaction_queue;
 mod storage;
 mod transactions;
 mod metadata;
+mod legacy;
+pub mod verify;
+pub mod snapshot;
+pub mod bootstrap;
+mod locator;
+mod wallet_status_cache;
 use storage::Storage;
 use metadata::BlockMetadata;
-use crate::block::Block;
+use branch::ReorgEvent;
+use wallet_status_cache::WalletStatusCache;
+use crate::block::{Block, BlockHeader};
 use crate::block::validate::BlockValidationResult;
 use crate::transaction::transfer::Transfer;
 use crate::transaction::page::Page;
 use crate::transaction_queue::TransactionQueue;
-use crate::config::BLOCK_SAMPLE_SIZE;
+use crate::config::{ChainConfig, WALLET_STATUS_CACHE_SIZE};
+use crate::error::ErrorMessage;
+use crate::hash::Hash;
 
 use std::error::Error;
 use std::path::PathBuf;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+#[cfg(test)]
+use std::sync::atomic::AtomicU64;
 
 pub struct BlockChain
 {
+    config: ChainConfig,
     metadata: Storage<BlockMetadata>,
+    headers: Storage<BlockHeader>,
     blocks: Storage<Block>,
 
+    // NOTE: Cached, so the top can be read without a mutable borrow
+    top: Option<Block>,
+
     transfer_queue: TransactionQueue<Transfer>,
     page_queue: TransactionQueue<Page>,
+
+    wallet_status_cache: Mutex<WalletStatusCache>,
+    #[cfg(test)]
+    wallet_status_blocks_scanned: AtomicU64,
+
+    // Block id of each header, by its hash. Built the first time it's 
+    // needed, then kept up to date as headers are stored.
+    header_index: Mutex<Option<HashMap<Hash, u64>>>,
+
+    reorg_subscribers: Vec<Sender<ReorgEvent>>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -36,42 +66,169 @@ pub enum BlockChainAddResult
     Ok,
     MoreNeeded,
     Duplicate,
+    NoValidBranch,
     Invalid(BlockValidationResult),
 }
 
+// Time between consecutive blocks, in milliseconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockTimeStats
+{
+    pub min: u128,
+    pub max: u128,
+    pub mean: f64,
+    pub median: f64,
+}
+
+impl std::fmt::Display for BlockChainAddResult
+{
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        match self
+        {
+            BlockChainAddResult::Ok => write!(f, "Ok"),
+            BlockChainAddResult::MoreNeeded => write!(f, "Previous blocks are needed"),
+            BlockChainAddResult::Duplicate => write!(f, "Block already in chain"),
+            BlockChainAddResult::NoValidBranch => write!(f, "A different block already exists at this height"),
+            BlockChainAddResult::Invalid(result) => write!(f, "{}", result),
+        }
+    }
+
+}
+
 impl BlockChain
 {
 
     pub fn open(path: &PathBuf) 
         -> Result<Self, Box<dyn Error>>
+    {
+        Self::open_with_config(path, ChainConfig::default())
+    }
+
+    pub fn open_with_config(path: &PathBuf, config: ChainConfig)
+        -> Result<Self, Box<dyn Error>>
     {
         info!("Open chain in {:?}", path);
-        Ok(BlockChain
+        config.validate()?;
+        Ok(Self::with_storage(config,
+            Storage::new(&path.join("metadata"))?,
+            Storage::new(&path.join("headers"))?,
+            Storage::new(path)?))
+    }
+
+    pub fn open_in_memory() -> Self
+    {
+        Self::with_storage(ChainConfig::default(),
+            Storage::in_memory(),
+            Storage::in_memory(),
+            Storage::in_memory())
+    }
+
+    pub fn open_in_memory_with_config(config: ChainConfig) 
+        -> Result<Self, Box<dyn Error>>
+    {
+        config.validate()?;
+        Ok(Self::with_storage(config,
+            Storage::in_memory(),
+            Storage::in_memory(),
+            Storage::in_memory()))
+    }
+
+    fn with_storage(config: ChainConfig, metadata: Storage<BlockMetadata>,
+                    headers: Storage<BlockHeader>, blocks: Storage<Block>)
+        -> Self
+    {
+        let mut chain = BlockChain
         {
-            metadata: Storage::new(&path.join("metadata"))?,
-            blocks: Storage::new(path)?,
+            config,
+            metadata,
+            headers,
+            blocks,
+            top: None,
 
             page_queue: TransactionQueue::new(),
             transfer_queue: TransactionQueue::new(),
-        })
+
+            wallet_status_cache: Mutex::new(WalletStatusCache::new(WALLET_STATUS_CACHE_SIZE)),
+            #[cfg(test)]
+            wallet_status_blocks_scanned: AtomicU64::new(0),
+
+            header_index: Mutex::new(None),
+
+            reorg_subscribers: Vec::new(),
+        };
+
+        // NOTE: Chains created before headers were stored separately 
+        //       need them filling in from the full blocks.
+        for block_id in chain.headers.next_top()..chain.blocks.next_top()
+        {
+            match chain.blocks.get(block_id)
+            {
+                Some(block) => chain.headers.store(block_id, block.header),
+                None => break,
+            }
+        }
+
+        if let Some(block_id) = chain.first_corrupt_block()
+        {
+            warn!("Chain is corrupt from block {}, starting from the block before it", block_id);
+            chain.metadata.truncate(block_id);
+            chain.headers.truncate(block_id);
+            chain.blocks.truncate(block_id);
+        }
+
+        chain.load_top();
+        chain
+    }
+
+    pub fn config(&self) -> &ChainConfig
+    {
+        &self.config
+    }
+
+    fn prune_depth(&self) -> Option<u64>
+    {
+        // NOTE: We always need the full sample to calculate the 
+        //       target of the next block, and any immature rewards.
+        let reward_maturity = self.config.reward_maturity.unwrap_or(0);
+        self.config.prune_depth.map(|depth| 
+            std::cmp::max(depth, self.config.block_sample_size + 1).max(reward_maturity))
+    }
+
+    fn prune(&mut self)
+    {
+        let depth_or_none = self.prune_depth();
+        if depth_or_none.is_none() {
+            return;
+        }
+
+        let next_top = self.blocks.next_top();
+        let depth = depth_or_none.unwrap();
+        if next_top > depth 
+        {
+            self.blocks.prune_below(next_top - depth);
+            debug!("Pruned blocks below {}", self.blocks.pruned_below());
+        }
     }
 
     pub fn take_sample_at(&mut self, block_id: u64) -> (Option<Block>, Option<Block>)
     {
+        let sample_size = self.config.sample_span();
         let end = self.block(block_id);
-        if end.is_none() || end.as_ref().unwrap().header.block_id < BLOCK_SAMPLE_SIZE {
+        if end.is_none() || end.as_ref().unwrap().header.block_id < sample_size {
             return (None, end);
         }
 
-        let start = self.block(end.as_ref().unwrap().header.block_id - BLOCK_SAMPLE_SIZE);
+        let start = self.block(end.as_ref().unwrap().header.block_id - sample_size);
         (start, end)
     }
 
     pub fn take_sample(&mut self) -> (Option<Block>, Option<Block>)
     {
-        match self.top()
+        match self.top_ref().map(|top| top.header.block_id)
         {
-            Some(top) => self.take_sample_at(top.header.block_id),
+            Some(top_id) => self.take_sample_at(top_id),
             None => (None, None),
         }
     }
@@ -81,12 +238,12 @@ impl BlockChain
     {
         if block.header.block_id < self.blocks.next_top() as u64
         {
-            let original = self.block(block.header.block_id).unwrap();
-            if block == &original {
-                return Ok(BlockChainAddResult::Duplicate);
-            } else {
-                return Ok(BlockChainAddResult::Invalid(BlockValidationResult::NotNextBlock));
-            }
+            // NOTE: The header may be missing if its chunk was corrupt
+            return match self.header(block.header.block_id)
+            {
+                Some(original) if block.header == original => Ok(BlockChainAddResult::Duplicate),
+                _ => Ok(BlockChainAddResult::NoValidBranch),
+            };
         }
 
         if block.header.block_id > self.blocks.next_top() as u64 {
@@ -112,33 +269,165 @@ impl BlockChain
 
         let metadata = self.metadata_for_block(&block);
         self.metadata.store(block.header.block_id, metadata);
+        self.headers.store(block.header.block_id, block.header.clone());
+        self.index_header(&block.header)?;
         self.blocks.store(block.header.block_id, block.clone());
+        self.top = Some(block.clone());
         self.remove_from_transaction_queue(block);
+        self.remove_expired_from_transaction_queue(block.header.timestamp);
+        self.prune();
         Ok(BlockChainAddResult::Ok)
     }
 
     pub fn walk<F>(&mut self, on_block: &mut F)
         where F: FnMut(&Block)
     {
-        for block_id in 0..self.blocks.next_top() {
+        // NOTE: Pruned blocks are skipped
+        for block_id in self.blocks.pruned_below()..self.blocks.next_top() {
             on_block(&self.block(block_id).unwrap());
         }
     }
 
-    pub fn block(&mut self, block_id: u64) -> Option<Block>
+    fn first_block_at_or_after(&self, timestamp: u128) -> u64
     {
-        self.blocks.get(block_id)
+        // NOTE: Block timestamps never decrease, so they can be binary searched
+        let mut low = 0;
+        let mut high = self.blocks.next_top();
+        while low < high
+        {
+            let middle = low + (high - low) / 2;
+            if self.header(middle).unwrap().timestamp < timestamp {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        low
+    }
+
+    pub fn blocks_in_time_range(&self, from: u128, to: u128) -> Vec<Block>
+    {
+        let mut blocks = Vec::new();
+        let start = self.first_block_at_or_after(from);
+        for block_id in start..self.blocks.next_top()
+        {
+            if self.header(block_id).unwrap().timestamp > to {
+                break;
+            }
+
+            // NOTE: Pruned blocks are skipped
+            if let Some(block) = self.block(block_id) {
+                blocks.push(block);
+            }
+        }
+
+        blocks
     }
 
-    pub fn top(&mut self) -> Option<Block>
+    // Stats on the time between each of the last `window` blocks, or 
+    // none if there's less then two of them
+    pub fn block_time_stats(&self, window: u64) -> Option<BlockTimeStats>
     {
-        if self.blocks.next_top() == 0 {
-            None
+        let next_top = self.blocks.next_top();
+        let timestamps = (next_top.saturating_sub(window)..next_top)
+            .map(|block_id| self.header(block_id).map(|header| header.timestamp))
+            .collect::<Option<Vec<_>>>()?;
+
+        let mut intervals = timestamps
+            .windows(2)
+            .map(|pair| pair[1].saturating_sub(pair[0]))
+            .collect::<Vec<_>>();
+        if intervals.is_empty() {
+            return None;
+        }
+
+        intervals.sort_unstable();
+        let count = intervals.len();
+        let median = if count % 2 == 0 {
+            (intervals[count / 2 - 1] + intervals[count / 2]) as f64 / 2.0
         } else {
-            self.blocks.get(self.blocks.next_top() - 1)
+            intervals[count / 2] as f64
+        };
+
+        Some(BlockTimeStats
+        {
+            min: intervals[0],
+            max: intervals[count - 1],
+            mean: intervals.iter().sum::<u128>() as f64 / count as f64,
+            median,
+        })
+    }
+
+    pub fn total_supply(&mut self, up_to_block_id: u64) -> f32
+    {
+        // NOTE: Fees are only moved between wallets, so just the block 
+        //       rewards count towards the supply, less any fees burned.
+        let real_to = std::cmp::min(up_to_block_id.saturating_add(1), self.blocks.next_top());
+
+        // NOTE: The premine is paid out by the genesis block
+        let mut supply = 0.0;
+        if real_to > 0 {
+            supply += self.config.premine.iter().fold(0.0, |acc, (_, amount)| acc + amount);
+        }
+
+        for block_id in 0..real_to 
+        {
+            supply += self.header(block_id).unwrap().reward;
+            supply -= self.metadata.get(block_id).unwrap().fees_burned;
+        }
+
+        supply
+    }
+
+    pub fn block(&self, block_id: u64) -> Option<Block>
+    {
+        self.blocks.get(block_id)
+    }
+
+    pub fn block_or_error(&self, block_id: u64) -> Result<Block, Box<dyn Error>>
+    {
+        if self.is_pruned(block_id) {
+            return Err(ErrorMessage::new(&format!("Block {} has been pruned", block_id)));
+        }
+
+        match self.blocks.get(block_id)
+        {
+            Some(block) => Ok(block),
+            None => Err(ErrorMessage::new(&format!("No block {}", block_id))),
         }
     }
 
+    pub fn header(&self, block_id: u64) -> Option<BlockHeader>
+    {
+        self.headers.get(block_id)
+    }
+
+    pub fn is_pruned(&self, block_id: u64) -> bool
+    {
+        block_id < self.blocks.pruned_below()
+    }
+
+    fn load_top(&mut self)
+    {
+        self.top = 
+            if self.blocks.next_top() == 0 {
+                None
+            } else {
+                self.blocks.get(self.blocks.next_top() - 1)
+            };
+    }
+
+    pub fn top(&self) -> Option<Block>
+    {
+        self.top.clone()
+    }
+
+    pub fn top_ref(&self) -> Option<&Block>
+    {
+        self.top.as_ref()
+    }
+
 }
 
 #[cfg(test)]
@@ -147,15 +436,28 @@ mod tests
 
     use super::*;
     use super::branch::BlockChainCanMergeResult;
+    use crate::block::builder::BlockBuilder;
+    use crate::block::current_timestamp;
+    use crate::transaction::builder::TransactionBuilder;
+    use crate::transaction::TransactionValidationResult;
+    use crate::data_store::data_unit::DataUnit;
+    use crate::data_store::page::CreatePageData;
+    use crate::wallet::Wallet;
     use crate::wallet::private_wallet::PrivateWallet;
     use crate::miner;
+    use std::sync::{Arc, RwLock, Barrier};
 
     impl BlockChain
     {
         pub fn open_temp() -> Self
+        {
+            Self::open_temp_with_config(ChainConfig::default())
+        }
+
+        pub fn open_temp_with_config(config: ChainConfig) -> Self
         {
             let path = std::env::temp_dir().join(rand::random::<u32>().to_string());
-            Self::open(&path).unwrap()
+            Self::open_with_config(&path, config).unwrap()
         }
     }
 
@@ -163,7 +465,9 @@ mod tests
     {
         fn drop(&mut self)
         {
-            let _ = std::fs::remove_dir_all(self.blocks.path());
+            if let Some(path) = self.blocks.path() {
+                let _ = std::fs::remove_dir_all(path);
+            }
         }
     }
 
@@ -200,8 +504,9 @@ mod tests
         assert_ne!(chain_a.add(&block_d_b).unwrap(), BlockChainAddResult::Ok);
 
         let mut branch = Vec::<Block>::new();
-        branch.push(block_c_b);
-        assert_ne!(chain_a.can_merge_branch(&branch).unwrap(), BlockChainCanMergeResult::Ok);
+        branch.push(block_c_b.clone());
+        let c_b_is_lower = block_c_b.hash().unwrap() < block_c_a.hash().unwrap();
+        assert_eq!(chain_a.can_merge_branch(&branch).unwrap() == BlockChainCanMergeResult::Ok, c_b_is_lower);
 
         branch.push(block_d_b);
         branch.push(block_e_b);
@@ -211,5 +516,810 @@ mod tests
         assert_eq!(chain_a.top().unwrap().header.block_id, 4);
    }
 
+    #[test]
+    fn test_open_invalid_path()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        // A file where the chain directory should be
+        let file_path = std::env::temp_dir().join(rand::random::<u32>().to_string());
+        std::fs::write(&file_path, b"not a directory").unwrap();
+
+        let chain_path = file_path.join("chain");
+        let err = BlockChain::open(&chain_path).err().unwrap().to_string();
+        assert!(err.contains(chain_path.to_str().unwrap()), "{}", err);
+
+        // A corrupt storage metadata file
+        let chain_path = std::env::temp_dir().join(rand::random::<u32>().to_string());
+        std::fs::create_dir_all(&chain_path).unwrap();
+        std::fs::write(chain_path.join("metadata.json"), b"{").unwrap();
+
+        let err = BlockChain::open(&chain_path).err().unwrap().to_string();
+        assert!(err.contains("metadata.json"), "{}", err);
+
+        let _ = std::fs::remove_file(&file_path);
+        let _ = std::fs::remove_dir_all(&chain_path);
+    }
+
+    #[test]
+    fn test_total_supply()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let mut chain = BlockChain::open_temp();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        assert_eq!(chain.total_supply(10), 0.0);
+
+        let block_a = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Ok);
+
+        let transfer = chain.new_transfer(vec![(&wallet, 5.0)], vec![(other.get_address(), 3.0)], 2.0).unwrap();
+        let block_b = miner::mine_block(BlockBuilder::new(&other)
+            .add_transfer(transfer)
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(chain.add(&block_b).unwrap(), BlockChainAddResult::Ok);
+
+        let block_c = miner::mine_block(Block::new_blank(&mut chain, &other).unwrap());
+        assert_eq!(chain.add(&block_c).unwrap(), BlockChainAddResult::Ok);
+
+        let subsidies = block_a.calculate_reward() + block_b.calculate_reward() + block_c.calculate_reward();
+        assert_eq!(chain.total_supply(0), block_a.calculate_reward());
+        assert_eq!(chain.total_supply(2), subsidies);
+        assert_eq!(chain.total_supply(100), subsidies);
+        assert_eq!(chain.total_supply(u64::MAX), subsidies);
+
+        let total_balance = 
+            chain.get_wallet_status(&wallet.get_address()).balance + 
+            chain.get_wallet_status(&other.get_address()).balance;
+        assert_eq!(chain.total_supply(2), total_balance);
+    }
+
+    #[test]
+    fn test_fee_burn()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let config = ChainConfig
+        {
+            fee_burn_ratio: 0.5,
+            ..ChainConfig::default()
+        };
+        let mut chain = BlockChain::open_temp_with_config(config);
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+
+        let block_a = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Ok);
+
+        // Other wins the block, so gets half the fee
+        let transfer = chain.new_transfer(vec![(&wallet, 5.0)], vec![(wallet.get_address(), 3.0)], 2.0).unwrap();
+        let block_b = miner::mine_block(BlockBuilder::new(&other)
+            .add_transfer(transfer)
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(block_b.total_fees(), 2.0);
+        assert_eq!(chain.add(&block_b).unwrap(), BlockChainAddResult::Ok);
+
+        let other_balance = chain.get_wallet_status(&other.get_address()).balance;
+        assert_eq!(other_balance, block_b.calculate_reward() + 1.0);
+
+        let subsidies = block_a.calculate_reward() + block_b.calculate_reward();
+        let total_balance = chain.get_wallet_status(&wallet.get_address()).balance + other_balance;
+        assert_eq!(chain.total_supply(1), subsidies - 1.0);
+        assert_eq!(chain.total_supply(1), total_balance);
+        assert_eq!(chain.validate_chain().unwrap().is_none(), true);
+
+        // The ratio has to be a fraction of the fee
+        for fee_burn_ratio in [-0.1, 1.5, f32::NAN]
+        {
+            let config = ChainConfig { fee_burn_ratio, ..ChainConfig::default() };
+            assert!(config.validate().is_err());
+            let path = std::env::temp_dir().join(rand::random::<u32>().to_string());
+            assert!(BlockChain::open_with_config(&path, config).is_err());
+        }
+    }
+
+    #[test]
+    fn test_invalid_config()
+    {
+        let invalid_configs = [
+            ChainConfig { block_time: 0, ..ChainConfig::default() },
+            ChainConfig { block_sample_size: 0, ..ChainConfig::default() },
+            ChainConfig { page_chunk_size: 0, ..ChainConfig::default() },
+            ChainConfig { min_difficulty: Some(f64::NAN), ..ChainConfig::default() },
+            ChainConfig { min_difficulty: Some(-1.0), ..ChainConfig::default() },
+            ChainConfig { min_difficulty: Some(0.5), ..ChainConfig::default() },
+        ];
+
+        for config in invalid_configs
+        {
+            assert!(config.validate().is_err());
+            assert!(BlockChain::open_in_memory_with_config(config).is_err());
+        }
+
+        let config = ChainConfig { min_difficulty: Some(1.0), ..ChainConfig::default() };
+        assert!(BlockChain::open_in_memory_with_config(config).is_ok());
+    }
+
+    #[test]
+    fn test_pruning()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let config = ChainConfig
+        {
+            block_time: 1,
+            block_sample_size: 10,
+            prune_depth: Some(20),
+            branch_prune_interval: None,
+            ..ChainConfig::default()
+        };
+
+        let mut chain = BlockChain::open_temp_with_config(config.clone());
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        for _ in 0..120
+        {
+            let block = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+            assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+        }
+
+        // The first chunk of blocks has been removed, but headers are kept
+        assert_eq!(chain.blocks.path().unwrap().join("blk0").exists(), false);
+        assert_eq!(chain.blocks.path().unwrap().join("blk1").exists(), true);
+        assert_eq!(chain.is_pruned(0), true);
+        assert_eq!(chain.is_pruned(100), false);
+        assert_eq!(chain.block(10), None);
+        assert_eq!(chain.block_or_error(10).is_err(), true);
+        assert_eq!(chain.block_or_error(110).unwrap().header.block_id, 110);
+        assert_eq!(chain.header(10).unwrap().block_id, 10);
+        assert_eq!(chain.total_supply(119), 120.0 * chain.header(0).unwrap().reward);
+        assert_eq!(chain.get_wallet_status(&wallet.get_address()).balance, 
+                   120.0 * chain.header(0).unwrap().reward);
+
+        // New blocks still validate against the pruned chain
+        let block = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+        assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Duplicate);
+
+        // And the pruned state survives reopening the chain
+        let reopened = BlockChain::open_with_config(chain.blocks.path().unwrap(), config).unwrap();
+        assert_eq!(reopened.is_pruned(0), true);
+    }
+
+    #[test]
+    fn test_recover_corrupt_chunk()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let config = ChainConfig
+        {
+            block_time: 1,
+            block_sample_size: 10,
+            ..ChainConfig::default()
+        };
+
+        let mut chain = BlockChain::open_temp_with_config(config.clone());
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let blocks = mine_blocks(&mut chain, &wallet, 120);
+        assert_eq!(chain.first_corrupt_block(), None);
+
+        // Corrupt the second chunk of blocks, leaving the first intact
+        let path = chain.blocks.path().unwrap().clone();
+        std::fs::write(path.join("blk1"), b"corrupt").unwrap();
+
+        let mut reopened = BlockChain::open_with_config(&path, config.clone()).unwrap();
+        assert_eq!(path.join("blk1").exists(), false);
+        assert_eq!(path.join("blk1.corrupt").exists(), true);
+        assert_eq!(reopened.top(), Some(blocks[99].clone()));
+        assert_eq!(reopened.first_corrupt_block(), None);
+
+        // The chain carries on from the last good block
+        let next = mine_blocks(&mut reopened, &wallet, 1);
+        assert_eq!(next[0].header.block_id, 100);
+
+        // Metadata is needed for every block, not just the last chunk
+        std::fs::write(path.join("metadata").join("blk0"), b"corrupt").unwrap();
+        let reopened = BlockChain::open_with_config(&path, config).unwrap();
+        assert_eq!(reopened.top(), None);
+    }
+
+    #[test]
+    fn test_add_result_reasons()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let mut chain = BlockChain::open_temp();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+
+        let block_a = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Ok);
+        assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Duplicate);
+
+        // Different block at a height we already have
+        let mut block_a_other = block_a.clone();
+        block_a_other.header.timestamp += 1;
+        block_a_other = miner::mine_block(block_a_other);
+        assert_eq!(chain.add(&block_a_other).unwrap(), BlockChainAddResult::NoValidBranch);
+
+        // Not mined
+        let mut unmined = Block::new_blank(&mut chain, &wallet).unwrap();
+        while unmined.validate_pow().unwrap() == BlockValidationResult::Ok {
+            unmined.header.pow += 1;
+        }
+        assert_eq!(chain.add(&unmined).unwrap(), 
+                   BlockChainAddResult::Invalid(BlockValidationResult::POW));
+
+        // From the future
+        let mut future = Block::new_blank(&mut chain, &wallet).unwrap();
+        future.header.timestamp += 60 * 60 * 1000;
+        future = miner::mine_block(future);
+        assert_eq!(chain.add(&future).unwrap(), 
+                   BlockChainAddResult::Invalid(BlockValidationResult::Timestamp));
+
+        // Gap in the chain
+        let mut ahead = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        ahead.header.block_id += 1;
+        assert_eq!(chain.add(&ahead).unwrap(), BlockChainAddResult::MoreNeeded);
+
+        assert_eq!(format!("{}", BlockChainAddResult::Invalid(BlockValidationResult::POW)), 
+                   "No valid proof or work");
+    }
+
+    fn mine_blocks(chain: &mut BlockChain, wallet: &PrivateWallet, count: usize) -> Vec<Block>
+    {
+        let mut blocks = Vec::new();
+        for _ in 0..count
+        {
+            let block = miner::mine_block(Block::new_blank(chain, wallet).unwrap());
+            assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+            blocks.push(block);
+        }
+
+        blocks
+    }
+
+    #[test]
+    fn test_reorg_event()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut chain_a = BlockChain::open_temp();
+        let mut chain_b = BlockChain::open_temp();
+        let shared = mine_blocks(&mut chain_a, &wallet, 2);
+        for block in &shared {
+            assert_eq!(chain_b.add(block).unwrap(), BlockChainAddResult::Ok);
+        }
+
+        let replaced = mine_blocks(&mut chain_a, &wallet, 2);
+        let branch = mine_blocks(&mut chain_b, &other, 3);
+        let reorgs = chain_a.subscribe_reorgs();
+        chain_a.merge_branch(branch.clone());
+
+        let event = reorgs.try_recv().unwrap();
+        assert_eq!(event.common_ancestor.as_ref(), shared.last());
+        assert_eq!(&event.old_top, replaced.last().unwrap());
+        assert_eq!(&event.new_top, branch.last().unwrap());
+        assert_eq!(event.disconnected, replaced);
+        assert_eq!(event.connected, branch);
+
+        let reward = branch[0].calculate_reward();
+        assert_eq!(chain_a.get_wallet_status(&wallet.get_address()).balance, 2.0 * reward);
+        assert_eq!(chain_a.get_wallet_status(&other.get_address()).balance, 3.0 * reward);
+
+        // Extending the chain isn't a reorg
+        let extension = mine_blocks(&mut chain_b, &other, 1);
+        chain_a.merge_branch(extension);
+        assert!(reorgs.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_premine()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let another = PrivateWallet::open_temp(2).unwrap();
+        let config = ChainConfig
+        {
+            premine: vec![(other.get_address(), 100.0), (another.get_address(), 50.0)],
+            ..ChainConfig::default()
+        };
+        let mut chain_a = BlockChain::open_temp_with_config(config.clone());
+        let mut chain_b = BlockChain::open_temp_with_config(config);
+        let mut chain_c = BlockChain::open_temp();
+
+        // Both chains build the same genesis transactions
+        let genesis = miner::mine_block(Block::new_blank(&mut chain_a, &wallet).unwrap());
+        let genesis_b = Block::new_blank(&mut chain_b, &wallet).unwrap();
+        assert_eq!(genesis_b.coinbase.hash().unwrap(), genesis.coinbase.hash().unwrap());
+        assert_eq!(genesis_b.header.transaction_merkle_root, genesis.header.transaction_merkle_root);
+
+        assert_eq!(chain_a.add(&genesis).unwrap(), BlockChainAddResult::Ok);
+        assert_eq!(chain_b.add(&genesis).unwrap(), BlockChainAddResult::Ok);
+        assert_eq!(chain_a.top().unwrap().hash().unwrap(), chain_b.top().unwrap().hash().unwrap());
+        assert_eq!(chain_c.add(&genesis).unwrap(), 
+                   BlockChainAddResult::Invalid(BlockValidationResult::Reward));
+
+        // A genesis without the premine isn't valid
+        let without_premine = miner::mine_block(Block::new_blank(&mut chain_c, &wallet).unwrap());
+        assert_eq!(chain_b.validate_branch(&[without_premine]).unwrap(), BlockValidationResult::Reward);
+
+        assert_eq!(chain_a.get_wallet_status(&other.get_address()).balance, 100.0);
+        assert_eq!(chain_a.get_wallet_status(&another.get_address()).balance, 50.0);
+        assert_eq!(chain_a.get_wallet_status(&wallet.get_address()).balance, genesis.calculate_reward());
+        assert_eq!(chain_a.total_supply(0), 150.0 + genesis.calculate_reward());
+
+        // Only the genesis block pays the premine
+        let block_b = miner::mine_block(Block::new_blank(&mut chain_a, &wallet).unwrap());
+        assert_eq!(block_b.coinbase.header.content.outputs.len(), 1);
+        assert_eq!(chain_a.add(&block_b).unwrap(), BlockChainAddResult::Ok);
+        assert_eq!(chain_a.get_wallet_status(&other.get_address()).balance, 100.0);
+    }
+
+    #[test]
+    fn test_branch_near_genesis()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        for branch_at in 0..3
+        {
+            let mut chain_a = BlockChain::open_temp();
+            let mut chain_b = BlockChain::open_temp();
+            let shared = mine_blocks(&mut chain_a, &wallet, branch_at);
+            for block in &shared {
+                assert_eq!(chain_b.add(block).unwrap(), BlockChainAddResult::Ok);
+            }
+
+            mine_blocks(&mut chain_a, &wallet, 3 - branch_at);
+            let branch = mine_blocks(&mut chain_b, &other, 5 - branch_at);
+            assert_eq!(branch.first().unwrap().header.block_id, branch_at as u64);
+            assert_eq!(chain_a.can_merge_branch(&branch).unwrap(), BlockChainCanMergeResult::Ok);
+
+            chain_a.merge_branch(branch.clone());
+            for block in shared.iter().chain(branch.iter()) {
+                assert_eq!(&chain_a.block(block.header.block_id).unwrap(), block);
+            }
+
+            let reward = branch[0].calculate_reward();
+            assert_eq!(chain_a.top().unwrap().header.block_id, 4);
+            assert_eq!(chain_a.get_wallet_status(&wallet.get_address()).balance, reward * branch_at as f32);
+            assert_eq!(chain_a.get_wallet_status(&other.get_address()).balance, reward * (5 - branch_at) as f32);
+        }
+    }
+
+    #[test]
+    fn test_max_reorg_depth()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let config = ChainConfig
+        {
+            max_reorg_depth: Some(2),
+            ..ChainConfig::default()
+        };
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        for (fork_depth, expected) in [(2, BlockChainCanMergeResult::Ok), (3, BlockChainCanMergeResult::TooDeep)]
+        {
+            let mut chain_a = BlockChain::open_temp_with_config(config.clone());
+            let mut chain_b = BlockChain::open_temp_with_config(config.clone());
+            for block in &mine_blocks(&mut chain_a, &wallet, 2) {
+                assert_eq!(chain_b.add(block).unwrap(), BlockChainAddResult::Ok);
+            }
+
+            // A longer branch, replacing `fork_depth` of chain a's blocks
+            mine_blocks(&mut chain_a, &wallet, fork_depth);
+            let branch = mine_blocks(&mut chain_b, &other, fork_depth + 1);
+            assert_eq!(chain_a.can_merge_branch(&branch).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_equal_height_tie_break()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut chain_a = BlockChain::open_temp();
+        let mut chain_b = BlockChain::open_temp();
+        let shared = mine_blocks(&mut chain_a, &wallet, 1);
+        assert_eq!(chain_b.add(&shared[0]).unwrap(), BlockChainAddResult::Ok);
+
+        let branch_a = mine_blocks(&mut chain_a, &wallet, 2);
+        let branch_b = mine_blocks(&mut chain_b, &other, 2);
+
+        // Each chain sees the other branch second
+        if chain_a.can_merge_branch(&branch_b).unwrap() == BlockChainCanMergeResult::Ok {
+            chain_a.merge_branch(branch_b.clone());
+        }
+        if chain_b.can_merge_branch(&branch_a).unwrap() == BlockChainCanMergeResult::Ok {
+            chain_b.merge_branch(branch_a.clone());
+        }
+
+        let top_a = branch_a.last().unwrap().hash().unwrap();
+        let top_b = branch_b.last().unwrap().hash().unwrap();
+        let expected_top = std::cmp::min(top_a, top_b);
+        assert_eq!(chain_a.top().unwrap().hash().unwrap(), expected_top);
+        assert_eq!(chain_b.top().unwrap().hash().unwrap(), expected_top);
+    }
+
+    #[test]
+    fn test_blocks_in_time_range()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let mut chain = BlockChain::open_in_memory();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        assert_eq!(chain.blocks_in_time_range(0, u128::MAX).len(), 0);
+
+        let mut blocks = Vec::new();
+        for _ in 0..6
+        {
+            blocks.append(&mut mine_blocks(&mut chain, &wallet, 1));
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+
+        let from = blocks[2].header.timestamp;
+        let to = blocks[4].header.timestamp;
+        assert_eq!(chain.blocks_in_time_range(from, to), blocks[2..=4].to_vec());
+        assert_eq!(chain.blocks_in_time_range(from + 1, to - 1), blocks[3..4].to_vec());
+        assert_eq!(chain.blocks_in_time_range(0, u128::MAX), blocks);
+        assert_eq!(chain.blocks_in_time_range(to, from).len(), 0);
+    }
+
+    #[test]
+    fn test_block_time_stats()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let mut chain = BlockChain::open_in_memory();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        assert_eq!(chain.block_time_stats(10), None);
+
+        let start = current_timestamp() - 60 * 1000;
+        for offset in [0, 1000, 3000, 4000, 10000]
+        {
+            let mut block = Block::new_blank(&mut chain, &wallet).unwrap();
+            block.header.timestamp = start + offset;
+            let block = miner::mine_block(block);
+            assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+
+            // Need at least two blocks for an interval
+            if offset == 0 {
+                assert_eq!(chain.block_time_stats(10), None);
+            }
+        }
+
+        assert_eq!(chain.block_time_stats(10), Some(BlockTimeStats 
+        { 
+            min: 1000, 
+            max: 6000, 
+            mean: 2500.0, 
+            median: 1500.0,
+        }));
+
+        // Only the last few blocks
+        assert_eq!(chain.block_time_stats(3), Some(BlockTimeStats 
+        { 
+            min: 1000, 
+            max: 6000, 
+            mean: 3500.0, 
+            median: 3500.0,
+        }));
+        assert_eq!(chain.block_time_stats(1), None);
+    }
+
+    #[test]
+    fn test_page_update_interval()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let config = ChainConfig
+        {
+            block_time: 1,
+            block_sample_size: 10,
+            page_update_interval: Some(3),
+            branch_prune_interval: None,
+            ..ChainConfig::default()
+        };
+
+        let mut chain = BlockChain::open_in_memory_with_config(config).unwrap();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let site = wallet.get_address();
+        mine_blocks(&mut chain, &wallet, 1);
+
+        let page_update = |id: u32|
+        {
+            let page = Page::new(id, site, vec![Hash::empty()], 1000, 0.25);
+            let total_input = page.cost() + page.fee;
+            TransactionBuilder::new(page)
+                .add_input(&wallet, total_input)
+                .build().unwrap()
+        };
+
+        // Two updates in the same block
+        let block = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_page(page_update(1))
+            .add_page(page_update(2))
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Invalid(
+            BlockValidationResult::PageUpdateInterval(site)));
+
+        let block = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_page(page_update(1))
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+        assert_eq!(chain.push_page_queue(page_update(2)).is_err(), true);
+
+        // Another update within the interval
+        let block = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_page(page_update(2))
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Invalid(
+            BlockValidationResult::PageUpdateInterval(site)));
+
+        // Once the interval has passed
+        mine_blocks(&mut chain, &wallet, 2);
+        chain.push_page_queue(page_update(2)).unwrap();
+        let block = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_page(page_update(2))
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+        assert_eq!(chain.validate_chain().unwrap().is_none(), true);
+    }
+
+    #[test]
+    fn test_page_chunk_size()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let config = ChainConfig
+        {
+            page_chunk_size: 100,
+            ..ChainConfig::default()
+        };
+
+        let mut chain_a = BlockChain::open_in_memory_with_config(config).unwrap();
+        let mut chain_b = BlockChain::open_in_memory_with_config(ChainConfig::default()).unwrap();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        for block in mine_blocks(&mut chain_a, &wallet, 1) {
+            assert_eq!(chain_b.add(&block).unwrap(), BlockChainAddResult::Ok);
+        }
+
+        let data = DataUnit::CreatePage(CreatePageData::new("index.html".to_owned(), vec![0; 150]));
+        let page = chain_a.new_page(&wallet, &data, 1.0).unwrap();
+        assert_eq!(page.header.content.data_hashes.len(), 2);
+        assert_eq!(page.header.content.is_data_valid(&data).is_ok(), true);
+
+        // Valid with the chunk size it was built with, but not any other
+        chain_a.push_page_queue(page.clone()).unwrap();
+        assert_eq!(chain_b.push_page_queue(page.clone()).is_err(), true);
+
+        let block = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_page(page)
+            .build(&mut chain_a)
+            .unwrap());
+        assert_eq!(chain_a.add(&block).unwrap(), BlockChainAddResult::Ok);
+        assert_eq!(chain_b.add(&block).unwrap(), BlockChainAddResult::Invalid(
+            BlockValidationResult::PageChunkSize));
+    }
+
+    #[test]
+    fn test_reward_maturity()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let config = ChainConfig
+        {
+            block_time: 1,
+            block_sample_size: 10,
+            reward_maturity: Some(3),
+            branch_prune_interval: None,
+            ..ChainConfig::default()
+        };
+
+        let mut chain = BlockChain::open_in_memory_with_config(config).unwrap();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        mine_blocks(&mut chain, &wallet, 1);
+
+        // The reward from block 0 can't be spent in block 1
+        let transfer = chain.new_transfer(vec![(&wallet, 1.25)], vec![(other.get_address(), 1.0)], 0.25).unwrap();
+        assert_eq!(chain.push_transfer_queue(transfer.clone()).is_err(), true);
+        assert_eq!(transfer.validate_against(&mut chain).unwrap(), 
+                   TransactionValidationResult::Balance(wallet.get_address()));
+
+        let block = miner::mine_block(BlockBuilder::new(&other)
+            .add_transfer(transfer.clone())
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Invalid(
+            BlockValidationResult::Balance(wallet.get_address())));
+
+        // Once it's 3 blocks deep it has matured
+        mine_blocks(&mut chain, &other, 2);
+        assert_eq!(transfer.validate_against(&mut chain).unwrap(), TransactionValidationResult::Ok);
+        chain.push_transfer_queue(transfer.clone()).unwrap();
+
+        let block = miner::mine_block(BlockBuilder::new(&other)
+            .add_transfer(transfer)
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+        assert_eq!(chain.validate_chain().unwrap().is_none(), true);
+    }
+
+    #[test]
+    fn test_in_memory_chain()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut chain_a = BlockChain::open_in_memory();
+        let mut chain_b = BlockChain::open_in_memory();
+        assert!(chain_a.blocks.path().is_none());
+        assert!(chain_a.headers.path().is_none());
+        assert!(chain_a.metadata.path().is_none());
+
+        let shared = mine_blocks(&mut chain_a, &wallet, 2);
+        for block in &shared {
+            assert_eq!(chain_b.add(block).unwrap(), BlockChainAddResult::Ok);
+        }
+        assert_eq!(chain_b.add(&shared[0]).unwrap(), BlockChainAddResult::Duplicate);
+
+        // Merge a longer branch
+        mine_blocks(&mut chain_a, &wallet, 1);
+        let branch = mine_blocks(&mut chain_b, &other, 3);
+        assert_eq!(chain_a.add(branch.last().unwrap()).unwrap(), BlockChainAddResult::MoreNeeded);
+        assert_eq!(chain_a.can_merge_branch(&branch).unwrap(), BlockChainCanMergeResult::Ok);
+        chain_a.merge_branch(branch.clone());
+        assert_eq!(chain_a.top().unwrap(), chain_b.top().unwrap());
+
+        let reward = shared[0].calculate_reward();
+        assert_eq!(chain_a.get_wallet_status(&wallet.get_address()).balance, reward * 2.0);
+        assert_eq!(chain_a.get_wallet_status(&other.get_address()).balance, reward * 3.0);
+        assert_eq!(chain_a.validate_chain().unwrap().is_none(), true);
+
+        // Prune old blocks from memory
+        let config = ChainConfig
+        {
+            block_time: 1,
+            block_sample_size: 10,
+            prune_depth: Some(20),
+            branch_prune_interval: None,
+            ..ChainConfig::default()
+        };
+
+        let mut chain = BlockChain::open_in_memory_with_config(config).unwrap();
+        mine_blocks(&mut chain, &wallet, 120);
+        assert_eq!(chain.is_pruned(0), true);
+        assert_eq!(chain.block(10), None);
+        assert_eq!(chain.header(10).is_some(), true);
+        assert_eq!(chain.block(119).is_some(), true);
+    }
+
+    #[test]
+    fn test_top_ref()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut chain = BlockChain::open_temp();
+        assert!(chain.top_ref().is_none());
+
+        let blocks = mine_blocks(&mut chain, &wallet, 3);
+        assert_eq!(chain.top_ref(), blocks.last());
+
+        // Many readers can see the top at once
+        let chain = Arc::new(RwLock::new(chain));
+        let readers = (0..4)
+            .map(|_|
+            {
+                let chain = chain.clone();
+                std::thread::spawn(move ||
+                {
+                    let chain = chain.read().unwrap();
+                    chain.top_ref().map(|top| top.header.block_id)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let reader = chain.read().unwrap();
+        for handle in readers {
+            assert_eq!(handle.join().unwrap(), Some(2));
+        }
+        drop(reader);
+
+        // Kept up to date after merging a branch
+        let mut chain = chain.write().unwrap();
+        let mut other = BlockChain::open_in_memory();
+        let branch = mine_blocks(&mut other, &wallet, 5);
+        chain.merge_branch(branch.clone());
+        assert_eq!(chain.top_ref(), branch.last());
+    }
+
+    #[test]
+    fn test_read_empty_chain()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let chain = BlockChain::open_temp();
+        let path = chain.blocks.path().unwrap().clone();
+        assert!(chain.top().is_none());
+        assert!(chain.top_ref().is_none());
+        assert!(chain.block(0).is_none());
+        assert!(chain.header(0).is_none());
+
+        // Reading leaves nothing behind on disk
+        fn files_in(path: &PathBuf) -> Vec<PathBuf>
+        {
+            std::fs::read_dir(path)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .flat_map(|path| if path.is_dir() { files_in(&path) } else { vec![path] })
+                .collect()
+        }
+        assert_eq!(files_in(&path), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn test_concurrent_reads()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut chain = BlockChain::open_temp();
+        let blocks = mine_blocks(&mut chain, &wallet, 3);
+        let reward = blocks[0].calculate_reward();
+
+        // Every reader holds the lock until they all have it, so this 
+        // would never finish if readers blocked each other
+        let chain = Arc::new(RwLock::new(chain));
+        let barrier = Arc::new(Barrier::new(5));
+        let readers = (0..4)
+            .map(|block_id|
+            {
+                let (chain, barrier) = (chain.clone(), barrier.clone());
+                let address = wallet.get_address();
+                std::thread::spawn(move ||
+                {
+                    let chain = chain.read().unwrap();
+                    barrier.wait();
+                    (chain.block(block_id), chain.get_wallet_status(&address).balance)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let reader = chain.read().unwrap();
+        barrier.wait();
+        drop(reader);
+
+        for (block_id, handle) in readers.into_iter().enumerate()
+        {
+            let (block, balance) = handle.join().unwrap();
+            assert_eq!(block.as_ref(), blocks.get(block_id));
+            assert_eq!(balance, 3.0 * reward);
+        }
+
+        // Adding blocks still takes the write lock
+        let mut chain = chain.write().unwrap();
+        mine_blocks(&mut chain, &wallet, 1);
+        assert_eq!(chain.get_wallet_status(&wallet.get_address()).balance, 4.0 * reward);
+    }
+
 }
 
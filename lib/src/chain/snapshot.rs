@@ -0,0 +1,313 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use super::BlockChain;
+use super::metadata::{BlockMetadata, PageMetadata};
+use crate::block::{Block, BlockHeader};
+use crate::block::validate::BlockValidationResult;
+use crate::wallet::WalletStatus;
+use crate::merkle_tree::calculate_merkle_root;
+use crate::error::ErrorMessage;
+use crate::hash::Hash;
+
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::error::Error;
+
+// The status of every wallet as of one block, so a new node can carry
+// on from there without replaying the chain before it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StateSnapshot
+{
+    pub block_id: u64,
+    pub block_hash: Hash,
+    pub wallets: Vec<(Hash, WalletStatus)>,
+    pub fees_burned: f32,
+
+    // Each site updated up to the block, and the id of the block it was 
+    // updated in.
+    pub page_updates: Vec<(u64, Hash, PageMetadata)>,
+
+    // NOTE: Every header is included, but only the blocks
+    //       needed to validate the next one.
+    pub headers: Vec<BlockHeader>,
+    pub blocks: Vec<Block>,
+}
+
+impl StateSnapshot
+{
+
+    // Commits to the block, and all the state that can't be checked against it
+    pub fn state_root(&self) -> Result<Hash, Box<dyn Error>>
+    {
+        let mut leaves = vec![
+            self.block_hash.data().to_vec(),
+            bincode::serialize(&self.fees_burned)?,
+        ];
+
+        for entry in &self.wallets {
+            leaves.push(bincode::serialize(entry)?);
+        }
+        for entry in &self.page_updates {
+            leaves.push(bincode::serialize(entry)?);
+        }
+
+        Ok(calculate_merkle_root(&leaves))
+    }
+
+}
+
+impl BlockChain
+{
+
+    fn snapshot_block_count(&self) -> u64
+    {
+        // NOTE: Same as the smallest prune depth
+        let reward_maturity = self.config.reward_maturity.unwrap_or(0);
+        std::cmp::max(self.config.block_sample_size + 1, reward_maturity)
+    }
+
+    pub fn export_state(&mut self, block_id: u64)
+        -> Result<StateSnapshot, Box<dyn Error>>
+    {
+        if block_id >= self.blocks.next_top() {
+            return Err(ErrorMessage::new(&format!("No block {}", block_id)));
+        }
+
+        let mut wallets = HashMap::new();
+        let mut fees_burned = 0.0;
+        let mut page_updates = Vec::new();
+        let mut headers = Vec::new();
+        for id in 0..=block_id
+        {
+            let metadata = self.metadata.get(id).unwrap();
+            wallets.extend(metadata.wallets);
+            fees_burned += metadata.fees_burned;
+            page_updates.extend(metadata.page_updates
+                .into_iter()
+                .map(|(site, page)| (id, site, page)));
+            headers.push(self.header(id).unwrap());
+        }
+
+        let first_block = (block_id + 1).saturating_sub(self.snapshot_block_count());
+        let blocks = (first_block..=block_id)
+            .map(|id| self.block_or_error(id))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut wallets = wallets.into_iter().collect::<Vec<_>>();
+        wallets.sort_by_key(|(address, _)| *address);
+        page_updates.sort_by_key(|(id, site, _)| (*id, *site));
+
+        Ok(StateSnapshot
+        {
+            block_id,
+            block_hash: headers.last().unwrap().hash()?,
+            wallets,
+            fees_burned,
+            page_updates,
+            headers,
+            blocks,
+        })
+    }
+
+    fn validate_snapshot(&self, snapshot: &StateSnapshot)
+        -> Result<(), Box<dyn Error>>
+    {
+        // The headers must link up to the snapshot's block
+        let mut prev_hash_or_none = None;
+        for (block_id, header) in snapshot.headers.iter().enumerate()
+        {
+            let links_to_prev = match &prev_hash_or_none
+            {
+                Some(prev_hash) => &header.prev_hash == prev_hash,
+                None => true,
+            };
+
+            if header.block_id != block_id as u64 || !links_to_prev {
+                return Err(ErrorMessage::new(&format!("Snapshot header {} is not part of the chain", block_id)));
+            }
+            prev_hash_or_none = Some(header.hash()?);
+        }
+
+        if snapshot.headers.len() as u64 != snapshot.block_id + 1 || prev_hash_or_none != Some(snapshot.block_hash) {
+            return Err(ErrorMessage::new("Snapshot headers do not lead to its block"));
+        }
+
+        if snapshot.page_updates.iter().any(|(block_id, _, _)| *block_id > snapshot.block_id) {
+            return Err(ErrorMessage::new("Snapshot has page updates after its block"));
+        }
+
+        // And the blocks must be the last ones of those headers
+        let block_count = std::cmp::min(self.snapshot_block_count(), snapshot.block_id + 1);
+        if (snapshot.blocks.len() as u64) < block_count {
+            return Err(ErrorMessage::new("Snapshot does not have enough blocks to continue from"));
+        }
+
+        let first_block = snapshot.block_id + 1 - snapshot.blocks.len() as u64;
+        for (block, block_id) in snapshot.blocks.iter().zip(first_block..)
+        {
+            if block.header != snapshot.headers[block_id as usize] ||
+                block.validate_merkle_root()? != BlockValidationResult::Ok
+            {
+                return Err(ErrorMessage::new(&format!("Snapshot block {} does not match its header", block_id)));
+            }
+        }
+
+        Ok(())
+    }
+
+    // NOTE: The wallet state itself is assumed valid, if it matches a
+    //       root we already trust. Only the headers and blocks are checked.
+    pub fn import_state(&mut self, snapshot: &StateSnapshot, expected_root: &Hash)
+        -> Result<(), Box<dyn Error>>
+    {
+        if self.headers.next_top() != 0 {
+            return Err(ErrorMessage::new("Can only import state into an empty chain"));
+        }
+
+        if &snapshot.state_root()? != expected_root {
+            return Err(ErrorMessage::new("Snapshot does not match the expected state root"));
+        }
+        self.validate_snapshot(snapshot)?;
+
+        let mut page_updates = HashMap::<u64, HashMap<Hash, PageMetadata>>::new();
+        for (block_id, site, page) in &snapshot.page_updates
+        {
+            page_updates
+                .entry(*block_id)
+                .or_default()
+                .insert(*site, page.clone());
+        }
+
+        // NOTE: All wallet statuses are stored in the metadata of the
+        //       snapshot's block, so they're found before any earlier one. 
+        //       Page updates are kept with the block they were made in.
+        for (block_id, header) in snapshot.headers.iter().enumerate()
+        {
+            let block_id = block_id as u64;
+            let page_updates = page_updates.remove(&block_id).unwrap_or_default();
+            let metadata =
+                if block_id == snapshot.block_id
+                {
+                    BlockMetadata
+                    {
+                        wallets: snapshot.wallets.iter().cloned().collect(),
+                        page_updates,
+                        fees_burned: snapshot.fees_burned,
+                    }
+                }
+                else
+                {
+                    BlockMetadata
+                    {
+                        wallets: HashMap::new(),
+                        page_updates,
+                        fees_burned: 0.0,
+                    }
+                };
+
+            self.metadata.store(block_id, metadata);
+            self.headers.store(block_id, header.clone());
+            self.index_header(header)?;
+        }
+
+        let first_block = snapshot.block_id + 1 - snapshot.blocks.len() as u64;
+        self.blocks.start_at(first_block);
+        for block in &snapshot.blocks {
+            self.blocks.store(block.header.block_id, block.clone());
+        }
+
+        self.invalidate_wallet_status_cache(0);
+        self.load_top();
+        info!("Imported the state of {} wallet(s) at block {}",
+            snapshot.wallets.len(), snapshot.block_id);
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::chain::BlockChainAddResult;
+    use crate::config::ChainConfig;
+    use crate::wallet::Wallet;
+    use crate::wallet::private_wallet::PrivateWallet;
+    use crate::data_store::data_unit::DataUnit;
+    use crate::data_store::page::CreatePageData;
+    use crate::miner;
+
+    #[test]
+    fn test_export_import_state()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let config = ChainConfig
+        {
+            block_time: 1,
+            block_sample_size: 2,
+            ..ChainConfig::default()
+        };
+
+        let mut chain = BlockChain::open_temp_with_config(config.clone());
+        let wallets = (0..3)
+            .map(|i| PrivateWallet::open_temp(i).unwrap())
+            .collect::<Vec<_>>();
+        for i in 0..4
+        {
+            let block = miner::mine_block(Block::new_blank(&mut chain, &wallets[i % 2]).unwrap());
+            assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+        }
+
+        let transfer = chain.new_transfer(vec![(&wallets[0], 3.0)],
+            vec![(wallets[2].get_address(), 2.0)], 1.0).unwrap();
+        let page_data = CreatePageData::new("index.html".to_owned(), Vec::new());
+        let page = chain.new_page(&wallets[1], &DataUnit::CreatePage(page_data), 0.25).unwrap();
+        let block = miner::mine_block(Block::new(&mut chain, &wallets[1], vec![transfer], vec![page.clone()]).unwrap());
+        assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+
+        let snapshot = chain.export_state(4).unwrap();
+        let root = snapshot.state_root().unwrap();
+        assert_eq!(snapshot.blocks.len(), 3);
+        assert_eq!(snapshot.page_updates.len(), 1);
+
+        // Tampering with the state is caught
+        let mut tampered = snapshot.clone();
+        tampered.wallets[0].1.balance += 100.0;
+        let mut imported = BlockChain::open_temp_with_config(config.clone());
+        assert!(imported.import_state(&tampered, &root).is_err());
+
+        let mut tampered = snapshot.clone();
+        tampered.page_updates[0].2.is_creation = false;
+        assert!(imported.import_state(&tampered, &root).is_err());
+
+        // Otherwise, every balance is the same
+        imported.import_state(&snapshot, &root).unwrap();
+        for wallet in &wallets
+        {
+            assert_eq!(imported.get_wallet_status(&wallet.get_address()),
+                chain.get_wallet_status(&wallet.get_address()));
+        }
+        assert_eq!(imported.top().unwrap().hash().unwrap(), snapshot.block_hash);
+        assert_eq!(imported.total_supply(4), chain.total_supply(4));
+        assert_eq!(imported.first_corrupt_block(), None);
+
+        // As are the page updates
+        assert_eq!(imported.known_sites(), chain.known_sites());
+        assert_eq!(imported.get_page_updates(&wallets[1].get_address()), [page]);
+
+        // And new blocks can be added on top
+        let block = miner::mine_block(Block::new_blank(&mut chain, &wallets[2]).unwrap());
+        assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+        assert_eq!(imported.add(&block).unwrap(), BlockChainAddResult::Ok);
+        assert_eq!(imported.get_wallet_status(&wallets[2].get_address()),
+            chain.get_wallet_status(&wallets[2].get_address()));
+    }
+
+}
+
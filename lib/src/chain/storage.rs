@@ -4,16 +4,101 @@
  * SPDX-License-Identifier: BSD-2-Clause
  */
 
+use super::metadata::BlockMetadata;
+use super::legacy::{BlockV1, BlockHeaderV1, BlockMetadataV1};
+use crate::block::{Block, BlockHeader};
+use crate::error::ErrorMessage;
+
 use serde::{Serialize, Deserialize};
 use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::sync::Mutex;
 use std::path::PathBuf;
 use std::error::Error;
 use std::fs::File;
+use std::io::{ErrorKind, Write};
 
 const CHUNK_SIZE: usize = 100;
 
+// NOTE: Chunks written before versioning have no header, and are 
+//       read as the first version.
+const CHUNK_MAGIC: &[u8; 4] = b"HYPC";
+const LEGACY_VERSION: u8 = 1;
+
+// Types kept in storage. The version is written with each chunk, and 
+// must be bumped whenever the serialized layout changes.
+pub trait Versioned: Sized
+{
+    const VERSION: u8;
+
+    // Read the entries of a chunk written by an older version
+    fn migrate(version: u8, _data: &[u8]) -> Result<Vec<Option<Self>>, Box<dyn Error>>
+    {
+        Err(ErrorMessage::new(&format!("No migration from version {}", version)))
+    }
+}
+
+// Read the entries of a chunk in the layout `V`, converting each of them
+fn migrate_entries<V, T>(data: &[u8]) -> Result<Vec<Option<T>>, Box<dyn Error>>
+    where V: DeserializeOwned + Into<T>
+{
+    Ok(read_chunk_entries::<V>(data)?
+        .into_iter()
+        .map(|entry| entry.map(Into::into))
+        .collect())
+}
+
+// NOTE: Version 2 added the block reward and coinbase, transaction 
+//       expiry, page chunk sizes and burned fees.
+impl Versioned for Block
+{
+    const VERSION: u8 = 2;
+
+    fn migrate(version: u8, data: &[u8]) -> Result<Vec<Option<Self>>, Box<dyn Error>>
+    {
+        match version
+        {
+            1 => migrate_entries::<BlockV1, Self>(data),
+            _ => Err(ErrorMessage::new(&format!("No migration from version {}", version))),
+        }
+    }
+}
+
+impl Versioned for BlockHeader
+{
+    const VERSION: u8 = 2;
+
+    fn migrate(version: u8, data: &[u8]) -> Result<Vec<Option<Self>>, Box<dyn Error>>
+    {
+        match version
+        {
+            1 => migrate_entries::<BlockHeaderV1, Self>(data),
+            _ => Err(ErrorMessage::new(&format!("No migration from version {}", version))),
+        }
+    }
+}
+
+impl Versioned for BlockMetadata
+{
+    const VERSION: u8 = 2;
+
+    fn migrate(version: u8, data: &[u8]) -> Result<Vec<Option<Self>>, Box<dyn Error>>
+    {
+        match version
+        {
+            1 => migrate_entries::<BlockMetadataV1, Self>(data),
+            _ => Err(ErrorMessage::new(&format!("No migration from version {}", version))),
+        }
+    }
+}
+
+fn legacy_version() -> u8
+{
+    LEGACY_VERSION
+}
+
 #[derive(Clone, Serialize, Deserialize)]
-struct Chunk<T>
+pub struct Chunk<T>
 {
     data: Vec<Option<T>>,
 }
@@ -30,10 +115,16 @@ impl<T> Default for Chunk<T>
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct Metadata
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Metadata
 {
     next_top: u64,
+
+    #[serde(default)]
+    pruned_below: u64,
+
+    #[serde(default = "legacy_version")]
+    version: u8,
 }
 
 impl Default for Metadata
@@ -43,109 +134,333 @@ impl Default for Metadata
         Self
         {
             next_top: 0,
+            pruned_below: 0,
+            version: LEGACY_VERSION,
         }
     }
 }
 
-pub struct Storage<T>
+pub trait StorageBackend<T>: Send + Sync
+{
+
+    fn load_metadata(&self) -> Result<Metadata, Box<dyn Error>>;
+    fn store_metadata(&mut self, metadata: &Metadata);
+
+    fn load_chunk(&self, id: usize) -> Chunk<T>;
+    fn store_chunk(&mut self, id: usize, chunk: &Chunk<T>);
+    fn remove_chunk(&mut self, id: usize);
+
+    #[cfg(test)]
+    fn path(&self) -> Option<&PathBuf>;
+
+}
+
+pub struct FileBackend
 {
     path: PathBuf,
-    metadata: Metadata,
-    cache: Option<(usize, Chunk<T>)>,
+}
+
+impl FileBackend
+{
+
+    pub fn new(path: &PathBuf) -> Result<Self, Box<dyn Error>>
+    {
+        if let Err(err) = std::fs::create_dir_all(path) {
+            return Err(ErrorMessage::new(&format!("Could not create chain directory {:?}: {}", path, err)));
+        }
+
+        Ok(Self
+        {
+            path: path.clone(),
+        })
+    }
+
+    fn get_chunk_file_path(&self, id: usize) -> PathBuf
+    {
+        let file_name = format!("blk{}", id);
+        self.path.join(file_name)
+    }
+
+}
+
+// The first id stored in the same chunk as `block_id`
+pub fn chunk_start(block_id: u64) -> u64
+{
+    block_id - block_id % CHUNK_SIZE as u64
+}
+
+// Entries of a chunk serialized without a version header
+pub fn read_chunk_entries<T>(data: &[u8]) -> Result<Vec<Option<T>>, Box<dyn Error>>
+    where T: DeserializeOwned
+{
+    let chunk: Chunk<T> = bincode::deserialize(data)?;
+    Ok(chunk.data)
+}
+
+fn chunk_version(data: &[u8]) -> (u8, &[u8])
+{
+    let header_len = CHUNK_MAGIC.len() + 1;
+    if data.len() >= header_len && data.starts_with(CHUNK_MAGIC) {
+        (data[CHUNK_MAGIC.len()], &data[header_len..])
+    } else {
+        (LEGACY_VERSION, data)
+    }
+}
+
+fn deserialize_chunk<T>(data: &[u8]) -> Result<Chunk<T>, Box<dyn Error>>
+    where T: Clone + DeserializeOwned + Versioned
+{
+    let (version, data) = chunk_version(data);
+    let entries = if version == T::VERSION {
+        read_chunk_entries(data)?
+    } else {
+        T::migrate(version, data)?
+    };
+
+    if entries.len() != CHUNK_SIZE {
+        return Err(ErrorMessage::new(&format!("Chunk has {} entries, expected {}", 
+            entries.len(), CHUNK_SIZE)));
+    }
+    Ok(Chunk { data: entries })
 }
 
 fn load_chunk_file<T>(path: PathBuf) -> Chunk<T>
-    where T: Clone + DeserializeOwned
+    where T: Clone + DeserializeOwned + Versioned
 {
-    match File::open(path)
+    let data = match std::fs::read(&path)
+    {
+        Ok(data) => data,
+        Err(_) => return Default::default(),
+    };
+
+    // NOTE: Leave chunks from a newer version alone, as they're not corrupt
+    let (version, _) = chunk_version(&data);
+    if version > T::VERSION 
+    {
+        error!("Chunk {:?} is version {}, newer then the supported version {}", 
+            path, version, T::VERSION);
+        return Default::default();
+    }
+
+    match deserialize_chunk(&data)
+    {
+        Ok(chunk) => chunk,
+        Err(err) => 
+        {
+            // NOTE: Move the file out of the way, so it's kept for 
+            //       inspection but won't be loaded again.
+            warn!("Quarantining corrupt chunk {:?}: {}", path, err);
+            let _ = std::fs::rename(&path, path.with_extension("corrupt"));
+            Default::default()
+        },
+    }
+}
+
+impl<T> StorageBackend<T> for FileBackend
+    where T: Clone + Serialize + DeserializeOwned + Versioned
+{
+
+    fn load_metadata(&self) -> Result<Metadata, Box<dyn Error>>
+    {
+        let metadata_path = self.path.join("metadata.json");
+        let file = match File::open(&metadata_path)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Default::default()),
+            Err(err) => return Err(ErrorMessage::new(&format!("Could not open {:?}: {}", metadata_path, err))),
+        };
+
+        match serde_json::from_reader(file)
+        {
+            Ok(metadata) => Ok(metadata),
+            Err(err) => Err(ErrorMessage::new(&format!("Could not read {:?}: {}", metadata_path, err))),
+        }
+    }
+
+    fn store_metadata(&mut self, metadata: &Metadata)
+    {
+        match File::create(self.path.join("metadata.json"))
+        {
+            Ok(file) => { let _ = serde_json::to_writer(file, metadata); },
+            Err(_) => {},
+        }
+    }
+
+    fn load_chunk(&self, id: usize) -> Chunk<T>
     {
-        Ok(file) => 
-            match bincode::deserialize_from(file)
+        let path = self.get_chunk_file_path(id);
+        if !path.exists() {
+            Default::default() 
+        } else {
+            load_chunk_file(path)
+        }
+    }
+
+    fn store_chunk(&mut self, id: usize, chunk: &Chunk<T>)
+    {
+        let path = self.get_chunk_file_path(id);
+        match File::create(path)
+        {
+            Ok(mut file) => 
             {
-                Ok(chunk) => chunk,
-                Err(_) => Default::default(),
+                let _ = file.write_all(CHUNK_MAGIC)
+                    .and_then(|_| file.write_all(&[T::VERSION]));
+                let _ = bincode::serialize_into(file, chunk);
             },
-        Err(_) => Default::default(),
+            Err(_) => {},
+        }
     }
+
+    fn remove_chunk(&mut self, id: usize)
+    {
+        let _ = std::fs::remove_file(self.get_chunk_file_path(id));
+    }
+
+    #[cfg(test)]
+    fn path(&self) -> Option<&PathBuf>
+    {
+        Some(&self.path)
+    }
+
 }
 
-fn load_metadata(path: &PathBuf) -> Result<Metadata, Box<dyn Error>>
+pub struct MemoryBackend<T>
 {
-    match File::open(path.join("metadata.json"))
+    metadata: Metadata,
+    chunks: HashMap<usize, Chunk<T>>,
+}
+
+impl<T> MemoryBackend<T>
+{
+
+    pub fn new() -> Self
     {
-        Ok(file) => Ok(serde_json::from_reader(file)?),
-        Err(_) => Ok(Default::default()),
+        Self
+        {
+            metadata: Default::default(),
+            chunks: HashMap::new(),
+        }
     }
+
 }
 
-impl<T> Storage<T>
-    where T: Clone + Serialize + DeserializeOwned
+impl<T> StorageBackend<T> for MemoryBackend<T>
+    where T: Clone + Send + Sync
 {
 
-    pub fn new(path: &PathBuf) -> Result<Self, Box<dyn Error>>
+    fn load_metadata(&self) -> Result<Metadata, Box<dyn Error>>
     {
-        std::fs::create_dir_all(path)?;
-        Ok(Self
+        Ok(self.metadata.clone())
+    }
+
+    fn store_metadata(&mut self, metadata: &Metadata)
+    {
+        self.metadata = metadata.clone();
+    }
+
+    fn load_chunk(&self, id: usize) -> Chunk<T>
+    {
+        match self.chunks.get(&id)
         {
-            path: path.clone(),
-            metadata: load_metadata(path)?,
-            cache: None,
-        })
+            Some(chunk) => chunk.clone(),
+            None => Default::default(),
+        }
+    }
+
+    fn store_chunk(&mut self, id: usize, chunk: &Chunk<T>)
+    {
+        self.chunks.insert(id, chunk.clone());
+    }
+
+    fn remove_chunk(&mut self, id: usize)
+    {
+        self.chunks.remove(&id);
     }
 
     #[cfg(test)]
-    pub fn path(&self) -> &PathBuf
+    fn path(&self) -> Option<&PathBuf>
     {
-        &self.path
+        None
     }
 
-    fn save_metadata(&self)
+}
+
+pub struct Storage<T>
+{
+    backend: Box<dyn StorageBackend<T>>,
+    metadata: Metadata,
+    // NOTE: Behind a lock so entries can be read through a shared 
+    //       reference, letting the chain be read concurrently.
+    cache: Mutex<Option<(usize, Chunk<T>)>>,
+}
+
+impl<T> Storage<T>
+    where T: Clone + Serialize + DeserializeOwned + Versioned + Send + Sync + 'static
+{
+
+    pub fn new(path: &PathBuf) -> Result<Self, Box<dyn Error>>
     {
-        match File::create(self.path.join("metadata.json"))
+        Self::with_backend(Box::new(FileBackend::new(path)?))
+    }
+
+    pub fn in_memory() -> Self
+    {
+        Self::with_backend(Box::new(MemoryBackend::new())).unwrap()
+    }
+
+    pub fn with_backend(backend: Box<dyn StorageBackend<T>>) 
+        -> Result<Self, Box<dyn Error>>
+    {
+        let mut metadata = backend.load_metadata()?;
+        if metadata.version > T::VERSION 
         {
-            Ok(file) => { let _ = serde_json::to_writer(file, &self.metadata); },
-            Err(_) => {},
+            return Err(ErrorMessage::new(&format!(
+                "Storage was written by a newer version ({}), only up to {} is supported",
+                metadata.version, T::VERSION)));
         }
+
+        // NOTE: Older chunks are migrated as they're read, and 
+        //       upgraded when they're next written.
+        metadata.version = T::VERSION;
+        Ok(Self
+        {
+            metadata,
+            backend,
+            cache: Mutex::new(None),
+        })
     }
 
-    fn get_chunk_file_path(&self, id: usize) -> PathBuf
+    #[cfg(test)]
+    pub fn path(&self) -> Option<&PathBuf>
     {
-        let file_name = format!("blk{}", id);
-        self.path.join(file_name)
+        self.backend.path()
+    }
+
+    fn save_metadata(&mut self)
+    {
+        self.backend.store_metadata(&self.metadata);
     }
 
-    fn get_chunk(&mut self, id: usize) -> Chunk<T>
+    fn get_chunk(&self, id: usize) -> Chunk<T>
     {
-        if self.cache.is_some()
+        if let Some((cache_id, cache_chunk)) = self.cache.lock().unwrap().as_ref()
         {
-            let (cache_id, cache_chunk) = self.cache.as_ref().unwrap();
             if *cache_id == id {
                 return cache_chunk.clone();
             }
         }
 
-        let path = self.get_chunk_file_path(id);
-        let chunk = 
-            if !path.exists() {
-                Default::default() 
-            } else {
-                load_chunk_file(path)
-            };
-
-        self.cache = Some((id, chunk.clone()));
-        return chunk;
+        // NOTE: Loaded without holding the lock, so other readers aren't 
+        //       held up waiting on the IO.
+        let chunk = self.backend.load_chunk(id);
+        *self.cache.lock().unwrap() = Some((id, chunk.clone()));
+        chunk
     }
 
     fn store_chunk(&mut self, id: usize, chunk: Chunk<T>)
     {
-        let path = self.get_chunk_file_path(id);
-        match File::create(path)
-        {
-            Ok(file) => { let _ = bincode::serialize_into(file, &chunk); },
-            Err(_) => {},
-        }
-
-        self.cache = Some((id, chunk.clone()));
+        self.backend.store_chunk(id, &chunk);
+        *self.cache.get_mut().unwrap() = Some((id, chunk));
     }
 
     pub fn store(&mut self, block_id: u64, block: T)
@@ -163,11 +478,53 @@ impl<T> Storage<T>
     pub fn truncate(&mut self, new_size: u64)
     {
         self.metadata.next_top = new_size;
+        self.metadata.pruned_below = std::cmp::min(self.metadata.pruned_below, new_size);
+        self.save_metadata();
+    }
+
+    pub fn prune_below(&mut self, block_id: u64)
+    {
+        // NOTE: Only whole chunks are removed, so some entries 
+        //       below `block_id` may be kept around.
+        let first_chunk = self.metadata.pruned_below as usize / CHUNK_SIZE;
+        let end_chunk = block_id as usize / CHUNK_SIZE;
+        if end_chunk <= first_chunk {
+            return;
+        }
+
+        for chunk_id in first_chunk..end_chunk 
+        {
+            self.backend.remove_chunk(chunk_id);
+            let cache = self.cache.get_mut().unwrap();
+            if matches!(cache, Some((cache_id, _)) if *cache_id == chunk_id) {
+                *cache = None;
+            }
+        }
+
+        self.metadata.pruned_below = (end_chunk * CHUNK_SIZE) as u64;
         self.save_metadata();
     }
 
-    pub fn get(&mut self, block_id: u64) -> Option<T>
+    // Treat everything below `block_id` as pruned, for storage that 
+    // starts part way through the chain
+    pub fn start_at(&mut self, block_id: u64)
     {
+        self.metadata.next_top = std::cmp::max(self.metadata.next_top, block_id);
+        self.metadata.pruned_below = block_id;
+        self.save_metadata();
+    }
+
+    pub fn pruned_below(&self) -> u64
+    {
+        self.metadata.pruned_below
+    }
+
+    pub fn get(&self, block_id: u64) -> Option<T>
+    {
+        if block_id < self.metadata.pruned_below {
+            return None;
+        }
+
         let chunk_id = block_id as usize / CHUNK_SIZE;
         let chunk = self.get_chunk(chunk_id);
         let index = block_id as usize % CHUNK_SIZE;
@@ -181,3 +538,235 @@ impl<T> Storage<T>
 
 }
 
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::chain::{BlockChain, BlockChainAddResult};
+    use crate::wallet::Wallet;
+    use crate::wallet::private_wallet::PrivateWallet;
+    use crate::miner;
+    use std::sync::{Arc, RwLock};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+    struct EntryV1
+    {
+        value: u64,
+    }
+
+    impl Versioned for EntryV1
+    {
+        const VERSION: u8 = 1;
+    }
+
+    // The same entry after a field was added
+    #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+    struct Entry
+    {
+        value: u64,
+        label: String,
+    }
+
+    impl Versioned for Entry
+    {
+        const VERSION: u8 = 2;
+
+        fn migrate(version: u8, data: &[u8]) -> Result<Vec<Option<Self>>, Box<dyn Error>>
+        {
+            if version != 1 {
+                return Err(ErrorMessage::new(&format!("No migration from version {}", version)));
+            }
+
+            let entries = read_chunk_entries::<EntryV1>(data)?
+                .into_iter()
+                .map(|entry| entry.map(|x| Entry { value: x.value, label: String::new() }))
+                .collect();
+            Ok(entries)
+        }
+    }
+
+    fn temp_path() -> PathBuf
+    {
+        std::env::temp_dir().join(rand::random::<u32>().to_string())
+    }
+
+    #[test]
+    fn test_migrate_older_version()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let path = temp_path();
+        {
+            let mut storage = Storage::<EntryV1>::new(&path).unwrap();
+            storage.store(0, EntryV1 { value: 21 });
+            storage.store(150, EntryV1 { value: 42 });
+        }
+
+        // Entries written by the old version are still readable
+        let mut storage = Storage::<Entry>::new(&path).unwrap();
+        assert_eq!(storage.next_top(), 151);
+        assert_eq!(storage.get(0), Some(Entry { value: 21, label: String::new() }));
+        assert_eq!(storage.get(150), Some(Entry { value: 42, label: String::new() }));
+
+        // Chunks are upgraded as they're written
+        storage.store(1, Entry { value: 84, label: "new".to_owned() });
+        let reopened = Storage::<Entry>::new(&path).unwrap();
+        assert_eq!(reopened.get(0), Some(Entry { value: 21, label: String::new() }));
+        assert_eq!(reopened.get(1), Some(Entry { value: 84, label: "new".to_owned() }));
+        assert!(!path.join("blk0.corrupt").exists());
+
+        // Storage from a newer version is rejected, rather then misread
+        assert!(Storage::<EntryV1>::new(&path).is_err());
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_unversioned_chunk()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let mut chain = BlockChain::open_temp();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let block = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+
+        // Written in the first layout, before chunks had a version header
+        let legacy_block = BlockV1
+        {
+            header: BlockHeaderV1
+            {
+                prev_hash: block.header.prev_hash,
+                block_id: block.header.block_id,
+                timestamp: block.header.timestamp,
+                raward_to: block.header.raward_to,
+                target: block.header.target,
+                transaction_merkle_root: block.header.transaction_merkle_root,
+                pow: block.header.pow,
+            },
+            pages: Vec::new(),
+            transfers: Vec::new(),
+        };
+
+        let path = temp_path();
+        std::fs::create_dir_all(&path).unwrap();
+        let mut chunk = Chunk::<BlockV1>::default();
+        chunk.data[0] = Some(legacy_block);
+        std::fs::write(path.join("blk0"), bincode::serialize(&chunk).unwrap()).unwrap();
+        std::fs::write(path.join("metadata.json"), b"{\"next_top\":1}").unwrap();
+
+        let storage = Storage::<Block>::new(&path).unwrap();
+        assert_eq!(storage.next_top(), 1);
+        assert_eq!(storage.get(0), Some(block));
+        assert!(!path.join("blk0.corrupt").exists());
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    // Waits for another reader to be loading a chunk at the same time, 
+    // or gives up after a while, keeping track of the most at once
+    struct OverlappingBackend<T>
+    {
+        inner: Box<dyn StorageBackend<T>>,
+        loading: Arc<AtomicUsize>,
+        most_at_once: Arc<AtomicUsize>,
+    }
+
+    impl<T> StorageBackend<T> for OverlappingBackend<T>
+    {
+
+        fn load_metadata(&self) -> Result<Metadata, Box<dyn Error>>
+        {
+            self.inner.load_metadata()
+        }
+
+        fn store_metadata(&mut self, metadata: &Metadata)
+        {
+            self.inner.store_metadata(metadata)
+        }
+
+        fn load_chunk(&self, id: usize) -> Chunk<T>
+        {
+            self.loading.fetch_add(1, Ordering::SeqCst);
+            let started = Instant::now();
+            while self.loading.load(Ordering::SeqCst) < 2 && started.elapsed() < Duration::from_secs(2) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            self.most_at_once.fetch_max(self.loading.load(Ordering::SeqCst), Ordering::SeqCst);
+            let chunk = self.inner.load_chunk(id);
+            self.loading.fetch_sub(1, Ordering::SeqCst);
+            chunk
+        }
+
+        fn store_chunk(&mut self, id: usize, chunk: &Chunk<T>)
+        {
+            self.inner.store_chunk(id, chunk)
+        }
+
+        fn remove_chunk(&mut self, id: usize)
+        {
+            self.inner.remove_chunk(id)
+        }
+
+        fn path(&self) -> Option<&PathBuf>
+        {
+            self.inner.path()
+        }
+
+    }
+
+    #[test]
+    fn test_readers_load_chunks_at_once()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let mut chain = BlockChain::open_temp();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        for _ in 0..3
+        {
+            let block = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+            assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+        }
+
+        // NOTE: Nothing is cached, so both readers scan the metadata
+        let most_at_once = Arc::new(AtomicUsize::new(0));
+        let backend = std::mem::replace(&mut chain.metadata.backend, Box::new(MemoryBackend::new()));
+        chain.metadata.backend = Box::new(OverlappingBackend
+        {
+            inner: backend,
+            loading: Arc::new(AtomicUsize::new(0)),
+            most_at_once: most_at_once.clone(),
+        });
+        *chain.metadata.cache.get_mut().unwrap() = None;
+
+        // Neither the wallet status cache or the chunk cache are held 
+        // while the other reader is loading
+        let chain = Arc::new(RwLock::new(chain));
+        let addresses = (1..=2)
+            .map(|i| PrivateWallet::open_temp(i).unwrap().get_address())
+            .collect::<Vec<_>>();
+        let readers = (1..=2).zip(addresses)
+            .map(|(i, address)|
+            {
+                let chain = chain.clone();
+                std::thread::spawn(move ||
+                {
+                    let chain = chain.read().unwrap();
+                    if i == 1 {
+                        chain.get_wallet_status(&address).balance
+                    } else {
+                        chain.get_wallet_statuses(&[address])[0].balance
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in readers {
+            assert_eq!(handle.join().unwrap(), 0.0);
+        }
+        assert_eq!(most_at_once.load(Ordering::SeqCst), 2);
+    }
+
+}
+
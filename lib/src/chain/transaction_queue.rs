@@ -5,7 +5,7 @@
  */
 
 use super::BlockChain;
-use crate::block::Block;
+use crate::block::{Block, current_timestamp};
 use crate::transaction::{Transaction, TransactionVariant};
 use crate::transaction::{TransactionContent, TransactionValidationResult};
 use crate::transaction::transfer::{Transfer, TransferBuilder};
@@ -14,6 +14,7 @@ use crate::transaction::builder::TransactionBuilder;
 use crate::wallet::{Wallet, WalletStatus};
 use crate::wallet::private_wallet::PrivateWallet;
 use crate::data_store::data_unit::DataUnit;
+use crate::config::MIN_REPLACEMENT_FEE_RATE_INCREASE;
 use crate::error::ErrorMessage;
 use crate::hash::Hash;
 
@@ -41,7 +42,7 @@ impl BlockChain
         }
 
         let transaction = builder.build()?;
-        if transaction.validate_content()? != TransactionValidationResult::Ok {
+        if transaction.validate_content(self.config().signature_scheme)? != TransactionValidationResult::Ok {
             return Err(ErrorMessage::new("Invalid content"));
         }
 
@@ -85,7 +86,8 @@ impl BlockChain
         -> Result<Transaction<Page>, Box<dyn Error>>
     {
         let status = self.get_wallet_status_after_queue(&from.get_address());
-        let page = Page::new_from_data(status.max_id + 1, from.get_address(), data, fee)?;
+        let page = Page::new_from_data(status.max_id + 1, from.get_address(), data, 
+            self.config.page_chunk_size, fee)?;
         let total_output = page.cost() + fee;
         self.new_transaction(vec![(from, total_output)], page)
     }
@@ -96,6 +98,14 @@ impl BlockChain
         // NOTE: We validate before adding, as everything in the transaction 
         //       queue is assumed to be valid.
 
+        if transaction.is_expired(current_timestamp()) {
+            return Err(ErrorMessage::new("Transaction has expired"));
+        }
+
+        if transaction.header.content.get_fee() < transaction.min_fee()? {
+            return Err(ErrorMessage::new("Fee is below the minimum"));
+        }
+
         for address in transaction.get_from_addresses()
         {
             let status = self.get_wallet_status_after_queue(&address);
@@ -104,15 +114,75 @@ impl BlockChain
             if new_status.balance < 0.0 {
                 return Err(ErrorMessage::new("Negative balance"));
             }
+
+            let next_block_id = self.blocks.next_top();
+            if new_status.balance < self.immature_reward(&address, next_block_id, &[]) {
+                return Err(ErrorMessage::new("Block reward has not matured"));
+            }
         }
 
         Ok(())
     }
 
+    fn replace_transfer_in_queue(&mut self, original: Transaction<Transfer>,
+                                 replacement: Transaction<Transfer>)
+        -> Result<(), Box<dyn Error>>
+    {
+        if replacement.header.content.get_fee() <= original.header.content.get_fee() {
+            return Err(ErrorMessage::new("Replacement must pay a higher fee"));
+        }
+
+        // NOTE: Otherwise the same transaction could be relayed over and 
+        //       over, each time for a tiny bit more fee
+        let min_fee_rate = original.fee_rate() * (1.0 + MIN_REPLACEMENT_FEE_RATE_INCREASE);
+        if replacement.fee_rate() < min_fee_rate {
+            return Err(ErrorMessage::new(&format!(
+                "Replacement must raise the fee rate by at least {}%", 
+                MIN_REPLACEMENT_FEE_RATE_INCREASE * 100.0)));
+        }
+
+        // NOTE: Later transactions from this wallet may depend on the original
+        if self.transfer_queue.has_later_from(&original) {
+            return Err(ErrorMessage::new("Only the latest queued transaction can be replaced"));
+        }
+
+        self.transfer_queue.remove(&original);
+        if let Err(err) = self.is_transaction_valid(&replacement)
+        {
+            self.transfer_queue.push(original)?;
+            return Err(err);
+        }
+
+        info!("Replacing queued transaction {} with a higher fee", original.hash()?);
+        self.transfer_queue.push(replacement)?;
+        Ok(())
+    }
+
+    // NOTE: Transfers and pages share ids, so either queue can conflict
+    fn is_conflicting_with_queue<C>(&self, transaction: &Transaction<C>) -> bool
+        where C: TransactionContent + Serialize
+    {
+        let id = transaction.get_id();
+        transaction.get_from_addresses()
+            .iter()
+            .any(|address| 
+                self.transfer_queue.is_pending(address, id) || 
+                self.page_queue.is_pending(address, id))
+    }
+
     pub fn push_transfer_queue(&mut self, transaction: Transaction<Transfer>) 
         -> Result<(), Box<dyn Error>>
     {
+        if let Some(original) = self.transfer_queue.find_conflicting(&transaction) {
+            return self.replace_transfer_in_queue(original, transaction);
+        }
+
+        if self.is_conflicting_with_queue(&transaction) {
+            return Err(ErrorMessage::new("Conflicts with a queued page update"));
+        }
+
         self.is_transaction_valid(&transaction)?;
+        self.transfer_queue.make_room(self.config.max_queue_size, transaction.fee_rate())?;
         self.transfer_queue.push(transaction)?;
         Ok(())
     }
@@ -120,7 +190,31 @@ impl BlockChain
     pub fn push_page_queue(&mut self, transaction: Transaction<Page>) 
         -> Result<(), Box<dyn Error>>
     {
+        if self.is_conflicting_with_queue(&transaction) {
+            return Err(ErrorMessage::new("Conflicts with a queued transaction"));
+        }
+
+        if transaction.header.content.chunk_size as usize != self.config.page_chunk_size {
+            return Err(ErrorMessage::new("Page uses a different chunk size to this chain"));
+        }
+
         self.is_transaction_valid(&transaction)?;
+
+        // NOTE: Only one update per site can be mined within the interval
+        if let Some(interval) = self.config.page_update_interval
+        {
+            let site = transaction.header.content.site;
+            if self.page_queue.transactions().any(|x| x.header.content.site == site) {
+                return Err(ErrorMessage::new("Site already has an update queued"));
+            }
+
+            let next_block_id = self.blocks.next_top();
+            if self.last_page_update_below(&site, next_block_id, interval).is_some() {
+                return Err(ErrorMessage::new("Site was updated too recently"));
+            }
+        }
+
+        self.page_queue.make_room(self.config.max_queue_size, transaction.fee_rate())?;
         self.page_queue.push(transaction)?;
         Ok(())
     }
@@ -137,12 +231,40 @@ impl BlockChain
         self.page_queue.get_next(count)
     }
 
+    pub fn transfers_in_queue_ahead_of(&self, fee_rate: f32) 
+        -> impl Iterator<Item = &Transaction<Transfer>>
+    {
+        self.transfer_queue.ahead_of(fee_rate)
+    }
+
+    pub fn transaction_queue_size(&self) -> usize
+    {
+        self.transfer_queue.len() + self.page_queue.len()
+    }
+
     pub fn remove_from_transaction_queue(&mut self, block: &Block)
     {
         self.transfer_queue.remove_in_block(&block.transfers);
         self.page_queue.remove_in_block(&block.pages);
     }
 
+    pub fn remove_expired_from_transaction_queue(&mut self, timestamp: u128)
+    {
+        self.transfer_queue.remove_expired(timestamp);
+        self.page_queue.remove_expired(timestamp);
+    }
+
+    pub fn transactions_in_queue(&self) -> Vec<TransactionVariant>
+    {
+        let transfers = self.transfer_queue.transactions()
+            .cloned()
+            .map(TransactionVariant::Transfer);
+        let pages = self.page_queue.transactions()
+            .cloned()
+            .map(TransactionVariant::Page);
+        transfers.chain(pages).collect()
+    }
+
     pub fn find_transaction_in_queue(&self, transaction_id: &Hash) -> Option<TransactionVariant>
     {
         let transfer = self.transfer_queue.find(transaction_id);
@@ -166,8 +288,10 @@ mod tests
 
     use super::*;
     use super::super::BlockChainAddResult;
+    use crate::block::validate::BlockValidationResult;
 
     use crate::block::builder::BlockBuilder;
+    use crate::config::{ChainConfig, PAGE_CHUNK_SIZE};
     use crate::miner;
 
     #[test]
@@ -233,5 +357,202 @@ mod tests
         assert_eq!(chain.get_next_transfers_in_queue(10).count() == 0, true);
     }
 
-}
+    #[test]
+    fn test_replace_by_fee()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let mut chain = BlockChain::open_temp();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+
+        let block_a = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Ok);
+
+        let transfer_with_fee = |amount: f32, fee: f32|
+        {
+            TransactionBuilder::new(
+                TransferBuilder::new(1, fee)
+                    .add_output(other.get_address(), amount)
+                    .build())
+                .add_input(&wallet, amount + fee)
+                .build()
+                .unwrap()
+        };
+
+        let original = transfer_with_fee(2.0, 0.5);
+        chain.push_transfer_queue(original.clone()).unwrap();
+
+        // Must strictly raise the fee
+        assert_eq!(chain.push_transfer_queue(transfer_with_fee(1.0, 0.5)).is_err(), true);
+        assert_eq!(chain.push_transfer_queue(transfer_with_fee(2.0, 0.25)).is_err(), true);
+        assert_eq!(chain.get_next_transfers_in_queue(10).collect::<Vec<_>>(), [&original]);
+
+        // By at least the minimum increase
+        assert_eq!(chain.push_transfer_queue(transfer_with_fee(2.0, 0.52)).is_err(), true);
+        assert_eq!(chain.get_next_transfers_in_queue(10).collect::<Vec<_>>(), [&original]);
+
+        // And still be valid
+        assert_eq!(chain.push_transfer_queue(transfer_with_fee(20.0, 1.0)).is_err(), true);
+        assert_eq!(chain.get_next_transfers_in_queue(10).collect::<Vec<_>>(), [&original]);
+
+        let replacement = transfer_with_fee(2.0, 1.0);
+        chain.push_transfer_queue(replacement.clone()).unwrap();
+        assert_eq!(chain.get_next_transfers_in_queue(10).collect::<Vec<_>>(), [&replacement]);
+        assert_eq!(chain.find_transaction_in_queue(&original.hash().unwrap()), None);
+    }
+
+    #[test]
+    fn test_queue_conflicts()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let mut chain = BlockChain::open_temp();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let third = PrivateWallet::open_temp(2).unwrap();
+
+        let block_a = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Ok);
+
+        let transfer_to = |to: &PrivateWallet, id: u32|
+        {
+            TransactionBuilder::new(
+                TransferBuilder::new(id, 1.0)
+                    .add_output(to.get_address(), 2.0)
+                    .build())
+                .add_input(&wallet, 3.0)
+                .build()
+                .unwrap()
+        };
+
+        // Spending the same id twice, without raising the fee
+        let first = transfer_to(&other, 1);
+        chain.push_transfer_queue(first.clone()).unwrap();
+        assert_eq!(chain.push_transfer_queue(transfer_to(&third, 1)).is_err(), true);
+        assert_eq!(chain.get_next_transfers_in_queue(10).collect::<Vec<_>>(), [&first]);
+
+        // Pages share the same ids
+        let page = TransactionBuilder::new(
+            Page::new(1, wallet.get_address(), vec![Hash::empty()], PAGE_CHUNK_SIZE as u32, 1.0))
+            .add_input(&wallet, 2.0)
+            .build()
+            .unwrap();
+        assert_eq!(chain.push_page_queue(page).is_err(), true);
+        assert_eq!(chain.transaction_queue_size(), 1);
+
+        // Once mined, the next id is free to use
+        let block_b = miner::mine_block(Block::new(&mut chain, &wallet, vec![first], Vec::new()).unwrap());
+        assert_eq!(chain.add(&block_b).unwrap(), BlockChainAddResult::Ok);
+        assert_eq!(chain.transaction_queue_size(), 0);
+        chain.push_transfer_queue(transfer_to(&third, 2)).unwrap();
+    }
+
+    #[test]
+    fn test_transaction_expiry()
+    {
+        let _ = pretty_env_logger::try_init();
 
+        let mut chain = BlockChain::open_temp();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+
+        let block_a = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Ok);
+
+        let expired = TransactionBuilder::new(
+            TransferBuilder::new(1, 1.0)
+                .add_output(other.get_address(), 2.0)
+                .build())
+            .add_input(&wallet, 3.0)
+            .valid_until(current_timestamp() - 1)
+            .build()
+            .unwrap();
+        assert_eq!(chain.push_transfer_queue(expired.clone()).is_err(), true);
+
+        let block_b = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_transfer(expired)
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(chain.add(&block_b).unwrap(), BlockChainAddResult::Invalid(
+            BlockValidationResult::Transaction(TransactionValidationResult::Expired)));
+
+        let valid_until = current_timestamp() + 60 * 1000;
+        let unexpired = TransactionBuilder::new(
+            TransferBuilder::new(1, 1.0)
+                .add_output(other.get_address(), 2.0)
+                .build())
+            .add_input(&wallet, 3.0)
+            .valid_until(valid_until)
+            .build()
+            .unwrap();
+        chain.push_transfer_queue(unexpired.clone()).unwrap();
+        assert_eq!(chain.get_next_transfers_in_queue(10).count(), 1);
+
+        // Kept while blocks are before its expiry
+        let block_c = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(chain.add(&block_c).unwrap(), BlockChainAddResult::Ok);
+        chain.remove_expired_from_transaction_queue(valid_until);
+        assert_eq!(chain.get_next_transfers_in_queue(10).count(), 1);
+
+        // And removed once past it
+        chain.remove_expired_from_transaction_queue(valid_until + 1);
+        assert_eq!(chain.get_next_transfers_in_queue(10).count(), 0);
+    }
+
+    #[test]
+    fn test_queue_size_limit()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let config = ChainConfig
+        {
+            max_queue_size: 3,
+            ..ChainConfig::default()
+        };
+
+        let mut chain = BlockChain::open_temp_with_config(config);
+        let other = PrivateWallet::open_temp(0).unwrap();
+        let wallets = (1..=5)
+            .map(|i| PrivateWallet::open_temp(i).unwrap())
+            .collect::<Vec<_>>();
+        for wallet in &wallets
+        {
+            let block = miner::mine_block(Block::new_blank(&mut chain, wallet).unwrap());
+            assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+        }
+
+        let transfer_from = |wallet: &PrivateWallet, fee: f32|
+        {
+            TransactionBuilder::new(
+                TransferBuilder::new(1, fee)
+                    .add_output(other.get_address(), 1.0)
+                    .build())
+                .add_input(wallet, 1.0 + fee)
+                .build()
+                .unwrap()
+        };
+
+        let cheapest = transfer_from(&wallets[0], 0.5);
+        let middle = transfer_from(&wallets[1], 1.0);
+        let best = transfer_from(&wallets[2], 1.5);
+        for transfer in [&cheapest, &middle, &best] {
+            chain.push_transfer_queue(transfer.clone()).unwrap();
+        }
+
+        // The cheapest is evicted to make room
+        let newcomer = transfer_from(&wallets[3], 2.0);
+        chain.push_transfer_queue(newcomer.clone()).unwrap();
+        assert_eq!(chain.get_next_transfers_in_queue(10).collect::<Vec<_>>(), 
+            [&newcomer, &best, &middle]);
+        assert_eq!(chain.find_transaction_in_queue(&cheapest.hash().unwrap()), None);
+
+        // Paying less then everything queued isn't enough to get in
+        let too_cheap = transfer_from(&wallets[4], 0.75);
+        assert!(chain.push_transfer_queue(too_cheap.clone()).is_err());
+        assert_eq!(chain.get_next_transfers_in_queue(10).collect::<Vec<_>>(), 
+            [&newcomer, &best, &middle]);
+        assert_eq!(chain.find_transaction_in_queue(&too_cheap.hash().unwrap()), None);
+    }
+
+}
@@ -12,6 +12,9 @@ use crate::wallet::WalletStatus;
 use crate::hash::Hash;
 
 use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+#[cfg(test)]
+use std::sync::atomic::Ordering;
 
 fn find_transaction<C>(transactions: &Vec<Transaction<C>>, transaction_id: &Hash)
         -> Option<Transaction<C>>
@@ -33,21 +36,63 @@ fn find_transaction<C>(transactions: &Vec<Transaction<C>>, transaction_id: &Hash
 impl BlockChain
 {
 
-    pub fn get_wallet_status_up_to_block(&mut self, to: u64, address: &Hash) -> WalletStatus
+    pub fn get_wallet_status_up_to_block(&self, to: u64, address: &Hash) -> WalletStatus
     {
-        let real_to = std::cmp::min(to + 1, self.blocks.next_top());
-        for block_id in (0..real_to).rev()
+        let real_to = std::cmp::min(to.saturating_add(1), self.blocks.next_top());
+        if real_to == 0 {
+            return WalletStatus::default();
+        }
+
+        // NOTE: Only the blocks since the cached status need checking. 
+        //       The cache isn't locked while scanning, so other readers 
+        //       can use it in the meantime.
+        let cached_or_none = self.wallet_status_cache.lock().unwrap()
+            .get(address)
+            .filter(|(cached_block_id, _)| *cached_block_id < real_to)
+            .cloned();
+        let scan_from = match &cached_or_none
+        {
+            Some((cached_block_id, _)) => cached_block_id + 1,
+            None => 0,
+        };
+
+        let mut status_or_none = None;
+        for block_id in (scan_from..real_to).rev()
         {
+            #[cfg(test)]
+            self.wallet_status_blocks_scanned.fetch_add(1, Ordering::Relaxed);
+
             let metadata = self.metadata.get(block_id).unwrap();
-            if metadata.wallets.contains_key(address) {
-                return metadata.wallets[address].clone();
+            if metadata.wallets.contains_key(address) 
+            {
+                status_or_none = Some(metadata.wallets[address].clone());
+                break;
             }
         }
 
-        WalletStatus::default()
+        let status = status_or_none
+            .or(cached_or_none.map(|(_, status)| status))
+            .unwrap_or_default();
+
+        let mut cache = self.wallet_status_cache.lock().unwrap();
+        let is_newer_then_cache = match cache.get(address)
+        {
+            Some((cached_block_id, _)) => *cached_block_id < real_to - 1,
+            None => true,
+        };
+        if is_newer_then_cache {
+            cache.insert(*address, real_to - 1, status.clone());
+        }
+
+        status
     }
 
-    pub fn get_wallet_status(&mut self, address: &Hash) -> WalletStatus
+    pub fn invalidate_wallet_status_cache(&mut self, from_block_id: u64)
+    {
+        self.wallet_status_cache.get_mut().unwrap().invalidate(from_block_id);
+    }
+
+    pub fn get_wallet_status(&self, address: &Hash) -> WalletStatus
     {
         if self.blocks.next_top() == 0 {
             WalletStatus::default()
@@ -56,6 +101,68 @@ impl BlockChain
         }
     }
 
+    #[cfg(test)]
+    fn wallet_status_blocks_scanned(&self) -> u64
+    {
+        self.wallet_status_blocks_scanned.load(Ordering::Relaxed)
+    }
+
+    pub fn get_wallet_statuses(&self, addresses: &[Hash]) -> Vec<WalletStatus>
+    {
+        let next_top = self.blocks.next_top();
+        let mut remaining = addresses.iter().cloned().collect::<HashSet<_>>();
+        let mut statuses = HashMap::new();
+        let cached = 
+        {
+            let cache = self.wallet_status_cache.lock().unwrap();
+            remaining.iter()
+                .filter_map(|address| cache.get(address).map(|entry| (*address, entry.clone())))
+                .collect::<HashMap<_, _>>()
+        };
+
+        // NOTE: Look for every address in a single pass from the top, 
+        //       stopping early at any cached status
+        for block_id in (0..next_top).rev()
+        {
+            if remaining.is_empty() {
+                break;
+            }
+
+            #[cfg(test)]
+            self.wallet_status_blocks_scanned.fetch_add(1, Ordering::Relaxed);
+
+            let metadata = self.metadata.get(block_id).unwrap();
+            for address in remaining.clone()
+            {
+                let status_or_none = metadata.wallets.get(&address).cloned().or_else(|| 
+                    match cached.get(&address)
+                    {
+                        Some((cached_block_id, status)) if *cached_block_id == block_id => 
+                            Some(status.clone()),
+                        _ => None,
+                    });
+
+                if let Some(status) = status_or_none 
+                {
+                    statuses.insert(address, status);
+                    remaining.remove(&address);
+                }
+            }
+        }
+
+        if next_top > 0
+        {
+            let mut cache = self.wallet_status_cache.lock().unwrap();
+            for (address, status) in &statuses {
+                cache.insert(*address, next_top - 1, status.clone());
+            }
+        }
+
+        addresses.iter()
+            .map(|address| statuses.get(address).cloned().unwrap_or_default())
+            .collect()
+    }
+
     pub fn last_page_update(&mut self, address: &Hash) -> Option<Block>
     {
         for block_id in (0..self.blocks.next_top()).rev()
@@ -69,6 +176,31 @@ impl BlockChain
         None
     }
 
+    pub fn known_sites(&mut self) -> HashMap<Hash, Option<u32>>
+    {
+        // NOTE: The latest page id is unknown if that block has been pruned
+        let mut sites = HashMap::new();
+        for block_id in (0..self.blocks.next_top()).rev()
+        {
+            let metadata = self.metadata.get(block_id).unwrap();
+            for site in metadata.page_updates.keys()
+            {
+                if sites.contains_key(site) {
+                    continue;
+                }
+
+                let latest_id = self.block(block_id).and_then(|block| block.pages
+                    .iter()
+                    .rev()
+                    .find(|page| &page.header.content.site == site)
+                    .map(|page| page.get_id()));
+                sites.insert(*site, latest_id);
+            }
+        }
+
+        sites
+    }
+
     pub fn get_page_updates(&mut self, address: &Hash) 
         -> Vec<Transaction<Page>>
     {
@@ -83,7 +215,13 @@ impl BlockChain
                 continue;
             }
 
-            let block = self.block(block_id).unwrap();
+            // NOTE: Updates before this have been pruned
+            let block_or_none = self.block(block_id);
+            if block_or_none.is_none() {
+                break;
+            }
+
+            let block = block_or_none.unwrap();
             for page in block.pages.iter().rev() 
             {
                 if page.get_from_addresses().contains(address) {
@@ -106,10 +244,15 @@ impl BlockChain
         // FIXME: Extremely slow, need to use metadata to 
         //        optimise this!
 
-        for block_id in 0..self.blocks.next_top() 
+        for block_id in self.blocks.pruned_below()..self.blocks.next_top() 
         {
             let block = self.block(block_id).unwrap();
 
+            let coinbase = find_transaction(&vec![block.coinbase.clone()], transaction_id);
+            if coinbase.is_some() {
+                return Some((TransactionVariant::Transfer(coinbase.unwrap()), block.clone()));
+            }
+
             let transfer = find_transaction(&block.transfers, transaction_id);
             if transfer.is_some() {
                 return Some((TransactionVariant::Transfer(transfer.unwrap()), block.clone()));
@@ -198,6 +341,43 @@ impl BlockChain
         transactions
     }
 
+    pub fn get_address_activity(&mut self, address: &Hash, from: u64, to: u64)
+        -> Vec<(TransactionVariant, u64)>
+    {
+        let mut activity = Vec::new();
+        let real_from = std::cmp::max(from, self.blocks.pruned_below());
+        let real_to = std::cmp::min(to.saturating_add(1), self.blocks.next_top());
+        for block_id in real_from..real_to
+        {
+            // NOTE: Bodies pruned or lost since are skipped
+            let block = match self.block(block_id)
+            {
+                Some(block) => block,
+                None => continue,
+            };
+
+            if !block.get_addresses_used().contains(address) {
+                continue;
+            }
+
+            for transfer in &block.transfers
+            {
+                if transfer.get_addresses_used().contains(address) {
+                    activity.push((TransactionVariant::Transfer(transfer.clone()), block_id));
+                }
+            }
+
+            for page in &block.pages
+            {
+                if page.get_addresses_used().contains(address) {
+                    activity.push((TransactionVariant::Page(page.clone()), block_id));
+                }
+            }
+        }
+
+        activity
+    }
+
 }
 
 #[cfg(test)]
@@ -226,12 +406,12 @@ mod tests
         assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Ok);
 
         // Create transfer
-        let transaction = chain.new_transfer(vec![(&wallet, 2.0)], vec![(other.get_address(), 2.0)], 0.0).unwrap();
+        let transaction = chain.new_transfer(vec![(&wallet, 2.0)], vec![(other.get_address(), 1.75)], 0.25).unwrap();
         chain.push_transfer_queue(transaction.clone()).expect("Valid");
 
         // Create page
         let page_data = CreatePageData::new("index.html".to_owned(), Vec::new());
-        let page = chain.new_page(&wallet, &DataUnit::CreatePage(page_data), 0.0).unwrap();
+        let page = chain.new_page(&wallet, &DataUnit::CreatePage(page_data), 0.25).unwrap();
         chain.push_page_queue(page.clone()).expect("Valid");
 
         // Add transactions to new block
@@ -252,7 +432,7 @@ mod tests
                    Some((TransactionVariant::Transfer(transaction.clone()), block_b.clone())));
 
         // Test 'push_transfer_queue'
-        let other_transaction = chain.new_transfer(vec![(&wallet, 2.0)], vec![(other.get_address(), 2.0)], 0.0).unwrap();
+        let other_transaction = chain.new_transfer(vec![(&wallet, 2.0)], vec![(other.get_address(), 1.75)], 0.25).unwrap();
         chain.push_transfer_queue(other_transaction.clone()).expect("Valid");
 
         // Test 'get_transaction_history'
@@ -264,5 +444,170 @@ mod tests
            ]);
     }
 
+    #[test]
+    fn test_known_sites()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let mut chain_a = BlockChain::open_in_memory();
+        let mut chain_b = BlockChain::open_in_memory();
+        let site_a = PrivateWallet::open_temp(0).unwrap();
+        let site_b = PrivateWallet::open_temp(1).unwrap();
+        let site_c = PrivateWallet::open_temp(2).unwrap();
+        let data = DataUnit::CreatePage(CreatePageData::new("index.html".to_owned(), Vec::new()));
+        assert_eq!(chain_a.known_sites().len(), 0);
+
+        let mine = |chain: &mut BlockChain, winner: &PrivateWallet, pages: Vec<Transaction<Page>>|
+        {
+            let mut builder = BlockBuilder::new(winner);
+            for page in pages {
+                builder = builder.add_page(page);
+            }
+
+            let block = miner::mine_block(builder.build(chain).unwrap());
+            assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+            block
+        };
+
+        let block_a = mine(&mut chain_a, &site_a, Vec::new());
+        assert_eq!(chain_b.add(&block_a).unwrap(), BlockChainAddResult::Ok);
+
+        // A shorter branch with its own site
+        mine(&mut chain_b, &site_c, Vec::new());
+        let page_c = chain_b.new_page(&site_c, &data, 0.25).unwrap();
+        mine(&mut chain_b, &site_c, vec![page_c.clone()]);
+        assert_eq!(chain_b.known_sites().get(&site_c.get_address()), Some(&Some(page_c.get_id())));
+
+        let mut branch = vec![mine(&mut chain_a, &site_b, Vec::new())];
+        let page_a = chain_a.new_page(&site_a, &data, 0.25).unwrap();
+        let page_b = chain_a.new_page(&site_b, &data, 0.25).unwrap();
+        branch.push(mine(&mut chain_a, &site_a, vec![page_a, page_b.clone()]));
+        let latest_page_a = chain_a.new_page(&site_a, &data, 0.25).unwrap();
+        branch.push(mine(&mut chain_a, &site_a, vec![latest_page_a.clone()]));
+
+        let mut expected = HashMap::new();
+        expected.insert(site_a.get_address(), Some(latest_page_a.get_id()));
+        expected.insert(site_b.get_address(), Some(page_b.get_id()));
+        assert_eq!(chain_a.known_sites(), expected);
+
+        // Sites only on the replaced branch are gone
+        chain_b.merge_branch(branch);
+        assert_eq!(chain_b.known_sites(), expected);
+    }
+
+    #[test]
+    fn test_address_activity()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let mut chain = BlockChain::open_temp();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+
+        let block_a = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Ok);
+
+        let sent = chain.new_transfer(vec![(&wallet, 4.0)], vec![(other.get_address(), 3.75)], 0.25).unwrap();
+        let block_b = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_transfer(sent.clone())
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(chain.add(&block_b).unwrap(), BlockChainAddResult::Ok);
+
+        let returned = chain.new_transfer(vec![(&other, 1.0)], vec![(wallet.get_address(), 0.75)], 0.25).unwrap();
+        let block_c = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_transfer(returned.clone())
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(chain.add(&block_c).unwrap(), BlockChainAddResult::Ok);
+
+        assert_eq!(chain.get_address_activity(&other.get_address(), 0, 2),
+           [
+               (TransactionVariant::Transfer(sent.clone()), 1),
+               (TransactionVariant::Transfer(returned.clone()), 2),
+           ]);
+        assert_eq!(chain.get_address_activity(&other.get_address(), 2, 10),
+           [(TransactionVariant::Transfer(returned), 2)]);
+        assert_eq!(chain.get_address_activity(&other.get_address(), 0, 0).len(), 0);
+        assert_eq!(chain.get_address_activity(&other.get_address(), 2, u64::MAX).len(), 1);
+    }
+
+    #[test]
+    fn test_wallet_status_cache()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let mut chain = BlockChain::open_temp();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        for _ in 0..5
+        {
+            let block = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+            assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+        }
+
+        let reward = chain.top().unwrap().calculate_reward();
+        assert_eq!(chain.get_wallet_status(&wallet.get_address()).balance, 5.0 * reward);
+
+        // Only the new block is checked on the next query
+        let block = miner::mine_block(Block::new_blank(&mut chain, &other).unwrap());
+        assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+
+        let scanned_before = chain.wallet_status_blocks_scanned();
+        assert_eq!(chain.get_wallet_status(&wallet.get_address()).balance, 5.0 * reward);
+        assert_eq!(chain.wallet_status_blocks_scanned() - scanned_before, 1);
+
+        // Nothing new to check
+        let scanned_before = chain.wallet_status_blocks_scanned();
+        assert_eq!(chain.get_wallet_status(&wallet.get_address()).balance, 5.0 * reward);
+        assert_eq!(chain.wallet_status_blocks_scanned() - scanned_before, 0);
+
+        // Reorg below the cached height
+        let mut chain_b = BlockChain::open_temp();
+        for block_id in 0..3 {
+            chain_b.add(&chain.block(block_id).unwrap()).unwrap();
+        }
+
+        let mut branch = Vec::new();
+        for _ in 0..5
+        {
+            let block = miner::mine_block(Block::new_blank(&mut chain_b, &other).unwrap());
+            assert_eq!(chain_b.add(&block).unwrap(), BlockChainAddResult::Ok);
+            branch.push(block);
+        }
+
+        chain.merge_branch(branch);
+        assert_eq!(chain.get_wallet_status(&wallet.get_address()).balance, 3.0 * reward);
+        assert_eq!(chain.get_wallet_status(&other.get_address()).balance, 5.0 * reward);
+    }
+
+    #[test]
+    fn test_wallet_statuses()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let mut chain = BlockChain::open_temp();
+        let wallets = (0..3)
+            .map(|i| PrivateWallet::open_temp(i).unwrap())
+            .collect::<Vec<_>>();
+        for i in 0..6
+        {
+            let block = miner::mine_block(Block::new_blank(&mut chain, &wallets[i % 2]).unwrap());
+            assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+        }
+
+        // Every address is found in one pass over the chain
+        let addresses = wallets.iter().map(|x| x.get_address()).collect::<Vec<_>>();
+        let scanned_before = chain.wallet_status_blocks_scanned();
+        let statuses = chain.get_wallet_statuses(&addresses);
+        assert_eq!(chain.wallet_status_blocks_scanned() - scanned_before, 6);
+
+        assert_eq!(statuses.len(), 3);
+        for (address, status) in addresses.iter().zip(&statuses) {
+            assert_eq!(&chain.get_wallet_status(address), status);
+        }
+        assert_eq!(statuses[2], WalletStatus::default());
+    }
+
 }
 
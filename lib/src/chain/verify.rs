@@ -0,0 +1,216 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use super::BlockChain;
+use super::storage::chunk_start;
+use crate::block::validate::BlockValidationResult;
+use crate::transaction::{Transaction, TransactionContent, TransactionValidationResult};
+use crate::wallet::WalletStatus;
+use crate::wallet::signature_scheme::SignatureSchemeKind;
+use crate::config::ChainConfig;
+use crate::error::ErrorMessage;
+use crate::hash::Hash;
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::error::Error;
+
+#[derive(Debug, PartialEq)]
+pub struct ChainValidationFailure
+{
+    pub block_id: u64,
+    pub transaction_id: Option<Hash>,
+    pub result: BlockValidationResult,
+}
+
+impl std::fmt::Display for ChainValidationFailure
+{
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        match &self.transaction_id
+        {
+            Some(id) => write!(f, "Block {}, transaction {}: {}", self.block_id, id, self.result),
+            None => write!(f, "Block {}: {}", self.block_id, self.result),
+        }
+    }
+
+}
+
+fn find_invalid_transaction<C>(transactions: &[Transaction<C>], scheme: SignatureSchemeKind)
+        -> Result<Option<(Hash, TransactionValidationResult)>, Box<dyn Error>>
+    where C: TransactionContent + Serialize
+{
+    for transaction in transactions
+    {
+        match transaction.validate_content(scheme)?
+        {
+            TransactionValidationResult::Ok => {},
+            result => return Ok(Some((transaction.hash()?, result))),
+        }
+    }
+
+    Ok(None)
+}
+
+impl BlockChain
+{
+
+    // Find the first block that's missing, or doesn't link to the one 
+    // before it. This doesn't validate the blocks themselves.
+    // NOTE: Every header and metadata entry is checked, as the rest of 
+    //       the chain expects them to be there. Block bodies are only 
+    //       checked in the last chunk, and anywhere the storages disagree 
+    //       on the top, where an interrupted write would leave them.
+    pub fn first_corrupt_block(&mut self) -> Option<u64>
+    {
+        let next_tops = [
+            self.metadata.next_top(),
+            self.headers.next_top(),
+            self.blocks.next_top(),
+        ];
+        let next_top = *next_tops.iter().max().unwrap();
+        if next_top == 0 {
+            return None;
+        }
+
+        let check_bodies_from = std::cmp::min(*next_tops.iter().min().unwrap(), chunk_start(next_top - 1));
+        let mut prev_hash_or_none = None;
+        for block_id in 0..next_top
+        {
+            let header = match self.headers.get(block_id)
+            {
+                Some(header) if header.block_id == block_id => header,
+                _ => return Some(block_id),
+            };
+
+            let links_to_prev = match &prev_hash_or_none
+            {
+                Some(prev_hash) => &header.prev_hash == prev_hash,
+                None => true,
+            };
+
+            let has_body = block_id < check_bodies_from || self.is_pruned(block_id) || 
+                matches!(self.blocks.get(block_id), Some(block) if block.header == header);
+            if !links_to_prev || !has_body || self.metadata.get(block_id).is_none() {
+                return Some(block_id);
+            }
+
+            prev_hash_or_none = header.hash().ok();
+        }
+
+        None
+    }
+
+    pub fn validate_all(path: &PathBuf, config: ChainConfig) 
+        -> Result<Option<ChainValidationFailure>, Box<dyn Error>>
+    {
+        let mut chain = BlockChain::open_with_config(path, config)?;
+        chain.validate_chain()
+    }
+
+    pub fn validate_chain(&mut self) 
+        -> Result<Option<ChainValidationFailure>, Box<dyn Error>>
+    {
+        if self.blocks.pruned_below() > 0 {
+            return Err(ErrorMessage::new("Can't validate a pruned chain"));
+        }
+
+        let signature_scheme = self.config().signature_scheme;
+        let mut wallets = HashMap::<Hash, WalletStatus>::new();
+        let mut page_updates = HashMap::<Hash, u64>::new();
+        let mut last_block_or_none = None;
+        for block_id in 0..self.blocks.next_top()
+        {
+            let failure = |transaction_id, result| Ok(Some(ChainValidationFailure
+            {
+                block_id,
+                transaction_id,
+                result,
+            }));
+
+            let block_or_none = self.block(block_id);
+            if block_or_none.is_none() {
+                return failure(None, BlockValidationResult::NotNextBlock);
+            }
+
+            // NOTE: Check each transaction first, so we can 
+            //       tell exactly which one is at fault.
+            let block = block_or_none.unwrap();
+            let invalid_transfer = find_invalid_transaction(&block.transfers, signature_scheme)?;
+            let invalid_page = find_invalid_transaction(&block.pages, signature_scheme)?;
+            if let Some((id, result)) = invalid_transfer.or(invalid_page) {
+                return failure(Some(id), BlockValidationResult::Transaction(result));
+            }
+
+            // NOTE: The whole chain is validated as one branch from genesis
+            match self.validate_block_in_branch(&block, last_block_or_none.as_ref(), &[], 0,
+                &mut wallets, &mut page_updates)?
+            {
+                BlockValidationResult::Ok => {},
+                result => return failure(None, result),
+            }
+
+            last_block_or_none = Some(block);
+        }
+
+        Ok(None)
+    }
+
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::block::builder::BlockBuilder;
+    use crate::chain::BlockChainAddResult;
+    use crate::hash::Signature;
+    use crate::wallet::Wallet;
+    use crate::wallet::private_wallet::PrivateWallet;
+    use crate::miner;
+
+    #[test]
+    fn test_validate_all()
+    {
+        let mut chain = BlockChain::open_temp();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+
+        let block_a = miner::mine_block(BlockBuilder::new(&wallet).build(&mut chain).unwrap());
+        assert_eq!(chain.add(&block_a).unwrap(), BlockChainAddResult::Ok);
+
+        let transfer = chain.new_transfer(vec![(&wallet, 3.0)], vec![(other.get_address(), 2.0)], 1.0).unwrap();
+        let block_b = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_transfer(transfer.clone())
+            .build(&mut chain)
+            .unwrap());
+        assert_eq!(chain.add(&block_b).unwrap(), BlockChainAddResult::Ok);
+
+        let block_c = miner::mine_block(BlockBuilder::new(&other).build(&mut chain).unwrap());
+        assert_eq!(chain.add(&block_c).unwrap(), BlockChainAddResult::Ok);
+        assert_eq!(chain.validate_chain().unwrap(), None);
+
+        // Tamper with the transfer's signature on disk
+        let mut tampered = block_b.clone();
+        for signature in tampered.transfers[0].signatures.values_mut() {
+            *signature = Signature::empty();
+        }
+        chain.blocks.store(1, tampered);
+
+        // NOTE: Opening a second chain on the same path removes it when 
+        //       dropped in tests, so this must be the last check.
+        let failure = BlockChain::validate_all(
+            chain.blocks.path().unwrap(), chain.config().clone()).unwrap().unwrap();
+        assert_eq!(failure.block_id, 1);
+        assert_eq!(failure.transaction_id, Some(transfer.hash().unwrap()));
+        assert!(matches!(failure.result, BlockValidationResult::Transaction(_)));
+    }
+
+}
+
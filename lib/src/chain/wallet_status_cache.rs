@@ -0,0 +1,108 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use crate::wallet::WalletStatus;
+use crate::hash::Hash;
+
+use std::collections::HashMap;
+
+// Last known status of each wallet, and the block it's from. Once full,
+// the status from the lowest block is forgotten to make room, as it
+// would take the most blocks to bring up to date anyway.
+pub struct WalletStatusCache
+{
+    entries: HashMap<Hash, (u64, WalletStatus)>,
+    max_size: usize,
+}
+
+impl WalletStatusCache
+{
+
+    pub fn new(max_size: usize) -> Self
+    {
+        Self
+        {
+            entries: HashMap::new(),
+            max_size,
+        }
+    }
+
+    pub fn get(&self, address: &Hash) -> Option<&(u64, WalletStatus)>
+    {
+        self.entries.get(address)
+    }
+
+    pub fn insert(&mut self, address: Hash, block_id: u64, status: WalletStatus)
+    {
+        if !self.entries.contains_key(&address) && self.entries.len() >= self.max_size
+        {
+            let lowest_or_none = self.entries
+                .iter()
+                .min_by_key(|(_, (cached_block_id, _))| *cached_block_id)
+                .map(|(address, _)| *address);
+            if let Some(lowest) = lowest_or_none {
+                self.entries.remove(&lowest);
+            }
+        }
+
+        if self.max_size > 0 {
+            self.entries.insert(address, (block_id, status));
+        }
+    }
+
+    // Forget every status from `from_block_id` or above
+    pub fn invalidate(&mut self, from_block_id: u64)
+    {
+        self.entries.retain(|_, (cached_block_id, _)| *cached_block_id < from_block_id);
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize
+    {
+        self.entries.len()
+    }
+
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+
+    fn address(n: u8) -> Hash
+    {
+        let mut bytes = [0u8; 32];
+        bytes[0] = n;
+        Hash::from(&bytes)
+    }
+
+    #[test]
+    fn test_wallet_status_cache_size()
+    {
+        let mut cache = WalletStatusCache::new(2);
+        cache.insert(address(0), 5, WalletStatus::default());
+        cache.insert(address(1), 3, WalletStatus::default());
+
+        // Updating an address already cached doesn't evict anything
+        cache.insert(address(0), 6, WalletStatus::default());
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&address(0)).map(|(block_id, _)| *block_id), Some(6));
+
+        // The status from the lowest block is forgotten to make room
+        cache.insert(address(2), 4, WalletStatus::default());
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&address(1)).is_none());
+        assert!(cache.get(&address(0)).is_some());
+        assert!(cache.get(&address(2)).is_some());
+
+        cache.invalidate(5);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(&address(2)).is_some());
+    }
+
+}
+
@@ -4,12 +4,186 @@
  * SPDX-License-Identifier: BSD-2-Clause
  */
 
+use crate::hash::Hash;
+use crate::wallet::signature_scheme::SignatureSchemeKind;
+use crate::error::ErrorMessage;
+
+use std::error::Error;
 
 pub const BLOCK_TIME: u64 = 10 * 1000; // 10 second blocks
 // pub const BLOCK_TIME: u64 = 10 * 60 * 1000; // 10 minute blocks
 pub const BLOCK_SAMPLE_SIZE: u64 = 100;
+pub const BRANCH_PRUNE_DEPTH: u64 = 10;
+pub const BRANCH_PRUNE_INTERVAL: u64 = 10;
+pub const MAX_BLOCK_SIZE: usize = 1000 * 1000; // 1MB
 pub const PAGE_CHUNK_SIZE: usize = 1000 * 1000; // 1MB
+pub const MAX_PAGE_DATA_LENGTH: u32 = 10 * 1000 * 1000; // 10MB
+pub const MIN_TRANSFER_FEE_PER_BYTE: f32 = 0.0001;
+pub const MIN_PAGE_FEE_PER_CHUNK: f32 = 0.01;
+pub const MAX_QUEUE_SIZE: usize = 10 * 1000;
+pub const MIN_REPLACEMENT_FEE_RATE_INCREASE: f32 = 0.1; // 10%
 
-pub const PUB_KEY_LEN: usize = 256;
+// NOTE: Smaller keys are padded to the full length when serialized
+pub const WALLET_KEY_BITS: usize = 2048;
+pub const MIN_WALLET_KEY_BITS: usize = 1024;
+pub const PUB_KEY_LEN: usize = WALLET_KEY_BITS / 8;
 pub const HASH_LEN: usize = 32;
 
+pub const SIGNATURE_CACHE_SIZE: usize = 10 * 1000;
+pub const WALLET_STATUS_CACHE_SIZE: usize = 10 * 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetargetMode
+{
+    // Retarget once every sample of blocks, from the time it took
+    Window,
+
+    // Retarget every block, from a decayed average of block times
+    Ema,
+}
+
+// NOTE: There's no chain id or halving interval here. Nodes only ever 
+//       join the one network, and the block reward is a flat amount, so 
+//       neither would change anything. Add them with the rules they drive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainConfig
+{
+    // Target time between blocks, in milliseconds
+    pub block_time: u64,
+    pub block_sample_size: u64,
+
+    // Discard block bodies this many blocks below the top, or keep 
+    // everything if not set. Headers and metadata are always kept.
+    pub prune_depth: Option<u64>,
+
+    // Minimum number of blocks between updates to the same site, or 
+    // no limit if not set.
+    pub page_update_interval: Option<u64>,
+
+    // Number of blocks before a block reward can be spent, or 
+    // straight away if not set.
+    pub reward_maturity: Option<u64>,
+
+    // Drop unmerged branches whose top is this many blocks below ours. 
+    // This must be more then the deepest reorg we want to follow.
+    pub branch_prune_depth: u64,
+
+    // Prune branches every time this many blocks are added, or only 
+    // when asked to if not set.
+    pub branch_prune_interval: Option<u64>,
+
+    // Fraction of each transaction's fee that's destroyed, rather 
+    // then paid to the block winner.
+    pub fee_burn_ratio: f32,
+
+    // Largest serialized block size, in bytes, to build when mining.
+    pub max_block_size: usize,
+
+    // How the target is adjusted to keep blocks on time.
+    pub retarget_mode: RetargetMode,
+
+    // Never replace more then this many of our blocks when merging a 
+    // branch, or follow any longer branch if not set.
+    pub max_reorg_depth: Option<u64>,
+
+    // Size in bytes of each chunk page data is split into. Every page 
+    // must be built with this size to be valid on this chain.
+    pub page_chunk_size: usize,
+
+    // Outputs paid by the genesis block's coinbase, on top of its 
+    // reward, to give the chain an initial distribution.
+    pub premine: Vec<(Hash, f32)>,
+
+    // Most transfers, and separately pages, kept waiting to be mined. 
+    // Once full, the lowest fee rates are evicted to make room.
+    pub max_queue_size: usize,
+
+    // How transaction inputs are signed. Every wallet spending on 
+    // this chain must use keys of this scheme.
+    pub signature_scheme: SignatureSchemeKind,
+
+    // Never let the target get easier then this difficulty, so blocks 
+    // can't be mined instantly on a quiet network. If not set, the 
+    // target is only limited by the min target.
+    pub min_difficulty: Option<f64>,
+
+    // Blocks from this id onwards must have their transactions in 
+    // canonical order. Those below it were mined before the rule.
+    pub transaction_order_height: u64,
+}
+
+impl Default for ChainConfig
+{
+
+    fn default() -> Self
+    {
+        Self
+        {
+            block_time: BLOCK_TIME,
+            block_sample_size: BLOCK_SAMPLE_SIZE,
+            prune_depth: None,
+            page_update_interval: None,
+            reward_maturity: None,
+            branch_prune_depth: BRANCH_PRUNE_DEPTH,
+            branch_prune_interval: Some(BRANCH_PRUNE_INTERVAL),
+            fee_burn_ratio: 0.0,
+            max_block_size: MAX_BLOCK_SIZE,
+            retarget_mode: RetargetMode::Window,
+            max_reorg_depth: None,
+            page_chunk_size: PAGE_CHUNK_SIZE,
+            premine: Vec::new(),
+            max_queue_size: MAX_QUEUE_SIZE,
+            signature_scheme: SignatureSchemeKind::Rsa,
+            min_difficulty: None,
+            transaction_order_height: 0,
+        }
+    }
+
+}
+
+impl ChainConfig
+{
+
+    // Check the values make sense together, before a chain is opened with them
+    pub fn validate(&self) -> Result<(), Box<dyn Error>>
+    {
+        // NOTE: This also rejects NaN, which fails every comparison
+        if !(0.0..=1.0).contains(&self.fee_burn_ratio) 
+        {
+            return Err(ErrorMessage::new(&format!(
+                "Fee burn ratio must be between 0 and 1, got {}", self.fee_burn_ratio)));
+        }
+
+        // NOTE: Both are divided by when calculating the target
+        if self.block_time == 0 || self.block_sample_size == 0 {
+            return Err(ErrorMessage::new("Block time and sample size must be more then zero"));
+        }
+
+        if self.page_chunk_size == 0 {
+            return Err(ErrorMessage::new("Page chunk size must be more then zero"));
+        }
+
+        if let Some(min_difficulty) = self.min_difficulty
+        {
+            if !(min_difficulty >= 1.0 && min_difficulty.is_finite()) 
+            {
+                return Err(ErrorMessage::new(&format!(
+                    "Min difficulty must be at least 1, got {}", min_difficulty)));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Number of blocks between the start and end of a target sample
+    pub fn sample_span(&self) -> u64
+    {
+        match self.retarget_mode
+        {
+            RetargetMode::Window => self.block_sample_size,
+            RetargetMode::Ema => 1,
+        }
+    }
+
+}
+
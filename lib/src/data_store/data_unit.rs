@@ -5,8 +5,8 @@
  */
 
 use super::page::CreatePageData;
-use crate::config::PAGE_CHUNK_SIZE;
 use crate::hash::Hash;
+use crate::error::ErrorMessage;
 use serde::{Serialize, Deserialize};
 use sha2::{Sha256, Digest};
 use std::error::Error;
@@ -20,15 +20,20 @@ pub enum DataUnit
 impl DataUnit
 {
 
-    pub fn chunks(&self) -> Result<Vec<(Vec<u8>, Hash)>, Box<dyn Error>>
+    pub fn chunks(&self, chunk_size: usize) -> Result<Vec<(Vec<u8>, Hash)>, Box<dyn Error>>
     {
+        // NOTE: The chunks would never advance through the data
+        if chunk_size == 0 {
+            return Err(ErrorMessage::new("Chunk size must be more then zero"));
+        }
+
         let data = bincode::serialize(self)?;
         
         let mut hashes = Vec::new();
         let mut chunk_start: usize = 0;
         while chunk_start < data.len()
         {
-            let chunk_end = std::cmp::min(chunk_start + PAGE_CHUNK_SIZE, data.len());
+            let chunk_end = std::cmp::min(chunk_start + chunk_size, data.len());
             let chunk = &data[chunk_start..chunk_end];
             
             let mut hasher = Sha256::new();
@@ -43,9 +48,9 @@ impl DataUnit
         Ok(hashes)
     }
 
-    pub fn hashes(&self) -> Result<Vec<Hash>, Box<dyn Error>>
+    pub fn hashes(&self, chunk_size: usize) -> Result<Vec<Hash>, Box<dyn Error>>
     {
-        Ok(self.chunks()?
+        Ok(self.chunks(chunk_size)?
             .iter()
             .map(|(_, hash)| *hash)
             .collect())
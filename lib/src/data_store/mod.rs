@@ -55,10 +55,10 @@ impl DataStore
         Ok(())
     }
 
-    pub fn store_data_unit(&self, data_unit: &DataUnit)
+    pub fn store_data_unit(&self, data_unit: &DataUnit, chunk_size: usize)
         -> Result<(), Box<dyn Error>>
     {
-        for (chunk, hash) in data_unit.chunks()? {
+        for (chunk, hash) in data_unit.chunks(chunk_size)? {
             self.store(&hash, &chunk)?;
         }
         Ok(())
@@ -141,6 +141,7 @@ mod tests
         let test_data = bincode::serialize(&test_unit).unwrap();
         data_store.store(&Hash::empty(), &test_data).unwrap();
         assert_eq!(data_store.get(&Hash::empty()).unwrap(), test_data);
+        assert!(test_unit.chunks(0).is_err());
     }
 
 }
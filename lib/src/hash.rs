@@ -12,7 +12,7 @@ use std::fmt;
 pub type Signature = HashData<PUB_KEY_LEN>;
 pub type Hash = HashData<HASH_LEN>;
 
-#[derive(Hash, PartialEq, Eq, Clone, Copy)]
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct HashData<const N: usize>
 {
     data: [u8; N]
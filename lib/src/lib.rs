@@ -37,5 +37,6 @@ pub mod data_store;
 pub mod merkle_tree;
 pub mod miner;
 pub mod hash;
+pub mod address;
 pub mod error;
 
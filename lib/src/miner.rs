@@ -4,15 +4,100 @@
  * SPDX-License-Identifier: BSD-2-Clause
  */
 
-use crate::block::Block;
+use crate::block::{Block, current_timestamp};
 use crate::block::validate::BlockValidationResult;
+use crate::block::target::{is_target_satisfiable, needs_extra_nonce};
 
-pub fn mine_block(mut block: Block) -> Block
+fn next_nonce_within(block: &mut Block, max_pow: u64)
 {
-    while block.validate_pow().unwrap() != BlockValidationResult::Ok {
+    if block.header.pow < max_pow 
+    {
         block.header.pow += 1;
+        return;
+    }
+
+    // NOTE: Every pow has been tried, so change the block's hash by 
+    //       rolling the timestamp, and start again.
+    block.header.timestamp = std::cmp::max(block.header.timestamp + 1, current_timestamp());
+    block.header.pow = 0;
+}
+
+pub fn next_nonce(block: &mut Block)
+{
+    next_nonce_within(block, u64::MAX);
+}
+
+fn mine_block_within(mut block: Block, max_pow: u64) -> Block
+{
+    if !is_target_satisfiable(&block.header.target) 
+    {
+        warn!("Target for block {} can never be met", block.header.block_id);
+        return block;
+    }
+
+    if needs_extra_nonce(&block.header.target) {
+        warn!("Block {} will likely need its timestamp rolled to be mined", block.header.block_id);
+    }
+
+    while block.validate_pow().unwrap() != BlockValidationResult::Ok {
+        next_nonce_within(&mut block, max_pow);
     }
 
     block
 }
 
+pub fn mine_block(block: Block) -> Block
+{
+    mine_block_within(block, u64::MAX)
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::chain::BlockChain;
+    use crate::wallet::private_wallet::PrivateWallet;
+
+    #[test]
+    fn test_roll_timestamp()
+    {
+        let mut chain = BlockChain::open_in_memory();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let max_pow = 3;
+
+        // Find a block that can't be solved within the pow range
+        let mut block = Block::new_blank(&mut chain, &wallet).unwrap();
+        let unsolvable = |block: &Block| (0..=max_pow).all(|pow|
+        {
+            let mut block = block.clone();
+            block.header.pow = pow;
+            block.validate_pow().unwrap() != BlockValidationResult::Ok
+        });
+        while !unsolvable(&block) {
+            block.header.timestamp -= 1;
+        }
+
+        let timestamp = block.header.timestamp;
+        let mined = mine_block_within(block, max_pow);
+        assert_eq!(mined.validate_pow().unwrap(), BlockValidationResult::Ok);
+        assert!(mined.header.timestamp > timestamp);
+        assert!(mined.header.pow <= max_pow);
+    }
+
+    #[test]
+    fn test_pow_wraps()
+    {
+        let mut chain = BlockChain::open_in_memory();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+
+        let mut block = Block::new_blank(&mut chain, &wallet).unwrap();
+        block.header.pow = u64::MAX;
+        let timestamp = block.header.timestamp;
+        next_nonce(&mut block);
+        assert_eq!(block.header.pow, 0);
+        assert!(block.header.timestamp > timestamp);
+    }
+
+}
+
@@ -9,22 +9,34 @@ use crate::transaction::{Transaction, TransactionVariant};
 use crate::transaction::page::Page;
 use crate::block::Block;
 use crate::data_store::data_unit::DataUnit;
+use crate::hash::Hash;
 use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Command
 {
     Exit,
     Balance(Vec<u8>),
+    BalanceMulti(Vec<Vec<u8>>),
+    BalanceAtHeight(Vec<u8>, u64),
     Send(Vec<(Vec<u8>, f32)>, Vec<(Vec<u8>, f32)>, f32),
+    SendBatch(Vec<(Vec<u8>, Vec<u8>, f32, f32)>),
     UpdatePage(Vec<u8>, String, Vec<u8>),
     TransactionInfo(Vec<u8>),
     TransactionHistory(Vec<u8>),
     Blocks(u64, u64),
+    BlocksByTime(u128, u128),
     TopBlock,
     PageUpdates(Vec<u8>),
     PageData(Vec<u8>),
     Statistics,
+    AddressActivity(Vec<u8>, u64, u64),
+    Status,
+    NetworkInfo,
+    BlockDetail(u64),
+    EstimateConfirmationTime(f32),
+    MempoolContents(u64, Option<u64>),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -35,18 +47,124 @@ pub struct Statistics
     pub replication: f64,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NodeStatus
+{
+    pub chain_height: Option<u64>,
+    pub best_peer_height: Option<u64>,
+    pub sync_progress: f64,
+    pub branch_count: usize,
+    pub peer_count: usize,
+    pub mempool_size: usize,
+    pub mining: bool,
+
+    // Totals of each packet type by name
+    pub packets_sent: BTreeMap<String, u64>,
+    pub packets_received: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NetworkInfo
+{
+    pub difficulty: f64,
+
+    // Estimated hashes per second, or none if there's not enough 
+    // blocks to estimate it from.
+    pub hash_rate: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConfirmationTime
+{
+    pub blocks: u64,
+    pub seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MempoolEntry
+{
+    pub hash: Hash,
+    pub transaction: TransactionVariant,
+    pub fee: f32,
+    pub size: usize,
+    pub fee_rate: f32,
+}
+
+// NOTE: Only a page of the entries is sent, `total` is the number 
+//       of transactions in the whole mempool.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MempoolContents
+{
+    pub total: usize,
+    pub entries: Vec<MempoolEntry>,
+}
+
+// NOTE: Amounts are paired with the bech32 encoded address they're from or to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TransferDetail
+{
+    pub hash: Hash,
+    pub id: u32,
+    pub inputs: Vec<(String, f32)>,
+    pub outputs: Vec<(String, f32)>,
+    pub fee: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PageDetail
+{
+    pub hash: Hash,
+    pub id: u32,
+    pub site: String,
+    pub inputs: Vec<(String, f32)>,
+    pub data_hashes: Vec<Hash>,
+    pub data_length: u32,
+    pub fee: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockDetail
+{
+    pub block_id: u64,
+    pub hash: Hash,
+    pub prev_hash: Hash,
+    pub timestamp: u128,
+    pub reward_to: String,
+    pub reward: f32,
+    pub difficulty: f64,
+    pub pow: u64,
+
+    // The root in the header, and the one calculated from the
+    // transactions. These only differ if the block is corrupt.
+    pub merkle_root: Hash,
+    pub calculated_merkle_root: Hash,
+
+    pub coinbase: TransferDetail,
+    pub transfers: Vec<TransferDetail>,
+    pub pages: Vec<PageDetail>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Response
 {
     Exit,
     WalletStatus(WalletStatus),
+    WalletStatuses(Vec<WalletStatus>),
     Sent(Vec<u8>),
+    SentBatch(Vec<Result<Vec<u8>, String>>),
     TransactionInfo(TransactionVariant, Option<Block>),
     TransactionHistory(Vec<(TransactionVariant, Option<Block>)>),
     Blocks(Vec<Block>),
     PageUpdates(Vec<Transaction<Page>>),
     PageData(DataUnit),
     Statistics(Statistics),
+    AddressActivity(Vec<(TransactionVariant, u64)>),
+    Status(NodeStatus),
+    NetworkInfo(NetworkInfo),
+    BlockDetail(BlockDetail),
+    ConfirmationTime(ConfirmationTime),
+    MempoolContents(MempoolContents),
+    NotFound,
     Failed,
 }
 
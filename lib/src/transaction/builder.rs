@@ -17,6 +17,7 @@ pub struct TransactionBuilder<'a, C>
 {
     content: C,
     inputs: Vec<(&'a PrivateWallet, Input)>,
+    valid_until: Option<u128>,
 }
 
 impl<'a, C> TransactionBuilder<'a, C>
@@ -29,6 +30,7 @@ impl<'a, C> TransactionBuilder<'a, C>
         {
             content,
             inputs: Vec::new(),
+            valid_until: None,
         }
     }
 
@@ -45,12 +47,19 @@ impl<'a, C> TransactionBuilder<'a, C>
         self
     }
 
+    pub fn valid_until(mut self, timestamp: u128) -> Self
+    {
+        self.valid_until = Some(timestamp);
+        self
+    }
+
     pub fn build(self) -> Result<Transaction<C>, Box<dyn Error>>
     {
         let header = TransactionHeader
         {
             content: self.content,
             inputs: self.inputs.iter().map(|x| x.1.clone()).collect::<Vec<_>>(),
+            valid_until: self.valid_until,
         };
 
         let header_hash = header.hash()?;
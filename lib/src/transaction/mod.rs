@@ -7,11 +7,18 @@
 pub mod transfer;
 pub mod page;
 pub mod builder;
+pub mod signature_cache;
 use transfer::Transfer;
 use page::Page;
-use crate::wallet::WalletStatus;
+use crate::wallet::{WalletStatus, address_from_public_key};
 use crate::wallet::public_wallet::{PublicWallet, WalletValidationResult};
+use crate::wallet::signature_scheme::SignatureSchemeKind;
+use crate::chain::BlockChain;
 use crate::hash::{Hash, Signature};
+use crate::address::encode_address;
+use crate::config::PUB_KEY_LEN;
+use crate::error::ErrorMessage;
+use signature_cache::{SignatureCache, with_signature_cache};
 
 use serde::{Serialize, Deserialize};
 use sha2::{Sha256, Digest};
@@ -25,6 +32,9 @@ pub enum TransactionValidationResult
 {
     Ok,
     Negative,
+    Expired,
+    Fee,
+    Balance(Hash),
     Wallet(WalletValidationResult),
 }
 
@@ -33,6 +43,8 @@ pub trait TransactionContent
 
     fn get_fee(&self) -> f32;
 
+    fn min_fee(&self, size_in_bytes: usize) -> f32;
+
     fn validate(&self, inputs: &Vec<Input>)
         -> Result<TransactionValidationResult, Box<dyn Error>>;
 
@@ -60,6 +72,7 @@ pub struct TransactionHeader<C>
 {
     pub content: C,
     pub inputs: Vec<Input>,
+    pub valid_until: Option<u128>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -86,12 +99,28 @@ impl std::fmt::Display for TransactionValidationResult
         {
             TransactionValidationResult::Ok => write!(f, "Ok"),
             TransactionValidationResult::Negative => write!(f, "Can't have negitive transfer amounts"),
+            TransactionValidationResult::Expired => write!(f, "Transaction has expired"),
+            TransactionValidationResult::Fee => write!(f, "Fee is below the minimum"),
+            TransactionValidationResult::Balance(address) => write!(f, "{} has insufficient balance", encode_address(address)),
             TransactionValidationResult::Wallet(wallet) => write!(f, "{}", wallet),
         }
     }
 
 }
 
+// Highest fee rate first, so the best paying transactions are mined first
+pub fn sorted_by_fee_rate<C>(transactions: Vec<Transaction<C>>) -> Vec<Transaction<C>>
+    where C: TransactionContent + Serialize
+{
+    let mut rated = transactions
+        .into_iter()
+        .map(|transaction| (transaction.fee_rate(), transaction))
+        .collect::<Vec<_>>();
+
+    rated.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    rated.into_iter().map(|(_, transaction)| transaction).collect()
+}
+
 impl<C> TransactionHeader<C>
     where C: TransactionContent + Serialize
 {
@@ -113,11 +142,7 @@ impl Input
 
     pub fn get_address(&self) -> Hash
     {
-        let mut hasher = Sha256::new();
-        hasher.update(&self.from);
-
-        let hash = hasher.finalize().to_vec();
-        Hash::from(&hash)
+        address_from_public_key(&self.from)
     }
 
 }
@@ -135,11 +160,46 @@ impl<C> Transaction<C>
         }
     }
 
+    pub fn from_parts(header: TransactionHeader<C>, signature: &[u8],
+                      e: [u8; 3], public_key: Signature)
+        -> Result<Self, Box<dyn Error>>
+    {
+        if signature.len() > PUB_KEY_LEN {
+            return Err(ErrorMessage::new("Signature is too long"));
+        }
+
+        // NOTE: Only one signature is given, so every input must be 
+        //       from the signing wallet
+        if header.inputs.is_empty() {
+            return Err(ErrorMessage::new("Transaction has no inputs"));
+        }
+        if header.inputs.iter().any(|x| x.from != public_key || x.e != e) {
+            return Err(ErrorMessage::new("Transaction has inputs not owned by this key"));
+        }
+
+        let hash = header.hash()?;
+        let wallet = PublicWallet::from_public_key_e(public_key, e);
+        let wallet_result = wallet.verify(hash.data(), signature)?;
+        if wallet_result != WalletValidationResult::Ok {
+            return Err(ErrorMessage::new(&format!("Invalid signature: {}", wallet_result)));
+        }
+
+        let address = header.inputs[0].get_address();
+        let mut signatures = HashMap::new();
+        signatures.insert(address, Signature::from(signature));
+        Ok(Self::new(header, signatures))
+    }
+
     pub fn hash(&self) -> Result<Hash, Box<dyn Error>>
     {
         self.header.hash()
     }
 
+    pub fn size_bytes(&self) -> Result<usize, Box<dyn Error>>
+    {
+        Ok(bincode::serialize(self)?.len())
+    }
+
     pub fn fee_per_byte(&self) -> Result<f32, Box<dyn Error>>
     {
         let total_fee = self.header.content.get_fee();
@@ -147,6 +207,28 @@ impl<C> Transaction<C>
         Ok(total_fee / size_in_bytes as f32)
     }
 
+    // NOTE: Transactions that can't be serialized won't be mined, 
+    //       so they're ranked below everything else.
+    pub fn fee_rate(&self) -> f32
+    {
+        self.fee_per_byte().unwrap_or(0.0)
+    }
+
+    pub fn min_fee(&self) -> Result<f32, Box<dyn Error>>
+    {
+        let size_in_bytes = bincode::serialize(&self.header)?.len();
+        Ok(self.header.content.min_fee(size_in_bytes))
+    }
+
+    pub fn is_expired(&self, timestamp: u128) -> bool
+    {
+        match self.header.valid_until
+        {
+            Some(valid_until) => timestamp > valid_until,
+            None => false,
+        }
+    }
+
     pub fn update_wallet_status(&self, address: &Hash, status: WalletStatus, 
                                 is_block_winner: bool)
         -> Result<WalletStatus, Box<dyn Error>>
@@ -162,22 +244,69 @@ impl<C> Transaction<C>
             from_amount, is_block_winner)
     }
 
-    pub fn validate_content(&self) -> Result<TransactionValidationResult, Box<dyn Error>>
+    pub fn validate_content(&self, scheme: SignatureSchemeKind) 
+        -> Result<TransactionValidationResult, Box<dyn Error>>
+    {
+        with_signature_cache(|cache| self.validate_content_with_cache(scheme, cache))
+    }
+
+    pub fn validate_content_with_cache(&self, scheme: SignatureSchemeKind, cache: &mut SignatureCache) 
+        -> Result<TransactionValidationResult, Box<dyn Error>>
     {
         let header_result = self.header.content.validate(&self.header.inputs)?;
         if header_result != TransactionValidationResult::Ok {
             return Ok(header_result);
         }
 
+        if self.header.content.get_fee() < self.min_fee()? {
+            return Ok(TransactionValidationResult::Fee);
+        }
+
+        let hash = self.hash()?;
+        let scheme = scheme.scheme();
         for input in &self.header.inputs
         {
-            let signature = &self.signatures[&input.get_address()];
-            let wallet = PublicWallet::from_public_key_e(input.from, input.e);
-            let wallet_result = wallet.verify(self.hash()?.data(), signature.data())?;
+            let key_result = scheme.validate_key(&input.from, &input.e);
+            if key_result != WalletValidationResult::Ok {
+                return Ok(TransactionValidationResult::Wallet(key_result));
+            }
+
+            let address = input.get_address();
+            let signature = match self.signatures.get(&address)
+            {
+                Some(signature) => signature,
+                None => return Ok(TransactionValidationResult::Wallet(WalletValidationResult::Signature)),
+            };
+            if cache.is_verified(&hash, &address, signature) {
+                continue;
+            }
 
+            let wallet_result = scheme.verify(&input.from, &input.e, hash.data(), signature.data())?;
             if wallet_result != WalletValidationResult::Ok {
                 return Ok(TransactionValidationResult::Wallet(wallet_result));
             }
+            cache.insert_verified(&hash, &address, signature);
+        }
+
+        Ok(TransactionValidationResult::Ok)
+    }
+
+    pub fn validate_against(&self, chain: &mut BlockChain)
+        -> Result<TransactionValidationResult, Box<dyn Error>>
+    {
+        let content_result = self.validate_content(chain.config().signature_scheme)?;
+        if content_result != TransactionValidationResult::Ok {
+            return Ok(content_result);
+        }
+
+        for address in self.get_from_addresses()
+        {
+            let status = chain.get_wallet_status(&address);
+            let new_status = self.update_wallet_status(&address, status, false)?;
+            let next_block_id = chain.top_ref().map_or(0, |top| top.header.block_id + 1);
+            if new_status.balance < chain.immature_reward(&address, next_block_id, &[]) {
+                return Ok(TransactionValidationResult::Balance(address));
+            }
         }
 
         Ok(TransactionValidationResult::Ok)
@@ -208,3 +337,176 @@ impl<C> Transaction<C>
 
 }
 
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::transaction::transfer::TransferBuilder;
+    use crate::transaction::builder::TransactionBuilder;
+    use crate::wallet::Wallet;
+    use crate::wallet::public_wallet::is_valid_exponent;
+    use crate::wallet::private_wallet::PrivateWallet;
+
+    #[test]
+    fn test_from_parts()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let transfer = TransferBuilder::new(1, 1.0)
+            .add_output(other.get_address(), 2.0)
+            .build();
+
+        let signed = TransactionBuilder::new(transfer.clone())
+            .add_input(&wallet, 3.0)
+            .build()
+            .unwrap();
+
+        // Build the header by hand and sign its hash externally
+        let header = TransactionHeader
+        {
+            content: transfer,
+            inputs: vec![Input
+            {
+                from: wallet.get_public_key(),
+                e: wallet.get_e(),
+                amount: 3.0,
+            }],
+            valid_until: None,
+        };
+        let signature = wallet.sign(header.hash().unwrap().data()).unwrap();
+        let transaction = Transaction::from_parts(header.clone(), &signature,
+            wallet.get_e(), wallet.get_public_key()).unwrap();
+        assert_eq!(transaction, signed);
+        assert_eq!(transaction.validate_content(SignatureSchemeKind::Rsa).unwrap(), signed.validate_content(SignatureSchemeKind::Rsa).unwrap());
+        assert_eq!(transaction.validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Ok);
+
+        // Signatures from the wrong key or over different data are rejected
+        let other_signature = other.sign(header.hash().unwrap().data()).unwrap();
+        assert!(Transaction::from_parts(header.clone(), &other_signature,
+            wallet.get_e(), wallet.get_public_key()).is_err());
+        assert!(Transaction::from_parts(header.clone(), &other_signature,
+            other.get_e(), other.get_public_key()).is_err());
+
+        let mut tampered = header.clone();
+        tampered.inputs[0].amount = 4.0;
+        assert!(Transaction::from_parts(tampered, &signature,
+            wallet.get_e(), wallet.get_public_key()).is_err());
+        assert!(Transaction::from_parts(header, &vec![0u8; PUB_KEY_LEN + 1],
+            wallet.get_e(), wallet.get_public_key()).is_err());
+    }
+
+    #[test]
+    fn test_validate_exponent()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let transaction = TransactionBuilder::new(TransferBuilder::new(1, 1.0)
+                .add_output(other.get_address(), 2.0)
+                .build())
+            .add_input(&wallet, 3.0)
+            .build()
+            .unwrap();
+
+        assert_eq!(is_valid_exponent(&wallet.get_e()), true);
+        assert_eq!(transaction.validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Ok);
+
+        for e in [[0, 0, 0], [1, 0, 0], [2, 0, 0], [0, 0, 1]]
+        {
+            assert_eq!(is_valid_exponent(&e), false);
+
+            let mut malformed = transaction.clone();
+            malformed.header.inputs[0].e = e;
+            assert_eq!(malformed.validate_content(SignatureSchemeKind::Rsa).unwrap(), 
+                       TransactionValidationResult::Wallet(WalletValidationResult::Exponent));
+
+            let wallet = PublicWallet::from_public_key_e(wallet.get_public_key(), e);
+            let signature = &transaction.signatures[&transaction.header.inputs[0].get_address()];
+            assert_eq!(wallet.verify(transaction.hash().unwrap().data(), signature.data()).unwrap(), 
+                       WalletValidationResult::Exponent);
+        }
+    }
+
+    #[test]
+    fn test_ed25519_signature_scheme()
+    {
+        let wallet = PrivateWallet::new_ed25519();
+        let rsa_wallet = PrivateWallet::open_temp(0).unwrap();
+        assert_eq!(wallet.signature_scheme(), SignatureSchemeKind::Ed25519);
+        assert_eq!(wallet.as_public().get_address(), wallet.get_address());
+
+        let transaction = TransactionBuilder::new(TransferBuilder::new(1, 1.0)
+                .add_output(rsa_wallet.get_address(), 2.0)
+                .build())
+            .add_input(&wallet, 3.0)
+            .build()
+            .unwrap();
+        assert_eq!(transaction.validate_content(SignatureSchemeKind::Ed25519).unwrap(), 
+                   TransactionValidationResult::Ok);
+        assert_eq!(transaction.validate_content(SignatureSchemeKind::Rsa).unwrap(), 
+                   TransactionValidationResult::Wallet(WalletValidationResult::Exponent));
+
+        // Still valid once it's been through the wire format
+        let bytes = bincode::serialize(&transaction).unwrap();
+        let decoded: Transaction<Transfer> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, transaction);
+        assert_eq!(decoded.validate_content(SignatureSchemeKind::Ed25519).unwrap(), 
+                   TransactionValidationResult::Ok);
+
+        let address = wallet.get_address();
+        let mut tampered = transaction.clone();
+        let mut signature = tampered.signatures[&address].data().to_vec();
+        signature[0] ^= 1;
+        tampered.signatures.insert(address, Signature::from(&signature));
+        assert_eq!(tampered.validate_content(SignatureSchemeKind::Ed25519).unwrap(), 
+                   TransactionValidationResult::Wallet(WalletValidationResult::Signature));
+
+        // Wallets from the other scheme are rejected
+        let rsa_transaction = TransactionBuilder::new(TransferBuilder::new(1, 1.0)
+                .add_output(address, 2.0)
+                .build())
+            .add_input(&rsa_wallet, 3.0)
+            .build()
+            .unwrap();
+        assert_eq!(rsa_transaction.validate_content(SignatureSchemeKind::Ed25519).unwrap(), 
+                   TransactionValidationResult::Wallet(WalletValidationResult::Exponent));
+
+        // Survives being written out and read back
+        let restored = PrivateWallet::deserialize(wallet.serialize()).unwrap();
+        assert_eq!(restored.get_address(), address);
+        assert_eq!(PrivateWallet::import_wif(&wallet.export_wif()).unwrap().get_address(), address);
+    }
+
+    #[test]
+    fn test_sorted_by_fee_rate()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let small = TransactionBuilder::new(TransferBuilder::new(1, 1.0)
+                .add_output(other.get_address(), 1.0)
+                .build())
+            .add_input(&wallet, 2.0)
+            .build()
+            .unwrap();
+
+        let mut large_content = TransferBuilder::new(2, 2.0);
+        for _ in 0..50 {
+            large_content = large_content.add_output(other.get_address(), 0.1);
+        }
+        let large = TransactionBuilder::new(large_content.build())
+            .add_input(&wallet, 7.0)
+            .build()
+            .unwrap();
+
+        // Less is paid in total, but more for each byte
+        assert!(small.header.content.get_fee() < large.header.content.get_fee());
+        assert!(small.fee_rate() > large.fee_rate());
+        assert_eq!(small.fee_rate(), small.fee_per_byte().unwrap());
+
+        let sorted = sorted_by_fee_rate(vec![large.clone(), small.clone()]);
+        assert_eq!(sorted, vec![small, large]);
+        assert!(sorted_by_fee_rate::<Transfer>(Vec::new()).is_empty());
+    }
+
+}
+
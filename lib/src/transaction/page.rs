@@ -9,7 +9,7 @@ use crate::wallet::WalletStatus;
 use crate::data_store::data_unit::DataUnit;
 use crate::error::ErrorMessage;
 use crate::hash::Hash;
-use crate::config::PAGE_CHUNK_SIZE;
+use crate::config::{PAGE_CHUNK_SIZE, MAX_PAGE_DATA_LENGTH, MIN_PAGE_FEE_PER_CHUNK};
 use serde::{Serialize, Deserialize};
 use std::error::Error;
 
@@ -21,6 +21,7 @@ pub struct Page
 
     pub data_hashes: Vec<Hash>,
     pub data_length: u32,
+    pub chunk_size: u32,
     pub fee: f32,
 }
 
@@ -38,14 +39,16 @@ impl Page
 
             data_hashes,
             data_length,
+            chunk_size: PAGE_CHUNK_SIZE as u32,
             fee,
         }
     }
 
-    pub fn new_from_data(id: u32, site: Hash, data: &DataUnit, fee: f32) 
+    pub fn new_from_data(id: u32, site: Hash, data: &DataUnit, 
+                         chunk_size: usize, fee: f32) 
         -> Result<Self, Box<dyn Error>>
     {
-        let data_hashes = data.hashes()?;
+        let data_hashes = data.hashes(chunk_size)?;
         let data_length = data.len()?;
         Ok(Page
         {
@@ -54,20 +57,21 @@ impl Page
 
             data_hashes,
             data_length,
+            chunk_size: chunk_size as u32,
             fee,
         })
     }
 
     pub fn cost(&self) -> f32
     {
-        // Bytes used into megabytes
-        self.data_length as f32 / PAGE_CHUNK_SIZE as f32
+        // Bytes used into chunks
+        self.data_length as f32 / self.chunk_size as f32
     }
 
     pub fn is_data_valid(&self, data: &DataUnit) 
         -> Result<(), Box<dyn Error>>
     {
-        let hashes = data.hashes()?;
+        let hashes = data.hashes(self.chunk_size as usize)?;
         if hashes.len() != self.data_hashes.len() {
             return Err(ErrorMessage::new("Missmatched data length"));
         }
@@ -92,13 +96,29 @@ impl TransactionContent for Page
         self.fee
     }
 
+    fn min_fee(&self, _size_in_bytes: usize) -> f32
+    {
+        self.data_hashes.len() as f32 * MIN_PAGE_FEE_PER_CHUNK
+    }
+
     fn validate(&self, inputs: &Vec<Input>) 
         -> Result<TransactionValidationResult, Box<dyn Error>>
     {
+        // NOTE: Every input must be signed by its own key, so requiring one
+        //       from the site means only its owner can publish to it.
         if !inputs.iter().any(|x| x.get_address() == self.site) {
             return Ok(TransactionValidationResult::Negative);
         }
 
+        if self.chunk_size == 0 {
+            return Ok(TransactionValidationResult::Negative);
+        }
+
+        // NOTE: Stop a single page from taking up a whole block
+        if self.data_length > MAX_PAGE_DATA_LENGTH {
+            return Ok(TransactionValidationResult::Negative);
+        }
+
         let total_input = inputs.iter().fold(0.0, |acc, x| acc + x.amount);
         if total_input != self.cost() + self.fee {
             return Ok(TransactionValidationResult::Negative);
@@ -147,3 +167,92 @@ impl TransactionContent for Page
 
 }
 
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::transaction::builder::TransactionBuilder;
+    use crate::wallet::Wallet;
+    use crate::wallet::private_wallet::PrivateWallet;
+    use crate::wallet::public_wallet::WalletValidationResult;
+    use crate::wallet::signature_scheme::SignatureSchemeKind;
+    use crate::hash::Signature;
+
+    fn validate_page_of_length(wallet: &PrivateWallet, data_length: u32) 
+        -> TransactionValidationResult
+    {
+        let mut page = Page::new(1, wallet.get_address(), Vec::new(), data_length, 1.0);
+        page.data_hashes = vec![Hash::empty(); page.cost().ceil() as usize];
+
+        let inputs = vec![Input
+        {
+            from: wallet.get_public_key(),
+            e: wallet.get_e(),
+            amount: page.cost() + page.fee,
+        }];
+        page.validate(&inputs).unwrap()
+    }
+
+    #[test]
+    fn test_page_max_data_length()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        assert_eq!(validate_page_of_length(&wallet, MAX_PAGE_DATA_LENGTH - 1), TransactionValidationResult::Ok);
+        assert_eq!(validate_page_of_length(&wallet, MAX_PAGE_DATA_LENGTH), TransactionValidationResult::Ok);
+        assert_eq!(validate_page_of_length(&wallet, MAX_PAGE_DATA_LENGTH + 1), TransactionValidationResult::Negative);
+    }
+
+    #[test]
+    fn test_page_fee_floor()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let page_with_fee = |fee: f32|
+        {
+            let data_length = 2 * PAGE_CHUNK_SIZE as u32;
+            let page = Page::new(1, wallet.get_address(), vec![Hash::empty(); 2], data_length, fee);
+            let total_input = page.cost() + fee;
+            TransactionBuilder::new(page)
+                .add_input(&wallet, total_input)
+                .build().unwrap()
+        };
+
+        let min_fee = 2.0 * MIN_PAGE_FEE_PER_CHUNK;
+        assert_eq!(page_with_fee(min_fee * 1.01).validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Ok);
+        assert_eq!(page_with_fee(min_fee * 0.99).validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Fee);
+    }
+
+    #[test]
+    fn test_page_signed_by_site()
+    {
+        let site = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let page_for = |site: Hash, author: &PrivateWallet|
+        {
+            let page = Page::new(1, site, vec![Hash::empty()], PAGE_CHUNK_SIZE as u32, 1.0);
+            let total_input = page.cost() + page.fee;
+            TransactionBuilder::new(page)
+                .add_input(author, total_input)
+                .build().unwrap()
+        };
+
+        let page = page_for(site.get_address(), &site);
+        assert_eq!(page.validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Ok);
+        assert_eq!(page_for(site.get_address(), &other).validate_content(SignatureSchemeKind::Rsa).unwrap(), 
+            TransactionValidationResult::Negative);
+
+        // Claiming the site's key as an input, without its signature
+        let mut forged = page.clone();
+        let hash = forged.hash().unwrap();
+        let signature = Signature::from(&other.sign(hash.data()).unwrap());
+        forged.signatures.insert(site.get_address(), signature);
+        assert_eq!(forged.validate_content(SignatureSchemeKind::Rsa).unwrap(), 
+            TransactionValidationResult::Wallet(WalletValidationResult::Signature));
+
+        forged.signatures.clear();
+        assert_eq!(forged.validate_content(SignatureSchemeKind::Rsa).unwrap(), 
+            TransactionValidationResult::Wallet(WalletValidationResult::Signature));
+    }
+
+}
+
@@ -0,0 +1,169 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use crate::hash::{Hash, Signature};
+use crate::config::SIGNATURE_CACHE_SIZE;
+
+use std::collections::{HashMap, BTreeMap};
+use std::sync::Mutex;
+
+static SIGNATURE_CACHE: Mutex<Option<SignatureCache>> = Mutex::new(None);
+
+pub fn with_signature_cache<F, R>(f: F) -> R
+    where F: FnOnce(&mut SignatureCache) -> R
+{
+    let mut cache = SIGNATURE_CACHE.lock().unwrap();
+    if cache.is_none() {
+        *cache = Some(SignatureCache::new(SIGNATURE_CACHE_SIZE));
+    }
+
+    f(cache.as_mut().unwrap())
+}
+
+// Remembers signatures that have already been verified as good, 
+// evicting the least recently used once full.
+// NOTE: The signer address is part of the key, so a valid signature 
+//       can't be reused for another input of the same transaction.
+pub struct SignatureCache
+{
+    capacity: usize,
+    next_use: u64,
+    entries: HashMap<(Hash, Hash, Signature), u64>,
+    by_last_use: BTreeMap<u64, (Hash, Hash, Signature)>,
+    hits: u64,
+}
+
+impl SignatureCache
+{
+
+    pub fn new(capacity: usize) -> Self
+    {
+        Self
+        {
+            capacity,
+            next_use: 0,
+            entries: HashMap::new(),
+            by_last_use: BTreeMap::new(),
+            hits: 0,
+        }
+    }
+
+    pub fn hits(&self) -> u64
+    {
+        self.hits
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.entries.is_empty()
+    }
+
+    fn touch(&mut self, key: (Hash, Hash, Signature))
+    {
+        let last_use = self.next_use;
+        self.next_use += 1;
+
+        if let Some(old_use) = self.entries.insert(key, last_use) {
+            self.by_last_use.remove(&old_use);
+        }
+        self.by_last_use.insert(last_use, key);
+    }
+
+    pub fn is_verified(&mut self, hash: &Hash, address: &Hash, signature: &Signature) -> bool
+    {
+        let key = (*hash, *address, *signature);
+        if !self.entries.contains_key(&key) {
+            return false;
+        }
+
+        self.hits += 1;
+        self.touch(key);
+        true
+    }
+
+    pub fn insert_verified(&mut self, hash: &Hash, address: &Hash, signature: &Signature)
+    {
+        self.touch((*hash, *address, *signature));
+        while self.entries.len() > self.capacity
+        {
+            let (oldest_use, oldest) = self.by_last_use
+                .iter()
+                .next()
+                .map(|(last_use, key)| (*last_use, *key))
+                .unwrap();
+
+            self.by_last_use.remove(&oldest_use);
+            self.entries.remove(&oldest);
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::transaction::TransactionValidationResult;
+    use crate::transaction::transfer::TransferBuilder;
+    use crate::transaction::builder::TransactionBuilder;
+    use crate::wallet::Wallet;
+    use crate::wallet::public_wallet::WalletValidationResult;
+    use crate::wallet::private_wallet::PrivateWallet;
+    use crate::wallet::signature_scheme::SignatureSchemeKind;
+
+    #[test]
+    fn test_signature_cache()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+
+        let transaction = TransactionBuilder::new(
+            TransferBuilder::new(1, 1.0)
+                .add_output(other.get_address(), 2.0)
+                .build())
+            .add_input(&wallet, 3.0)
+            .build()
+            .unwrap();
+
+        let mut cache = SignatureCache::new(2);
+        assert_eq!(transaction.validate_content_with_cache(SignatureSchemeKind::Rsa, &mut cache).unwrap(), TransactionValidationResult::Ok);
+        assert_eq!(cache.hits(), 0);
+        assert_eq!(cache.len(), 1);
+
+        assert_eq!(transaction.validate_content_with_cache(SignatureSchemeKind::Rsa, &mut cache).unwrap(), TransactionValidationResult::Ok);
+        assert_eq!(cache.hits(), 1);
+
+        // Tampered signatures are still checked
+        let mut tampered = transaction.clone();
+        for signature in tampered.signatures.values_mut() {
+            *signature = Signature::empty();
+        }
+        assert_eq!(tampered.validate_content_with_cache(SignatureSchemeKind::Rsa, &mut cache).unwrap(), 
+                   TransactionValidationResult::Wallet(WalletValidationResult::Signature));
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cache.len(), 1);
+
+        // The least recently used entry is evicted
+        let hash = transaction.hash().unwrap();
+        let address = wallet.get_address();
+        let signature = transaction.signatures[&address];
+        cache.insert_verified(&Hash::empty(), &address, &Signature::empty());
+        assert_eq!(cache.is_verified(&hash, &address, &signature), true);
+        cache.insert_verified(&Hash::empty(), &address, &signature);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.is_verified(&Hash::empty(), &address, &Signature::empty()), false);
+        assert_eq!(cache.is_verified(&hash, &address, &signature), true);
+        assert_eq!(cache.is_verified(&hash, &other.get_address(), &signature), false);
+    }
+
+}
+
@@ -8,6 +8,7 @@ use super::{Input, TransactionContent, TransactionValidationResult};
 use crate::wallet::WalletStatus;
 use crate::error::ErrorMessage;
 use crate::hash::Hash;
+use crate::config::MIN_TRANSFER_FEE_PER_BYTE;
 
 use serde::{Serialize, Deserialize};
 use std::error::Error;
@@ -89,6 +90,11 @@ impl TransactionContent for Transfer
         self.fee
     }
 
+    fn min_fee(&self, size_in_bytes: usize) -> f32
+    {
+        size_in_bytes as f32 * MIN_TRANSFER_FEE_PER_BYTE
+    }
+
     fn validate(&self, inputs: &Vec<Input>) 
         -> Result<TransactionValidationResult, Box<dyn Error>>
     {
@@ -161,6 +167,7 @@ mod tests
     use crate::chain::BlockChain;
     use crate::wallet::Wallet;
     use crate::wallet::private_wallet::PrivateWallet;
+    use crate::wallet::signature_scheme::SignatureSchemeKind;
     use crate::miner;
 
     #[test]
@@ -184,7 +191,7 @@ mod tests
                     .add_input(&wallet, 2.4 + 0.2)
                     .build().unwrap();
             transfer.hash().expect("Hash header");
-            assert_eq!(transfer.validate_content().unwrap(), TransactionValidationResult::Ok);
+            assert_eq!(transfer.validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Ok);
         }
 
         {
@@ -195,7 +202,7 @@ mod tests
                         .build())
                     .add_input(&wallet, -1.6)
                     .build().unwrap();
-            assert_ne!(transfer.validate_content().unwrap(), TransactionValidationResult::Ok);
+            assert_ne!(transfer.validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Ok);
         }
 
         {
@@ -206,7 +213,7 @@ mod tests
                         .build())
                     .add_input(&wallet, -0.0001)
                     .build().unwrap();
-            assert_ne!(transfer.validate_content().unwrap(), TransactionValidationResult::Ok);
+            assert_ne!(transfer.validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Ok);
         }
 
         {
@@ -219,7 +226,7 @@ mod tests
                     .add_input(&wallet, 5.0)
                     .add_input(&other, 6.0)
                     .build().unwrap();
-            assert_eq!(transfer.validate_content().unwrap(), TransactionValidationResult::Ok);
+            assert_eq!(transfer.validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Ok);
         }
 
         {
@@ -232,9 +239,63 @@ mod tests
                     .add_input(&wallet, 5.0)
                     .add_input(&other, 5.0)
                     .build().unwrap();
-            assert_ne!(transfer.validate_content().unwrap(), TransactionValidationResult::Ok);
+            assert_ne!(transfer.validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Ok);
         }
     }
 
+    #[test]
+    fn test_transfer_fee_floor()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+
+        let transfer_with_fee = |fee: f32|
+        {
+            TransactionBuilder::new(
+                TransferBuilder::new(1, fee)
+                    .add_output(other.get_address(), 2.0)
+                    .build())
+                .add_input(&wallet, 2.0 + fee)
+                .build().unwrap()
+        };
+
+        // NOTE: The fee doesn't change the size of the transaction
+        let min_fee = transfer_with_fee(0.0).min_fee().unwrap();
+        assert_eq!(transfer_with_fee(min_fee * 1.01).validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Ok);
+        assert_eq!(transfer_with_fee(min_fee * 0.99).validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Fee);
+    }
+
+    #[test]
+    fn test_validate_against_chain()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let mut chain = BlockChain::open_in_memory();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+
+        let block = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        chain.add(&block).unwrap();
+        let balance = chain.get_wallet_status(&wallet.get_address()).balance;
+
+        let transfer_of = |amount: f32|
+        {
+            TransactionBuilder::new(
+                TransferBuilder::new(1, 0.25)
+                    .add_output(other.get_address(), amount)
+                    .build())
+                .add_input(&wallet, amount + 0.25)
+                .build().unwrap()
+        };
+
+        let funded = transfer_of(balance - 0.25);
+        assert_eq!(funded.validate_against(&mut chain).unwrap(), TransactionValidationResult::Ok);
+
+        let overdrawn = transfer_of(balance);
+        assert_eq!(overdrawn.validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Ok);
+        assert_eq!(overdrawn.validate_against(&mut chain).unwrap(), 
+                   TransactionValidationResult::Balance(wallet.get_address()));
+    }
+
 }
 
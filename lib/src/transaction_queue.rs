@@ -6,15 +6,65 @@
 
 use crate::transaction::{Transaction, TransactionContent};
 use crate::wallet::WalletStatus;
+use crate::error::ErrorMessage;
 use crate::hash::Hash;
 
 use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{HashMap, BTreeMap};
 use std::error::Error;
 
+// NOTE: Gives fee rates a total order, so they can be used as a key
+#[derive(Clone, Copy, Debug)]
+struct FeeRate(f32);
+
+impl PartialEq for FeeRate
+{
+
+    fn eq(&self, other: &Self) -> bool
+    {
+        self.cmp(other) == Ordering::Equal
+    }
+
+}
+
+impl Eq for FeeRate {}
+
+impl PartialOrd for FeeRate
+{
+
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering>
+    {
+        Some(self.cmp(other))
+    }
+
+}
+
+impl Ord for FeeRate
+{
+
+    fn cmp(&self, other: &Self) -> Ordering
+    {
+        self.0.total_cmp(&other.0)
+    }
+
+}
+
 pub struct TransactionQueue<C>
     where C: TransactionContent
 {
     queue: Vec<(f32, Transaction<C>)>,
+
+    // NOTE: Maps each (from address, id) to the queued transaction using 
+    //       it, as only one of them could ever be mined.
+    pending: HashMap<(Hash, u32), Hash>,
+
+    // Queued transactions from the lowest fee rate, with their id and 
+    // the addresses they use, and how many use each id of every address. 
+    // So the cheapest one nothing depends on can be found without 
+    // comparing every pair.
+    by_fee_rate: BTreeMap<(FeeRate, Hash), (u32, Vec<Hash>)>,
+    ids_used: HashMap<Hash, BTreeMap<u32, usize>>,
 }
 
 pub fn is_depenency<C>(transaction: &Transaction<C>, depencency: &Transaction<C>) -> bool
@@ -39,9 +89,78 @@ impl<C> TransactionQueue<C>
         Self
         {
             queue: Vec::new(),
+            pending: HashMap::new(),
+            by_fee_rate: BTreeMap::new(),
+            ids_used: HashMap::new(),
         }
     }
 
+    fn pending_keys(transaction: &Transaction<C>) -> Vec<(Hash, u32)>
+    {
+        let id = transaction.get_id();
+        transaction.get_from_addresses()
+            .into_iter()
+            .map(|address| (address, id))
+            .collect()
+    }
+
+    fn track(&mut self, transaction: &Transaction<C>, hash: Hash)
+    {
+        for key in Self::pending_keys(transaction) {
+            self.pending.insert(key, hash);
+        }
+
+        let id = transaction.get_id();
+        let addresses = transaction.get_addresses_used();
+        for address in &addresses
+        {
+            *self.ids_used
+                .entry(*address)
+                .or_default()
+                .entry(id)
+                .or_insert(0) += 1;
+        }
+        self.by_fee_rate.insert((FeeRate(transaction.fee_rate()), hash), (id, addresses));
+    }
+
+    fn untrack(&mut self, transaction: &Transaction<C>)
+    {
+        let mut hash_or_none = None;
+        for key in Self::pending_keys(transaction) {
+            hash_or_none = self.pending.remove(&key).or(hash_or_none);
+        }
+
+        if let Some(hash) = hash_or_none {
+            self.by_fee_rate.remove(&(FeeRate(transaction.fee_rate()), hash));
+        }
+
+        let id = transaction.get_id();
+        for address in transaction.get_addresses_used()
+        {
+            let ids = match self.ids_used.get_mut(&address)
+            {
+                Some(ids) => ids,
+                None => continue,
+            };
+
+            if let Some(count) = ids.get_mut(&id)
+            {
+                *count -= 1;
+                if *count == 0 {
+                    ids.remove(&id);
+                }
+            }
+            if ids.is_empty() {
+                self.ids_used.remove(&address);
+            }
+        }
+    }
+
+    pub fn is_pending(&self, from: &Hash, id: u32) -> bool
+    {
+        self.pending.contains_key(&(*from, id))
+    }
+
     pub fn transactions(&self) -> impl Iterator<Item = &Transaction<C>>
     {
         self.queue
@@ -49,6 +168,16 @@ impl<C> TransactionQueue<C>
             .map(|(_, x)| x)
     }
 
+    pub fn len(&self) -> usize
+    {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.queue.is_empty()
+    }
+
     fn find_position_for_transaction(&self, new_priority: f32, new_transaction: &Transaction<C>)
         -> usize
     {
@@ -76,7 +205,15 @@ impl<C> TransactionQueue<C>
     pub fn push(&mut self, transaction: Transaction<C>) 
         -> Result<(), Box<dyn Error>>
     {
-        let priority = transaction.fee_per_byte()?;
+        let keys = Self::pending_keys(&transaction);
+        if keys.iter().any(|key| self.pending.contains_key(key)) {
+            return Err(ErrorMessage::new("Conflicts with a queued transaction"));
+        }
+
+        let hash = transaction.hash()?;
+        self.track(&transaction, hash);
+
+        let priority = transaction.fee_rate();
         let position = self.find_position_for_transaction(priority, &transaction);
         println!("pos: {}", position);
 
@@ -89,6 +226,56 @@ impl<C> TransactionQueue<C>
         Ok(())
     }
 
+    fn position_of(&self, fee_rate: f32, hash: &Hash) -> Option<usize>
+    {
+        // NOTE: Only those with the same fee rate need hashing
+        self.queue
+            .iter()
+            .position(|(priority, x)| 
+                priority.total_cmp(&fee_rate) == Ordering::Equal && x.hash().ok().as_ref() == Some(hash))
+    }
+
+    // Nothing queued depends on it, if no transaction using the same 
+    // addresses has a later id
+    fn is_evictable(&self, id: u32, addresses: &[Hash]) -> bool
+    {
+        addresses
+            .iter()
+            .filter_map(|address| self.ids_used.get(address))
+            .all(|ids| ids.keys().next_back() <= Some(&id))
+    }
+
+    // The transaction paying the lowest fee rate, that no other 
+    // queued transaction depends on
+    fn find_lowest_evictable(&self) -> Option<usize>
+    {
+        self.by_fee_rate
+            .iter()
+            .find(|(_, (id, addresses))| self.is_evictable(*id, addresses))
+            .and_then(|((fee_rate, hash), _)| self.position_of(fee_rate.0, hash))
+    }
+
+    // Evict the cheapest transactions until there's room for one paying 
+    // `fee_rate`, or fail if it pays no more then them
+    pub fn make_room(&mut self, max_size: usize, fee_rate: f32)
+        -> Result<(), Box<dyn Error>>
+    {
+        while self.queue.len() >= max_size
+        {
+            let index = match self.find_lowest_evictable()
+            {
+                Some(index) if self.queue[index].0 < fee_rate => index,
+                _ => return Err(ErrorMessage::new("Fee rate is too low for the full queue")),
+            };
+
+            let (_, evicted) = self.queue.remove(index);
+            self.untrack(&evicted);
+            info!("Evicted {} from the full queue", evicted.hash()?);
+        }
+
+        Ok(())
+    }
+
     pub fn get_next(&self, count: usize) -> impl Iterator<Item = &Transaction<C>>
     {
         let real_count = std::cmp::min(count, self.queue.len());
@@ -97,17 +284,67 @@ impl<C> TransactionQueue<C>
             .map(|(_, x)| x)
     }
 
+    // Queued transactions that would be mined before one paying `fee_rate`
+    pub fn ahead_of(&self, fee_rate: f32) -> impl Iterator<Item = &Transaction<C>>
+    {
+        self.queue
+            .iter()
+            .filter(move |(priority, _)| *priority >= fee_rate)
+            .map(|(_, x)| x)
+    }
+
     pub fn remove_in_block(&mut self, transactions: &[Transaction<C>])
     {
         for transaction in transactions
         {
             let index = self.queue.iter().position(|(_, x)| x == transaction);
-            if index.is_some() {
+            if index.is_some() 
+            {
                 self.queue.remove(index.unwrap());
+                self.untrack(transaction);
             }
         }
     }
 
+    pub fn remove(&mut self, transaction: &Transaction<C>)
+    {
+        let count = self.queue.len();
+        self.queue.retain(|(_, x)| x != transaction);
+        if self.queue.len() != count {
+            self.untrack(transaction);
+        }
+    }
+
+    // Find a queued transaction from the same wallet, with the same id
+    pub fn find_conflicting(&self, transaction: &Transaction<C>) 
+        -> Option<Transaction<C>>
+    {
+        Self::pending_keys(transaction)
+            .iter()
+            .find_map(|key| self.pending.get(key))
+            .and_then(|hash| self.find(hash))
+    }
+
+    pub fn has_later_from(&self, transaction: &Transaction<C>) -> bool
+    {
+        let from_addresses = transaction.get_from_addresses();
+        self.transactions()
+            .any(|x| x.get_id() > transaction.get_id() && 
+                x.get_from_addresses().iter().any(|address| from_addresses.contains(address)))
+    }
+
+    pub fn remove_expired(&mut self, timestamp: u128)
+    {
+        let expired = self.transactions()
+            .filter(|x| x.is_expired(timestamp))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for transaction in &expired {
+            self.remove(transaction);
+        }
+    }
+
     pub fn remove_from_address(&mut self, address: &Hash)
     {
         self.queue
@@ -6,6 +6,7 @@
 
 pub mod private_wallet;
 pub mod public_wallet;
+pub mod signature_scheme;
 use crate::chain::BlockChain;
 use crate::hash::{Hash, Signature};
 use sha2::{Sha256, Digest};
@@ -18,6 +19,15 @@ pub struct WalletStatus
     pub max_id: u32,
 }
 
+// The change a single block makes to a wallet, with `max_id` only 
+// set if the wallet spent something in it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WalletStatusDelta
+{
+    pub balance: f32,
+    pub max_id: Option<u32>,
+}
+
 impl Default for WalletStatus
 {
 
@@ -32,6 +42,15 @@ impl Default for WalletStatus
 
 }
 
+// NOTE: Used both for wallets and transaction inputs, so the address 
+//       a wallet receives to is always the one its inputs spend from.
+pub fn address_from_public_key(public_key: &Signature) -> Hash
+{
+    let mut hasher = Sha256::default();
+    hasher.update(public_key);
+    Hash::from(&hasher.finalize())
+}
+
 pub trait Wallet
 {
 
@@ -39,9 +58,7 @@ pub trait Wallet
 
     fn get_address(&self) -> Hash
     {
-        let mut hasher = Sha256::default();
-        hasher.update(&self.get_public_key());
-        Hash::from(&hasher.finalize())
+        address_from_public_key(&self.get_public_key())
     }
 
     fn get_status(&self, chain: &mut BlockChain) -> WalletStatus
@@ -106,5 +123,65 @@ mod tests
         assert_eq!(other_status.max_id, 1);
     }
 
+    #[test]
+    fn test_block_wallet_delta()
+    {
+        let mut chain = BlockChain::open_temp();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let fee_burn_ratio = chain.config().fee_burn_ratio;
+
+        let block_a = miner::mine_block(Block::new_blank(&mut chain, &wallet).expect("Create block"));
+        chain.add(&block_a).unwrap();
+
+        let block_b = miner::mine_block(BlockBuilder::new(&wallet)
+            .add_transfer(
+                TransactionBuilder::new(
+                    TransferBuilder::new(1, 0.2)
+                        .add_output(other.get_address(), 4.6)
+                        .build())
+                    .add_input(&wallet, 4.6 + 0.2)
+                    .build().unwrap())
+            .build(&mut chain)
+            .expect("Create block"));
+        chain.add(&block_b).unwrap();
+
+        // The miner gets the reward and its own fee back, less what's burned
+        let delta = block_b.wallet_delta(&wallet.get_address(), fee_burn_ratio).unwrap();
+        assert_eq!(delta, WalletStatusDelta
+        {
+            balance: block_b.calculate_reward() - 4.6 - 0.2 + 0.2 - block_b.fees_burned(fee_burn_ratio),
+            max_id: Some(1),
+        });
+
+        assert_eq!(block_b.wallet_delta(&other.get_address(), fee_burn_ratio).unwrap(),
+                   WalletStatusDelta { balance: 4.6, max_id: None });
+        assert_eq!(block_a.wallet_delta(&other.get_address(), fee_burn_ratio).unwrap(),
+                   WalletStatusDelta { balance: 0.0, max_id: None });
+
+        // Together the deltas make up the wallets status
+        let total = block_a.wallet_delta(&wallet.get_address(), fee_burn_ratio).unwrap().balance + delta.balance;
+        assert!((wallet.get_status(&mut chain).balance - total).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_address_matches_inputs()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        assert_eq!(wallet.get_address(), address_from_public_key(&wallet.get_public_key()));
+
+        let transaction = TransactionBuilder::new(
+            TransferBuilder::new(1, 0.2)
+                .add_output(other.get_address(), 1.0)
+                .build())
+            .add_input(&wallet, 1.2)
+            .add_input(&other, 0.5)
+            .build()
+            .unwrap();
+        assert_eq!(transaction.get_from_addresses(), vec![wallet.get_address(), other.get_address()]);
+        assert_eq!(transaction.header.inputs[0].get_address(), wallet.get_address());
+    }
+
 }
 
@@ -5,20 +5,76 @@
  */
 
 use super::Wallet;
-use super::public_wallet::PublicWallet;
-use crate::hash::Signature;
-use crate::config::PUB_KEY_LEN;
+use super::public_wallet::{PublicWallet, is_valid_exponent};
+use super::signature_scheme::{SignatureSchemeKind, ED25519_EXPONENT};
+use crate::hash::{Hash, Signature};
+use crate::config::{WALLET_KEY_BITS, MIN_WALLET_KEY_BITS};
+use crate::error::ErrorMessage;
 use rsa::{RSAPrivateKey, PaddingScheme, PrivateKeyEncoding, PublicKeyParts};
+use ed25519_dalek::{SigningKey, Signer, SECRET_KEY_LENGTH};
+use sha2::{Sha256, Digest};
 use rand::rngs::OsRng;
 use std::fs::File;
 use std::path::PathBuf;
 use std::io::{Read, Write};
+use std::convert::TryInto;
 use std::error::Error;
 use slice_as_array;
 
+const WALLET_EXPORT_PREFIX: &str = "hcw";
+const WALLET_EXPORT_VERSION: u8 = 1;
+const WALLET_EXPORT_CHECKSUM_LEN: usize = 4;
+const ED25519_WALLET_PREFIX: &[u8] = b"hcw-ed25519:";
+
+fn export_checksum(payload: &[u8]) -> Vec<u8>
+{
+    let mut hasher = Sha256::default();
+    hasher.update(payload);
+    hasher.finalize()[0..WALLET_EXPORT_CHECKSUM_LEN].to_vec()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WalletKeyStatus
+{
+    Ok,
+    Inconsistent,
+    Exponent,
+    KeySize,
+}
+
+impl std::fmt::Display for WalletKeyStatus
+{
+
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+    {
+        match self
+        {
+            WalletKeyStatus::Ok => write!(f, "Ok"),
+            WalletKeyStatus::Inconsistent => write!(f, "Key parts are inconsistent"),
+            WalletKeyStatus::Exponent => write!(f, "Public exponent not valid"),
+            WalletKeyStatus::KeySize => write!(f, "Key size not valid"),
+        }
+    }
+
+}
+
+#[derive(Debug, PartialEq)]
+pub struct WalletInspection
+{
+    pub address: Hash,
+    pub key_bits: usize,
+    pub status: WalletKeyStatus,
+}
+
+enum WalletKey
+{
+    Rsa(RSAPrivateKey),
+    Ed25519(SigningKey),
+}
+
 pub struct PrivateWallet
 {
-    key: RSAPrivateKey,
+    key: WalletKey,
 }
 
 impl Wallet for PrivateWallet
@@ -26,8 +82,11 @@ impl Wallet for PrivateWallet
 
     fn get_public_key(&self) -> Signature
     {
-        let bytes = self.key.n().to_bytes_le();
-        Signature::from(&bytes)
+        match &self.key
+        {
+            WalletKey::Rsa(key) => Signature::from(&key.n().to_bytes_le()),
+            WalletKey::Ed25519(key) => Signature::from(key.verifying_key().as_bytes()),
+        }
     }
 
 }
@@ -38,13 +97,54 @@ impl PrivateWallet
     pub fn new() -> rsa::errors::Result<Self>
     {
         let mut rng = OsRng;
-        let key = RSAPrivateKey::new(&mut rng, PUB_KEY_LEN * 8)?;
+        let key = RSAPrivateKey::new(&mut rng, WALLET_KEY_BITS)?;
+
+        Ok(Self {
+            key: WalletKey::Rsa(key),
+        })
+    }
+
+    pub fn new_with_key_bits(key_bits: usize) -> Result<Self, Box<dyn Error>>
+    {
+        if key_bits < MIN_WALLET_KEY_BITS || key_bits > WALLET_KEY_BITS || key_bits % 8 != 0 {
+            return Err(ErrorMessage::new(&format!(
+                "Key size must be a multiple of 8, from {} to {} bits", 
+                MIN_WALLET_KEY_BITS, WALLET_KEY_BITS)));
+        }
 
+        let mut rng = OsRng;
+        let key = RSAPrivateKey::new(&mut rng, key_bits)?;
         Ok(Self {
-            key,
+            key: WalletKey::Rsa(key),
         })
     }
 
+    pub fn new_ed25519() -> Self
+    {
+        let mut rng = OsRng;
+        Self {
+            key: WalletKey::Ed25519(SigningKey::generate(&mut rng)),
+        }
+    }
+
+    pub fn signature_scheme(&self) -> SignatureSchemeKind
+    {
+        match &self.key
+        {
+            WalletKey::Rsa(_) => SignatureSchemeKind::Rsa,
+            WalletKey::Ed25519(_) => SignatureSchemeKind::Ed25519,
+        }
+    }
+
+    pub fn key_bits(&self) -> usize
+    {
+        match &self.key
+        {
+            WalletKey::Rsa(key) => key.size() * 8,
+            WalletKey::Ed25519(_) => SECRET_KEY_LENGTH * 8,
+        }
+    }
+
     pub fn open_temp(id: u32) 
         -> Result<Self, Box<dyn Error>>
     {
@@ -58,20 +158,75 @@ impl PrivateWallet
         Ok(wallet)
     }
 
+    // NOTE: RSA keys are stored as PKCS#8, and Ed25519 
+    //       keys as their secret after a short prefix
     pub fn serialize(&self) -> Vec<u8>
     {
-        self.key.to_pkcs8().unwrap()
+        match &self.key
+        {
+            WalletKey::Rsa(key) => key.to_pkcs8().unwrap(),
+            WalletKey::Ed25519(key) => [ED25519_WALLET_PREFIX, key.as_bytes()].concat(),
+        }
     }
 
     pub fn deserialize(buffer: Vec<u8>) -> Result<Self, Box<dyn Error>>
     {
+        if let Some(secret) = buffer.strip_prefix(ED25519_WALLET_PREFIX)
+        {
+            let secret: &[u8; SECRET_KEY_LENGTH] = match secret.try_into()
+            {
+                Ok(secret) => secret,
+                Err(_) => return Err(ErrorMessage::new("Ed25519 wallet is the wrong length")),
+            };
+
+            return Ok(Self
+            {
+                key: WalletKey::Ed25519(SigningKey::from_bytes(secret)),
+            });
+        }
+
         let key = RSAPrivateKey::from_pkcs8(&buffer)?;
         Ok(Self
         {
-            key,
+            key: WalletKey::Rsa(key),
         })
     }
 
+    pub fn export_wif(&self) -> String
+    {
+        let mut payload = vec![WALLET_EXPORT_VERSION];
+        payload.append(&mut self.serialize());
+
+        let mut checksum = export_checksum(&payload);
+        payload.append(&mut checksum);
+        format!("{}{}:{}", WALLET_EXPORT_PREFIX, WALLET_EXPORT_VERSION, 
+            base_62::encode(&payload))
+    }
+
+    pub fn import_wif(exported: &str) -> Result<Self, Box<dyn Error>>
+    {
+        let expected_prefix = format!("{}{}:", WALLET_EXPORT_PREFIX, WALLET_EXPORT_VERSION);
+        if !exported.starts_with(&expected_prefix) {
+            return Err(ErrorMessage::new("Unsupported wallet export format or version"));
+        }
+
+        let payload = base_62::decode(exported[expected_prefix.len()..].trim())?;
+        if payload.len() <= WALLET_EXPORT_CHECKSUM_LEN + 1 {
+            return Err(ErrorMessage::new("Wallet export is too short"));
+        }
+
+        let (data, checksum) = payload.split_at(payload.len() - WALLET_EXPORT_CHECKSUM_LEN);
+        if export_checksum(data) != checksum {
+            return Err(ErrorMessage::new("Wallet export checksum does not match"));
+        }
+
+        if data[0] != WALLET_EXPORT_VERSION {
+            return Err(ErrorMessage::new("Unsupported wallet export version"));
+        }
+
+        Self::deserialize(data[1..].to_vec())
+    }
+
     pub fn as_public(&self) -> PublicWallet
     {
         PublicWallet::from_public_key_e(self.get_public_key(), self.get_e())
@@ -80,7 +235,7 @@ impl PrivateWallet
     pub fn write_to_file(&self, path: &PathBuf) -> std::io::Result<()>
     {
         let mut file = File::create(path)?;
-        file.write(&self.key.to_pkcs8().unwrap())?;
+        file.write_all(&self.serialize())?;
         Ok(())
     }
 
@@ -91,23 +246,191 @@ impl PrivateWallet
         file.read_to_end(&mut buffer)?;
         info!("Opened wallet '{:?}'", path);
 
-        let key = RSAPrivateKey::from_pkcs8(&buffer).unwrap();
-        Ok(Self
+        match Self::deserialize(buffer)
         {
-            key,
+            Ok(wallet) => Ok(wallet),
+            Err(err) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())),
+        }
+    }
+
+    fn key_status(&self) -> WalletKeyStatus
+    {
+        let key = match &self.key
+        {
+            WalletKey::Rsa(key) => key,
+            WalletKey::Ed25519(_) => return WalletKeyStatus::Ok,
+        };
+
+        // NOTE: Don't use `get_e` here, it expects exactly three bytes
+        let e = key.e().to_bytes_le();
+        let mut e_bytes = [0u8; 3];
+        let is_exponent_valid = e.len() <= 3 && {
+            e_bytes[..e.len()].copy_from_slice(&e);
+            is_valid_exponent(&e_bytes)
+        };
+
+        if key.validate().is_err() {
+            WalletKeyStatus::Inconsistent
+        } else if !is_exponent_valid {
+            WalletKeyStatus::Exponent
+        } else if self.key_bits() > WALLET_KEY_BITS || self.key_bits() < MIN_WALLET_KEY_BITS {
+            WalletKeyStatus::KeySize
+        } else {
+            WalletKeyStatus::Ok
+        }
+    }
+
+    // Check a wallet file is usable and find its address, without
+    // keeping the private key around
+    pub fn inspect(path: &PathBuf) -> Result<WalletInspection, Box<dyn Error>>
+    {
+        let buffer = std::fs::read(path)?;
+        let wallet = match Self::deserialize(buffer)
+        {
+            Ok(wallet) => wallet,
+            Err(_) => return Err(ErrorMessage::new(&format!(
+                "Wallet file '{}' does not contain a valid private key", path.display()))),
+        };
+
+        Ok(WalletInspection
+        {
+            address: wallet.get_address(),
+            key_bits: wallet.key_bits(),
+            status: wallet.key_status(),
         })
     }
 
     pub fn sign(&self, digest: &[u8]) -> rsa::errors::Result<Vec<u8>>
     {
-        self.key.sign(PaddingScheme::new_pkcs1v15_sign(None), digest)
+        match &self.key
+        {
+            WalletKey::Rsa(key) => key.sign(PaddingScheme::new_pkcs1v15_sign(None), digest),
+            WalletKey::Ed25519(key) => Ok(key.sign(digest).to_bytes().to_vec()),
+        }
     }
 
     pub fn get_e(&self) -> [u8; 3]
     {
-        let bytes = self.key.e().to_bytes_le();
-        *slice_as_array!(&bytes, [u8; 3]).unwrap()
+        match &self.key
+        {
+            WalletKey::Rsa(key) =>
+            {
+                let bytes = key.e().to_bytes_le();
+                *slice_as_array!(&bytes, [u8; 3]).unwrap()
+            },
+
+            WalletKey::Ed25519(_) => ED25519_EXPONENT,
+        }
     }
 
 }
 
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::wallet::public_wallet::WalletValidationResult;
+    use crate::transaction::TransactionValidationResult;
+    use crate::transaction::builder::TransactionBuilder;
+    use crate::transaction::transfer::TransferBuilder;
+
+    #[test]
+    fn test_wallet_export()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let exported = wallet.export_wif();
+        assert!(exported.starts_with("hcw1:"));
+
+        let imported = PrivateWallet::import_wif(&exported).unwrap();
+        assert_eq!(imported.get_address(), wallet.get_address());
+        assert_eq!(imported.serialize(), wallet.serialize());
+
+        // Move the export through a file, as if it were copied to another node
+        let path = std::env::temp_dir().join(format!("{}.wif", rand::random::<u32>()));
+        std::fs::write(&path, format!("{}\n", exported)).unwrap();
+        let from_file = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let other_node_wallet = PrivateWallet::import_wif(&from_file).unwrap();
+        assert_eq!(other_node_wallet.get_address(), wallet.get_address());
+        assert_eq!(other_node_wallet.get_e(), wallet.get_e());
+
+        // Corrupted exports are rejected
+        let mut corrupted = exported.clone();
+        let last = corrupted.pop().unwrap();
+        corrupted.push(if last == 'a' { 'b' } else { 'a' });
+        assert!(PrivateWallet::import_wif(&corrupted).is_err());
+        assert!(PrivateWallet::import_wif("hcw9:abc").is_err());
+    }
+
+    #[test]
+    fn test_small_key_wallet()
+    {
+        let wallet = PrivateWallet::new_with_key_bits(1024).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        assert_eq!(wallet.key_bits(), 1024);
+        assert_eq!(other.key_bits(), WALLET_KEY_BITS);
+        assert!(PrivateWallet::new_with_key_bits(WALLET_KEY_BITS * 2).is_err());
+        assert!(PrivateWallet::new_with_key_bits(MIN_WALLET_KEY_BITS - 8).is_err());
+
+        let transfer = TransactionBuilder::new(
+            TransferBuilder::new(1, 0.25)
+                .add_output(other.get_address(), 1.0)
+                .build())
+            .add_input(&wallet, 1.25)
+            .build().unwrap();
+        assert_eq!(transfer.validate_content(SignatureSchemeKind::Rsa).unwrap(), TransactionValidationResult::Ok);
+
+        // Signed by the wrong key
+        let hash = transfer.hash().unwrap();
+        let signature = Signature::from(&other.sign(hash.data()).unwrap());
+        assert_eq!(wallet.as_public().verify(hash.data(), signature.data()).unwrap(),
+                   WalletValidationResult::Signature);
+
+        let signature = Signature::from(&wallet.sign(hash.data()).unwrap());
+        assert_eq!(wallet.as_public().verify(hash.data(), signature.data()).unwrap(),
+                   WalletValidationResult::Ok);
+
+        // Keys below the minimum size are never valid, even if signed correctly
+        let weak = PrivateWallet { key: WalletKey::Rsa(RSAPrivateKey::new(&mut OsRng, 512).unwrap()) };
+        let signature = Signature::from(&weak.sign(hash.data()).unwrap());
+        assert_eq!(weak.as_public().verify(hash.data(), signature.data()).unwrap(),
+                   WalletValidationResult::Key);
+    }
+
+    #[test]
+    fn test_inspect_wallet()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let path = std::env::temp_dir().join(format!("{}.wallet", rand::random::<u32>()));
+        wallet.write_to_file(&path).unwrap();
+
+        let inspection = PrivateWallet::inspect(&path).unwrap();
+        assert_eq!(inspection, WalletInspection
+        {
+            address: wallet.get_address(),
+            key_bits: WALLET_KEY_BITS,
+            status: WalletKeyStatus::Ok,
+        });
+
+        // Keys too small to make valid signatures
+        let weak = PrivateWallet { key: WalletKey::Rsa(RSAPrivateKey::new(&mut OsRng, 512).unwrap()) };
+        weak.write_to_file(&path).unwrap();
+        assert_eq!(PrivateWallet::inspect(&path).unwrap().status, WalletKeyStatus::KeySize);
+
+        // Truncated or garbage files are reported, not panicked on
+        let mut corrupted = wallet.serialize();
+        corrupted.truncate(corrupted.len() / 2);
+        std::fs::write(&path, &corrupted).unwrap();
+        let error = PrivateWallet::inspect(&path).unwrap_err();
+        assert!(error.to_string().contains("does not contain a valid private key"));
+
+        std::fs::write(&path, b"not a wallet").unwrap();
+        assert!(PrivateWallet::inspect(&path).is_err());
+
+        let _ = std::fs::remove_file(&path);
+        assert!(PrivateWallet::inspect(&path).is_err());
+    }
+
+}
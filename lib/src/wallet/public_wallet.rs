@@ -6,8 +6,9 @@
 
 use super::Wallet;
 use crate::hash::Signature;
+use crate::config::MIN_WALLET_KEY_BITS;
 use serde::{Serialize, Deserialize};
-use rsa::{RSAPublicKey, PublicKey, PaddingScheme, BigUint};
+use rsa::{RSAPublicKey, PublicKey, PublicKeyParts, PaddingScheme, BigUint};
 use std::error::Error;
 
 big_array! { BigArray; }
@@ -17,6 +18,8 @@ pub enum WalletValidationResult
 {
     Ok,
     Signature,
+    Exponent,
+    Key,
 }
 
 impl std::fmt::Display for WalletValidationResult
@@ -28,11 +31,20 @@ impl std::fmt::Display for WalletValidationResult
         {
             WalletValidationResult::Ok => write!(f, "Ok"),
             WalletValidationResult::Signature => write!(f, "Signature not valid"),
+            WalletValidationResult::Exponent => write!(f, "Public exponent not valid"),
+            WalletValidationResult::Key => write!(f, "Public key not valid"),
         }
     }
 
 }
 
+// NOTE: RSA needs an odd exponent greater then one
+pub fn is_valid_exponent(e: &[u8; 3]) -> bool
+{
+    let e = u32::from_le_bytes([e[0], e[1], e[2], 0]);
+    e > 1 && e % 2 == 1
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PublicWallet
 {
@@ -74,10 +86,23 @@ impl PublicWallet
     pub fn verify(&self, hash: &[u8], signature: &[u8]) -> Result<WalletValidationResult, Box<dyn Error>>
     {
         assert_eq!(self.e.is_none(), false);
+        if !is_valid_exponent(&self.e.unwrap()) {
+            return Ok(WalletValidationResult::Exponent);
+        }
 
         let n = BigUint::from_bytes_le(self.public_key.data());
         let e = BigUint::from_bytes_le(&self.e.unwrap());
         let key = RSAPublicKey::new(n, e)?;
+        if key.size() * 8 < MIN_WALLET_KEY_BITS {
+            return Ok(WalletValidationResult::Key);
+        }
+
+        // NOTE: Signatures from smaller keys are padded to the full length
+        if key.size() > signature.len() || signature[key.size()..].iter().any(|x| *x != 0) {
+            return Ok(WalletValidationResult::Signature);
+        }
+
+        let signature = &signature[..key.size()];
         if key.verify(PaddingScheme::new_pkcs1v15_sign(None), hash, signature).is_ok() {
             Ok(WalletValidationResult::Ok)
         } else {
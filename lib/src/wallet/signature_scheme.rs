@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use super::public_wallet::{PublicWallet, WalletValidationResult, is_valid_exponent};
+use crate::hash::Signature;
+
+use ed25519_dalek::{VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use std::convert::TryInto;
+use std::error::Error;
+
+// NOTE: Ed25519 keys have no exponent, so inputs leave it as zero
+pub const ED25519_EXPONENT: [u8; 3] = [0; 3];
+
+// How the inputs of a chain's transactions are signed. Every scheme
+// uses the same fixed size key and signature fields, padded with zeros,
+// so the transaction format doesn't depend on the scheme.
+pub trait SignatureScheme
+{
+
+    // NOTE: Checked before any signature, so a key can only
+    //       ever be valid under one scheme
+    fn validate_key(&self, public_key: &Signature, e: &[u8; 3]) -> WalletValidationResult;
+
+    fn verify(&self, public_key: &Signature, e: &[u8; 3], hash: &[u8], signature: &[u8])
+        -> Result<WalletValidationResult, Box<dyn Error>>;
+
+}
+
+pub struct RsaScheme;
+pub struct Ed25519Scheme;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignatureSchemeKind
+{
+    Rsa,
+    Ed25519,
+}
+
+impl SignatureSchemeKind
+{
+
+    pub fn scheme(&self) -> &'static dyn SignatureScheme
+    {
+        match self
+        {
+            SignatureSchemeKind::Rsa => &RsaScheme,
+            SignatureSchemeKind::Ed25519 => &Ed25519Scheme,
+        }
+    }
+
+}
+
+impl SignatureScheme for RsaScheme
+{
+
+    fn validate_key(&self, _public_key: &Signature, e: &[u8; 3]) -> WalletValidationResult
+    {
+        if is_valid_exponent(e) {
+            WalletValidationResult::Ok
+        } else {
+            WalletValidationResult::Exponent
+        }
+    }
+
+    fn verify(&self, public_key: &Signature, e: &[u8; 3], hash: &[u8], signature: &[u8])
+        -> Result<WalletValidationResult, Box<dyn Error>>
+    {
+        PublicWallet::from_public_key_e(*public_key, *e).verify(hash, signature)
+    }
+
+}
+
+fn is_zero_padded(data: &[u8], len: usize) -> bool
+{
+    data.len() >= len && data[len..].iter().all(|x| *x == 0)
+}
+
+fn ed25519_key(public_key: &Signature) -> Option<VerifyingKey>
+{
+    let bytes: &[u8; PUBLIC_KEY_LENGTH] = public_key.data()[..PUBLIC_KEY_LENGTH].try_into().ok()?;
+    VerifyingKey::from_bytes(bytes).ok()
+}
+
+impl SignatureScheme for Ed25519Scheme
+{
+
+    fn validate_key(&self, public_key: &Signature, e: &[u8; 3]) -> WalletValidationResult
+    {
+        if e != &ED25519_EXPONENT {
+            return WalletValidationResult::Exponent;
+        }
+
+        // NOTE: Anything in the padding would give the same key another address
+        if !is_zero_padded(public_key.data(), PUBLIC_KEY_LENGTH) || ed25519_key(public_key).is_none() {
+            return WalletValidationResult::Key;
+        }
+
+        WalletValidationResult::Ok
+    }
+
+    fn verify(&self, public_key: &Signature, e: &[u8; 3], hash: &[u8], signature: &[u8])
+        -> Result<WalletValidationResult, Box<dyn Error>>
+    {
+        let key_result = self.validate_key(public_key, e);
+        if key_result != WalletValidationResult::Ok {
+            return Ok(key_result);
+        }
+
+        let key = match ed25519_key(public_key)
+        {
+            Some(key) => key,
+            None => return Ok(WalletValidationResult::Key),
+        };
+
+        if !is_zero_padded(signature, SIGNATURE_LENGTH) {
+            return Ok(WalletValidationResult::Signature);
+        }
+
+        let signature = ed25519_dalek::Signature::from_slice(&signature[..SIGNATURE_LENGTH])?;
+        if key.verify_strict(hash, &signature).is_ok() {
+            Ok(WalletValidationResult::Ok)
+        } else {
+            Ok(WalletValidationResult::Signature)
+        }
+    }
+
+}
+
@@ -0,0 +1,53 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use crate::network::NetworkConnection;
+use crate::node::packet_handler::NodePacketHandler;
+
+use libhyperchain::service::command::Response;
+use libhyperchain::hash::Hash;
+use libhyperchain::config::HASH_LEN;
+
+pub fn address_activity(connection: &mut NetworkConnection<NodePacketHandler>,
+                        address_vec: Vec<u8>, from: u64, to: u64) -> Response
+{
+    if address_vec.len() != HASH_LEN {
+        return Response::Failed;
+    }
+
+    let address = Hash::from(&address_vec);
+
+    let mut node = connection.handler().node();
+    let chain = node.chain();
+    let activity = chain.get_address_activity(&address, from, to);
+    Response::AddressActivity(activity)
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::node::tests::{create_node, mine_block};
+    use libhyperchain::wallet::Wallet;
+    use libhyperchain::wallet::private_wallet::PrivateWallet;
+
+    #[test]
+    fn test_address_activity()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut connection = create_node(8127);
+        mine_block(&mut connection, &wallet);
+
+        let address = wallet.get_address().data().to_vec();
+        assert_eq!(address_activity(&mut connection, address, 0, 10), Response::AddressActivity(Vec::new()));
+        assert_eq!(address_activity(&mut connection, vec![0u8; 33], 0, 10), Response::Failed);
+    }
+
+}
+
@@ -9,12 +9,13 @@ use crate::node::packet_handler::NodePacketHandler;
 
 use libhyperchain::service::command::Response;
 use libhyperchain::hash::Hash;
+use libhyperchain::config::HASH_LEN;
 
 pub fn balance(connection: &mut NetworkConnection<NodePacketHandler>,
                address_vec: Vec<u8>) -> Response
 {
-    let mut node = connection.handler().node();
-    let chain = node.chain();
+    let node = connection.handler().node_reader();
+    let chain = node.chain_ref();
 
     // TODO: Varify this is a valid hash
     let address = Hash::from(&address_vec);
@@ -23,3 +24,136 @@ pub fn balance(connection: &mut NetworkConnection<NodePacketHandler>,
     Response::WalletStatus(status)
 }
 
+pub fn balance_multi(connection: &mut NetworkConnection<NodePacketHandler>,
+                     address_vecs: Vec<Vec<u8>>) -> Response
+{
+    let node = connection.handler().node_reader();
+    let chain = node.chain_ref();
+
+    // NOTE: Hashing a longer address would overrun it
+    if address_vecs.iter().any(|address_vec| address_vec.len() != HASH_LEN) {
+        return Response::Failed;
+    }
+
+    let addresses = address_vecs
+        .iter()
+        .map(|address_vec| Hash::from(address_vec))
+        .collect::<Vec<_>>();
+
+    let statuses = chain.get_wallet_statuses(&addresses);
+    Response::WalletStatuses(statuses)
+}
+
+// The wallet's status just after block `height`, ignoring any blocks since
+pub fn balance_at_height(connection: &mut NetworkConnection<NodePacketHandler>,
+                         address_vec: Vec<u8>, height: u64) -> Response
+{
+    let node = connection.handler().node_reader();
+    let chain = node.chain_ref();
+
+    let is_past_top = match chain.top_ref()
+    {
+        Some(top) => height > top.header.block_id,
+        None => true,
+    };
+    if is_past_top || address_vec.len() != HASH_LEN {
+        return Response::Failed;
+    }
+
+    let address = Hash::from(&address_vec);
+    let status = chain.get_wallet_status_up_to_block(height, &address);
+    Response::WalletStatus(status)
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::node::tests::{create_node, mine_block};
+    use libhyperchain::wallet::Wallet;
+    use libhyperchain::wallet::private_wallet::PrivateWallet;
+    use libhyperchain::block::Block;
+    use libhyperchain::miner;
+
+    #[test]
+    fn test_balance_multi()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let unused = PrivateWallet::open_temp(2).unwrap();
+        let mut connection = create_node(8092);
+        mine_block(&mut connection, &wallet);
+        mine_block(&mut connection, &other);
+        mine_block(&mut connection, &wallet);
+
+        let addresses = vec![
+            other.get_address().data().to_vec(),
+            unused.get_address().data().to_vec(),
+            wallet.get_address().data().to_vec(),
+        ];
+
+        let statuses = match balance_multi(&mut connection, addresses.clone())
+        {
+            Response::WalletStatuses(statuses) => statuses,
+            _ => panic!("Expected wallet statuses"),
+        };
+        assert_eq!(statuses.len(), 3);
+
+        for (address, status) in addresses.iter().cloned().zip(statuses) {
+            assert_eq!(balance(&mut connection, address), Response::WalletStatus(status));
+        }
+
+        // Any address that isn't a hash fails the whole lookup
+        let mut invalid = addresses.clone();
+        invalid.push(vec![0u8; 33]);
+        assert_eq!(balance_multi(&mut connection, invalid), Response::Failed);
+        assert_eq!(balance_multi(&mut connection, vec![vec![0u8; 4]]), Response::Failed);
+    }
+
+    #[test]
+    fn test_balance_at_height()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut connection = create_node(8122);
+        let address = wallet.get_address().data().to_vec();
+        assert_eq!(balance_at_height(&mut connection, address.clone(), 0), Response::Failed);
+
+        mine_block(&mut connection, &wallet);
+        let before = match balance(&mut connection, address.clone())
+        {
+            Response::WalletStatus(status) => status,
+            _ => panic!("Expected wallet status"),
+        };
+
+        // Spend some of it in a later block
+        {
+            let mut node = connection.handler().node();
+            let chain = node.chain();
+            let transfer = chain.new_transfer(vec![(&wallet, 1.5)],
+                vec![(other.get_address(), 1.0)], 0.5).unwrap();
+            let block = miner::mine_block(Block::new(chain, &other, vec![transfer], Vec::new()).unwrap());
+            chain.add(&block).unwrap();
+        }
+
+        let after = match balance(&mut connection, address.clone())
+        {
+            Response::WalletStatus(status) => status,
+            _ => panic!("Expected wallet status"),
+        };
+        assert_eq!(after.balance, before.balance - 1.5);
+        assert_eq!(after.max_id, before.max_id + 1);
+
+        assert_eq!(balance_at_height(&mut connection, address.clone(), 0), Response::WalletStatus(before));
+        assert_eq!(balance_at_height(&mut connection, address.clone(), 1), Response::WalletStatus(after));
+        assert_eq!(balance_at_height(&mut connection, address, 2), Response::Failed);
+        assert_eq!(balance_at_height(&mut connection, vec![0u8; 33], 0), Response::Failed);
+    }
+
+}
+
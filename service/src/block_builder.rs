@@ -10,16 +10,20 @@ use libhyperchain::chain::BlockChain;
 use libhyperchain::wallet::Wallet;
 use std::error::Error;
 
+pub const TRANSFERS_PER_BLOCK: usize = 10;
+pub const PAGES_PER_BLOCK: usize = 10;
+
 pub fn build<W>(chain: &mut BlockChain, wallet: &W) -> Result<Block, Box<dyn Error>>
     where W: Wallet
 {
     // FIXME: Validate transfer
-    let mut block_builder = BlockBuilder::new(wallet);
-    for transfer in chain.get_next_transfers_in_queue(10) {
-        block_builder = block_builder.add_transfer(transfer.clone());
+    let mut block_builder = BlockBuilder::new(wallet)
+        .with_max_size(chain.config().max_block_size);
+    for transfer in chain.get_next_transfers_in_queue(TRANSFERS_PER_BLOCK) {
+        block_builder.try_add_transfer(transfer)?;
     }
-    for page in chain.get_next_pages_in_queue(10) {
-        block_builder = block_builder.add_page(page.clone());
+    for page in chain.get_next_pages_in_queue(PAGES_PER_BLOCK) {
+        block_builder.try_add_page(page)?;
     }
 
     Ok(block_builder.build(chain)?)
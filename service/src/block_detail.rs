@@ -0,0 +1,186 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use crate::network::NetworkConnection;
+use crate::node::packet_handler::NodePacketHandler;
+
+use libhyperchain::service::command::{Response, BlockDetail, TransferDetail, PageDetail};
+use libhyperchain::transaction::{Transaction, TransactionContent, Input};
+use libhyperchain::transaction::transfer::Transfer;
+use libhyperchain::transaction::page::Page;
+use libhyperchain::block::Block;
+use libhyperchain::block::target::difficulty;
+use libhyperchain::address::encode_address;
+use std::error::Error;
+
+fn input_details(inputs: &[Input]) -> Vec<(String, f32)>
+{
+    inputs
+        .iter()
+        .map(|input| (encode_address(&input.get_address()), input.amount))
+        .collect()
+}
+
+fn transfer_detail(transfer: &Transaction<Transfer>)
+    -> Result<TransferDetail, Box<dyn Error>>
+{
+    let content = &transfer.header.content;
+    Ok(TransferDetail
+    {
+        hash: transfer.hash()?,
+        id: content.id,
+        inputs: input_details(&transfer.header.inputs),
+        outputs: content.outputs
+            .iter()
+            .map(|output| (encode_address(&output.to), output.amount))
+            .collect(),
+        fee: content.get_fee(),
+    })
+}
+
+fn page_detail(page: &Transaction<Page>)
+    -> Result<PageDetail, Box<dyn Error>>
+{
+    let content = &page.header.content;
+    Ok(PageDetail
+    {
+        hash: page.hash()?,
+        id: content.id,
+        site: encode_address(&content.site),
+        inputs: input_details(&page.header.inputs),
+        data_hashes: content.data_hashes.clone(),
+        data_length: content.data_length,
+        fee: content.fee,
+    })
+}
+
+pub fn block_detail_of(block: &Block) -> Result<BlockDetail, Box<dyn Error>>
+{
+    let header = &block.header;
+    Ok(BlockDetail
+    {
+        block_id: header.block_id,
+        hash: block.hash()?,
+        prev_hash: header.prev_hash,
+        timestamp: header.timestamp,
+        reward_to: encode_address(&header.raward_to),
+        reward: header.reward,
+        difficulty: difficulty(&header.target),
+        pow: header.pow,
+
+        merkle_root: header.transaction_merkle_root,
+        calculated_merkle_root: block.calculate_merkle_root()?,
+
+        coinbase: transfer_detail(&block.coinbase)?,
+        transfers: block.transfers
+            .iter()
+            .map(transfer_detail)
+            .collect::<Result<Vec<_>, _>>()?,
+        pages: block.pages
+            .iter()
+            .map(page_detail)
+            .collect::<Result<Vec<_>, _>>()?,
+    })
+}
+
+pub fn block_detail(connection: &mut NetworkConnection<NodePacketHandler>,
+                    block_id: u64)
+    -> Response
+{
+    let node = connection.handler().node_reader();
+    let chain = node.chain_ref();
+
+    let block = match chain.block(block_id)
+    {
+        Some(block) => block,
+        None => return Response::NotFound,
+    };
+
+    match block_detail_of(&block)
+    {
+        Ok(detail) => Response::BlockDetail(detail),
+        Err(_) => Response::Failed,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::node::tests::{create_node, mine_block};
+    use libhyperchain::miner;
+    use libhyperchain::transaction::builder::TransactionBuilder;
+    use libhyperchain::chain::BlockChainAddResult;
+    use libhyperchain::wallet::Wallet;
+    use libhyperchain::wallet::private_wallet::PrivateWallet;
+    use libhyperchain::config::PAGE_CHUNK_SIZE;
+    use libhyperchain::hash::Hash;
+
+    #[test]
+    fn test_block_detail()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut connection = create_node(8100);
+        mine_block(&mut connection, &wallet);
+        mine_block(&mut connection, &other);
+
+        let block =
+        {
+            let mut node = connection.handler().node();
+            let chain = node.chain();
+            let transfer = chain.new_transfer(vec![(&wallet, 3.0)],
+                vec![(other.get_address(), 2.0)], 1.0).unwrap();
+
+            let page_content = Page::new(1, other.get_address(),
+                vec![Hash::empty()], PAGE_CHUNK_SIZE as u32, 1.0);
+            let page = TransactionBuilder::new(page_content)
+                .add_input(&other, 2.0)
+                .build().unwrap();
+
+            let block = miner::mine_block(Block::new(chain, &wallet,
+                vec![transfer], vec![page]).unwrap());
+            assert_eq!(chain.add(&block).unwrap(), BlockChainAddResult::Ok);
+            block
+        };
+
+        let detail = match block_detail(&mut connection, 2)
+        {
+            Response::BlockDetail(detail) => detail,
+            response => panic!("Expected block detail, got {:?}", response),
+        };
+
+        assert_eq!(detail.block_id, 2);
+        assert_eq!(detail.hash, block.hash().unwrap());
+        assert_eq!(detail.prev_hash, block.header.prev_hash);
+        assert_eq!(detail.reward_to, encode_address(&wallet.get_address()));
+        assert_eq!(detail.merkle_root, block.header.transaction_merkle_root);
+        assert_eq!(detail.calculated_merkle_root, detail.merkle_root);
+        assert_eq!(detail.coinbase.outputs[0].0, encode_address(&wallet.get_address()));
+
+        assert_eq!(detail.transfers.len(), 1);
+        let transfer = &detail.transfers[0];
+        assert_eq!(transfer.hash, block.transfers[0].hash().unwrap());
+        assert_eq!(transfer.inputs, vec![(encode_address(&wallet.get_address()), 3.0)]);
+        assert_eq!(transfer.outputs, vec![(encode_address(&other.get_address()), 2.0)]);
+        assert_eq!(transfer.fee, 1.0);
+
+        assert_eq!(detail.pages.len(), 1);
+        let page = &detail.pages[0];
+        assert_eq!(page.site, encode_address(&other.get_address()));
+        assert_eq!(page.inputs, vec![(encode_address(&other.get_address()), 2.0)]);
+        assert_eq!(page.data_hashes, vec![Hash::empty()]);
+        assert_eq!(page.data_length, PAGE_CHUNK_SIZE as u32);
+        assert_eq!(page.fee, 1.0);
+
+        assert_eq!(block_detail(&mut connection, 3), Response::NotFound);
+    }
+
+}
+
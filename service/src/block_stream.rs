@@ -0,0 +1,163 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use crate::node::packet_handler::NodePacketHandler;
+use crate::network::error::NetworkError;
+
+use libhyperchain::block::Block;
+use tcp_channel::{SenderBuilder, ChannelSend, LittleEndian};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::io::{BufWriter, ErrorKind};
+use std::error::Error;
+
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn stream_blocks(stream: TcpStream, blocks: Receiver<Block>)
+{
+    let mut sender = SenderBuilder::new()
+        .with_type::<Block>()
+        .with_endianness::<LittleEndian>()
+        .build(BufWriter::new(stream));
+
+    // NOTE: Returning drops the receiver, which unsubscribes it the 
+    //       next time a block is published.
+    for block in blocks
+    {
+        if sender.send(&block).is_err() || sender.flush().is_err() {
+            break;
+        }
+    }
+}
+
+pub fn start_block_stream_thread(handler: NodePacketHandler, port: u16,
+                                 shutdown_signal: Arc<Mutex<bool>>)
+    -> Result<JoinHandle<()>, Box<dyn Error>>
+{
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port))
+        .map_err(|err| NetworkError::from_bind_error(port, err))?;
+
+    // NOTE: Accept without blocking, so the shutdown signal is seen 
+    //       even if nobody connects.
+    listener.set_nonblocking(true)?;
+
+    info!("Streaming new blocks on port {}", port);
+    Ok(std::thread::spawn(move || loop
+    {
+        if *shutdown_signal.lock().unwrap() {
+            break;
+        }
+
+        match listener.accept()
+        {
+            Ok((stream, _)) =>
+            {
+                if let Err(err) = stream.set_nonblocking(false)
+                {
+                    error!("Block stream error: {}", err);
+                    continue;
+                }
+
+                let blocks = handler.node().subscribe_blocks();
+                std::thread::spawn(move || stream_blocks(stream, blocks));
+            },
+
+            Err(err) if err.kind() == ErrorKind::WouldBlock =>
+                std::thread::sleep(ACCEPT_POLL_INTERVAL),
+
+            Err(err) =>
+            {
+                error!("Block stream error: {}", err);
+                break;
+            },
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::node::MAX_QUEUED_SUBSCRIBER_BLOCKS;
+    use crate::node::tests::{create_node, mine_block, wait_for_block};
+    use libhyperchain::wallet::private_wallet::PrivateWallet;
+    use tcp_channel::{ReceiverBuilder, ChannelRecv};
+    use std::io::BufReader;
+    use std::time::Duration;
+
+    #[test]
+    fn test_block_stream()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut connection_a = create_node(8084);
+        let mut connection_b = create_node(8086);
+        connection_b.manager().register_node("127.0.0.1:8084");
+        mine_block(&mut connection_a, &wallet);
+        wait_for_block(&connection_b, 0);
+
+        let block_stream_thread = start_block_stream_thread(connection_b.handler().clone(), 8085, 
+            connection_b.shutdown_signal()).unwrap();
+
+        let stream = TcpStream::connect("127.0.0.1:8085").unwrap();
+        let mut receiver = ReceiverBuilder::new()
+            .with_type::<Block>()
+            .with_endianness::<LittleEndian>()
+            .build(BufReader::new(stream.try_clone().unwrap()));
+
+        // Wait for the subscription to be registered
+        while connection_b.handler().node().block_subscriber_count() == 0 {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // Each block b accepts from a is streamed
+        let mut blocks = Vec::new();
+        for _ in 0..3 {
+            blocks.push(mine_block(&mut connection_a, &wallet));
+        }
+        for block in &blocks {
+            assert_eq!(&receiver.recv().unwrap(), block);
+        }
+
+        // Once disconnected, the subscriber is removed
+        stream.shutdown(std::net::Shutdown::Both).unwrap();
+        drop(receiver);
+
+        let mut timer = 0;
+        while connection_b.handler().node().block_subscriber_count() > 0 && timer < 10000
+        {
+            mine_block(&mut connection_a, &wallet);
+            std::thread::sleep(Duration::from_millis(500));
+            timer += 500;
+        }
+        assert_eq!(connection_b.handler().node().block_subscriber_count(), 0);
+
+        // As is one that stops reading, once too many blocks are queued for it
+        {
+            let mut node = connection_b.handler().node();
+            let _slow_subscriber = node.subscribe_blocks();
+            for _ in 0..MAX_QUEUED_SUBSCRIBER_BLOCKS 
+            {
+                node.publish_block(&blocks[0]);
+                assert_eq!(node.block_subscriber_count(), 1);
+            }
+
+            node.publish_block(&blocks[0]);
+            assert_eq!(node.block_subscriber_count(), 0);
+        }
+
+        // The thread stops on shutdown, without needing a connection to wake it
+        *connection_b.shutdown_signal().lock().unwrap() = true;
+        block_stream_thread.join().unwrap();
+    }
+
+}
+
@@ -14,8 +14,8 @@ pub fn blocks(connection: &mut NetworkConnection<NodePacketHandler>,
               from: u64, to: u64) 
     -> Response
 {
-    let mut node = connection.handler().node();
-    let chain = node.chain();
+    let node = connection.handler().node_reader();
+    let chain = node.chain_ref();
 
     let mut blocks = Vec::<Block>::new();
     for block_id in from..=to
@@ -30,11 +30,20 @@ pub fn blocks(connection: &mut NetworkConnection<NodePacketHandler>,
     Response::Blocks(blocks)
 }
 
+pub fn blocks_by_time(connection: &mut NetworkConnection<NodePacketHandler>,
+                      from: u128, to: u128) 
+    -> Response
+{
+    let node = connection.handler().node_reader();
+    let chain = node.chain_ref();
+    Response::Blocks(chain.blocks_in_time_range(from, to))
+}
+
 pub fn top_block(connection: &mut NetworkConnection<NodePacketHandler>)
     -> Response
 {
-    let mut node = connection.handler().node();
-    let chain = node.chain();
+    let node = connection.handler().node_reader();
+    let chain = node.chain_ref();
 
     match chain.top()
     {
@@ -0,0 +1,124 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use crate::network::NetworkConnection;
+use crate::node::packet_handler::NodePacketHandler;
+use crate::block_builder::TRANSFERS_PER_BLOCK;
+
+use libhyperchain::service::command::{Response, ConfirmationTime};
+use libhyperchain::block::Block;
+use libhyperchain::chain::BlockChain;
+use libhyperchain::hash::Hash;
+use std::error::Error;
+
+// NOTE: Queued transfers paying at least as much are mined first, so
+//       count the blocks needed to clear them, limited by both the
+//       number of transfers and the size of each block.
+fn estimate_blocks(chain: &BlockChain, fee_rate: f32) -> Result<u64, Box<dyn Error>>
+{
+    let mut count = 0;
+    let mut size = 0;
+    for transfer in chain.transfers_in_queue_ahead_of(fee_rate)
+    {
+        count += 1;
+        size += transfer.size_bytes()?;
+    }
+
+    let empty_size = Block::empty_size_bytes(Hash::empty())?;
+    let room_per_block = std::cmp::max(chain.config().max_block_size.saturating_sub(empty_size), 1);
+    let blocks_ahead = std::cmp::max(count / TRANSFERS_PER_BLOCK, size / room_per_block);
+    Ok(blocks_ahead as u64 + 1)
+}
+
+pub fn estimate_confirmation_time(connection: &mut NetworkConnection<NodePacketHandler>,
+                                  fee_rate: f32)
+    -> Response
+{
+    let node = connection.handler().node_reader();
+    let chain = node.chain_ref();
+
+    let blocks = match estimate_blocks(chain, fee_rate)
+    {
+        Ok(blocks) => blocks,
+        Err(_) => return Response::Failed,
+    };
+
+    // Block time is in milliseconds
+    Response::ConfirmationTime(ConfirmationTime
+    {
+        blocks,
+        seconds: blocks * chain.config().block_time / 1000,
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::node::tests::{create_node, mine_block};
+    use libhyperchain::wallet::Wallet;
+    use libhyperchain::wallet::private_wallet::PrivateWallet;
+
+    fn get_confirmation_time(connection: &mut NetworkConnection<NodePacketHandler>,
+                             fee_rate: f32)
+        -> ConfirmationTime
+    {
+        match estimate_confirmation_time(connection, fee_rate)
+        {
+            Response::ConfirmationTime(time) => time,
+            response => panic!("Expected confirmation time, got {:?}", response),
+        }
+    }
+
+    #[test]
+    fn test_estimate_confirmation_time()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut connection = create_node(8105);
+        let block_time = connection.handler().node_reader().chain_ref().config().block_time;
+
+        // Nothing to wait for with an empty mempool
+        let time = get_confirmation_time(&mut connection, 0.0);
+        assert_eq!(time, ConfirmationTime { blocks: 1, seconds: block_time / 1000 });
+
+        mine_block(&mut connection, &wallet);
+        let mut fee_rates =
+        {
+            let mut node = connection.handler().node();
+            let chain = node.chain();
+            (0..25)
+                .map(|i|
+                {
+                    let fee = 0.1 + i as f32 * 0.01;
+                    let transfer = chain.new_transfer(vec![(&wallet, fee + 0.01)],
+                        vec![(other.get_address(), 0.01)], fee).unwrap();
+                    let fee_rate = transfer.fee_rate();
+                    chain.push_transfer_queue(transfer).unwrap();
+                    fee_rate
+                })
+                .collect::<Vec<_>>()
+        };
+        fee_rates.sort_by(|a, b| b.partial_cmp(a).unwrap());
+
+        // Paying more then everything in the mempool
+        let time = get_confirmation_time(&mut connection, fee_rates[0] * 2.0);
+        assert_eq!(time.blocks, 1);
+
+        // Behind the 13 best paying transfers
+        let time = get_confirmation_time(&mut connection, fee_rates[12]);
+        assert_eq!(time, ConfirmationTime { blocks: 2, seconds: 2 * block_time / 1000 });
+
+        // Behind everything
+        let time = get_confirmation_time(&mut connection, 0.0);
+        assert_eq!(time, ConfirmationTime { blocks: 3, seconds: 3 * block_time / 1000 });
+    }
+
+}
+
@@ -10,6 +10,7 @@ extern crate rand;
 extern crate pretty_env_logger;
 extern crate serde_json;
 extern crate base_62;
+extern crate ctrlc;
 
 #[macro_use]
 extern crate log;
@@ -26,27 +27,92 @@ mod transaction_history;
 mod page;
 mod blocks;
 mod statistics;
+mod address_activity;
+mod status;
+mod network_info;
+mod block_stream;
 mod report;
+mod block_detail;
+mod confirmation_time;
+mod mempool;
 
-use miner::start_miner_thread;
-use send::send;
+use miner::{start_miner_thread, RewardWallets};
+use send::{send, send_batch};
 use update_page::update_page;
-use balance::balance;
+use balance::{balance, balance_multi, balance_at_height};
 use transaction_history::transaction_history;
 use transaction_info::transaction_info;
 use page::page_updates;
 use page::page_data;
-use blocks::{blocks, top_block};
+use blocks::{blocks, blocks_by_time, top_block};
 use statistics::statistics;
+use address_activity::address_activity;
+use status::status;
+use network_info::network_info;
+use block_stream::start_block_stream_thread;
+use block_detail::block_detail;
+use confirmation_time::estimate_confirmation_time;
+use mempool::mempool_contents;
 use crate::network::NetworkConnection;
 use crate::node::Node;
 use crate::node::packet_handler::NodePacketHandler;
 
 use libhyperchain::service::server;
+use libhyperchain::service::client::Client;
+use libhyperchain::wallet::private_wallet::PrivateWallet;
+use libhyperchain::chain::BlockChain;
+use libhyperchain::error::ErrorMessage;
+use libhyperchain::config::ChainConfig;
+use libhyperchain::wallet::signature_scheme::SignatureSchemeKind;
 use libhyperchain::service::command::{Command, Response};
 use clap::{App, Arg};
 use std::error::Error;
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::time::Duration;
+
+fn read_listen_addresses(addresses: &str) -> Result<Vec<IpAddr>, Box<dyn Error>>
+{
+    let listen_addresses = addresses
+        .split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| x.parse::<IpAddr>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if listen_addresses.is_empty() {
+        return Err(ErrorMessage::new("No addresses to listen on"));
+    }
+    Ok(listen_addresses)
+}
+
+fn read_signature_scheme(scheme: &str) -> Result<SignatureSchemeKind, Box<dyn Error>>
+{
+    match scheme
+    {
+        "rsa" => Ok(SignatureSchemeKind::Rsa),
+        "ed25519" => Ok(SignatureSchemeKind::Ed25519),
+        _ => Err(ErrorMessage::new(&format!("Unknown signature scheme '{}'", scheme))),
+    }
+}
+
+fn read_reward_wallets(wallets: &str) -> Result<RewardWallets, Box<dyn Error>>
+{
+    let mut wallets_and_weights = Vec::new();
+    for wallet in wallets.split(',').map(str::trim).filter(|x| !x.is_empty())
+    {
+        let (path, weight) = match wallet.rsplit_once(':')
+        {
+            Some((path, weight)) => (path, weight.parse::<u32>()?),
+            None => (wallet, 1),
+        };
+
+        let wallet = PrivateWallet::read_from_file(&PathBuf::from(path))?;
+        wallets_and_weights.push((wallet, weight));
+    }
+
+    RewardWallets::new(wallets_and_weights)
+}
 
 fn main() -> Result<(), Box<dyn Error>>
 {
@@ -74,6 +140,71 @@ fn main() -> Result<(), Box<dyn Error>>
             .takes_value(false)
             .required(false)
             .help("Disable mining"))
+        .arg(Arg::with_name("verify")
+            .long("verify")
+            .takes_value(false)
+            .required(false)
+            .help("Validate the local chain and exit"))
+        .arg(Arg::with_name("import-blocks")
+            .long("import-blocks")
+            .takes_value(true)
+            .required(false)
+            .help("Add the blocks from a bootstrap file to the local chain before starting"))
+        .arg(Arg::with_name("slow-packet-ms")
+            .long("slow-packet-ms")
+            .takes_value(true)
+            .required(false)
+            .help("Warn when handling a packet takes longer then this"))
+        .arg(Arg::with_name("block-stream-port")
+            .long("block-stream-port")
+            .takes_value(true)
+            .required(false)
+            .help("Stream each new block to clients connected on this port"))
+        .arg(Arg::with_name("connect-interval-ms")
+            .long("connect-interval-ms")
+            .takes_value(true)
+            .required(false)
+            .help("How often to try connecting to known nodes"))
+        .arg(Arg::with_name("stale-tip-intervals")
+            .long("stale-tip-intervals")
+            .takes_value(true)
+            .required(false)
+            .help("Ask peers for their top block after this many block times without a new one"))
+        .arg(Arg::with_name("block-request-timeout-ms")
+            .long("block-request-timeout-ms")
+            .takes_value(true)
+            .required(false)
+            .help("Ask another peer for a block if it's not sent within this time"))
+        .arg(Arg::with_name("reward-wallets")
+            .long("reward-wallets")
+            .takes_value(true)
+            .required(false)
+            .help("Comma separated wallet files to take turns mining to, each optionally followed by :weight"))
+        .arg(Arg::with_name("no-empty-blocks")
+            .long("no-empty-blocks")
+            .takes_value(false)
+            .required(false)
+            .help("Only mine once there's a transaction waiting to be included"))
+        .arg(Arg::with_name("no-relay")
+            .long("no-relay")
+            .takes_value(false)
+            .required(false)
+            .help("Keep transactions received from peers without passing them on"))
+        .arg(Arg::with_name("listen")
+            .long("listen")
+            .takes_value(true)
+            .required(false)
+            .help("Comma separated IPv4 or IPv6 addresses to accept connections on"))
+        .arg(Arg::with_name("no-encryption")
+            .long("no-encryption")
+            .takes_value(false)
+            .required(false)
+            .help("Disable encrypting connections to other nodes"))
+        .arg(Arg::with_name("signature-scheme")
+            .long("signature-scheme")
+            .takes_value(true)
+            .required(false)
+            .help("How transactions on the chain are signed, either 'rsa' or 'ed25519'"))
         .get_matches();
 
     // Crate logger and read port from command line
@@ -83,19 +214,93 @@ fn main() -> Result<(), Box<dyn Error>>
 
     // Create and open node
     let data_directory = PathBuf::from("hyperchain");
-    let node = Node::new(port, &data_directory)?;
+    let config = ChainConfig
+    {
+        signature_scheme: read_signature_scheme(matches.value_of("signature-scheme").unwrap_or("rsa"))?,
+        ..ChainConfig::default()
+    };
+
+    if matches.is_present("verify")
+    {
+        match BlockChain::validate_all(&data_directory.join("blockchain"), config)?
+        {
+            Some(failure) => println!("Chain is invalid! {}", failure),
+            None => println!("Chain is valid"),
+        }
+        return Ok(());
+    }
+
+    let node = Node::new_with_config(port, &data_directory, config)?;
+    if let Some(bootstrap_path) = matches.value_of("import-blocks")
+    {
+        let report = node.write().unwrap().chain().import_from_file(&PathBuf::from(bootstrap_path))?;
+        println!("Imported {} block(s)", report.blocks_imported);
+        if let Some((offset, reason)) = report.stopped_at {
+            println!("Stopped at byte {}: {}", offset, reason);
+        }
+    }
+
+    if let Some(stale_tip_intervals) = matches.value_of("stale-tip-intervals") {
+        node.write().unwrap().set_stale_tip_intervals(stale_tip_intervals.parse::<u64>()?);
+    }
+
+    if let Some(timeout) = matches.value_of("block-request-timeout-ms") {
+        node.write().unwrap().set_block_request_timeout(Duration::from_millis(timeout.parse::<u64>()?));
+    }
+
+    node.write().unwrap().set_mine_empty_blocks(!matches.is_present("no-empty-blocks"));
+    node.write().unwrap().set_relay_transactions(!matches.is_present("no-relay"));
     let packet_handler = NodePacketHandler::new(node);
 
+    // NOTE: Outlives the connection, so its tasks can finish on shutdown
+    #[cfg(feature = "async-network")]
+    let runtime = tokio::runtime::Runtime::new()?;
+
     let mut miner_thread = None;
+    let mut block_stream_thread = None;
     {
         // Register a common node to connect to
-        let mut network_connection = NetworkConnection::open(port, &data_directory, packet_handler)?;
+        let listen_addresses = read_listen_addresses(matches.value_of("listen").unwrap_or("0.0.0.0"))?;
+        #[cfg(feature = "async-network")]
+        let mut network_connection = NetworkConnection::open_async_on(&listen_addresses, port, 
+            &data_directory, packet_handler, runtime.handle().clone())?;
+        #[cfg(not(feature = "async-network"))]
+        let mut network_connection = NetworkConnection::open_on(&listen_addresses, port, 
+            &data_directory, packet_handler)?;
+        network_connection.manager().set_encryption(!matches.is_present("no-encryption"));
         network_connection.manager().register_node("192.168.0.53:8001");
+        if let Some(slow_packet_ms) = matches.value_of("slow-packet-ms")
+        {
+            let threshold = Duration::from_millis(slow_packet_ms.parse::<u64>()?);
+            network_connection.manager().set_slow_packet_threshold(threshold);
+        }
+        if let Some(connect_interval_ms) = matches.value_of("connect-interval-ms")
+        {
+            let interval = Duration::from_millis(connect_interval_ms.parse::<u64>()?);
+            network_connection.manager().set_connect_interval(interval);
+        }
+
+        if let Some(block_stream_port) = matches.value_of("block-stream-port")
+        {
+            block_stream_thread = Some(start_block_stream_thread(network_connection.handler().clone(), 
+                block_stream_port.parse::<u16>()?, network_connection.shutdown_signal())?);
+        }
+
+        // NOTE: Stop the miner and local server, so the connection 
+        //       can shut down cleanly when it's dropped.
+        let shutdown_signal = network_connection.shutdown_signal();
+        ctrlc::set_handler(move ||
+        {
+            info!("Got shutdown signal");
+            *shutdown_signal.lock().unwrap() = true;
+            let _ = Client::new().and_then(|mut client| client.send(Command::Exit));
+        })?;
 
         // Start miner thread
         if !disable_mining
         {
-            miner_thread = Some(start_miner_thread(network_connection.clone()));
+            let rewards = read_reward_wallets(matches.value_of("reward-wallets").unwrap_or("test.wallet"))?;
+            miner_thread = Some(start_miner_thread(network_connection.clone(), rewards));
             if disable_local_server
             {
                 miner_thread.unwrap().join().unwrap();
@@ -115,9 +320,18 @@ fn main() -> Result<(), Box<dyn Error>>
                 Command::Balance(address) => 
                     balance(&mut connection, address),
 
+                Command::BalanceMulti(addresses) => 
+                    balance_multi(&mut connection, addresses),
+
+                Command::BalanceAtHeight(address, height) => 
+                    balance_at_height(&mut connection, address, height),
+
                 Command::Send(inputs, outputs, fee) =>
                     send(&mut connection, inputs, outputs, fee),
 
+                Command::SendBatch(transfers) =>
+                    send_batch(&mut connection, transfers),
+
                 Command::UpdatePage(from, name, data) =>
                     update_page(&mut connection, from, name, data),
 
@@ -136,12 +350,33 @@ fn main() -> Result<(), Box<dyn Error>>
                 Command::Blocks(from, to) =>
                     blocks(&mut connection, from, to),
 
+                Command::BlocksByTime(from, to) =>
+                    blocks_by_time(&mut connection, from, to),
+
                 Command::TopBlock =>
                     top_block(&mut connection),
 
                 Command::Statistics =>
                     statistics(&mut connection),
 
+                Command::AddressActivity(address, from, to) =>
+                    address_activity(&mut connection, address, from, to),
+
+                Command::Status =>
+                    status(&mut connection),
+
+                Command::NetworkInfo =>
+                    network_info(&mut connection),
+
+                Command::BlockDetail(block_id) =>
+                    block_detail(&mut connection, block_id),
+
+                Command::EstimateConfirmationTime(fee_rate) =>
+                    estimate_confirmation_time(&mut connection, fee_rate),
+
+                Command::MempoolContents(offset, count) =>
+                    mempool_contents(&mut connection, offset, count),
+
             }
         })?;
     }
@@ -149,6 +384,9 @@ fn main() -> Result<(), Box<dyn Error>>
     if miner_thread.is_some() {
         miner_thread.unwrap().join().unwrap();
     }
+    if let Some(block_stream_thread) = block_stream_thread {
+        block_stream_thread.join().unwrap();
+    }
     Ok(())
 }
 
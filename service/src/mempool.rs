@@ -0,0 +1,154 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use crate::network::NetworkConnection;
+use crate::node::packet_handler::NodePacketHandler;
+
+use libhyperchain::service::command::{Response, MempoolContents, MempoolEntry};
+use libhyperchain::transaction::{TransactionContent, TransactionVariant};
+use std::cmp::Ordering;
+use std::error::Error;
+
+const MAX_MEMPOOL_ENTRIES: u64 = 1000;
+
+fn mempool_entry(transaction: TransactionVariant) -> Result<MempoolEntry, Box<dyn Error>>
+{
+    let (hash, fee, size, fee_rate) = match &transaction
+    {
+        TransactionVariant::Transfer(transfer) =>
+            (transfer.hash()?, transfer.header.content.get_fee(),
+             transfer.size_bytes()?, transfer.fee_rate()),
+
+        TransactionVariant::Page(page) =>
+            (page.hash()?, page.header.content.get_fee(),
+             page.size_bytes()?, page.fee_rate()),
+    };
+
+    Ok(MempoolEntry
+    {
+        hash,
+        transaction,
+        fee,
+        size,
+        fee_rate,
+    })
+}
+
+pub fn mempool_contents(connection: &mut NetworkConnection<NodePacketHandler>,
+                        offset: u64, count: Option<u64>)
+    -> Response
+{
+    let node = connection.handler().node_reader();
+    let chain = node.chain_ref();
+
+    let entries_or_error = chain.transactions_in_queue()
+        .into_iter()
+        .map(mempool_entry)
+        .collect::<Result<Vec<_>, _>>();
+
+    let mut entries = match entries_or_error
+    {
+        Ok(entries) => entries,
+        Err(_) => return Response::Failed,
+    };
+    entries.sort_by(|a, b| b.fee_rate.partial_cmp(&a.fee_rate).unwrap_or(Ordering::Equal));
+
+    // NOTE: The mempool can be large, so only send a page of it at a time
+    let count = std::cmp::min(count.unwrap_or(MAX_MEMPOOL_ENTRIES), MAX_MEMPOOL_ENTRIES);
+    let total = entries.len();
+    Response::MempoolContents(MempoolContents
+    {
+        total,
+        entries: entries
+            .into_iter()
+            .skip(offset as usize)
+            .take(count as usize)
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::node::tests::{create_node, mine_block};
+    use libhyperchain::data_store::data_unit::DataUnit;
+    use libhyperchain::data_store::page::CreatePageData;
+    use libhyperchain::wallet::Wallet;
+    use libhyperchain::wallet::private_wallet::PrivateWallet;
+
+    fn get_mempool_contents(connection: &mut NetworkConnection<NodePacketHandler>,
+                            offset: u64, count: Option<u64>)
+        -> MempoolContents
+    {
+        match mempool_contents(connection, offset, count)
+        {
+            Response::MempoolContents(contents) => contents,
+            response => panic!("Expected mempool contents, got {:?}", response),
+        }
+    }
+
+    #[test]
+    fn test_mempool_contents()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut connection = create_node(8111);
+        assert_eq!(get_mempool_contents(&mut connection, 0, None),
+            MempoolContents { total: 0, entries: Vec::new() });
+
+        mine_block(&mut connection, &wallet);
+        let (transfers, page) =
+        {
+            let mut node = connection.handler().node();
+            let chain = node.chain();
+            let transfers = [0.1, 0.3, 0.2]
+                .iter()
+                .map(|fee|
+                {
+                    let transfer = chain.new_transfer(vec![(&wallet, fee + 0.5)],
+                        vec![(other.get_address(), 0.5)], *fee).unwrap();
+                    chain.push_transfer_queue(transfer.clone()).unwrap();
+                    transfer
+                })
+                .collect::<Vec<_>>();
+
+            let data_unit = DataUnit::CreatePage(
+                CreatePageData::new("test".to_owned(), vec![0, 1, 2]));
+            let page = chain.new_page(&wallet, &data_unit, 1.0).unwrap();
+            chain.push_page_queue(page.clone()).unwrap();
+            (transfers, page)
+        };
+
+        // Everything is listed, best paying first
+        let contents = get_mempool_contents(&mut connection, 0, None);
+        assert_eq!(contents.total, 4);
+        assert!(contents.entries.windows(2).all(|x| x[0].fee_rate >= x[1].fee_rate));
+
+        let entry = contents.entries.iter().find(|x| x.hash == page.hash().unwrap()).unwrap();
+        assert_eq!(entry.transaction, TransactionVariant::Page(page.clone()));
+        assert_eq!(entry.fee, 1.0);
+        assert_eq!(entry.size, page.size_bytes().unwrap());
+
+        let transfer_hashes = contents.entries
+            .iter()
+            .filter(|x| matches!(x.transaction, TransactionVariant::Transfer(_)))
+            .map(|x| x.hash)
+            .collect::<Vec<_>>();
+        let expected_order = [&transfers[1], &transfers[2], &transfers[0]];
+        assert_eq!(transfer_hashes, expected_order.iter().map(|x| x.hash().unwrap()).collect::<Vec<_>>());
+
+        // A page at a time
+        let page_of_entries = get_mempool_contents(&mut connection, 1, Some(2));
+        assert_eq!(page_of_entries.total, 4);
+        assert_eq!(page_of_entries.entries, contents.entries[1..3]);
+    }
+
+}
+
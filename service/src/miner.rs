@@ -15,10 +15,72 @@ use libhyperchain::block;
 use libhyperchain::chain::BlockChainAddResult;
 use libhyperchain::wallet::private_wallet::PrivateWallet;
 use libhyperchain::block::target::difficulty;
-use std::path::PathBuf;
+use libhyperchain::miner;
+use libhyperchain::error::ErrorMessage;
 use std::thread::JoinHandle;
+use std::time::Duration;
 use std::error::Error;
 
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Picks the wallet each block's reward goes to, taking turns between 
+// them. Each wallet gets as many blocks in a row as its weight.
+pub struct RewardWallets
+{
+    wallets: Vec<(PrivateWallet, u32)>,
+    current: usize,
+    blocks_to_current: u32,
+}
+
+impl RewardWallets
+{
+
+    pub fn new(wallets: Vec<(PrivateWallet, u32)>) -> Result<Self, Box<dyn Error>>
+    {
+        if wallets.is_empty() {
+            return Err(ErrorMessage::new("No wallets to mine to"));
+        }
+        if wallets.iter().any(|(_, weight)| *weight == 0) {
+            return Err(ErrorMessage::new("Reward wallets must have a weight of at least one"));
+        }
+
+        Ok(Self
+        {
+            wallets,
+            current: 0,
+            blocks_to_current: 0,
+        })
+    }
+
+    pub fn single(wallet: PrivateWallet) -> Self
+    {
+        Self
+        {
+            wallets: vec![(wallet, 1)],
+            current: 0,
+            blocks_to_current: 0,
+        }
+    }
+
+    pub fn current(&self) -> &PrivateWallet
+    {
+        &self.wallets[self.current].0
+    }
+
+    // NOTE: Only called once a block's been won, so the same wallet 
+    //       is kept while a block is lost to another node.
+    pub fn advance(&mut self)
+    {
+        self.blocks_to_current += 1;
+        if self.blocks_to_current >= self.wallets[self.current].1
+        {
+            self.current = (self.current + 1) % self.wallets.len();
+            self.blocks_to_current = 0;
+        }
+    }
+
+}
+
 pub fn mine_block_unless_found(connection: &NetworkConnection<NodePacketHandler>, 
                                mut block: Block) 
     -> Result<Block, Box<dyn Error>>
@@ -29,7 +91,7 @@ pub fn mine_block_unless_found(connection: &NetworkConnection<NodePacketHandler>
 
     while block.validate_pow()? != BlockValidationResult::Ok
     { 
-        block.header.pow += 1;
+        miner::next_nonce(&mut block);
 
         // Check this block wasn't already mined
         if block.header.pow % 100 == 0
@@ -54,9 +116,16 @@ pub fn mine_block_unless_found(connection: &NetworkConnection<NodePacketHandler>
     Ok(block)
 }
 
+// Returns whether we won the block
 fn mine_next_block(connection: &mut NetworkConnection<NodePacketHandler>,
-                   wallet: &PrivateWallet) -> Result<(), Box<dyn Error>>
+                   wallet: &PrivateWallet) -> Result<bool, Box<dyn Error>>
 {
+    if !connection.handler().node_reader().has_block_to_mine()
+    {
+        std::thread::sleep(IDLE_POLL_INTERVAL);
+        return Ok(false);
+    }
+
     let mut block;
     {
         // Create the next block
@@ -68,7 +137,7 @@ fn mine_next_block(connection: &mut NetworkConnection<NodePacketHandler>,
     // Do the mining work
     block = mine_block_unless_found(connection, block)?;
     if block.validate_pow()? != BlockValidationResult::Ok {
-        return Ok(());
+        return Ok(false);
     }
 
     // Add it to the chain if it's still the top
@@ -86,28 +155,146 @@ fn mine_next_block(connection: &mut NetworkConnection<NodePacketHandler>,
                 println!("Won block {}! With difficulty {}", 
                     block.header.block_id, 
                     block::target::difficulty(&block.header.target));
+                node.publish_block(&block);
+                node.blocks_added(1);
                 connection.manager().send(Packet::Block(block))?;
+                return Ok(true);
             },
 
-            _ => {},
+            result => 
+                warn!("Could not add mined block {}: {}", block.header.block_id, result),
         }
     }
 
-    Ok(())
+    Ok(false)
 }
 
-pub fn start_miner_thread(mut connection: NetworkConnection<NodePacketHandler>) 
+pub fn start_miner_thread(mut connection: NetworkConnection<NodePacketHandler>,
+                          mut rewards: RewardWallets) 
     -> JoinHandle<()>
 {
-    // Create chain a wallet
-    let wallet = PrivateWallet::read_from_file(&PathBuf::from("test.wallet")).unwrap();
-
-    std::thread::spawn(move || loop 
+    std::thread::spawn(move || 
     {
-        mine_next_block(&mut connection, &wallet).unwrap();
-        if connection.should_shutdown() {
-            break;
+        connection.handler().node().set_mining(true);
+        loop
+        {
+            if mine_next_block(&mut connection, rewards.current()).unwrap() {
+                rewards.advance();
+            }
+            if connection.should_shutdown() {
+                break;
+            }
         }
+        connection.handler().node().set_mining(false);
     })
 }
 
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::node::tests::{create_node, mine_block, wait_for_block};
+    use libhyperchain::wallet::Wallet;
+
+    #[test]
+    fn test_miner_shutdown()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let connection_a = create_node(8069);
+        let mut connection_b = create_node(8070);
+        connection_b.manager().register_node("127.0.0.1:8069");
+
+        let miner = start_miner_thread(connection_a.clone(), RewardWallets::single(wallet));
+        wait_for_block(&connection_b, 0);
+        assert_eq!(connection_a.handler().node().is_mining(), true);
+
+        // Setting the shutdown flag stops the miner
+        *connection_a.shutdown_signal().lock().unwrap() = true;
+        miner.join().unwrap();
+        assert_eq!(connection_a.handler().node().is_mining(), false);
+
+        // Dropping the last connection joins the network threads
+        drop(connection_a);
+        let mut timer = 0;
+        while connection_b.manager().peer_count() > 0 && timer < 5000
+        {
+            std::thread::sleep(Duration::from_millis(100));
+            timer += 100;
+        }
+        assert_eq!(connection_b.manager().peer_count(), 0);
+    }
+
+    #[test]
+    fn test_miner_reward_rotation()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallets = (0..3)
+            .map(|id| PrivateWallet::open_temp(id).unwrap())
+            .collect::<Vec<_>>();
+        let addresses = wallets
+            .iter()
+            .map(|wallet| wallet.get_address())
+            .collect::<Vec<_>>();
+
+        assert!(RewardWallets::new(Vec::new()).is_err());
+        assert!(RewardWallets::new(vec![(PrivateWallet::open_temp(3).unwrap(), 0)]).is_err());
+
+        let weights = vec![1, 2, 1];
+        let rewards = RewardWallets::new(wallets.into_iter().zip(weights).collect()).unwrap();
+        let connection = create_node(8108);
+        let miner = start_miner_thread(connection.clone(), rewards);
+        let blocks = (0..8)
+            .map(|block_id| wait_for_block(&connection, block_id))
+            .collect::<Vec<_>>();
+
+        *connection.shutdown_signal().lock().unwrap() = true;
+        miner.join().unwrap();
+
+        // Each wallet gets as many blocks in a row as its weight
+        let expected = [0, 1, 1, 2, 0, 1, 1, 2];
+        for (block, wallet_index) in blocks.iter().zip(expected) {
+            assert_eq!(block.header.raward_to, addresses[wallet_index]);
+        }
+    }
+
+    #[test]
+    fn test_miner_waits_for_transactions()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut connection = create_node(8112);
+        connection.handler().node().set_mine_empty_blocks(false);
+        mine_block(&mut connection, &wallet);
+
+        // Nothing is mined while the mempool's empty
+        let miner = start_miner_thread(connection.clone(), 
+            RewardWallets::single(PrivateWallet::open_temp(2).unwrap()));
+        std::thread::sleep(Duration::from_millis(1000));
+        assert!(connection.handler().node_reader().chain_ref().block(1).is_none());
+
+        let transfer =
+        {
+            let mut node = connection.handler().node();
+            let chain = node.chain();
+            let transfer = chain.new_transfer(vec![(&wallet, 1.25)], 
+                vec![(other.get_address(), 1.0)], 0.25).unwrap();
+            chain.push_transfer_queue(transfer.clone()).unwrap();
+            transfer
+        };
+
+        // Until there's a transaction to include
+        let block = wait_for_block(&connection, 1);
+        assert_eq!(block.transfers, vec![transfer]);
+
+        *connection.shutdown_signal().lock().unwrap() = true;
+        miner.join().unwrap();
+    }
+
+}
+
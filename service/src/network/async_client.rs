@@ -0,0 +1,264 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use super::packet::{Message, PacketHandler, deserialize_canonical};
+use super::client::{client_handshake, register_client, handle_message};
+use super::client_manager::ClientManager;
+use super::encryption::{read_frame_async, EncryptedWriter, FrameDecrypter};
+
+use libhyperchain::error::ErrorMessage;
+use tcp_channel::{SenderBuilder, LittleEndian};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::mpsc::{Sender, Receiver, channel};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::runtime::Handle;
+use tokio::task::spawn_blocking;
+use tokio::time::timeout;
+use std::io::Write;
+use std::net::TcpStream;
+use std::time::Duration;
+use std::error::Error;
+
+// NOTE: A peer that can't keep up with this many queued writes is 
+//       dropped, rather then letting its queue grow without limit.
+const MAX_QUEUED_WRITES: usize = 1024;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Queues written messages for the connection's writer task
+struct ChannelWriter
+{
+    channel: Sender<Vec<u8>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChannelWriter
+{
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize>
+    {
+        self.buffer.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let data = std::mem::take(&mut self.buffer);
+        self.channel.try_send(data).map_err(|err| match err
+        {
+            TrySendError::Full(_) => std::io::Error::new(
+                std::io::ErrorKind::WouldBlock, "Connection is too far behind"),
+            TrySendError::Closed(_) => std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe, "Connection closed"),
+        })
+    }
+
+}
+
+struct MessageReader
+{
+    reader: OwnedReadHalf,
+    decrypter: Option<FrameDecrypter>,
+    frame: Vec<u8>,
+    buffer: Vec<u8>,
+    offset: usize,
+    max_packet_size: usize,
+}
+
+impl MessageReader
+{
+
+    async fn read_exact(&mut self, data: &mut [u8]) -> std::io::Result<()>
+    {
+        let decrypter = match &mut self.decrypter
+        {
+            Some(decrypter) => decrypter,
+            None =>
+            {
+                self.reader.read_exact(data).await?;
+                return Ok(());
+            },
+        };
+
+        let mut filled = 0;
+        while filled < data.len()
+        {
+            if self.offset >= self.buffer.len()
+            {
+                read_frame_async(&mut self.reader, &mut self.frame).await?;
+                decrypter.decrypt(&self.frame, &mut self.buffer)?;
+                self.offset = 0;
+                continue;
+            }
+
+            let count = std::cmp::min(data.len() - filled, self.buffer.len() - self.offset);
+            data[filled..filled + count].copy_from_slice(&self.buffer[self.offset..self.offset + count]);
+            self.offset += count;
+            filled += count;
+        }
+
+        Ok(())
+    }
+
+    // NOTE: Uses the same framing as `tcp_channel`, a little endian
+    //       length followed by the bincode encoded message.
+    async fn recv(&mut self) -> Result<Message, Box<dyn Error>>
+    {
+        let mut length = [0u8; 8];
+        self.read_exact(&mut length).await?;
+
+        let length = u64::from_le_bytes(length) as usize;
+        if length > self.max_packet_size 
+        {
+            return Err(ErrorMessage::new(&format!(
+                "Packet of {} bytes is larger then the max of {}", 
+                length, self.max_packet_size)));
+        }
+
+        let mut data = vec![0u8; length];
+        self.read_exact(&mut data).await?;
+        deserialize_canonical(&data)
+    }
+
+}
+
+async fn write_queued(mut writer: OwnedWriteHalf, mut queue: Receiver<Vec<u8>>)
+{
+    while let Some(data) = queue.recv().await
+    {
+        if writer.write_all(&data).await.is_err() {
+            break;
+        }
+    }
+}
+
+// NOTE: Runs on the blocking pool, as the handshake uses the same
+//       blocking code as the threaded client.
+fn connect(manager: &mut ClientManager, stream: &TcpStream,
+           ip: &str, outgoing: bool)
+    -> Result<(String, MessageReader), Box<dyn Error>>
+{
+    let handshake = client_handshake(stream, ip, manager, outgoing)?;
+
+    let (channel, queue) = channel(MAX_QUEUED_WRITES);
+    let channel_writer = ChannelWriter { channel, buffer: Vec::new() };
+    let writer: Box<dyn Write + Send> = match &handshake.transport
+    {
+        Some(transport) => Box::new(EncryptedWriter::new(channel_writer, transport.clone())),
+        None => Box::new(channel_writer),
+    };
+
+    let sender = SenderBuilder::new()
+        .with_type::<Message>()
+        .with_endianness::<LittleEndian>()
+        .build(writer);
+    register_client(manager, &handshake, sender, stream)?;
+
+    let async_stream = stream.try_clone()?;
+    async_stream.set_nonblocking(true)?;
+    let (reader, writer) = tokio::net::TcpStream::from_std(async_stream)?.into_split();
+    tokio::spawn(write_queued(writer, queue));
+
+    let reader = MessageReader
+    {
+        reader,
+        decrypter: handshake.transport.map(FrameDecrypter::new),
+        frame: Vec::new(),
+        buffer: Vec::new(),
+        offset: 0,
+        max_packet_size: manager.max_packet_size(),
+    };
+    Ok((handshake.address, reader))
+}
+
+pub fn spawn_client_task<H>(runtime: &Handle, packet_handler: H, manager: ClientManager,
+                            stream: TcpStream, ip: String, outgoing: bool)
+    where H: PacketHandler + Clone + Send + Sync + 'static
+{
+    runtime.spawn(async move
+    {
+        let port = manager.port();
+        let connecting_manager = manager.clone();
+        let timeout_stream = stream.try_clone();
+        let mut connecting = spawn_blocking(move ||
+        {
+            let mut manager = connecting_manager;
+            connect(&mut manager, &stream, &ip, outgoing).map_err(|err|
+            {
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                err.to_string()
+            })
+        });
+
+        let connected = match timeout(HANDSHAKE_TIMEOUT, &mut connecting).await
+        {
+            Ok(result) => result.unwrap_or_else(|err| Err(err.to_string())),
+            Err(_) =>
+            {
+                // NOTE: The blocking handshake can't be cancelled, so close 
+                //       its stream to make it fail, and wait for it to finish.
+                if let Ok(stream) = &timeout_stream {
+                    let _ = stream.shutdown(std::net::Shutdown::Both);
+                }
+
+                if let Ok(Ok((address, _))) = connecting.await 
+                {
+                    let mut manager = manager.clone();
+                    let _ = spawn_blocking(move || manager.register_disconnect(&address)).await;
+                }
+                Err(format!("Handshake timed out after {:?}", HANDSHAKE_TIMEOUT))
+            },
+        };
+
+        let (address, mut reader) = match connected
+        {
+            Ok(result) => result,
+            Err(err) =>
+            {
+                warn!("[{}] Disconnecting: {}", port, err);
+                return;
+            },
+        };
+
+        info!("[{}] Connected to {}", port, address);
+
+        // NOTE: Like the threaded client, the next message isn't read until
+        //       this one's handled, but no thread is held while waiting.
+        loop
+        {
+            let message = match reader.recv().await
+            {
+                Ok(message) => message,
+                Err(_) => break,
+            };
+
+            let (packet_handler, mut manager, from) =
+                (packet_handler.clone(), manager.clone(), address.clone());
+            let handled = spawn_blocking(move ||
+                handle_message(message, &packet_handler, &from, &mut manager)
+                    .map_err(|err| err.to_string()))
+                .await.unwrap_or_else(|err| Err(err.to_string()));
+
+            if let Err(err) = handled
+            {
+                warn!("[{}] Disconnecting: {}", port, err);
+                break;
+            }
+        }
+
+        let mut manager = manager;
+        spawn_blocking(move ||
+        {
+            manager.register_disconnect(&address);
+            packet_handler.handle_disconnect(&address);
+        });
+    });
+}
+
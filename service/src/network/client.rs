@@ -5,104 +5,325 @@
  */
 
 use super::packet::{Packet, PacketHandler};
-use super::packet::{Message, MessageSender, MessageReceiver};
+use super::packet::{Message, MessageSender, MessageReceiver, PROTOCOL_VERSION};
+use super::packet::decompress_packet;
 use super::client_manager::ClientManager;
+use super::encryption::{handshake, EncryptedReader, EncryptedWriter};
 
+use libhyperchain::error::ErrorMessage;
+use snow::StatelessTransportState;
 use tcp_channel::LittleEndian;
 use tcp_channel::{SenderBuilder, ChannelSend};
-use tcp_channel::{ReceiverBuilder, ChannelRecv};
-use std::io::{BufReader, BufWriter};
-use std::net::TcpStream;
+use std::io::{Read, Write, BufReader, BufWriter};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::Arc;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use std::error::Error;
 
 fn handle_command<H>(packet: Packet, command_handler: &H, 
                      address: &str, manager: &mut ClientManager)
+    -> Option<Duration>
     where H: PacketHandler
 {
+    let name = packet.name();
+    let start = Instant::now();
+    manager.count_received(&packet);
+
     // FIXME: Handler errors
     let _ = command_handler.handle(address, packet, manager);
+
+    let duration = start.elapsed();
+    if duration <= manager.slow_packet_threshold() {
+        return None;
+    }
+
+    warn!("[{}] Handling {} packet from {} took {:?}", 
+        manager.port(), name, address, duration);
+    Some(duration)
 }
 
-fn request_client_address(
-        mut sender: MessageSender, receiver: &mut MessageReceiver,
-        ip: &str, manager: &mut ClientManager)
-    -> Result<String, Box<dyn Error>>
+fn open_channel(stream: &TcpStream, transport: Option<Arc<StatelessTransportState>>,
+                max_packet_size: usize)
+    -> Result<(MessageSender, MessageReceiver), Box<dyn Error>>
 {
-    sender.send(&Message::OnConnected(manager.port()))?;
-    sender.flush()?;
+    let (writer, reader): (Box<dyn Write + Send>, Box<dyn Read + Send>) = match transport
+    {
+        Some(transport) =>
+        (
+            Box::new(EncryptedWriter::new(BufWriter::new(stream.try_clone()?), transport.clone())),
+            Box::new(EncryptedReader::new(BufReader::new(stream.try_clone()?), transport)),
+        ),
+
+        None =>
+        (
+            Box::new(BufWriter::new(stream.try_clone()?)),
+            Box::new(BufReader::new(stream.try_clone()?)),
+        ),
+    };
+
+    let sender = SenderBuilder::new()
+        .with_type::<Message>()
+        .with_endianness::<LittleEndian>()
+        .build(writer);
+
+    let receiver = MessageReceiver::new(reader, max_packet_size);
+
+    Ok((sender, receiver))
+}
+
+// NOTE: IPv6 addresses are bracketed, so the port can be told apart
+pub fn format_address(ip: &str, port: u16) -> String
+{
+    match ip.parse::<IpAddr>()
+    {
+        Ok(ip) => SocketAddr::new(ip, port).to_string(),
+        Err(_) => format!("{}:{}", ip, port),
+    }
+}
+
+pub struct Handshake
+{
+    pub address: String,
+    pub compression: bool,
+    pub transport: Option<Arc<StatelessTransportState>>,
+}
+
+pub fn client_handshake(stream: &TcpStream, ip: &str, 
+                        manager: &mut ClientManager, outgoing: bool)
+    -> Result<Handshake, Box<dyn Error>>
+{
+    // NOTE: The handshake is read unbuffered, so nothing sent after it
+    //       is lost when switching to the encrypted stream.
+    let mut handshake_sender = SenderBuilder::new()
+        .with_type::<Message>()
+        .with_endianness::<LittleEndian>()
+        .build(stream.try_clone()?);
+
+    let mut handshake_receiver = MessageReceiver::new(
+        Box::new(stream.try_clone()?), manager.max_packet_size());
+
+    handshake_sender.send(&Message::OnConnected(manager.port(), PROTOCOL_VERSION))?;
+    handshake_sender.send(&Message::Capabilities(manager.compression(), manager.encryption()))?;
+    handshake_sender.flush()?;
     
-    match receiver.recv()
+    match handshake_receiver.recv()
     {
-        Ok(Message::OnConnected(port)) =>
+        Ok(Message::OnConnected(port, version)) =>
         {
-            let address = format!("{}:{}", ip, port);
-            sender.send(&Message::Packet(Packet::OnConnected))?;
-            sender.flush()?;
+            let address = format_address(ip, port);
+            if version != PROTOCOL_VERSION 
+            {
+                return Err(ErrorMessage::new(&format!(
+                    "{} uses protocol version {}, but we use {}", 
+                    address, version, PROTOCOL_VERSION)));
+            }
+
+            let (compression, encryption) = match handshake_receiver.recv()
+            {
+                Ok(Message::Capabilities(compression, encryption)) => (compression, encryption),
+                Ok(_) => return Err(ErrorMessage::new(&format!("{} did not send its capabilities", address))),
+                Err(err) => return Err(ErrorMessage::new(&format!("Handshake with {} failed: {}", address, err))),
+            };
+
+            if !encryption && manager.encryption() && manager.is_known_encrypted(&address)
+            {
+                return Err(ErrorMessage::new(&format!(
+                    "{} has supported encryption before, refusing to fall back to plaintext", 
+                    address)));
+            }
 
-            manager.register_client_sender(address.clone(), sender)?;
-            Ok(address)
+            let transport = 
+                if encryption && manager.encryption() 
+                {
+                    debug!("[{}] Encrypting connection to {}", manager.port(), address);
+                    let transport = handshake(&mut stream.try_clone()?, outgoing)?;
+                    manager.register_encrypted(&address);
+                    Some(transport)
+                } 
+                else 
+                {
+                    None
+                };
+
+            Ok(Handshake
+            {
+                address,
+                compression: compression && manager.compression(),
+                transport,
+            })
         }
 
-        _ => panic!(),
+        Ok(_) => Err(ErrorMessage::new(&format!("{} did not start with a handshake", ip))),
+        Err(err) => Err(ErrorMessage::new(&format!("Handshake with {} failed: {}", ip, err))),
     }
 }
 
-pub fn client_handler_thread<H>(packet_handler: H, mut manager: ClientManager,
-                                stream: TcpStream, ip: String)
-    -> Result<JoinHandle<()>, Box<dyn Error>>
-    where H: PacketHandler + Send + Sync + 'static
+pub fn register_client(manager: &mut ClientManager, handshake: &Handshake,
+                       mut sender: MessageSender, stream: &TcpStream)
+    -> Result<(), Box<dyn Error>>
 {
-    let mut receiver = ReceiverBuilder::new()
-        .with_type::<Message>()
-        .with_endianness::<LittleEndian>()
-        .build(BufReader::new(stream.try_clone()?));
-        
-    let sender = SenderBuilder::new()
-        .with_type::<Message>()
-        .with_endianness::<LittleEndian>()
-        .build(BufWriter::new(stream.try_clone().unwrap()));
+    sender.send(&Message::Packet(Packet::OnConnected))?;
+    sender.flush()?;
+    manager.count_sent(&Packet::OnConnected);
 
-    Ok(std::thread::spawn(move ||
+    manager.register_client_sender(handshake.address.clone(), sender, 
+        stream.try_clone()?, handshake.compression)
+}
+
+fn request_client_address(stream: &TcpStream, ip: &str, 
+                          manager: &mut ClientManager, outgoing: bool)
+    -> Result<(String, MessageReceiver), Box<dyn Error>>
+{
+    let handshake = client_handshake(stream, ip, manager, outgoing)?;
+    let (sender, receiver) = open_channel(stream, handshake.transport.clone(), 
+        manager.max_packet_size())?;
+    register_client(manager, &handshake, sender, stream)?;
+    Ok((handshake.address, receiver))
+}
+
+pub fn handle_message<H>(message: Message, packet_handler: &H,
+                         address: &str, manager: &mut ClientManager)
+    -> Result<(), Box<dyn Error>>
+    where H: PacketHandler
+{
+    match message
     {
-        let address = request_client_address(
-            sender, &mut receiver, &ip, &mut manager).unwrap();
+        // NOTE: We shouldn't be sending an `OnConnected` 
+        //       message more then once, do disconnect the 
+        //       client, just to be sure.
+        Message::OnConnected(..) | Message::Capabilities(..) =>
+            return Err(ErrorMessage::new(&format!("{} sent a second handshake", address))),
 
-        info!("[{}] Connected to {}", manager.port(), address);
-        loop
+        Message::KnownNode(node) => 
         {
-            match receiver.recv()
+            if manager.register_node(&node) 
             {
-                // NOTE: We shouldn't be sending an `OnConnected` 
-                //       message more then once, do disconnect the 
-                //       client, just to be sure.
-                Ok(Message::OnConnected(_port)) =>
-                    panic!(),
+                manager.send_message_to(Message::KnownNode(node),
+                    |x| x != address)?;
+            }
+        },
 
-                Ok(Message::KnownNode(node)) => 
-                {
-                    if manager.register_node(&node) 
-                    {
-                        manager.send_message_to(Message::KnownNode(node),
-                            |x| x != address).unwrap();
-                    }
-                },
+        Message::Packet(packet) =>
+        {
+            debug!("[{}] Got packet {:?}", manager.port(), packet);
+            handle_command(packet, packet_handler, address, manager);
+        },
 
-                Ok(Message::Packet(packet)) =>
+        Message::CompressedPacket(data) =>
+        {
+            match decompress_packet(&data, manager.max_packet_size())
+            {
+                Ok(packet) =>
                 {
-                    debug!("[{}] Got packet {:?}", manager.port(), packet);
-                    handle_command(packet, &packet_handler, 
-                        &address, &mut manager);
+                    debug!("[{}] Got compressed packet {:?}", manager.port(), packet);
+                    handle_command(packet, packet_handler, address, manager);
                 },
 
-                // FIXME: Handler errors
-                Err(_) => 
+                Err(err) =>
+                    warn!("[{}] Could not decompress packet from {}: {}", 
+                        manager.port(), address, err),
+            }
+        },
+    }
+
+    Ok(())
+}
+
+pub fn client_handler_thread<H>(packet_handler: H, mut manager: ClientManager,
+                                stream: TcpStream, ip: String, outgoing: bool)
+    -> Result<JoinHandle<()>, Box<dyn Error>>
+    where H: PacketHandler + Send + Sync + 'static
+{
+    Ok(std::thread::spawn(move ||
+    {
+        let handshake = request_client_address(&stream, &ip, &mut manager, outgoing);
+        let (address, mut receiver) = match handshake
+        {
+            Ok(result) => result,
+            Err(err) =>
+            {
+                warn!("[{}] Disconnecting: {}", manager.port(), err);
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                return;
+            },
+        };
+
+        info!("[{}] Connected to {}", manager.port(), address);
+
+        // NOTE: Packets are handled on this thread before the next one is 
+        //       read, so a fast peer is held back by TCP flow control 
+        //       rather than being buffered in memory.
+        loop
+        {
+            // FIXME: Handler errors
+            let message = match receiver.recv()
+            {
+                Ok(message) => message,
+                Err(err) =>
                 {
-                    manager.register_disconnect(&address);
+                    debug!("[{}] Stopped receiving from {}: {}", manager.port(), address, err);
+                    let _ = stream.shutdown(std::net::Shutdown::Both);
                     break;
                 },
+            };
+
+            if let Err(err) = handle_message(message, &packet_handler, &address, &mut manager)
+            {
+                warn!("[{}] Disconnecting: {}", manager.port(), err);
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                break;
             }
         }
+
+        manager.register_disconnect(&address);
+        packet_handler.handle_disconnect(&address);
     }))
 }
 
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct SleepPacketHandler
+    {
+        delay: Duration,
+    }
+
+    impl PacketHandler for SleepPacketHandler
+    {
+
+        fn handle(&self, _: &str, _: Packet, _: &mut ClientManager)
+            -> Result<(), Box<dyn Error>>
+        {
+            std::thread::sleep(self.delay);
+            Ok(())
+        }
+
+        fn update_reports(&self, _: &mut ClientManager)
+        {
+        }
+
+    }
+
+    #[test]
+    fn test_slow_packet_logging()
+    {
+        let path = std::env::temp_dir().join(format!("{}slow_packet", 
+            libhyperchain::block::current_timestamp()));
+        let mut manager = ClientManager::new(8067, &path, Arc::from(Mutex::from(false)));
+        manager.set_slow_packet_threshold(Duration::from_millis(50));
+
+        let fast = SleepPacketHandler { delay: Duration::from_millis(0) };
+        assert!(handle_command(Packet::Ping(0), &fast, "fast", &mut manager).is_none());
+
+        let slow = SleepPacketHandler { delay: Duration::from_millis(100) };
+        let duration = handle_command(Packet::Ping(0), &slow, "slow", &mut manager);
+        assert!(duration.unwrap() >= Duration::from_millis(100));
+    }
+
+}
+
@@ -4,27 +4,39 @@
  * SPDX-License-Identifier: BSD-2-Clause
  */
 
-use super::packet::{Packet, PacketHandler};
+use super::packet::{Packet, PacketHandler, compress_packet};
 use super::packet::{Message, MessageSender};
 use super::client::client_handler_thread;
+use super::packet_counters::PacketCounters;
+#[cfg(feature = "async-network")]
+use super::async_client::spawn_client_task;
 
 use serde_json;
 use serde::{Serialize, Deserialize};
 use tcp_channel::ChannelSend;
 use std::net::TcpStream;
 use std::sync::{Arc, Mutex};
-use std::collections::{HashSet, HashMap};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::collections::{HashSet, HashMap, BTreeMap};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::path::PathBuf;
 use std::fs::File;
 use std::error::Error;
 
 const MAX_CONNECTION_COUNT: usize = 14;
+const RECONNECT_BACKOFF_BASE: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_SLOW_PACKET_THRESHOLD: Duration = Duration::from_millis(500);
+const DEFAULT_CONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_PACKET_SIZE: usize = 64 * 0x100000;
+const GOOD_BEHAVIOUR_SCORE: i32 = 1;
+const BAD_BEHAVIOUR_SCORE: i32 = -5;
 
 struct ClientSender
 {
     address: String,
     sender: MessageSender,
+    stream: TcpStream,
+    compression: bool,
 }
 
 struct ClientReceiver
@@ -36,6 +48,12 @@ struct ClientReceiver
 struct NodeConnectionInformation
 {
     ping_time_samples: Vec<Duration>,
+
+    // NOTE: Once both sides have supported encryption, the node is 
+    //       never connected to in plaintext again. Otherwise a man in 
+    //       the middle could strip it from the handshake.
+    #[serde(default)]
+    encrypted: bool,
 }
 
 impl NodeConnectionInformation
@@ -62,6 +80,32 @@ impl NodeConnectionInformation
 
 }
 
+struct ReconnectBackoff
+{
+    failures: u32,
+    last_failed: Instant,
+}
+
+fn reconnect_delay(failures: u32) -> Duration
+{
+    if failures == 0 {
+        return Duration::from_secs(0);
+    }
+
+    let exponent = std::cmp::min(failures - 1, 16);
+    std::cmp::min(RECONNECT_BACKOFF_BASE * 2u32.pow(exponent), RECONNECT_BACKOFF_MAX)
+}
+
+impl ReconnectBackoff
+{
+
+    pub fn next_attempt(&self) -> Instant
+    {
+        self.last_failed + reconnect_delay(self.failures)
+    }
+
+}
+
 impl Default for NodeConnectionInformation
 {
 
@@ -70,6 +114,7 @@ impl Default for NodeConnectionInformation
         Self
         {
             ping_time_samples: Vec::new(),
+            encrypted: false,
         }
     }
 
@@ -83,6 +128,17 @@ struct ConnectionData
     data_directory: PathBuf,
     known_nodes: HashMap<String, NodeConnectionInformation>,
     connected_nodes: HashSet<String>,
+    backoff: HashMap<String, ReconnectBackoff>,
+    scores: HashMap<String, i32>,
+    max_connections: usize,
+    slow_packet_threshold: Duration,
+    max_packet_size: usize,
+    compression: bool,
+    encryption: bool,
+    connect_interval: Duration,
+
+    #[cfg(feature = "async-network")]
+    runtime: Option<tokio::runtime::Handle>,
 }
 
 impl ConnectionData
@@ -101,6 +157,17 @@ impl ConnectionData
             data_directory: data_directory.clone(),
             known_nodes,
             connected_nodes: HashSet::new(),
+            backoff: HashMap::new(),
+            scores: HashMap::new(),
+            max_connections: MAX_CONNECTION_COUNT,
+            slow_packet_threshold: DEFAULT_SLOW_PACKET_THRESHOLD,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+            compression: true,
+            encryption: true,
+            connect_interval: DEFAULT_CONNECT_INTERVAL,
+
+            #[cfg(feature = "async-network")]
+            runtime: None,
         }))
     }
 
@@ -130,6 +197,8 @@ pub struct ClientManager
     port: u16,
     shutdown_signal: Arc<Mutex<bool>>,
     data: Arc<Mutex<ConnectionData>>,
+    packets_sent: Arc<PacketCounters>,
+    packets_received: Arc<PacketCounters>,
 }
 
 impl ClientManager
@@ -144,6 +213,8 @@ impl ClientManager
             port,
             shutdown_signal,
             data: ConnectionData::new(data_directory),
+            packets_sent: Arc::new(PacketCounters::default()),
+            packets_received: Arc::new(PacketCounters::default()),
         }
     }
 
@@ -157,6 +228,144 @@ impl ClientManager
         *self.shutdown_signal.lock().unwrap()
     }
 
+    pub fn slow_packet_threshold(&self) -> Duration
+    {
+        self.data.lock().unwrap().slow_packet_threshold
+    }
+
+    pub fn set_slow_packet_threshold(&mut self, threshold: Duration)
+    {
+        self.data.lock().unwrap().slow_packet_threshold = threshold;
+    }
+
+    pub fn max_packet_size(&self) -> usize
+    {
+        self.data.lock().unwrap().max_packet_size
+    }
+
+    pub fn set_max_packet_size(&mut self, max_packet_size: usize)
+    {
+        self.data.lock().unwrap().max_packet_size = max_packet_size;
+    }
+
+    pub fn compression(&self) -> bool
+    {
+        self.data.lock().unwrap().compression
+    }
+
+    pub fn set_compression(&mut self, compression: bool)
+    {
+        self.data.lock().unwrap().compression = compression;
+    }
+
+    pub fn encryption(&self) -> bool
+    {
+        self.data.lock().unwrap().encryption
+    }
+
+    pub fn set_encryption(&mut self, encryption: bool)
+    {
+        self.data.lock().unwrap().encryption = encryption;
+    }
+
+    pub fn is_known_encrypted(&self, address: &str) -> bool
+    {
+        let data = self.data.lock().unwrap();
+        matches!(data.known_nodes.get(address), Some(node) if node.encrypted)
+    }
+
+    pub fn register_encrypted(&mut self, address: &str)
+    {
+        let mut data = self.data.lock().unwrap();
+        data.known_nodes
+            .entry(address.to_owned())
+            .or_insert_with(Default::default)
+            .encrypted = true;
+
+        // FIXME: We probably shouldn't be ignoring this error, but it 
+        //        doesn't stop us running for now.
+        let _ = data.flush_changes();
+    }
+
+    pub fn connect_interval(&self) -> Duration
+    {
+        self.data.lock().unwrap().connect_interval
+    }
+
+    pub fn set_connect_interval(&mut self, interval: Duration)
+    {
+        self.data.lock().unwrap().connect_interval = interval;
+    }
+
+    // NOTE: Once set, new clients are handled as tasks on this runtime,
+    //       instead of each getting their own thread.
+    #[cfg(feature = "async-network")]
+    pub fn set_runtime(&mut self, runtime: tokio::runtime::Handle)
+    {
+        self.data.lock().unwrap().runtime = Some(runtime);
+    }
+
+    pub fn peer_count(&self) -> usize
+    {
+        self.data.lock().unwrap().connected_nodes.len()
+    }
+
+    pub fn max_connections(&self) -> usize
+    {
+        self.data.lock().unwrap().max_connections
+    }
+
+    pub fn set_max_connections(&mut self, max_connections: usize)
+    {
+        self.data.lock().unwrap().max_connections = max_connections;
+    }
+
+    pub fn count_sent(&self, packet: &Packet)
+    {
+        self.packets_sent.count(packet);
+    }
+
+    pub fn count_received(&self, packet: &Packet)
+    {
+        self.packets_received.count(packet);
+    }
+
+    pub fn packets_sent(&self) -> BTreeMap<String, u64>
+    {
+        self.packets_sent.totals()
+    }
+
+    pub fn packets_received(&self) -> BTreeMap<String, u64>
+    {
+        self.packets_received.totals()
+    }
+
+    pub fn score(&self, address: &str) -> i32
+    {
+        let data = self.data.lock().unwrap();
+        data.scores.get(address).cloned().unwrap_or(0)
+    }
+
+    fn adjust_score(&mut self, address: &str, change: i32)
+    {
+        let mut data = self.data.lock().unwrap();
+        let score = data.scores.entry(address.to_owned()).or_insert(0);
+        *score += change;
+        debug!("[{}] Score of {} is now {}", self.port, address, score);
+    }
+
+    // Peer relayed a valid block or transaction
+    pub fn report_good_behaviour(&mut self, address: &str)
+    {
+        self.adjust_score(address, GOOD_BEHAVIOUR_SCORE);
+    }
+
+    // Peer relayed something we had to reject
+    pub fn report_bad_behaviour(&mut self, address: &str)
+    {
+        self.adjust_score(address, BAD_BEHAVIOUR_SCORE);
+    }
+
     pub fn register_node(&mut self, address: &str) -> bool
     {
         if address == format!("127.0.0.1:{}", self.port) {
@@ -179,10 +388,15 @@ impl ClientManager
     }
 
     pub fn pending_connections(&self) -> Vec<String>
+    {
+        self.pending_connections_at(Instant::now())
+    }
+
+    fn pending_connections_at(&self, now: Instant) -> Vec<String>
     {
         let data = self.data.lock().unwrap();
         let connection_count_left = 
-            std::cmp::max(MAX_CONNECTION_COUNT - data.connected_nodes.len(), 0);
+            data.max_connections.saturating_sub(data.connected_nodes.len());
 
         if connection_count_left == 0 {
             return Vec::new();
@@ -191,6 +405,11 @@ impl ClientManager
         let mut all_unconnected = data.known_nodes
             .iter()
             .filter(|(x, _)| !data.connected_nodes.contains(*x))
+            .filter(|(x, _)| match data.backoff.get(*x)
+            {
+                Some(backoff) => backoff.next_attempt() <= now,
+                None => true,
+            })
             .collect::<Vec<_>>();
 
         all_unconnected.sort_by_key(|(_, info)| info.average_ping_time());
@@ -201,21 +420,21 @@ impl ClientManager
             .collect()
     }
 
-    pub fn new_client<H>(&mut self, packet_handler: H, stream: TcpStream, ip: String)
+    pub fn new_client<H>(&mut self, packet_handler: H, stream: TcpStream, 
+                         ip: String, outgoing: bool)
         -> Result<(), Box<dyn Error>>
         where H: PacketHandler + Clone + Sync + Send + 'static
     {
         let mut data = self.data.lock().unwrap();
-        if data.connected_nodes.len() >= MAX_CONNECTION_COUNT 
+        if data.connected_nodes.len() >= data.max_connections && 
+            !data.evict_lowest_scoring(self.port)
         {
             info!("Rejecting incoming connection from '{}', as we've reached our maximum :))", ip);
             return Ok(());
         }
 
-        client_handler_thread(
-            packet_handler,
-            self.clone(),
-            stream.try_clone().unwrap(), ip)?;
+        self.start_client_handler(&data, packet_handler,
+            stream.try_clone()?, ip, outgoing)?;
 
         data.client_receivers.push(ClientReceiver
         {
@@ -225,12 +444,44 @@ impl ClientManager
         Ok(())
     }
 
+    #[cfg(not(feature = "async-network"))]
+    fn start_client_handler<H>(&self, _: &ConnectionData, packet_handler: H, 
+                               stream: TcpStream, ip: String, outgoing: bool)
+        -> Result<(), Box<dyn Error>>
+        where H: PacketHandler + Clone + Sync + Send + 'static
+    {
+        client_handler_thread(packet_handler, self.clone(), stream, ip, outgoing)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "async-network")]
+    fn start_client_handler<H>(&self, data: &ConnectionData, packet_handler: H, 
+                               stream: TcpStream, ip: String, outgoing: bool)
+        -> Result<(), Box<dyn Error>>
+        where H: PacketHandler + Clone + Sync + Send + 'static
+    {
+        match &data.runtime
+        {
+            Some(runtime) => 
+                spawn_client_task(runtime, packet_handler, self.clone(), stream, ip, outgoing),
+
+            None =>
+            {
+                client_handler_thread(packet_handler, self.clone(), stream, ip, outgoing)?;
+            },
+        }
+
+        Ok(())
+    }
+
     pub fn register_client_sender(&mut self, address: String, 
-                                  mut sender: MessageSender)
+                                  mut sender: MessageSender, stream: TcpStream,
+                                  compression: bool)
         -> Result<(), Box<dyn Error>>
     {
         let mut data = self.data.lock().unwrap();
         data.connected_nodes.insert(address.clone());
+        data.backoff.remove(&address);
 
         if !data.known_nodes.contains_key(&address) 
         {
@@ -254,6 +505,8 @@ impl ClientManager
         {
             address,
             sender,
+            stream,
+            compression,
         });
 
         Ok(())
@@ -277,19 +530,49 @@ impl ClientManager
         data.flush_changes().expect("Can flush changes");
     }
 
+    pub fn register_connection_failure(&mut self, address: &str)
+    {
+        let mut data = self.data.lock().unwrap();
+        let backoff = data.backoff
+            .entry(address.to_owned())
+            .or_insert(ReconnectBackoff
+            {
+                failures: 0,
+                last_failed: Instant::now(),
+            });
+
+        backoff.failures += 1;
+        backoff.last_failed = Instant::now();
+        debug!("[{}] Will retry {} in {:?}", self.port, address, 
+            reconnect_delay(backoff.failures));
+    }
+
     pub fn register_disconnect(&mut self, address: &str)
     {
         info!("[{}] Client {} disconnected", self.port, address);
 
-        let mut data = self.data.lock().unwrap();
-        data.connected_nodes.remove(address);
-        data.client_senders.retain(|x| x.address != address);
+        {
+            let mut data = self.data.lock().unwrap();
+            data.connected_nodes.remove(address);
+            data.client_senders.retain(|x| x.address != address);
+            data.scores.remove(address);
+        }
+
+        self.register_connection_failure(address);
     }
 
     pub fn send_message_to<F>(&mut self, message: Message, mut predicate: F)
         -> Result<(), Box<dyn Error>>
         where F: FnMut(&str) -> bool
     {
+        // NOTE: Only blocks are large enough to be worth compressing
+        let compressed_or_none = match &message
+        {
+            Message::Packet(packet @ Packet::Block(_)) =>
+                Some(Message::CompressedPacket(compress_packet(packet)?)),
+            _ => None,
+        };
+
         let mut disconnected_clients = Vec::new();
         {
             let mut data = self.data.lock().unwrap();
@@ -299,10 +582,22 @@ impl ClientManager
                     continue;
                 }
 
-                let send_result = connection.sender.send(&message);
+                let message_for_connection = match &compressed_or_none
+                {
+                    Some(compressed) if connection.compression => compressed,
+                    _ => &message,
+                };
+
+                let send_result = connection.sender.send(message_for_connection);
                 let flush_result = connection.sender.flush();
-                if send_result.is_err() || flush_result.is_err() {
+                if send_result.is_err() || flush_result.is_err() 
+                {
                     disconnected_clients.push(connection.address.clone());
+                    continue;
+                }
+
+                if let Message::Packet(packet) = &message {
+                    self.packets_sent.count(packet);
                 }
             }
         }
@@ -336,6 +631,32 @@ impl ClientManager
 impl ConnectionData
 {
 
+    // Make room for a new connection by dropping the lowest scoring 
+    // peer, but only if it's done more harm then good.
+    fn evict_lowest_scoring(&mut self, port: u16) -> bool
+    {
+        let scores = &self.scores;
+        let lowest_or_none = self.client_senders
+            .iter()
+            .enumerate()
+            .map(|(index, client)| (index, scores.get(&client.address).cloned().unwrap_or(0)))
+            .min_by_key(|(_, score)| *score);
+
+        let (index, score) = match lowest_or_none
+        {
+            Some((index, score)) if score < 0 => (index, score),
+            _ => return false,
+        };
+
+        // NOTE: Closing the stream also ends the client's receiver thread
+        let client = self.client_senders.remove(index);
+        let _ = client.stream.shutdown(std::net::Shutdown::Both);
+        self.connected_nodes.remove(&client.address);
+
+        info!("[{}] Evicted {} with a score of {}", port, client.address, score);
+        true
+    }
+
     fn shutdown(&mut self, port: u16)
     {
         info!("[{}] Closing {} open client(s)", port, self.client_senders.len());
@@ -348,3 +669,239 @@ impl ConnectionData
 
 }
 
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use super::super::client::handle_message;
+    use libhyperchain::block::Block;
+    use libhyperchain::chain::BlockChain;
+    use libhyperchain::wallet::private_wallet::PrivateWallet;
+    use tcp_channel::{SenderBuilder, ReceiverBuilder, LittleEndian, ChannelRecv};
+    use std::net::TcpListener;
+    use std::io::{BufWriter, Read, Write};
+
+    fn next_attempt_delay(manager: &ClientManager, address: &str) -> (Instant, Duration)
+    {
+        let data = manager.data.lock().unwrap();
+        let backoff = &data.backoff[address];
+        (backoff.next_attempt(), backoff.next_attempt() - backoff.last_failed)
+    }
+
+    #[test]
+    fn test_reconnect_backoff()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let path = std::env::temp_dir().join(rand::random::<u32>().to_string());
+        std::fs::create_dir_all(&path).unwrap();
+
+        let address = "127.0.0.1:8051";
+        let mut manager = ClientManager::new(8050, &path, Arc::from(Mutex::from(false)));
+        manager.register_node(address);
+        assert_eq!(manager.pending_connections(), [address]);
+
+        let mut last_delay = Duration::from_secs(0);
+        for _ in 0..5
+        {
+            manager.register_connection_failure(address);
+
+            // Each retry should be further apart then the last
+            let (next_attempt, delay) = next_attempt_delay(&manager, address);
+            assert!(delay > last_delay);
+            assert_eq!(manager.pending_connections().len(), 0);
+            assert_eq!(manager.pending_connections_at(next_attempt), [address]);
+            last_delay = delay;
+        }
+
+        // A successful connection resets the backoff
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let sender = SenderBuilder::new()
+            .with_type::<Message>()
+            .with_endianness::<LittleEndian>()
+            .build(Box::new(BufWriter::new(stream.try_clone().unwrap())) as Box<dyn Write + Send>);
+        manager.register_client_sender(address.to_owned(), sender, stream, false).unwrap();
+        assert_eq!(manager.pending_connections().len(), 0);
+
+        manager.register_disconnect(address);
+        let (_, delay) = next_attempt_delay(&manager, address);
+        assert_eq!(delay, RECONNECT_BACKOFF_BASE);
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    fn connect_sender(listener: &TcpListener) -> (MessageSender, TcpStream, TcpStream)
+    {
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        let sender = SenderBuilder::new()
+            .with_type::<Message>()
+            .with_endianness::<LittleEndian>()
+            .build(Box::new(BufWriter::new(stream.try_clone().unwrap())) as Box<dyn Write + Send>);
+        (sender, stream, accepted)
+    }
+
+    #[test]
+    fn test_send_to_broken_peer()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let path = std::env::temp_dir().join(rand::random::<u32>().to_string());
+        std::fs::create_dir_all(&path).unwrap();
+
+        let broken_address = "127.0.0.1:8088";
+        let healthy_address = "127.0.0.1:8089";
+        let mut manager = ClientManager::new(8087, &path, Arc::from(Mutex::from(false)));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let (broken_sender, broken_stream, _broken_accepted) = connect_sender(&listener);
+        manager.register_client_sender(broken_address.to_owned(), broken_sender, 
+            broken_stream.try_clone().unwrap(), false).unwrap();
+        let (healthy_sender, healthy_stream, healthy_accepted) = connect_sender(&listener);
+        manager.register_client_sender(healthy_address.to_owned(), healthy_sender, 
+            healthy_stream, false).unwrap();
+        assert_eq!(manager.peer_count(), 2);
+
+        // Break one peer, the others should still be sent to
+        broken_stream.shutdown(std::net::Shutdown::Both).unwrap();
+        assert!(manager.send_to(Packet::Ping(1234), |_| true).is_ok());
+        assert_eq!(manager.peer_count(), 1);
+        assert!(manager.pending_connections().is_empty());
+
+        let mut receiver = ReceiverBuilder::new()
+            .with_type::<Message>()
+            .with_endianness::<LittleEndian>()
+            .build(healthy_accepted);
+        loop
+        {
+            match receiver.recv().unwrap()
+            {
+                Message::KnownNode(_) => continue,
+                Message::Packet(Packet::Ping(time)) => break assert_eq!(time, 1234),
+                _ => panic!("Unexpected message"),
+            }
+        }
+
+        // The manager is still usable afterwards
+        assert!(manager.send(Packet::Ping(5678)).is_ok());
+        assert!(matches!(receiver.recv(), Ok(Message::Packet(Packet::Ping(5678)))));
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[derive(Clone)]
+    struct IgnorePacketHandler;
+
+    impl PacketHandler for IgnorePacketHandler
+    {
+
+        fn handle(&self, _: &str, _: Packet, _: &mut ClientManager)
+            -> Result<(), Box<dyn Error>>
+        {
+            Ok(())
+        }
+
+        fn update_reports(&self, _: &mut ClientManager)
+        {
+        }
+
+    }
+
+    #[test]
+    fn test_packet_counters()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let path = std::env::temp_dir().join(rand::random::<u32>().to_string());
+        std::fs::create_dir_all(&path).unwrap();
+
+        let address = "127.0.0.1:8119";
+        let mut manager = ClientManager::new(8118, &path, Arc::from(Mutex::from(false)));
+        let mut other_manager = ClientManager::new(8119, &path, Arc::from(Mutex::from(false)));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (sender, stream, accepted) = connect_sender(&listener);
+        manager.register_client_sender(address.to_owned(), sender, stream, false).unwrap();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut chain = BlockChain::open_in_memory();
+        let block = Block::new_blank(&mut chain, &wallet).unwrap();
+        for i in 0..3 {
+            manager.send(Packet::Ping(i)).unwrap();
+        }
+        manager.send(Packet::Block(block)).unwrap();
+
+        let mut receiver = ReceiverBuilder::new()
+            .with_type::<Message>()
+            .with_endianness::<LittleEndian>()
+            .build(accepted);
+        let mut packet_count = 0;
+        while packet_count < 4
+        {
+            let message = receiver.recv().unwrap();
+            if matches!(message, Message::Packet(_)) {
+                packet_count += 1;
+            }
+            handle_message(message, &IgnorePacketHandler, "127.0.0.1:8118", &mut other_manager).unwrap();
+        }
+
+        let expected = vec![("Block".to_owned(), 1), ("Ping".to_owned(), 3)]
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(manager.packets_sent(), expected);
+        assert_eq!(manager.packets_received(), BTreeMap::new());
+        assert_eq!(other_manager.packets_received(), expected);
+        assert_eq!(other_manager.packets_sent(), BTreeMap::new());
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_evict_lowest_scoring_peer()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let path = std::env::temp_dir().join(rand::random::<u32>().to_string());
+        std::fs::create_dir_all(&path).unwrap();
+
+        let good_address = "127.0.0.1:8095";
+        let bad_address = "127.0.0.1:8096";
+        let mut manager = ClientManager::new(8094, &path, Arc::from(Mutex::from(false)));
+        manager.set_max_connections(2);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let (good_sender, good_stream, _good_accepted) = connect_sender(&listener);
+        manager.register_client_sender(good_address.to_owned(), good_sender, 
+            good_stream, false).unwrap();
+        let (bad_sender, bad_stream, mut bad_accepted) = connect_sender(&listener);
+        manager.register_client_sender(bad_address.to_owned(), bad_sender, 
+            bad_stream, false).unwrap();
+
+        manager.report_good_behaviour(good_address);
+        manager.report_good_behaviour(bad_address);
+        manager.report_bad_behaviour(bad_address);
+        assert!(manager.score(good_address) > 0);
+        assert!(manager.score(bad_address) < 0);
+
+        // A new connection at the cap replaces the low scoring peer
+        let new_stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        manager.new_client(IgnorePacketHandler, new_stream, "127.0.0.1".to_owned(), true).unwrap();
+        assert_eq!(manager.peer_count(), 1);
+        assert!(manager.send_to(Packet::Ping(0), |x| x == good_address).is_ok());
+        assert_eq!(manager.peer_count(), 1);
+
+        // And its connection is closed
+        let mut buffer = Vec::new();
+        bad_accepted.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert!(bad_accepted.read_to_end(&mut buffer).is_ok());
+
+        // Scores are forgotten once a peer disconnects
+        manager.register_disconnect(good_address);
+        assert_eq!(manager.score(good_address), 0);
+
+        manager.shutdown();
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+}
@@ -0,0 +1,320 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use snow::{Builder, HandshakeState, StatelessTransportState};
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::error::Error;
+
+// NOTE: The NN pattern only protects against passive observers, as
+//       neither side is authenticated
+const NOISE_PARAMS: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+const MAX_FRAME_LEN: usize = 65535;
+const TAG_LEN: usize = 16;
+const MAX_PAYLOAD_LEN: usize = MAX_FRAME_LEN - TAG_LEN;
+
+fn write_frame<W: Write>(writer: &mut W, frame: &[u8]) -> std::io::Result<()>
+{
+    writer.write_all(&(frame.len() as u16).to_le_bytes())?;
+    writer.write_all(frame)?;
+    Ok(())
+}
+
+fn read_frame<R: Read>(reader: &mut R, frame: &mut Vec<u8>) -> std::io::Result<()>
+{
+    let mut length = [0u8; 2];
+    reader.read_exact(&mut length)?;
+
+    frame.resize(u16::from_le_bytes(length) as usize, 0);
+    reader.read_exact(frame)?;
+    Ok(())
+}
+
+#[cfg(feature = "async-network")]
+pub async fn read_frame_async<R>(reader: &mut R, frame: &mut Vec<u8>) -> std::io::Result<()>
+    where R: tokio::io::AsyncRead + Unpin
+{
+    use tokio::io::AsyncReadExt;
+
+    let mut length = [0u8; 2];
+    reader.read_exact(&mut length).await?;
+
+    frame.resize(u16::from_le_bytes(length) as usize, 0);
+    reader.read_exact(frame).await?;
+    Ok(())
+}
+
+fn send_handshake<S: Write>(stream: &mut S, noise: &mut HandshakeState)
+    -> Result<(), Box<dyn Error>>
+{
+    let mut message = vec![0u8; MAX_FRAME_LEN];
+    let length = noise.write_message(&[], &mut message)?;
+    write_frame(stream, &message[..length])?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn recv_handshake<S: Read>(stream: &mut S, noise: &mut HandshakeState)
+    -> Result<(), Box<dyn Error>>
+{
+    let mut message = Vec::new();
+    let mut payload = vec![0u8; MAX_FRAME_LEN];
+    read_frame(stream, &mut message)?;
+    noise.read_message(&message, &mut payload)?;
+    Ok(())
+}
+
+pub fn handshake<S: Read + Write>(stream: &mut S, initiator: bool)
+    -> Result<Arc<StatelessTransportState>, Box<dyn Error>>
+{
+    let builder = Builder::new(NOISE_PARAMS.parse()?);
+    let mut noise =
+        if initiator {
+            builder.build_initiator()?
+        } else {
+            builder.build_responder()?
+        };
+
+    // -> e
+    // <- e, ee
+    if initiator
+    {
+        send_handshake(stream, &mut noise)?;
+        recv_handshake(stream, &mut noise)?;
+    }
+    else
+    {
+        recv_handshake(stream, &mut noise)?;
+        send_handshake(stream, &mut noise)?;
+    }
+
+    Ok(Arc::from(noise.into_stateless_transport_mode()?))
+}
+
+fn to_io_error(err: snow::Error) -> std::io::Error
+{
+    std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())
+}
+
+pub struct EncryptedWriter<W: Write>
+{
+    writer: W,
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptedWriter<W>
+{
+
+    pub fn new(writer: W, transport: Arc<StatelessTransportState>) -> Self
+    {
+        Self
+        {
+            writer,
+            transport,
+            nonce: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn write_payload(&mut self, length: usize) -> std::io::Result<()>
+    {
+        let mut frame = vec![0u8; length + TAG_LEN];
+        let frame_length = self.transport
+            .write_message(self.nonce, &self.buffer[..length], &mut frame)
+            .map_err(to_io_error)?;
+        self.nonce += 1;
+
+        write_frame(&mut self.writer, &frame[..frame_length])?;
+        self.buffer.drain(..length);
+        Ok(())
+    }
+
+}
+
+impl<W: Write> Write for EncryptedWriter<W>
+{
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize>
+    {
+        self.buffer.extend_from_slice(data);
+        while self.buffer.len() >= MAX_PAYLOAD_LEN {
+            self.write_payload(MAX_PAYLOAD_LEN)?;
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        if !self.buffer.is_empty() {
+            self.write_payload(self.buffer.len())?;
+        }
+
+        self.writer.flush()
+    }
+
+}
+
+// Decrypts frames in the order they were sent, however they were read
+pub struct FrameDecrypter
+{
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+impl FrameDecrypter
+{
+
+    pub fn new(transport: Arc<StatelessTransportState>) -> Self
+    {
+        Self
+        {
+            transport,
+            nonce: 0,
+        }
+    }
+
+    pub fn decrypt(&mut self, frame: &[u8], payload: &mut Vec<u8>) -> std::io::Result<()>
+    {
+        payload.resize(frame.len(), 0);
+        let length = self.transport
+            .read_message(self.nonce, frame, payload)
+            .map_err(to_io_error)?;
+        self.nonce += 1;
+
+        payload.truncate(length);
+        Ok(())
+    }
+
+}
+
+pub struct EncryptedReader<R: Read>
+{
+    reader: R,
+    decrypter: FrameDecrypter,
+    frame: Vec<u8>,
+    buffer: Vec<u8>,
+    offset: usize,
+}
+
+impl<R: Read> EncryptedReader<R>
+{
+
+    pub fn new(reader: R, transport: Arc<StatelessTransportState>) -> Self
+    {
+        Self
+        {
+            reader,
+            decrypter: FrameDecrypter::new(transport),
+            frame: Vec::new(),
+            buffer: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    fn read_payload(&mut self) -> std::io::Result<()>
+    {
+        read_frame(&mut self.reader, &mut self.frame)?;
+        self.decrypter.decrypt(&self.frame, &mut self.buffer)?;
+        self.offset = 0;
+        Ok(())
+    }
+
+}
+
+impl<R: Read> Read for EncryptedReader<R>
+{
+
+    fn read(&mut self, data: &mut [u8]) -> std::io::Result<usize>
+    {
+        while self.offset >= self.buffer.len() {
+            self.read_payload()?;
+        }
+
+        let count = std::cmp::min(data.len(), self.buffer.len() - self.offset);
+        data[..count].copy_from_slice(&self.buffer[self.offset..self.offset + count]);
+        self.offset += count;
+        Ok(count)
+    }
+
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::network::packet::{Message, Packet};
+    use tcp_channel::{SenderBuilder, ReceiverBuilder, LittleEndian};
+    use tcp_channel::{ChannelSend, ChannelRecv};
+    use std::net::{TcpListener, TcpStream};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_encrypted_stream()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let responder = std::thread::spawn(move ||
+        {
+            let (mut stream, _) = listener.accept().unwrap();
+            let transport = handshake(&mut stream, false).unwrap();
+
+            // Capture everything sent over the wire
+            let mut wire = Vec::new();
+            stream.read_to_end(&mut wire).unwrap();
+            (wire, transport)
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        let transport = handshake(&mut stream, true).unwrap();
+
+        // Large enough to be split over multiple frames
+        let marker = 0x1122334455667788u128;
+        let data = vec![0xAB; MAX_FRAME_LEN * 2];
+        let messages = vec![
+            Message::Packet(Packet::Ping(marker)),
+            Message::CompressedPacket(data.clone()),
+        ];
+
+        let mut sender = SenderBuilder::new()
+            .with_type::<Message>()
+            .with_endianness::<LittleEndian>()
+            .build(EncryptedWriter::new(stream.try_clone().unwrap(), transport));
+        for message in &messages {
+            sender.send(message).unwrap();
+        }
+        sender.flush().unwrap();
+        stream.shutdown(std::net::Shutdown::Both).unwrap();
+
+        // A plaintext observer can't see the payload
+        let (wire, transport) = responder.join().unwrap();
+        let contains = |needle: &[u8]| wire.windows(needle.len()).any(|x| x == needle);
+        assert!(!contains(&marker.to_le_bytes()));
+        assert!(!contains(&data[..32]));
+
+        let mut receiver = ReceiverBuilder::new()
+            .with_type::<Message>()
+            .with_endianness::<LittleEndian>()
+            .build(EncryptedReader::new(Cursor::new(wire.clone()), transport.clone()));
+        assert!(matches!(receiver.recv(), Ok(Message::Packet(Packet::Ping(x))) if x == marker));
+        assert!(matches!(receiver.recv(), Ok(Message::CompressedPacket(x)) if x == data));
+        assert!(receiver.recv().is_err());
+
+        // Tampering is detected
+        let mut tampered = wire.clone();
+        tampered[4] ^= 0xFF;
+        let mut receiver = ReceiverBuilder::new()
+            .with_type::<Message>()
+            .with_endianness::<LittleEndian>()
+            .build(EncryptedReader::new(Cursor::new(tampered), transport));
+        assert!(receiver.recv().is_err());
+    }
+
+}
+
@@ -0,0 +1,49 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+
+#[derive(Debug)]
+pub enum NetworkError
+{
+    PortInUse(u16),
+    Bind(u16, std::io::Error),
+}
+
+impl NetworkError
+{
+
+    pub fn from_bind_error(port: u16, err: std::io::Error) -> Self
+    {
+        match err.kind()
+        {
+            ErrorKind::AddrInUse => NetworkError::PortInUse(port),
+            _ => NetworkError::Bind(port, err),
+        }
+    }
+
+}
+
+impl Display for NetworkError
+{
+
+    fn fmt(&self, f: &mut Formatter) 
+        -> Result<(), std::fmt::Error>
+    {
+        match self
+        {
+            NetworkError::PortInUse(port) => write!(f, "Port {} is already in use", port),
+            NetworkError::Bind(port, err) => write!(f, "Could not listen on port {}: {}", port, err),
+        }
+    }
+
+}
+
+impl std::error::Error for NetworkError
+{
+}
+
@@ -8,15 +8,20 @@ pub mod packet;
 pub mod client;
 pub mod server;
 pub mod client_manager;
+pub mod encryption;
+pub mod error;
+pub mod packet_counters;
+#[cfg(feature = "async-network")]
+pub mod async_client;
 mod node_discovery;
 mod report_manager;
 use packet::PacketHandler;
 use client_manager::ClientManager;
-use server::start_server_thread;
+use server::{start_server_threads, wake_address, DEFAULT_LISTEN_ADDRESS};
 use node_discovery::start_node_discovery_thread;
 use report_manager::start_report_manager_thread;
 
-use std::net::TcpStream;
+use std::net::{IpAddr, TcpStream};
 use std::thread::JoinHandle;
 use std::sync::{Arc, Mutex};
 use std::path::PathBuf;
@@ -25,8 +30,9 @@ use std::error::Error;
 struct NetworkConnectionData
 {
     port: u16,
+    listen_addresses: Vec<IpAddr>,
     shutdown_signal: Arc<Mutex<bool>>,
-    server_thread: Option<JoinHandle<()>>,
+    server_threads: Vec<JoinHandle<()>>,
     node_discovery_thread: Option<JoinHandle<()>>,
     report_manager_thread: Option<JoinHandle<()>>,
     manager: ClientManager,
@@ -48,12 +54,47 @@ impl<H> NetworkConnection<H>
 
     pub fn open(port: u16, data_directory: &PathBuf, packet_handler: H) 
         -> Result<Self, Box<dyn Error>>
+    {
+        Self::open_on(&[DEFAULT_LISTEN_ADDRESS], port, data_directory, packet_handler)
+    }
+
+    // Same as `open`, but only accepting connections on the given addresses
+    pub fn open_on(listen_addresses: &[IpAddr], port: u16, data_directory: &PathBuf, 
+                   packet_handler: H) 
+        -> Result<Self, Box<dyn Error>>
     {
         let shutdown_signal = Arc::from(Mutex::from(false));
         let manager = ClientManager::new(port, data_directory, shutdown_signal.clone());
+        Self::start(listen_addresses, manager, shutdown_signal, packet_handler)
+    }
 
-        let server = start_server_thread(
-            packet_handler.clone(), manager.clone())?;
+    // Same as `open`, but clients are handled as tasks on the given runtime
+    #[cfg(feature = "async-network")]
+    pub fn open_async(port: u16, data_directory: &PathBuf, packet_handler: H,
+                      runtime: tokio::runtime::Handle) 
+        -> Result<Self, Box<dyn Error>>
+    {
+        Self::open_async_on(&[DEFAULT_LISTEN_ADDRESS], port, data_directory, 
+            packet_handler, runtime)
+    }
+
+    #[cfg(feature = "async-network")]
+    pub fn open_async_on(listen_addresses: &[IpAddr], port: u16, data_directory: &PathBuf, 
+                         packet_handler: H, runtime: tokio::runtime::Handle) 
+        -> Result<Self, Box<dyn Error>>
+    {
+        let shutdown_signal = Arc::from(Mutex::from(false));
+        let mut manager = ClientManager::new(port, data_directory, shutdown_signal.clone());
+        manager.set_runtime(runtime);
+        Self::start(listen_addresses, manager, shutdown_signal, packet_handler)
+    }
+
+    fn start(listen_addresses: &[IpAddr], manager: ClientManager, 
+             shutdown_signal: Arc<Mutex<bool>>, packet_handler: H)
+        -> Result<Self, Box<dyn Error>>
+    {
+        let servers = start_server_threads(
+            packet_handler.clone(), manager.clone(), listen_addresses)?;
         
         let node_discovery = start_node_discovery_thread(
             packet_handler.clone(), manager.clone());
@@ -65,9 +106,10 @@ impl<H> NetworkConnection<H>
         {
             _data: Arc::from(Mutex::from(NetworkConnectionData
             {
-                port,
+                port: manager.port(),
+                listen_addresses: listen_addresses.to_vec(),
                 shutdown_signal: shutdown_signal.clone(),
-                server_thread: Some(server),
+                server_threads: servers,
                 node_discovery_thread: Some(node_discovery),
                 report_manager_thread: Some(report_manager),
                 manager: manager.clone(),
@@ -84,6 +126,11 @@ impl<H> NetworkConnection<H>
         *self.shutdown_signal.lock().unwrap()
     }
 
+    pub fn shutdown_signal(&self) -> Arc<Mutex<bool>>
+    {
+        self.shutdown_signal.clone()
+    }
+
     pub fn manager(&mut self) -> &mut ClientManager
     {
         &mut self.manager
@@ -105,10 +152,13 @@ impl Drop for NetworkConnectionData
 
         *self.shutdown_signal.lock().unwrap() = true;
         self.manager.shutdown();
-        let _ = TcpStream::connect(format!("127.0.0.1:{}", self.port));
+        for address in &self.listen_addresses {
+            let _ = TcpStream::connect(wake_address(*address, self.port));
+        }
 
-        let server = self.server_thread.take().unwrap();
-        server.join().unwrap();
+        for server in self.server_threads.drain(..) {
+            server.join().unwrap();
+        }
 
         let node_discovery = self.node_discovery_thread.take().unwrap();
         node_discovery.join().unwrap();
@@ -120,17 +170,22 @@ impl Drop for NetworkConnectionData
 }
 
 #[cfg(test)]
-mod tests
+pub mod tests
 {
 
     use super::*;
-    use super::packet::Packet;
+    use super::packet::{Packet, Message, PROTOCOL_VERSION};
+    use super::error::NetworkError;
+    use tcp_channel::{SenderBuilder, ReceiverBuilder, LittleEndian};
+    use tcp_channel::{ChannelSend, ChannelRecv};
+    use std::io::{Write, BufReader, BufWriter};
+    use std::time::Duration;
 
     use std::sync::mpsc::{Sender, Receiver, channel};
     use std::error::Error;
 
     #[derive(Clone)]
-    struct TestCommandHandler
+    pub struct TestCommandHandler
     {
         test_sender: Arc<Mutex<Sender<Packet>>>,
     }
@@ -164,7 +219,7 @@ mod tests
 
     }
 
-    fn create_connection(port: u16) -> (NetworkConnection<TestCommandHandler>, Receiver<Packet>)
+    pub fn create_connection(port: u16) -> (NetworkConnection<TestCommandHandler>, Receiver<Packet>)
     {
         let (send, recv) = channel();
         let command_handler = TestCommandHandler { test_sender: Arc::from(Mutex::from(send)) };
@@ -199,6 +254,236 @@ mod tests
         assert!(matches!(recv_b.recv().unwrap(), Packet::Ping {..}));
     }
 
+    #[test]
+    fn test_port_in_use()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let (_connection, _recv) = create_connection(8093);
+        let (send, _recv) = channel();
+        let command_handler = TestCommandHandler { test_sender: Arc::from(Mutex::from(send)) };
+        let err = NetworkConnection::open_temp(8093, command_handler)
+            .err()
+            .expect("Port should already be in use");
+
+        let network_error = err.downcast_ref::<NetworkError>();
+        assert!(matches!(network_error, Some(NetworkError::PortInUse(8093))));
+        assert_eq!(err.to_string(), "Port 8093 is already in use");
+    }
+
+    #[test]
+    fn test_listen_on_ipv4_and_ipv6()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let (send, recv_a) = channel();
+        let command_handler = TestCommandHandler { test_sender: Arc::from(Mutex::from(send)) };
+        let path = std::env::temp_dir().join(rand::random::<u32>().to_string());
+        let listen_addresses = ["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()];
+        let mut connection_a = NetworkConnection::open_on(&listen_addresses, 8113, 
+            &path, command_handler).unwrap();
+
+        // Connections are accepted on each address
+        let (mut connection_b, recv_b) = create_connection(8114);
+        let (mut connection_c, recv_c) = create_connection(8115);
+        connection_b.manager().register_node("127.0.0.1:8113");
+        connection_c.manager().register_node("[::1]:8113");
+        assert_eq!(recv_b.recv().unwrap(), Packet::OnConnected);
+        assert_eq!(recv_c.recv().unwrap(), Packet::OnConnected);
+        assert_eq!(recv_a.recv().unwrap(), Packet::OnConnected);
+        while connection_a.manager().peer_count() < 2 {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        // NOTE: Skip over pings sent by node discovery
+        let recv_ping = |recv: &Receiver<Packet>, expected: u128| loop
+        {
+            if recv.recv_timeout(Duration::from_secs(10)).unwrap() == Packet::Ping(expected) {
+                break;
+            }
+        };
+
+        // With the IPv6 peer's address in bracket notation
+        connection_a.manager().send_to(Packet::Ping(1), |x| x == "[::1]:8115").unwrap();
+        recv_ping(&recv_c, 1);
+        connection_a.manager().send_to(Packet::Ping(2), |x| x == "127.0.0.1:8114").unwrap();
+        recv_ping(&recv_b, 2);
+    }
+
+    #[test]
+    fn test_protocol_version_mismatch()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let (mut connection, recv) = create_connection(8068);
+        let stream = TcpStream::connect("127.0.0.1:8068").unwrap();
+        let mut sender = SenderBuilder::new()
+            .with_type::<Message>()
+            .with_endianness::<LittleEndian>()
+            .build(BufWriter::new(stream.try_clone().unwrap()));
+        let mut receiver = ReceiverBuilder::new()
+            .with_type::<Message>()
+            .with_endianness::<LittleEndian>()
+            .build(BufReader::new(stream));
+
+        assert!(matches!(receiver.recv(), Ok(Message::OnConnected(8068, PROTOCOL_VERSION))));
+        assert!(matches!(receiver.recv(), Ok(Message::Capabilities(_, _))));
+        sender.send(&Message::OnConnected(8069, PROTOCOL_VERSION + 1)).unwrap();
+        sender.flush().unwrap();
+
+        // The connection is closed without being registered
+        assert!(receiver.recv().is_err());
+        assert!(recv.recv_timeout(Duration::from_millis(500)).is_err());
+        assert_eq!(connection.manager().peer_count(), 0);
+    }
+
+    #[test]
+    fn test_oversized_packet()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let (mut connection, recv) = create_connection(8103);
+        connection.manager().set_max_packet_size(0x1000);
+
+        let stream = TcpStream::connect("127.0.0.1:8103").unwrap();
+        let mut writer = BufWriter::new(stream.try_clone().unwrap());
+        let mut sender = SenderBuilder::new()
+            .with_type::<Message>()
+            .with_endianness::<LittleEndian>()
+            .build(&mut writer);
+        let mut receiver = ReceiverBuilder::new()
+            .with_type::<Message>()
+            .with_endianness::<LittleEndian>()
+            .build(BufReader::new(stream));
+
+        assert!(matches!(receiver.recv(), Ok(Message::OnConnected(8103, PROTOCOL_VERSION))));
+        assert!(matches!(receiver.recv(), Ok(Message::Capabilities(_, _))));
+        sender.send(&Message::OnConnected(8104, PROTOCOL_VERSION)).unwrap();
+        sender.send(&Message::Capabilities(false, false)).unwrap();
+        sender.send(&Message::Packet(Packet::Ping(0))).unwrap();
+        sender.flush().unwrap();
+        assert_eq!(recv.recv().unwrap(), Packet::Ping(0));
+
+        // Claim a huge packet, without sending any of it
+        writer.write_all(&(1u64 << 40).to_le_bytes()).unwrap();
+        writer.flush().unwrap();
+
+        // The peer is dropped, rather than the node trying to allocate it
+        while receiver.recv().is_ok() {}
+        let mut timer = 0;
+        while connection.manager().peer_count() > 0 && timer < 10000
+        {
+            std::thread::sleep(Duration::from_millis(100));
+            timer += 100;
+        }
+        assert_eq!(connection.manager().peer_count(), 0);
+    }
+
+    #[test]
+    fn test_compressed_block_fallback()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let (mut connection_a, _recv_a) = create_connection(8071);
+        let (mut connection_b, recv_b) = create_connection(8072);
+        let (mut connection_c, recv_c) = create_connection(8073);
+        connection_c.manager().set_compression(false);
+        connection_b.manager().register_node("127.0.0.1:8071");
+        connection_c.manager().register_node("127.0.0.1:8071");
+        assert_eq!(recv_b.recv().unwrap(), Packet::OnConnected);
+        assert_eq!(recv_c.recv().unwrap(), Packet::OnConnected);
+
+        // Wait for both to be connected to a
+        while connection_a.manager().peer_count() < 2 {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        let block = packet::tests::large_block();
+        connection_a.manager().send(Packet::Block(block.clone())).unwrap();
+        let recv_block = |recv: &Receiver<Packet>| loop
+        {
+            match recv.recv_timeout(Duration::from_secs(10)).unwrap()
+            {
+                Packet::Block(block) => return block,
+                _ => {},
+            }
+        };
+        assert_eq!(recv_block(&recv_b), block);
+        assert_eq!(recv_block(&recv_c), block);
+    }
+
+    #[test]
+    fn test_encrypted_connection()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let (mut connection_a, recv_a) = create_connection(8123);
+        let (mut connection_b, recv_b) = create_connection(8077);
+        let (mut connection_c, recv_c) = create_connection(8078);
+        connection_c.manager().set_encryption(false);
+        connection_b.manager().register_node("127.0.0.1:8123");
+        connection_c.manager().register_node("127.0.0.1:8123");
+        assert_eq!(recv_b.recv().unwrap(), Packet::OnConnected);
+        assert_eq!(recv_c.recv().unwrap(), Packet::OnConnected);
+
+        while connection_a.manager().peer_count() < 2 {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        // b is encrypted, and c falls back to plaintext
+        let recv_ping = |recv: &Receiver<Packet>, expected: u128| loop
+        {
+            match recv.recv_timeout(Duration::from_secs(10)).unwrap()
+            {
+                Packet::Ping(x) if x == expected => return,
+                _ => {},
+            }
+        };
+
+        connection_a.manager().send(Packet::Ping(1)).unwrap();
+        recv_ping(&recv_b, 1);
+        recv_ping(&recv_c, 1);
+
+        connection_b.manager().send(Packet::Ping(2)).unwrap();
+        recv_ping(&recv_a, 2);
+        connection_c.manager().send(Packet::Ping(3)).unwrap();
+        recv_ping(&recv_a, 3);
+
+        // a remembers which of them supported encryption
+        assert!(connection_a.manager().is_known_encrypted("127.0.0.1:8077"));
+        assert!(!connection_a.manager().is_known_encrypted("127.0.0.1:8078"));
+    }
+
+    #[test]
+    fn test_refuse_encryption_downgrade()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let (mut connection, recv) = create_connection(8124);
+        connection.manager().register_encrypted("127.0.0.1:8125");
+
+        let stream = TcpStream::connect("127.0.0.1:8124").unwrap();
+        let mut sender = SenderBuilder::new()
+            .with_type::<Message>()
+            .with_endianness::<LittleEndian>()
+            .build(BufWriter::new(stream.try_clone().unwrap()));
+        let mut receiver = ReceiverBuilder::new()
+            .with_type::<Message>()
+            .with_endianness::<LittleEndian>()
+            .build(BufReader::new(stream));
+
+        assert!(matches!(receiver.recv(), Ok(Message::OnConnected(8124, PROTOCOL_VERSION))));
+        assert!(matches!(receiver.recv(), Ok(Message::Capabilities(_, true))));
+        sender.send(&Message::OnConnected(8125, PROTOCOL_VERSION)).unwrap();
+        sender.send(&Message::Capabilities(false, false)).unwrap();
+        sender.flush().unwrap();
+
+        // Having been encrypted before, it's not accepted in plaintext
+        assert!(receiver.recv().is_err());
+        assert!(recv.recv_timeout(Duration::from_millis(500)).is_err());
+        assert_eq!(connection.manager().peer_count(), 0);
+    }
+
     #[test]
     fn test_network()
     {
@@ -248,5 +533,56 @@ mod tests
         assert!(matches!(recv_c.recv().expect("Got packet"), Packet::Ping {..}));
     }
 
+    #[cfg(feature = "async-network")]
+    #[test]
+    fn test_async_network()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let create_async_connection = |port: u16, runtime: &tokio::runtime::Runtime|
+        {
+            let (send, recv) = channel();
+            let command_handler = TestCommandHandler { test_sender: Arc::from(Mutex::from(send)) };
+            let path = std::env::temp_dir().join(rand::random::<u32>().to_string());
+            let connection = NetworkConnection::open_async(port, &path, 
+                command_handler, runtime.handle().clone()).unwrap();
+            (connection, recv)
+        };
+
+        // a and b are async, and c is threaded and unencrypted
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (mut connection_a, recv_a) = create_async_connection(8097, &runtime);
+        let (mut connection_b, recv_b) = create_async_connection(8098, &runtime);
+        let (mut connection_c, recv_c) = create_connection(8099);
+        connection_c.manager().set_encryption(false);
+        connection_b.manager().register_node("127.0.0.1:8097");
+        connection_c.manager().register_node("127.0.0.1:8097");
+        assert_eq!(recv_b.recv().unwrap(), Packet::OnConnected);
+        assert_eq!(recv_c.recv().unwrap(), Packet::OnConnected);
+
+        while connection_a.manager().peer_count() < 2 {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        let recv_packet = |recv: &Receiver<Packet>, expected: &Packet| loop
+        {
+            let packet = recv.recv_timeout(Duration::from_secs(10)).unwrap();
+            if &packet == expected {
+                return;
+            }
+        };
+
+        connection_b.manager().send(Packet::Ping(1)).unwrap();
+        recv_packet(&recv_a, &Packet::Ping(1));
+        connection_c.manager().send(Packet::Ping(2)).unwrap();
+        recv_packet(&recv_a, &Packet::Ping(2));
+
+        // Large enough to be split over many reads and encrypted frames
+        let block = Packet::Block(packet::tests::large_block());
+        connection_a.manager().send(block.clone()).unwrap();
+        recv_packet(&recv_b, &block);
+        recv_packet(&recv_c, &block);
+    }
+
 }
 
@@ -9,9 +9,12 @@ use super::client_manager::ClientManager;
 
 use std::net::TcpStream;
 use std::thread::JoinHandle;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::error::Error;
 
+const DISCOVERY_TICK: Duration = Duration::from_millis(100);
+const PING_INTERVAL: Duration = Duration::from_millis(1000);
+
 fn try_connect_to_node<H>(address: String, packet_handler: &H,
                           manager: &mut ClientManager)
     -> Result<(), Box<dyn Error>>
@@ -22,7 +25,7 @@ fn try_connect_to_node<H>(address: String, packet_handler: &H,
     let sock_address = address.parse()?;
     let stream = TcpStream::connect_timeout(&sock_address, Duration::from_secs(1))?;
     let ip = sock_address.ip().to_string();
-    manager.new_client(packet_handler.clone(), stream, ip)?;
+    manager.new_client(packet_handler.clone(), stream, ip, true)?;
     Ok(())
 }
 
@@ -31,8 +34,11 @@ fn connect_to_new_nodes<H>(packet_handler: &H,
     where H: PacketHandler + Clone + Send + Sync + 'static
 {
     let not_connected_nodes = manager.pending_connections();
-    for address in not_connected_nodes {
-        let _ = try_connect_to_node(address, packet_handler, manager);
+    for address in not_connected_nodes 
+    {
+        if try_connect_to_node(address.clone(), packet_handler, manager).is_err() {
+            manager.register_connection_failure(&address);
+        }
     }
 }
 
@@ -45,11 +51,26 @@ fn ping_old_nodes(manager: &mut ClientManager)
     let _ = manager.send(Packet::Ping(current_time_nanos));
 }
 
+fn is_due(last_run: &mut Option<Instant>, interval: Duration, now: Instant) -> bool
+{
+    match last_run
+    {
+        Some(last) if now.duration_since(*last) < interval => false,
+        _ =>
+        {
+            *last_run = Some(now);
+            true
+        },
+    }
+}
+
 pub fn start_node_discovery_thread<H>(packet_handler: H, 
                                       mut manager: ClientManager)
         -> JoinHandle<()>
     where H: PacketHandler + Clone + Send + Sync + 'static
 {
+    let mut last_connect = None;
+    let mut last_ping = None;
     std::thread::spawn(move || loop
     {
         if manager.should_shutdown() 
@@ -58,10 +79,46 @@ pub fn start_node_discovery_thread<H>(packet_handler: H,
             break;
         }
 
-        connect_to_new_nodes(&packet_handler, &mut manager);
-        ping_old_nodes(&mut manager);
+        // NOTE: Only take the manager lock to reconnect once per interval, 
+        //       not on every tick
+        let now = Instant::now();
+        if is_due(&mut last_connect, manager.connect_interval(), now) {
+            connect_to_new_nodes(&packet_handler, &mut manager);
+        }
+        if is_due(&mut last_ping, PING_INTERVAL, now) {
+            ping_old_nodes(&mut manager);
+        }
 
-        std::thread::sleep(Duration::from_millis(1000));
+        std::thread::sleep(DISCOVERY_TICK);
     })
 }
 
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+
+    #[test]
+    fn test_connect_interval()
+    {
+        let start = Instant::now();
+        let interval = Duration::from_millis(500);
+        let mut last_connect = None;
+
+        // Simulate 3 seconds of discovery ticks
+        let ticks = 30;
+        let sweeps = (0..ticks)
+            .map(|tick| start + DISCOVERY_TICK * tick)
+            .filter(|now| is_due(&mut last_connect, interval, *now))
+            .count();
+        assert_eq!(sweeps, 6);
+
+        // Always sweep immediately on startup
+        let mut last_connect = None;
+        assert!(is_due(&mut last_connect, Duration::from_secs(60), start));
+        assert!(!is_due(&mut last_connect, Duration::from_secs(60), start + DISCOVERY_TICK));
+    }
+
+}
+
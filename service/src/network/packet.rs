@@ -7,35 +7,220 @@
 use super::client_manager::ClientManager;
 use crate::report::NodeReport;
 
-use libhyperchain::block::Block;
+use libhyperchain::block::{Block, BlockHeader};
 use libhyperchain::data_store::data_unit::DataUnit;
 use libhyperchain::transaction::Transaction;
 use libhyperchain::transaction::transfer::Transfer;
 use libhyperchain::transaction::page::Page;
+use libhyperchain::hash::Hash;
+use libhyperchain::error::ErrorMessage;
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use std::io::{Read, Write};
 use std::error::Error;
 
-pub type MessageSender = tcp_channel::Sender<Message, tcp_channel::LittleEndian>;
-pub type MessageReceiver = tcp_channel::Receiver<Message, tcp_channel::LittleEndian>;
+// NOTE: Bump this whenever the format of `Message` or `Packet`, or the 
+//       rules for a valid block, change
+pub const PROTOCOL_VERSION: u32 = 10;
+const COMPRESSION_LEVEL: i32 = 3;
+
+pub type MessageSender = tcp_channel::Sender<Message, tcp_channel::LittleEndian, Box<dyn Write + Send>>;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum Packet
 {
     OnConnected,
+    Height(u64),
     Block(Block),
     BlockRequest(u64),
+    BlockNotFound(u64),
+    TopRequest,
     Transfer(Transaction<Transfer>),
     Page(Transaction<Page>, DataUnit),
     Report(Option<String>, NodeReport),
     Ping(u128),
+    Reject(Hash, String),
+    GetHeaders(Vec<Hash>),
+    Headers(Vec<BlockHeader>),
+}
+
+impl Packet
+{
+
+    pub fn name(&self) -> &'static str
+    {
+        match self
+        {
+            Packet::OnConnected => "OnConnected",
+            Packet::Height(_) => "Height",
+            Packet::Block(_) => "Block",
+            Packet::BlockRequest(_) => "BlockRequest",
+            Packet::BlockNotFound(_) => "BlockNotFound",
+            Packet::TopRequest => "TopRequest",
+            Packet::Transfer(_) => "Transfer",
+            Packet::Page(_, _) => "Page",
+            Packet::Report(_, _) => "Report",
+            Packet::Ping(_) => "Ping",
+            Packet::Reject(_, _) => "Reject",
+            Packet::GetHeaders(_) => "GetHeaders",
+            Packet::Headers(_) => "Headers",
+        }
+    }
+
 }
 
+// NOTE: `OnConnected` must keep the same shape, so a peer of any 
+//       version can read the protocol version from it. Optional 
+//       features are sent after it in `Capabilities`.
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Message
 {
-    OnConnected(u16),
+    OnConnected(u16, u32),
     KnownNode(String),
     Packet(Packet),
+    CompressedPacket(Vec<u8>),
+    Capabilities(bool, bool),
+}
+
+pub fn compress_packet(packet: &Packet) -> Result<Vec<u8>, Box<dyn Error>>
+{
+    let bytes = bincode::serialize(packet)?;
+    Ok(zstd::encode_all(bytes.as_slice(), COMPRESSION_LEVEL)?)
+}
+
+// NOTE: Bincode ignores anything after the value it reads, so check it 
+//       encodes back to exactly what was received. Otherwise the same 
+//       block or transaction could be sent in any number of forms.
+pub fn deserialize_canonical<T>(bytes: &[u8]) -> Result<T, Box<dyn Error>>
+    where T: Serialize + DeserializeOwned
+{
+    let value = bincode::deserialize(bytes)?;
+    if bincode::serialize(&value)? != bytes {
+        return Err(ErrorMessage::new("Non-canonical encoding"));
+    }
+
+    Ok(value)
+}
+
+// NOTE: A small packet can decompress to any size, so stop once it's 
+//       larger then a packet we would have accepted uncompressed.
+pub fn decompress_packet(data: &[u8], max_packet_size: usize) -> Result<Packet, Box<dyn Error>>
+{
+    let mut bytes = Vec::new();
+    zstd::stream::read::Decoder::new(data)?
+        .take(max_packet_size as u64 + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() > max_packet_size {
+        return Err(ErrorMessage::new("Decompressed packet is larger then the max packet size"));
+    }
+
+    deserialize_canonical(&bytes)
+}
+
+// NOTE: Checks the length each message is prefixed with, so a peer 
+//       can't make the receiver allocate an arbitrarily large buffer.
+pub struct PacketSizeLimiter<R>
+    where R: Read
+{
+    reader: R,
+    max_packet_size: usize,
+    length: [u8; 8],
+    length_read: usize,
+    remaining: u64,
+}
+
+impl<R> PacketSizeLimiter<R>
+    where R: Read
+{
+
+    pub fn new(reader: R, max_packet_size: usize) -> Self
+    {
+        Self
+        {
+            reader,
+            max_packet_size,
+            length: [0u8; 8],
+            length_read: 0,
+            remaining: 0,
+        }
+    }
+
+    fn read_length(&mut self, data: &mut [u8]) -> std::io::Result<usize>
+    {
+        let count = std::cmp::min(data.len(), self.length.len() - self.length_read);
+        let count = self.reader.read(&mut data[..count])?;
+        self.length[self.length_read..self.length_read + count].copy_from_slice(&data[..count]);
+        self.length_read += count;
+        if self.length_read < self.length.len() {
+            return Ok(count);
+        }
+
+        let length = u64::from_le_bytes(self.length);
+        if length > self.max_packet_size as u64
+        {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!(
+                "Packet of {} bytes is larger then the max of {}", 
+                length, self.max_packet_size)));
+        }
+
+        self.length_read = 0;
+        self.remaining = length;
+        Ok(count)
+    }
+
+}
+
+impl<R> Read for PacketSizeLimiter<R>
+    where R: Read
+{
+
+    fn read(&mut self, data: &mut [u8]) -> std::io::Result<usize>
+    {
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            return self.read_length(data);
+        }
+
+        let count = std::cmp::min(data.len() as u64, self.remaining) as usize;
+        let count = self.reader.read(&mut data[..count])?;
+        self.remaining -= count as u64;
+        Ok(count)
+    }
+
+}
+
+// NOTE: Uses the same framing as `tcp_channel`, a little endian length 
+//       followed by the bincode encoded message, but only accepts 
+//       messages in their canonical encoding.
+pub struct MessageReceiver
+{
+    reader: PacketSizeLimiter<Box<dyn Read + Send>>,
+}
+
+impl MessageReceiver
+{
+
+    pub fn new(reader: Box<dyn Read + Send>, max_packet_size: usize) -> Self
+    {
+        Self
+        {
+            reader: PacketSizeLimiter::new(reader, max_packet_size),
+        }
+    }
+
+    pub fn recv(&mut self) -> Result<Message, Box<dyn Error>>
+    {
+        let mut length = [0u8; 8];
+        self.reader.read_exact(&mut length)?;
+
+        let mut data = vec![0u8; u64::from_le_bytes(length) as usize];
+        self.reader.read_exact(&mut data)?;
+        deserialize_canonical(&data)
+    }
+
 }
 
 pub trait PacketHandler
@@ -46,5 +231,92 @@ pub trait PacketHandler
 
     fn update_reports(&self, manager: &mut ClientManager);
 
+    // Called once a peer has disconnected
+    fn handle_disconnect(&self, _from: &str)
+    {
+    }
+
+}
+
+#[cfg(test)]
+pub mod tests
+{
+
+    use super::*;
+    use libhyperchain::chain::BlockChain;
+    use libhyperchain::block::builder::BlockBuilder;
+    use libhyperchain::wallet::Wallet;
+    use libhyperchain::wallet::private_wallet::PrivateWallet;
+    use libhyperchain::miner;
+
+    pub fn large_block() -> Block
+    {
+        let mut chain = BlockChain::open_in_memory();
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let block = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        chain.add(&block).unwrap();
+
+        let mut builder = BlockBuilder::new(&wallet);
+        for _ in 0..50
+        {
+            let transfer = chain.new_transfer(vec![(&wallet, 1.25)], 
+                vec![(other.get_address(), 1.0)], 0.25).unwrap();
+            builder = builder.add_transfer(transfer);
+        }
+        builder.build(&mut chain).unwrap()
+    }
+
+    #[test]
+    fn test_compressed_packet()
+    {
+        let packet = Packet::Block(large_block());
+        let compressed = compress_packet(&packet).unwrap();
+        assert!(compressed.len() < bincode::serialize(&packet).unwrap().len());
+        assert_eq!(decompress_packet(&compressed, 0x100000).unwrap(), packet);
+
+        assert!(decompress_packet(&compressed[1..], 0x100000).is_err());
+
+        // Would decompress to more then the max packet size
+        let bytes = bincode::serialize(&packet).unwrap();
+        assert!(decompress_packet(&compressed, bytes.len() - 1).is_err());
+    }
+
+    #[test]
+    fn test_reject_non_canonical_encoding()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut chain = BlockChain::open_in_memory();
+        let block = miner::mine_block(Block::new_blank(&mut chain, &wallet).unwrap());
+        let packet = Packet::Block(block);
+
+        let bytes = bincode::serialize(&packet).unwrap();
+        assert_eq!(deserialize_canonical::<Packet>(&bytes).unwrap(), packet);
+
+        // Decodes to the same block, but with padding on the end
+        let mut padded = bytes.clone();
+        padded.extend_from_slice(&[0u8; 4]);
+        assert_eq!(bincode::deserialize::<Packet>(&padded).unwrap(), packet);
+        assert!(deserialize_canonical::<Packet>(&padded).is_err());
+
+        let compressed = zstd::encode_all(padded.as_slice(), COMPRESSION_LEVEL).unwrap();
+        assert!(decompress_packet(&compressed, 0x100000).is_err());
+
+        // Or as part of a message
+        let message = bincode::serialize(&Message::Packet(packet)).unwrap();
+        assert!(deserialize_canonical::<Message>(&message).is_ok());
+        assert!(deserialize_canonical::<Message>(&[message.as_slice(), &[1]].concat()).is_err());
+
+        // Including when it's read from a connection
+        let framed = |message: &[u8]| [&(message.len() as u64).to_le_bytes(), message].concat();
+        let mut receiver = MessageReceiver::new(
+            Box::new(std::io::Cursor::new(framed(&message))), 0x100000);
+        assert!(matches!(receiver.recv(), Ok(Message::Packet(Packet::Block(_)))));
+
+        let mut receiver = MessageReceiver::new(
+            Box::new(std::io::Cursor::new(framed(&[message.as_slice(), &[1]].concat()))), 0x100000);
+        assert!(receiver.recv().is_err());
+    }
+
 }
 
@@ -0,0 +1,48 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use super::packet::Packet;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// NOTE: Each packet type only needs the write lock the first time it's
+//       seen, after that counting is an atomic add under the read lock.
+#[derive(Default)]
+pub struct PacketCounters
+{
+    counts: RwLock<HashMap<&'static str, AtomicU64>>,
+}
+
+impl PacketCounters
+{
+
+    pub fn count(&self, packet: &Packet)
+    {
+        let name = packet.name();
+        if let Some(count) = self.counts.read().unwrap().get(name)
+        {
+            count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        self.counts.write().unwrap()
+            .entry(name)
+            .or_insert_with(|| AtomicU64::new(0))
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn totals(&self) -> BTreeMap<String, u64>
+    {
+        self.counts.read().unwrap()
+            .iter()
+            .map(|(name, count)| (name.to_string(), count.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+}
+
@@ -6,20 +6,30 @@
 
 use super::packet::PacketHandler;
 use super::client_manager::ClientManager;
+use super::error::NetworkError;
 
-use std::net::TcpListener;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
 use std::error::Error;
 use std::thread::JoinHandle;
 
-pub fn start_server_thread<H>(command_handler: H, mut manager: ClientManager)
-    -> Result<JoinHandle<()>, Box<dyn Error>>
-    where H: PacketHandler + Clone + Send + Sync + 'static
+pub const DEFAULT_LISTEN_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+// Where to connect to wake up a listener blocked on accept
+pub fn wake_address(listen_address: IpAddr, port: u16) -> SocketAddr
 {
-    // FIXME: Allow changing this port
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", manager.port()))?;
+    let ip = match listen_address
+    {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    SocketAddr::new(ip, port)
+}
 
-    info!("[{}] Starting server", manager.port());
-    Ok(std::thread::spawn(move || loop
+fn accept_loop<H>(command_handler: H, mut manager: ClientManager, listener: TcpListener)
+    where H: PacketHandler + Clone + Send + Sync + 'static
+{
+    loop
     {
         match listener.accept()
         {
@@ -29,10 +39,14 @@ pub fn start_server_thread<H>(command_handler: H, mut manager: ClientManager)
                     break;
                 }
 
-                let ip = socket.ip().to_string();
+                // NOTE: Clients on a dual stack listener are seen as IPv4 
+                //       mapped addresses, so turn them back into IPv4.
+                let ip = socket.ip().to_canonical().to_string();
                 info!("[{}] Got connection from {}", manager.port(), ip);
 
-                manager.new_client(command_handler.clone(), stream, ip).unwrap();
+                if let Err(err) = manager.new_client(command_handler.clone(), stream, ip, false) {
+                    warn!("[{}] Could not accept client: {}", manager.port(), err);
+                }
             },
 
             Err(err) =>
@@ -41,6 +55,33 @@ pub fn start_server_thread<H>(command_handler: H, mut manager: ClientManager)
                 break;
             },
         }
-    }))
+    }
+}
+
+// NOTE: Binding `::` also accepts IPv4 on most systems, in which case 
+//       it can't be bound along side `0.0.0.0`.
+pub fn start_server_threads<H>(command_handler: H, manager: ClientManager,
+                               listen_addresses: &[IpAddr])
+    -> Result<Vec<JoinHandle<()>>, Box<dyn Error>>
+    where H: PacketHandler + Clone + Send + Sync + 'static
+{
+    let port = manager.port();
+    let mut listeners = Vec::new();
+    for address in listen_addresses
+    {
+        let listener = TcpListener::bind(SocketAddr::new(*address, port))
+            .map_err(|err| NetworkError::from_bind_error(port, err))?;
+        listeners.push(listener);
+    }
+
+    info!("[{}] Starting server on {:?}", port, listen_addresses);
+    Ok(listeners
+        .into_iter()
+        .map(|listener|
+        {
+            let (command_handler, manager) = (command_handler.clone(), manager.clone());
+            std::thread::spawn(move || accept_loop(command_handler, manager, listener))
+        })
+        .collect())
 }
 
@@ -0,0 +1,97 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use crate::network::NetworkConnection;
+use crate::node::packet_handler::NodePacketHandler;
+
+use libhyperchain::service::command::{Response, NetworkInfo};
+use libhyperchain::block::target::{difficulty, hash_rate, calculate_target};
+use libhyperchain::chain::BlockChain;
+
+fn estimate_hash_rate(chain: &mut BlockChain) -> Option<f64>
+{
+    let top = chain.top()?;
+    let top_id = top.header.block_id;
+
+    // NOTE: Use headers, so this still works on a pruned chain
+    let block_count = std::cmp::min(chain.config().block_sample_size, top_id);
+    if block_count == 0 {
+        return None;
+    }
+
+    let start = chain.header(top_id - block_count)?;
+    let time = top.header.timestamp.saturating_sub(start.timestamp) as u64;
+    if time == 0 {
+        return None;
+    }
+
+    // Blocks are timestamped in milliseconds
+    let diff = difficulty(&top.header.target);
+    Some(hash_rate(diff, block_count, time) * 1000.0)
+}
+
+pub fn network_info(connection: &mut NetworkConnection<NodePacketHandler>)
+    -> Response
+{
+    let mut node = connection.handler().node();
+    let chain = node.chain();
+
+    let (sample_start, sample_end) = chain.take_sample();
+    let target = calculate_target(chain.config(), sample_start, sample_end);
+    Response::NetworkInfo(NetworkInfo
+    {
+        difficulty: difficulty(&target),
+        hash_rate: estimate_hash_rate(chain),
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::node::tests::{create_node, mine_block};
+    use libhyperchain::wallet::private_wallet::PrivateWallet;
+    use std::time::Duration;
+
+    fn get_network_info(connection: &mut NetworkConnection<NodePacketHandler>) -> NetworkInfo
+    {
+        match network_info(connection)
+        {
+            Response::NetworkInfo(info) => info,
+            _ => panic!("Expected network info response"),
+        }
+    }
+
+    #[test]
+    fn test_network_info()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut connection = create_node(8081);
+
+        // Not enough blocks to estimate from
+        let info = get_network_info(&mut connection);
+        assert_eq!(info.difficulty, 1.0);
+        assert_eq!(info.hash_rate, None);
+
+        mine_block(&mut connection, &wallet);
+        assert_eq!(get_network_info(&mut connection).hash_rate, None);
+
+        for _ in 0..3
+        {
+            std::thread::sleep(Duration::from_millis(10));
+            mine_block(&mut connection, &wallet);
+        }
+
+        let info = get_network_info(&mut connection);
+        assert_eq!(info.difficulty, 1.0);
+        assert!(info.hash_rate.unwrap() > 0.0);
+    }
+
+}
+
@@ -5,24 +5,55 @@
  */
 
 pub mod packet_handler;
+mod rejections;
 use crate::network::packet::Packet;
 use crate::network::client_manager::ClientManager;
 use crate::report::{Report, NodeReport};
+use rejections::Rejections;
 
 use libhyperchain::chain::{BlockChain, BlockChainAddResult};
 use libhyperchain::chain::branch::BlockChainCanMergeResult;
-use libhyperchain::block::Block;
+use libhyperchain::block::{Block, BlockHeader};
+use libhyperchain::block::validate::BlockValidationResult;
 use libhyperchain::data_store::DataStore;
 use libhyperchain::data_store::data_unit::DataUnit;
-use libhyperchain::transaction::Transaction;
+use libhyperchain::transaction::{Transaction, TransactionVariant, TransactionValidationResult};
 use libhyperchain::transaction::transfer::Transfer;
 use libhyperchain::transaction::page::Page;
+use libhyperchain::config::ChainConfig;
 use libhyperchain::hash::Hash;
 use std::path::PathBuf;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{sync_channel, SyncSender, Receiver, TrySendError};
+use std::time::{Duration, Instant};
 use std::error::Error;
 
+const DEFAULT_REBROADCAST_WINDOW: u64 = 5;
+const DEFAULT_STALE_TIP_INTERVALS: u64 = 10;
+const MAX_HEADERS: usize = 2000;
+const MAX_BRANCH_FILL_REQUESTS: u64 = 16;
+const DEFAULT_BLOCK_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REJECTIONS: usize = 10 * 1000;
+const MAX_PEER_REJECTIONS: usize = 100;
+const REJECTION_EXPIRY: Duration = Duration::from_secs(10 * 60);
+pub const MAX_QUEUED_SUBSCRIBER_BLOCKS: usize = 64;
+
+// NOTE: Honest peers only ask for blocks a little past our top, while
+//       catching up to us or racing a newly mined block.
+const SUSPICIOUS_REQUEST_DISTANCE: u64 = 1000;
+
+// NOTE: Other failures may just mean the block is on a different branch
+fn is_invalid_on_any_chain(result: &BlockValidationResult) -> bool
+{
+    matches!(result, 
+        BlockValidationResult::POW | 
+        BlockValidationResult::MerkleRoot | 
+        BlockValidationResult::Reward |
+        BlockValidationResult::TransactionOrder |
+        BlockValidationResult::Transaction(_))
+}
+
 pub struct Node
 {
     port: u16,
@@ -30,24 +61,81 @@ pub struct Node
     data_store: DataStore,
     report: Report,
     branches: HashMap<String, Vec<Block>>,
+    peer_heights: HashMap<String, u64>,
+
+    // NOTE: The block last requested from each peer to catch up to it, 
+    //       kept apart from those requested to fill in a branch.
+    catch_up_requests: HashMap<String, (u64, Instant)>,
+    block_request_timeout: Duration,
+
+    // The last block we have in common with each peer, and the lowest 
+    // block requested at once to fill in their branch down to it.
+    common_ancestors: HashMap<String, u64>,
+    branch_fills: HashMap<String, u64>,
+
+    // NOTE: Only what we reject ourselves is skipped when seen again. 
+    //       Rejections from peers are kept apart, so they can't stop 
+    //       us from accepting something valid.
+    rejected: Rejections,
+    peer_rejections: HashMap<String, Rejections>,
+    blocks_since_prune: u64,
+
+    // Our own unconfirmed transactions, and the chain height they 
+    // were last broadcast at.
+    own_transactions: HashMap<Hash, u64>,
+    rebroadcast_window: u64,
+    block_subscribers: Vec<SyncSender<Block>>,
+    last_block_accepted: Instant,
+    stale_tip_intervals: u64,
+    mining: bool,
+
+    // Mine blocks with nothing but the reward in them, or wait for 
+    // something to be queued first if not.
+    mine_empty_blocks: bool,
+
+    // Pass on transactions received from peers. Nodes that only keep 
+    // the chain, like private archives, can turn this off.
+    relay_transactions: bool,
 }
 
 impl Node
 {
 
-    pub fn new(port: u16, path: &PathBuf) -> Result<Arc<Mutex<Self>>, Box<dyn Error>>
+    pub fn new(port: u16, path: &PathBuf) -> Result<Arc<RwLock<Self>>, Box<dyn Error>>
+    {
+        Self::new_with_config(port, path, ChainConfig::default())
+    }
+
+    pub fn new_with_config(port: u16, path: &PathBuf, config: ChainConfig) 
+        -> Result<Arc<RwLock<Self>>, Box<dyn Error>>
     {
-        let chain = BlockChain::open(&path.join("blockchain"))?;
+        let chain = BlockChain::open_with_config(&path.join("blockchain"), config)?;
         let data_store = DataStore::open(&path.join("data"))?;
         let report = Report::open(&path.join("report.json"));
 
-        Ok(Arc::from(Mutex::from(Self
+        Ok(Arc::from(RwLock::from(Self
         {
             port,
             chain,
             data_store,
             report,
-            branches: HashMap::new()
+            branches: HashMap::new(),
+            peer_heights: HashMap::new(),
+            catch_up_requests: HashMap::new(),
+            block_request_timeout: DEFAULT_BLOCK_REQUEST_TIMEOUT,
+            common_ancestors: HashMap::new(),
+            branch_fills: HashMap::new(),
+            rejected: Rejections::new(MAX_REJECTIONS, REJECTION_EXPIRY),
+            peer_rejections: HashMap::new(),
+            blocks_since_prune: 0,
+            own_transactions: HashMap::new(),
+            rebroadcast_window: DEFAULT_REBROADCAST_WINDOW,
+            block_subscribers: Vec::new(),
+            last_block_accepted: Instant::now(),
+            stale_tip_intervals: DEFAULT_STALE_TIP_INTERVALS,
+            mining: false,
+            mine_empty_blocks: true,
+            relay_transactions: true,
         })))
     }
 
@@ -56,11 +144,255 @@ impl Node
         &mut self.chain
     }
 
+    pub fn chain_ref(&self) -> &BlockChain
+    {
+        &self.chain
+    }
+
     pub fn data_store(&mut self) -> &mut DataStore
     {
         &mut self.data_store
     }
 
+    pub fn branch_count(&self) -> usize
+    {
+        self.branches.len()
+    }
+
+    pub fn is_mining(&self) -> bool
+    {
+        self.mining
+    }
+
+    pub fn set_mining(&mut self, mining: bool)
+    {
+        self.mining = mining;
+    }
+
+    pub fn set_mine_empty_blocks(&mut self, mine_empty_blocks: bool)
+    {
+        self.mine_empty_blocks = mine_empty_blocks;
+    }
+
+    pub fn set_relay_transactions(&mut self, relay_transactions: bool)
+    {
+        self.relay_transactions = relay_transactions;
+    }
+
+    // NOTE: The first block is always worth mining, as nothing 
+    //       can be spent until it exists.
+    pub fn has_block_to_mine(&self) -> bool
+    {
+        self.mine_empty_blocks 
+            || self.chain.top_ref().is_none()
+            || self.chain.transaction_queue_size() > 0
+    }
+
+    pub fn best_peer_height(&self) -> Option<u64>
+    {
+        self.peer_heights.values().max().cloned()
+    }
+
+    pub fn common_ancestor(&self, peer: &str) -> Option<u64>
+    {
+        self.common_ancestors.get(peer).cloned()
+    }
+
+    pub fn sync_progress(&self) -> f64
+    {
+        let best_height_or_none = self.best_peer_height();
+        if best_height_or_none.is_none() {
+            return 100.0;
+        }
+
+        let best_block_count = best_height_or_none.unwrap().saturating_add(1);
+        let our_block_count = match self.chain.top_ref()
+        {
+            Some(top) => top.header.block_id.saturating_add(1),
+            None => 0,
+        };
+
+        let progress = our_block_count as f64 / best_block_count as f64 * 100.0;
+        progress.min(100.0)
+    }
+
+    // Forget what we knew about a peer, once it's gone
+    fn handle_disconnect(&mut self, from: &str)
+    {
+        self.peer_heights.remove(from);
+        self.catch_up_requests.remove(from);
+        self.peer_rejections.remove(from);
+    }
+
+    fn handle_height(&mut self, from: &str, height: u64)
+    {
+        let peer_height = self.peer_heights
+            .entry(from.to_owned())
+            .or_insert(height);
+
+        *peer_height = std::cmp::max(*peer_height, height);
+    }
+
+    // Request the block after our top, if it's below `below_block_id`
+    fn request_next_block(&mut self, manager: &mut ClientManager, from: &str,
+                          below_block_id: u64)
+        -> Result<(), Box<dyn Error>>
+    {
+        // NOTE: A branch being followed from this peer already has 
+        //       its own requests in flight.
+        if self.branches.contains_key(from) {
+            return Ok(());
+        }
+
+        let next_block_id = self.chain_height();
+        if next_block_id < below_block_id
+        {
+            debug!("[{}] Requesting block {} from {}", self.port, next_block_id, from);
+            self.catch_up_requests.insert(from.to_owned(), (next_block_id, Instant::now()));
+            manager.send_to(Packet::BlockRequest(next_block_id), |x| x == from)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_block_request_timeout(&mut self, timeout: Duration)
+    {
+        self.block_request_timeout = timeout;
+    }
+
+    // Ask another peer for each block we've been waiting on for too long. The 
+    // one with the most blocks is picked, out of those not busy catching us up.
+    fn retry_timed_out_requests(&mut self, manager: &mut ClientManager)
+        -> Result<(), Box<dyn Error>>
+    {
+        let timeout = self.block_request_timeout;
+        let timed_out = self.catch_up_requests
+            .iter()
+            .filter(|(_, (_, sent))| sent.elapsed() > timeout)
+            .map(|(peer, (id, _))| (peer.clone(), *id))
+            .collect::<Vec<_>>();
+
+        for (peer, block_id) in timed_out
+        {
+            self.catch_up_requests.remove(&peer);
+
+            let catch_up_requests = &self.catch_up_requests;
+            let branches = &self.branches;
+            let retry_peer_or_none = self.peer_heights
+                .iter()
+                .filter(|(other, height)| 
+                    **other != peer && **height >= block_id &&
+                    !catch_up_requests.contains_key(*other) && 
+                    !branches.contains_key(*other))
+                .max_by_key(|(_, height)| **height)
+                .map(|(other, _)| other.clone());
+
+            let retry_peer = match retry_peer_or_none
+            {
+                Some(retry_peer) => retry_peer,
+                None =>
+                {
+                    warn!("[{}] {} didn't send block {}, and no other peer has it", 
+                        self.port, peer, block_id);
+                    continue;
+                },
+            };
+
+            warn!("[{}] {} didn't send block {} in time, requesting it from {}", 
+                self.port, peer, block_id, retry_peer);
+            self.catch_up_requests.insert(retry_peer.clone(), (block_id, Instant::now()));
+            manager.send_to(Packet::BlockRequest(block_id), |x| x == retry_peer)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_height_from_top(&mut self, manager: &mut ClientManager, from: &str, 
+                              height: u64)
+        -> Result<(), Box<dyn Error>>
+    {
+        self.handle_height(from, height);
+
+        // NOTE: The peer's top block is sent along with its height, 
+        //       so only the blocks below it are requested.
+        self.request_next_block(manager, from, height)
+    }
+
+    pub fn subscribe_blocks(&mut self) -> Receiver<Block>
+    {
+        let (sender, receiver) = sync_channel(MAX_QUEUED_SUBSCRIBER_BLOCKS);
+        self.block_subscribers.push(sender);
+        receiver
+    }
+
+    pub fn block_subscriber_count(&self) -> usize
+    {
+        self.block_subscribers.len()
+    }
+
+    pub fn publish_block(&mut self, block: &Block)
+    {
+        // NOTE: Subscribers are removed once their receiver is dropped, 
+        //       or if they fall too far behind, rather than queueing 
+        //       blocks for them without limit.
+        self.block_subscribers.retain(|subscriber| match subscriber.try_send(block.clone())
+        {
+            Ok(_) => true,
+            Err(TrySendError::Full(_)) =>
+            {
+                warn!("Dropping a block subscriber that has fallen too far behind");
+                false
+            },
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    // Why we, or failing that any peer, rejected this item
+    pub fn rejection(&self, hash: &Hash) -> Option<&String>
+    {
+        self.rejected.get(hash).or_else(|| self.peer_rejections
+            .values()
+            .find_map(|rejections| rejections.get(hash)))
+    }
+
+    fn handle_reject(&mut self, from: &str, hash: Hash, reason: String)
+    {
+        warn!("[{}] {} rejected {}: {}", self.port, from, hash, reason);
+        self.peer_rejections
+            .entry(from.to_owned())
+            .or_insert_with(|| Rejections::new(MAX_PEER_REJECTIONS, REJECTION_EXPIRY))
+            .insert(hash, reason);
+    }
+
+    fn prune_rejections(&mut self)
+    {
+        self.rejected.prune();
+        for rejections in self.peer_rejections.values_mut() {
+            rejections.prune();
+        }
+        self.peer_rejections.retain(|_, rejections| !rejections.is_empty());
+    }
+
+    fn reject(&mut self, manager: &mut ClientManager, from: &str, 
+              hash: Hash, reason: String)
+        -> Result<(), Box<dyn Error>>
+    {
+        info!("[{}] Rejecting {} from {}: {}", self.port, hash, from, reason);
+        self.rejected.insert(hash, reason.clone());
+        manager.send_to(Packet::Reject(hash, reason), |x| x == from)
+    }
+
+    // NOTE: Only for items that could never be valid. Others may have 
+    //       been rejected for reasons local to us, like a full queue, 
+    //       so aren't held against the peer.
+    fn reject_invalid(&mut self, manager: &mut ClientManager, from: &str, 
+                      hash: Hash, reason: String)
+        -> Result<(), Box<dyn Error>>
+    {
+        manager.report_bad_behaviour(from);
+        self.reject(manager, from, hash, reason)
+    }
+
     pub fn our_report(&self) -> Result<NodeReport, Box<dyn Error>>
     {
         Ok(NodeReport::new(self.data_store.report()?))
@@ -92,7 +424,7 @@ impl Node
 
         // Can be added to the bottom
         let bottom = branch.first().unwrap();
-        if bottom.validate_next(&block).is_ok() 
+        if matches!(bottom.validate_next(&block), Ok(BlockValidationResult::Ok))
         {
             branch.insert(0, block);
             return true;
@@ -100,24 +432,19 @@ impl Node
 
         // Can be added to the top
         let top = branch.last().unwrap();
-        if block.validate_next(top).is_ok() {
+        if matches!(block.validate_next(top), Ok(BlockValidationResult::Ok)) {
             branch.push(block);
             return true;
         }
 
         // Already exists
         let bottom_id = bottom.header.block_id;
-        let top_id = top.header.block_id;
-        if (bottom_id..=top_id).contains(&block.header.block_id) 
+        let branch_index_or_none = block.header.block_id.checked_sub(bottom_id);
+        match branch_index_or_none.and_then(|index| branch.get(index as usize))
         {
-            let branch_index = (block.header.block_id - bottom_id) as usize;
-            let existing_block_in_branch = branch.get(branch_index).unwrap();
-            if &block == existing_block_in_branch {
-                return true;
-            }
+            Some(existing_block_in_branch) => block.is_same_block(existing_block_in_branch),
+            None => false,
         }
-
-        false
     }
 
     fn add_to_branch(&mut self, from: &str, block: Block)
@@ -136,7 +463,8 @@ impl Node
         let bottom_id = bottom.header.block_id;
         self.branches.insert(from.to_owned(), branch);
 
-        if bottom_id == 0 {
+        // NOTE: Stop following a branch once it forks too far back to merge
+        if bottom_id == 0 || !self.chain.is_within_reorg_depth(bottom_id - 1) {
             None
         } else {
             Some(bottom_id - 1)
@@ -149,19 +477,76 @@ impl Node
             return Ok(());
         }
 
+        self.branch_fills.remove(from);
         let branch = self.branches.remove(from).unwrap();
-        if self.chain.can_merge_branch(&branch)? == BlockChainCanMergeResult::Ok
+        match self.chain.can_merge_branch(&branch)?
         {
-            info!("[{}] Merge longer branch", self.port);
-            self.chain.merge_branch(branch);
+            BlockChainCanMergeResult::Ok =>
+            {
+                info!("[{}] Merge longer branch", self.port);
+                self.chain.merge_branch(branch.clone());
+                for block in &branch {
+                    self.publish_block(block);
+                }
+                self.blocks_added(branch.len() as u64);
+            },
+
+            BlockChainCanMergeResult::TooDeep =>
+                warn!("[{}] Ignoring branch from {}, as it forks deeper then the max reorg depth", 
+                    self.port, from),
+
+            _ => {},
         }
         Ok(())
     }
 
+    pub fn prune_branches(&mut self) -> usize
+    {
+        let top_id = match self.chain.top_ref()
+        {
+            Some(top) => top.header.block_id,
+            None => return 0,
+        };
+
+        let depth = self.chain.config().branch_prune_depth;
+        let branch_count = self.branches.len();
+        self.branches.retain(|_, branch| match branch.last()
+        {
+            Some(branch_top) => branch_top.header.block_id + depth >= top_id,
+            None => false,
+        });
+
+        let branches = &self.branches;
+        self.branch_fills.retain(|peer, _| branches.contains_key(peer));
+
+        let pruned_count = branch_count - self.branches.len();
+        if pruned_count > 0 {
+            info!("[{}] Pruned {} stale branch(es)", self.port, pruned_count);
+        }
+        pruned_count
+    }
+
+    pub fn blocks_added(&mut self, count: u64)
+    {
+        self.last_block_accepted = Instant::now();
+
+        let interval_or_none = self.chain.config().branch_prune_interval;
+        if interval_or_none.is_none() {
+            return;
+        }
+
+        self.blocks_since_prune += count;
+        if self.blocks_since_prune >= interval_or_none.unwrap()
+        {
+            self.prune_branches();
+            self.blocks_since_prune = 0;
+        }
+    }
+
     fn should_ignore_block(&mut self, from: &str, block: &Block)
         -> bool
     {
-        let top_or_none = self.chain.top();
+        let top_or_none = self.chain.top_ref();
         if top_or_none.is_none() {
             return false;
         }
@@ -184,31 +569,100 @@ impl Node
                     block: Block) 
         -> Result<(), Box<dyn Error>>
     {
+        self.handle_height(from, block.header.block_id);
         if self.should_ignore_block(from, &block) {
             return Ok(());
         }
 
+        // NOTE: Don't retry blocks we already know are invalid
+        let hash = block.hash()?;
+        if self.rejected.contains(&hash) {
+            return Ok(());
+        }
+
+        let catch_up_or_none = self.catch_up_requests.get(from).map(|(id, _)| *id);
+        if catch_up_or_none == Some(block.header.block_id) {
+            self.catch_up_requests.remove(from);
+        }
+
+        let height_before = self.chain_height();
         match self.chain.add(&block)?
         {
             BlockChainAddResult::Ok =>
             {
                 info!("[{}] Added block {}", self.port, block.header.block_id);
+                self.publish_block(&block);
+                self.blocks_added(1);
+                manager.report_good_behaviour(from);
 
                 // Relay this block to the rest of the network
                 manager.send(Packet::Block(block.clone()))?;
+
+                // NOTE: When syncing from scratch, the first block is added 
+                //       directly, so the rest of the branch can be merged now.
+                if self.branches.contains_key(from) {
+                    self.complete_branch(from)?;
+                }
+
+                let sync_progress = self.sync_progress();
+                if sync_progress < 100.0 {
+                    info!("[{}] Sync progress {:.1}%", self.port, sync_progress);
+                }
             },
 
-            BlockChainAddResult::Invalid(_) | BlockChainAddResult::MoreNeeded => 
+            BlockChainAddResult::Invalid(result) if is_invalid_on_any_chain(&result) =>
+                self.reject_invalid(manager, from, hash, result.to_string())?,
+
+            result @ BlockChainAddResult::Invalid(_) | 
+            result @ BlockChainAddResult::NoValidBranch | 
+            result @ BlockChainAddResult::MoreNeeded => 
             {
-                info!("[{}] Invalid block {}", self.port, block.header.block_id);
+                info!("[{}] Could not add block {}: {}", 
+                    self.port, block.header.block_id, result);
 
-                // Add block to this nodes branch
+                // NOTE: While catching up to this peer, blocks past the one 
+                //       requested are left until we get to them.
+                if catch_up_or_none.is_some() && catch_up_or_none != Some(block.header.block_id) {
+                    return Ok(());
+                }
+
+                // Add block to this nodes branch. It's held there until the 
+                // missing ancestors have been requested and received.
+                let is_new_branch = !self.branches.contains_key(from);
                 let next_block = self.add_to_branch(from, block);
+                let fill_or_none = self.branch_fills.get(from).cloned();
                 
-                // Request the next block. If there's no more, complete the branch
-                if next_block.is_some() {
+                // NOTE: Find where our chains fork first, so the missing 
+                //       blocks can all be requested at once.
+                if is_new_branch && next_block.is_some() 
+                {
+                    let locator = self.chain.block_locator()?;
+                    manager.send_to(Packet::GetHeaders(locator), |x| x == from)?;
+                }
+                else if let Some(fill_to) = fill_or_none
+                {
+                    match next_block
+                    {
+                        // NOTE: Keep the same number of requests in flight, 
+                        //       until the rest of the fill has been requested
+                        Some(next) if next >= fill_to =>
+                        {
+                            let request = (next + 1).checked_sub(MAX_BRANCH_FILL_REQUESTS);
+                            if let Some(block_id) = request.filter(|id| *id >= fill_to) {
+                                manager.send_to(Packet::BlockRequest(block_id), |x| x == from)?;
+                            }
+                        },
+
+                        _ => self.complete_branch(from)?,
+                    }
+                }
+                else if next_block.is_some()
+                {
+                    // Request the next block. If there's no more, complete the branch
                     manager.send_to(Packet::BlockRequest(next_block.unwrap()), |x| x == from)?;
-                } else {
+                } 
+                else 
+                {
                     self.complete_branch(from)?;
                 }
             },
@@ -220,20 +674,136 @@ impl Node
             },
         }
 
+        // NOTE: Keep catching up, one block at a time, while 
+        //       this peer is still ahead of us.
+        if self.chain_height() > height_before 
+        {
+            if let Some(&peer_height) = self.peer_heights.get(from) 
+            {
+                if let Some(next_height) = peer_height.checked_add(1) {
+                    self.request_next_block(manager, from, next_height)?;
+                }
+            }
+        }
+
         Ok(())
     }
 
-    fn handle_block_request(&mut self, manager: &mut ClientManager, 
-                            from: &str, id: u64)
+    fn handle_block_request(&self, manager: &mut ClientManager, 
+                        from: &str, id: u64)
         -> Result<(), Box<dyn Error>>
     {
         info!("Got request for block {}", id);
 
-        let block_or_none = self.chain.block(id);
-        if block_or_none.is_some() 
+        let height = self.chain_height();
+        if id >= height.saturating_add(SUSPICIOUS_REQUEST_DISTANCE) {
+            warn!("[{}] {} requested block {}, far past our height of {}", self.port, from, id, height);
+        }
+
+        match self.chain.block(id)
+        {
+            Some(block) => manager.send_to(Packet::Block(block), |x| x == from),
+            None => manager.send_to(Packet::BlockNotFound(id), |x| x == from),
+        }
+    }
+
+    fn handle_block_not_found(&mut self, from: &str, id: u64)
+    {
+        debug!("[{}] {} doesn't have block {}", self.port, from, id);
+
+        // NOTE: The peer's height was only a guess if it can't give us 
+        //       the block we needed to catch up to it.
+        let catch_up_or_none = self.catch_up_requests.get(from).map(|(id, _)| *id);
+        if catch_up_or_none == Some(id)
+        {
+            self.catch_up_requests.remove(from);
+            if let Some(peer_height) = self.peer_heights.get_mut(from) {
+                *peer_height = std::cmp::min(*peer_height, id.saturating_sub(1));
+            }
+        }
+    }
+
+    fn handle_get_headers(&self, manager: &mut ClientManager, from: &str, 
+                          locator: Vec<Hash>)
+        -> Result<(), Box<dyn Error>>
+    {
+        let headers = self.chain.headers_after_locator(&locator, MAX_HEADERS)?;
+        manager.send_to(Packet::Headers(headers), |x| x == from)
+    }
+
+    fn handle_headers(&mut self, manager: &mut ClientManager, from: &str, 
+                      headers: Vec<BlockHeader>)
+        -> Result<(), Box<dyn Error>>
+    {
+        let first = match headers.first()
+        {
+            Some(first) if headers.len() <= MAX_HEADERS => first,
+            _ => return Ok(()),
+        };
+
+        // NOTE: Each header must follow on from the one before it
+        for (header, next) in headers.iter().zip(headers.iter().skip(1))
         {
-            let block = block_or_none.unwrap();
-            manager.send_to(Packet::Block(block.clone()), |x| x == from)?;
+            if Some(next.block_id) != header.block_id.checked_add(1) || next.prev_hash != header.hash()? {
+                return Ok(());
+            }
+        }
+
+        // NOTE: The headers start just after the last block we have in common
+        let fork_or_none = first.block_id.checked_sub(1);
+        if let Some(fork) = fork_or_none
+        {
+            let our_hash_or_none = self.chain.header(fork).map(|header| header.hash());
+            match our_hash_or_none
+            {
+                Some(Ok(hash)) if hash == first.prev_hash => {},
+                _ => return Ok(()),
+            }
+
+            debug!("[{}] Common ancestor with {} is block {}", self.port, from, fork);
+            self.common_ancestors.insert(from.to_owned(), fork);
+        }
+
+        let (bottom_id, bottom_prev_hash) = match self.branches.get(from).and_then(|branch| branch.first())
+        {
+            Some(bottom) => (bottom.header.block_id, bottom.header.prev_hash),
+            None => return Ok(()),
+        };
+
+        // NOTE: If the headers reach our branch, they have to lead to it
+        let below_bottom = headers.iter().find(|header| header.block_id.checked_add(1) == Some(bottom_id));
+        if let Some(header) = below_bottom 
+        {
+            if header.hash()? != bottom_prev_hash {
+                return Ok(());
+            }
+        }
+
+        let fill_to = fork_or_none.map(|fork| fork + 1).unwrap_or(0);
+        if fill_to >= bottom_id || !self.chain.is_within_reorg_depth(fill_to) {
+            return self.complete_branch(from);
+        }
+
+        // Requested from the top down, so each block extends the bottom of 
+        // the branch. The rest are requested as these arrive.
+        self.branch_fills.insert(from.to_owned(), fill_to);
+        let first_request = std::cmp::max(fill_to, bottom_id.saturating_sub(MAX_BRANCH_FILL_REQUESTS));
+        for block_id in (first_request..bottom_id).rev() {
+            manager.send_to(Packet::BlockRequest(block_id), |x| x == from)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_top_request(&self, manager: &mut ClientManager, from: &str)
+        -> Result<(), Box<dyn Error>>
+    {
+        if let Some(top) = self.chain.top()
+        {
+            manager.send_to(Packet::Height(top.header.block_id),
+                |addr| addr == from)?;
+            manager.send_to(Packet::Block(top.clone()),
+                |addr| addr == from)?;
         }
 
         Ok(())
@@ -245,10 +815,26 @@ impl Node
     {
         info!("Got transfer {:?}", transfer);
 
-        self.chain.push_transfer_queue(transfer.clone())?;
-        manager.send_to(
-            Packet::Transfer(transfer), 
-            |x| x != from)?;
+        let hash = transfer.hash()?;
+        if self.rejected.contains(&hash) || self.chain.find_transaction_in_queue(&hash).is_some() {
+            return Ok(());
+        }
+
+        let content_result = transfer.validate_content(self.chain.config().signature_scheme)?;
+        if content_result != TransactionValidationResult::Ok {
+            return self.reject_invalid(manager, from, hash, content_result.to_string());
+        }
+
+        if let Err(err) = self.chain.push_transfer_queue(transfer.clone()) {
+            return self.reject(manager, from, hash, err.to_string());
+        }
+        manager.report_good_behaviour(from);
+        if self.relay_transactions
+        {
+            manager.send_to(
+                Packet::Transfer(transfer), 
+                |x| x != from)?;
+        }
 
         Ok(())
     }
@@ -258,15 +844,41 @@ impl Node
         -> Result<(), Box<dyn Error>>
     {
         info!("Got page {:?}", page);
-        
-        page.header.content.is_data_valid(&data)?;
-        self.chain.push_page_queue(page.clone())?;
-        self.data_store.store_data_unit(&data)?;
+
+        let hash = page.hash()?;
+        if self.rejected.contains(&hash) || self.chain.find_transaction_in_queue(&hash).is_some() {
+            return Ok(());
+        }
+
+        // NOTE: Checked before the data is hashed, so a bad chunk size 
+        //       can't make us split it in to any number of chunks
+        let chunk_size = page.header.content.chunk_size as usize;
+        if chunk_size != self.chain.config().page_chunk_size {
+            return self.reject(manager, from, hash, "Page uses a different chunk size".to_owned());
+        }
+
+        let content_result = page.validate_content(self.chain.config().signature_scheme)?;
+        if content_result != TransactionValidationResult::Ok {
+            return self.reject_invalid(manager, from, hash, content_result.to_string());
+        }
+
+        if let Err(err) = page.header.content.is_data_valid(&data) {
+            return self.reject_invalid(manager, from, hash, err.to_string());
+        }
+
+        if let Err(err) = self.chain.push_page_queue(page.clone()) {
+            return self.reject(manager, from, hash, err.to_string());
+        }
+        manager.report_good_behaviour(from);
+        self.data_store.store_data_unit(&data, page.header.content.chunk_size as usize)?;
 
         manager.send(Packet::Report(None, self.our_report()?))?;
-        manager.send_to(
-            Packet::Page(page, data),
-            |x| x != from)?;
+        if self.relay_transactions
+        {
+            manager.send_to(
+                Packet::Page(page, data),
+                |x| x != from)?;
+        }
 
         Ok(())
     }
@@ -285,8 +897,106 @@ impl Node
         Ok(())
     }
 
-    fn update_reports(&mut self, _manager: &mut ClientManager)
+    fn chain_height(&self) -> u64
+    {
+        match self.chain.top_ref()
+        {
+            Some(top) => top.header.block_id + 1,
+            None => 0,
+        }
+    }
+
+    pub fn set_rebroadcast_window(&mut self, window: u64)
     {
+        self.rebroadcast_window = window;
+    }
+
+    pub fn track_own_transaction(&mut self, transaction_id: Hash)
+    {
+        let height = self.chain_height();
+        self.own_transactions.insert(transaction_id, height);
+    }
+
+    fn rebroadcast_unconfirmed(&mut self, manager: &mut ClientManager)
+        -> Result<(), Box<dyn Error>>
+    {
+        let height = self.chain_height();
+        let own_transactions = self.own_transactions.clone();
+        for (transaction_id, last_broadcast) in own_transactions
+        {
+            // NOTE: Once it's left the queue, it's either been confirmed 
+            //       or has expired, so there's nothing more to do.
+            let transaction_or_none = self.chain.find_transaction_in_queue(&transaction_id);
+            if transaction_or_none.is_none() 
+            {
+                self.own_transactions.remove(&transaction_id);
+                continue;
+            }
+
+            if height < last_broadcast + self.rebroadcast_window {
+                continue;
+            }
+
+            info!("[{}] Rebroadcasting unconfirmed transaction {}", self.port, transaction_id);
+            match transaction_or_none.unwrap()
+            {
+                TransactionVariant::Transfer(transfer) =>
+                    manager.send(Packet::Transfer(transfer))?,
+
+                TransactionVariant::Page(page) =>
+                {
+                    let data = self.data_store.get_data_unit(&page)?;
+                    manager.send(Packet::Page(page, data))?;
+                },
+            }
+            self.own_transactions.insert(transaction_id, height);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_stale_tip_intervals(&mut self, intervals: u64)
+    {
+        self.stale_tip_intervals = intervals;
+    }
+
+    fn stale_tip_timeout(&self) -> Duration
+    {
+        let block_time = self.chain.config().block_time;
+        Duration::from_millis(block_time * self.stale_tip_intervals)
+    }
+
+    fn request_tip_if_stale(&mut self, manager: &mut ClientManager)
+        -> Result<(), Box<dyn Error>>
+    {
+        let time_since_last_block = self.last_block_accepted.elapsed();
+        if time_since_last_block <= self.stale_tip_timeout() {
+            return Ok(());
+        }
+
+        // NOTE: Wait another full timeout before asking again
+        warn!("[{}] No new blocks in {:?}, requesting the top block from peers", 
+            self.port, time_since_last_block);
+        self.last_block_accepted = Instant::now();
+        manager.send(Packet::TopRequest)
+    }
+
+    fn update_reports(&mut self, manager: &mut ClientManager)
+    {
+        if let Err(err) = self.request_tip_if_stale(manager) {
+            warn!("[{}] Failed to request top block: {}", self.port, err);
+        }
+
+        if let Err(err) = self.retry_timed_out_requests(manager) {
+            warn!("[{}] Failed to retry block requests: {}", self.port, err);
+        }
+
+        if let Err(err) = self.rebroadcast_unconfirmed(manager) {
+            warn!("[{}] Failed to rebroadcast transactions: {}", self.port, err);
+        }
+
+        self.prune_rejections();
+
         for address in self.report.update()
         {
             info!("Report for {} expired", address);
@@ -303,10 +1013,13 @@ pub mod tests
     use super::*;
     use super::packet_handler::NodePacketHandler;
     use crate::network::NetworkConnection;
+    use crate::network::tests::create_connection;
+    use libhyperchain::wallet::Wallet;
     use libhyperchain::wallet::private_wallet::PrivateWallet;
     use libhyperchain::block::Block;
     use libhyperchain::miner;
 
+    use std::sync::Mutex;
     use std::time::Duration;
 
     pub fn wait_for_block(connection: &NetworkConnection<NodePacketHandler>, block_id: u64) 
@@ -454,5 +1167,542 @@ pub mod tests
         assert_eq!(block_f_on_a, block_f_on_d);
     }
 
+    #[test]
+    fn test_node_sync_progress()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+
+        let mut connection_a = create_node(8062);
+        let mut blocks = Vec::new();
+        for _ in 0..4 {
+            blocks.push(mine_block(&mut connection_a, &wallet));
+        }
+
+        // Progress increases as each block is received
+        {
+            let path = std::env::temp_dir().join(format!("{}sync", 
+                libhyperchain::block::current_timestamp()));
+            let node = Node::new(8063, &path).unwrap();
+            let mut node = node.write().unwrap();
+            assert_eq!(node.sync_progress(), 100.0);
+
+            node.handle_height("127.0.0.1:8062", 3);
+            assert_eq!(node.best_peer_height(), Some(3));
+            assert_eq!(node.sync_progress(), 0.0);
+
+            let mut last_progress = node.sync_progress();
+            for block in &blocks 
+            {
+                node.chain().add(block).unwrap();
+                assert!(node.sync_progress() > last_progress);
+                last_progress = node.sync_progress();
+            }
+            assert_eq!(last_progress, 100.0);
+
+            // A peer at the highest possible height doesn't overflow
+            node.handle_height("127.0.0.1:8062", u64::MAX);
+            assert!(node.sync_progress() < 1.0);
+
+            node.handle_disconnect("127.0.0.1:8062");
+            assert_eq!(node.best_peer_height(), None);
+            assert_eq!(node.sync_progress(), 100.0);
+        }
+
+        // A new node syncs the whole chain from its peer
+        let mut connection_b = create_node(8064);
+        connection_b.manager().register_node("127.0.0.1:8062");
+        let top_on_b = wait_for_block(&connection_b, 3);
+        assert_eq!(&top_on_b, blocks.last().unwrap());
+
+        let mut node_b = connection_b.handler().node();
+        assert_eq!(node_b.best_peer_height(), Some(3));
+        assert_eq!(node_b.sync_progress(), 100.0);
+        drop(node_b);
+
+        // The peer's height is forgotten once it disconnects
+        drop(connection_a);
+        let mut timer = 0;
+        while connection_b.handler().node().best_peer_height().is_some() && timer < 10000
+        {
+            std::thread::sleep(Duration::from_millis(100));
+            timer += 100;
+        }
+        assert_eq!(connection_b.handler().node().best_peer_height(), None);
+    }
+
+    #[test]
+    fn test_node_fills_missing_ancestors()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+
+        let mut connection_a = create_node(8065);
+        let mut connection_b = create_node(8066);
+        connection_b.manager().register_node("127.0.0.1:8065");
+        mine_block(&mut connection_a, &wallet);
+        wait_for_block(&connection_b, 0);
+
+        // Node b falls two blocks behind
+        for _ in 0..2
+        {
+            let mut node_a = connection_a.handler().node();
+            let chain = node_a.chain();
+            let block = miner::mine_block(Block::new_blank(chain, &wallet).unwrap());
+            chain.add(&block).unwrap();
+        }
+
+        // Receiving the newest block should fill the gap
+        let block_d_on_a = mine_block(&mut connection_a, &wallet);
+        let block_d_on_b = wait_for_block(&connection_b, 3);
+        assert_eq!(block_d_on_b, block_d_on_a);
+        assert_eq!(wait_for_block(&connection_b, 1), wait_for_block(&connection_a, 1));
+        assert_eq!(wait_for_block(&connection_b, 2), wait_for_block(&connection_a, 2));
+    }
+
+    #[test]
+    fn test_node_reject_invalid_block()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+
+        let mut connection_a = create_node(8074);
+        let mut connection_b = create_node(8075);
+        connection_b.manager().register_node("127.0.0.1:8074");
+        mine_block(&mut connection_a, &wallet);
+        wait_for_block(&connection_b, 0);
+
+        let mut invalid = Block::new_blank(connection_b.handler().node().chain(), &wallet).unwrap();
+        while invalid.validate_pow().unwrap() == BlockValidationResult::Ok {
+            invalid.header.pow += 1;
+        }
+
+        let hash = invalid.hash().unwrap();
+        connection_b.manager().send(Packet::Block(invalid)).unwrap();
+
+        let mut timer = 0;
+        while connection_b.handler().node().rejection(&hash).is_none() && timer < 10000
+        {
+            std::thread::sleep(Duration::from_millis(100));
+            timer += 100;
+        }
+
+        let reason = BlockValidationResult::POW.to_string();
+        assert_eq!(connection_b.handler().node().rejection(&hash), Some(&reason));
+        assert_eq!(connection_a.handler().node().rejection(&hash), Some(&reason));
+        assert!(connection_a.manager().score("127.0.0.1:8075") < 0);
+    }
+
+    #[test]
+    fn test_prune_branches()
+    {
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let path = std::env::temp_dir().join(format!("{}prune_branches", 
+            libhyperchain::block::current_timestamp()));
+        let config = ChainConfig
+        {
+            branch_prune_depth: 3,
+            branch_prune_interval: Some(2),
+            ..ChainConfig::default()
+        };
+
+        let node = Node::new_with_config(8079, &path, config).unwrap();
+        let mut node = node.write().unwrap();
+        let mut manager = ClientManager::new(8079, &path, Arc::from(Mutex::from(false)));
+        let mut add_block = |node: &mut Node|
+        {
+            let block = miner::mine_block(Block::new_blank(node.chain(), &wallet).unwrap());
+            node.handle_block(&mut manager, "local", block.clone()).unwrap();
+            block
+        };
+
+        let block_0 = add_block(&mut node);
+        add_block(&mut node);
+        add_block(&mut node);
+        node.branches.insert("stale".to_owned(), vec![block_0]);
+
+        // Still within the prune depth
+        add_block(&mut node);
+        assert_eq!(node.branch_count(), 1);
+
+        // Out of depth, but pruning isn't due yet
+        let block_4 = add_block(&mut node);
+        node.branches.insert("recent".to_owned(), vec![block_4]);
+        assert_eq!(node.branch_count(), 2);
+
+        add_block(&mut node);
+        assert_eq!(node.branch_count(), 1);
+        assert!(node.branches.contains_key("recent"));
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_request_tip_when_stale()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut connection_a = create_node(8090);
+        let mut connection_b = create_node(8091);
+        connection_b.manager().register_node("127.0.0.1:8090");
+        mine_block(&mut connection_a, &wallet);
+        wait_for_block(&connection_b, 0);
+
+        // A block b never hears about, as if the network was partitioned
+        {
+            let mut node = connection_a.handler().node();
+            let chain = node.chain();
+            let block = miner::mine_block(Block::new_blank(chain, &wallet).unwrap());
+            chain.add(&block).unwrap();
+        }
+
+        std::thread::sleep(Duration::from_millis(2000));
+        assert!(connection_b.handler().node().chain().block(1).is_none());
+
+        // Simulate a long gap since b last accepted a block
+        {
+            let mut node = connection_b.handler().node();
+            let timeout = node.stale_tip_timeout();
+            node.last_block_accepted = Instant::now() - timeout * 2;
+        }
+        wait_for_block(&connection_b, 1);
+    }
+
+    #[test]
+    fn test_rebroadcast_unconfirmed()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut connection_a = create_node(8082);
+        let mut connection_b = create_node(8083);
+        connection_a.handler().node().set_rebroadcast_window(2);
+        connection_b.manager().register_node("127.0.0.1:8082");
+        mine_block(&mut connection_a, &wallet);
+        wait_for_block(&connection_b, 0);
+
+        // Queued on a, but b missed the original broadcast
+        let transfer_id = 
+        {
+            let mut node = connection_a.handler().node();
+            let chain = node.chain();
+            let transfer = chain.new_transfer(vec![(&wallet, 1.25)], 
+                vec![(other.get_address(), 1.0)], 0.25).unwrap();
+            chain.push_transfer_queue(transfer.clone()).unwrap();
+
+            let transfer_id = transfer.hash().unwrap();
+            node.track_own_transaction(transfer_id);
+            transfer_id
+        };
+
+        let in_queue_on_b = |timeout: u64|
+        {
+            let mut timer = 0;
+            while timer < timeout
+            {
+                let mut node = connection_b.handler().node();
+                if node.chain().find_transaction_in_queue(&transfer_id).is_some() {
+                    return true;
+                }
+
+                drop(node);
+                std::thread::sleep(Duration::from_millis(100));
+                timer += 100;
+            }
+            false
+        };
+
+        // Still within the window
+        mine_block(&mut connection_a, &wallet);
+        assert_eq!(in_queue_on_b(1500), false);
+
+        // Unconfirmed past the window, so it's sent again
+        mine_block(&mut connection_a, &wallet);
+        wait_for_block(&connection_b, 2);
+        assert_eq!(in_queue_on_b(5000), true);
+    }
+
+    #[test]
+    fn test_node_catches_up_to_peer()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut connection_a = create_node(8101);
+        let first = mine_block(&mut connection_a, &wallet);
+
+        let mut connection_b = create_node(8102);
+        connection_b.handler().node().chain().add(&first).unwrap();
+
+        // Node A gets 5 blocks ahead before they connect
+        let mut blocks = Vec::new();
+        for _ in 0..5 {
+            blocks.push(mine_block(&mut connection_a, &wallet));
+        }
+
+        connection_b.manager().register_node("127.0.0.1:8101");
+        let top_on_b = wait_for_block(&connection_b, 5);
+        assert_eq!(&top_on_b, blocks.last().unwrap());
+
+        let node_b = connection_b.handler().node_reader();
+        for block in &blocks {
+            assert_eq!(node_b.chain_ref().block(block.header.block_id).as_ref(), Some(block));
+        }
+        assert_eq!(node_b.sync_progress(), 100.0);
+    }
+
+    #[test]
+    fn test_node_finds_common_ancestor()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut connection_a = create_node(8106);
+        let mut connection_b = create_node(8107);
+        for _ in 0..3
+        {
+            let block = mine_block(&mut connection_a, &wallet);
+            connection_b.handler().node().chain().add(&block).unwrap();
+        }
+
+        // The chains diverge after block 2, with b getting further ahead. 
+        // Further then the blocks requested at once to fill the branch.
+        for _ in 0..2 {
+            mine_block(&mut connection_a, &wallet);
+        }
+        let mut blocks_on_b = Vec::new();
+        for _ in 0..30 {
+            blocks_on_b.push(mine_block(&mut connection_b, &other));
+        }
+
+        connection_b.manager().register_node("127.0.0.1:8106");
+        let top_on_a = wait_for_block(&connection_a, 32);
+        assert_eq!(&top_on_a, blocks_on_b.last().unwrap());
+
+        let node_a = connection_a.handler().node_reader();
+        assert_eq!(node_a.common_ancestor("127.0.0.1:8107"), Some(2));
+        for block in &blocks_on_b {
+            assert_eq!(node_a.chain_ref().block(block.header.block_id).as_ref(), Some(block));
+        }
+    }
+
+    #[test]
+    fn test_block_request_out_of_range()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut connection_a = create_node(8109);
+        let mut connection_b = create_node(8110);
+        for _ in 0..3
+        {
+            let block = mine_block(&mut connection_a, &wallet);
+            connection_b.handler().node().chain().add(&block).unwrap();
+        }
+
+        connection_b.manager().register_node("127.0.0.1:8109");
+        while connection_b.handler().node_reader().best_peer_height() != Some(2) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        // Requests past the top, however far, are answered as not found
+        let from = "127.0.0.1:8109";
+        connection_b.manager().send_to(Packet::BlockRequest(u64::MAX), |x| x == from).unwrap();
+        {
+            let mut manager = connection_b.manager().clone();
+            let mut node = connection_b.handler().node();
+            node.peer_heights.insert(from.to_owned(), 10);
+            node.request_next_block(&mut manager, from, 10).unwrap();
+        }
+
+        // So the peer's height is corrected
+        while connection_b.handler().node_reader().best_peer_height() != Some(2) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(connection_b.handler().node_reader().catch_up_requests.get(from), None);
+        assert!(connection_a.manager().score("127.0.0.1:8110") >= 0);
+
+        // The reply names the block that was asked for
+        let (mut connection_c, recv_c) = create_connection(8126);
+        connection_c.manager().register_node(from);
+        assert_eq!(recv_c.recv().unwrap(), Packet::OnConnected);
+        connection_c.manager().send_to(Packet::BlockRequest(u64::MAX), |x| x == from).unwrap();
+
+        let reply = std::iter::from_fn(|| recv_c.recv_timeout(Duration::from_secs(10)).ok())
+            .find(|packet| matches!(packet, Packet::BlockNotFound(_)));
+        assert_eq!(reply, Some(Packet::BlockNotFound(u64::MAX)));
+    }
+
+    #[test]
+    fn test_block_request_timeout()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut connection_a = create_node(8120);
+        let mut connection_b = create_node(8121);
+        connection_b.handler().node().set_block_request_timeout(Duration::from_millis(500));
+        connection_b.manager().register_node("127.0.0.1:8120");
+        while connection_b.manager().peer_count() == 0 {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        // Only A has the blocks, but it isn't told about them
+        let mut blocks = Vec::new();
+        for _ in 0..3
+        {
+            let mut node = connection_a.handler().node();
+            let chain = node.chain();
+            let block = miner::mine_block(Block::new_blank(chain, &wallet).unwrap());
+            chain.add(&block).unwrap();
+            blocks.push(block);
+        }
+
+        // The first peer asked never answers
+        let silent_peer = "127.0.0.1:9999";
+        {
+            let mut manager = connection_b.manager().clone();
+            let mut node = connection_b.handler().node();
+            node.peer_heights.insert(silent_peer.to_owned(), 2);
+            node.peer_heights.insert("127.0.0.1:8120".to_owned(), 2);
+            node.request_next_block(&mut manager, silent_peer, 3).unwrap();
+            assert_eq!(node.catch_up_requests.get(silent_peer).map(|(id, _)| *id), Some(0));
+        }
+
+        // So the blocks are requested from A instead
+        let top = wait_for_block(&connection_b, 2);
+        assert_eq!(&top, blocks.last().unwrap());
+
+        let node_b = connection_b.handler().node_reader();
+        assert_eq!(node_b.catch_up_requests.get(silent_peer), None);
+        for block in &blocks {
+            assert_eq!(node_b.chain_ref().block(block.header.block_id).as_ref(), Some(block));
+        }
+    }
+
+    #[test]
+    fn test_relay_transactions()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut connection_a = create_node(8116);
+        let mut connection_b = create_node(8117);
+        for miner_wallet in [&wallet, &other]
+        {
+            let block = mine_block(&mut connection_a, miner_wallet);
+            connection_b.handler().node().chain().add(&block).unwrap();
+        }
+
+        connection_b.manager().register_node("127.0.0.1:8116");
+        while connection_b.handler().node_reader().best_peer_height() != Some(1) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        // Received by b from some other peer
+        let receive_transfer = |connection: &mut NetworkConnection<NodePacketHandler>,
+                                from_wallet: &PrivateWallet|
+        {
+            let mut manager = connection.manager().clone();
+            let mut node = connection.handler().node();
+            let transfer = node.chain().new_transfer(vec![(from_wallet, 1.25)], 
+                vec![(wallet.get_address(), 1.0)], 0.25).unwrap();
+            node.handle_transfer(&mut manager, "127.0.0.1:9999", transfer.clone()).unwrap();
+            assert!(node.chain().find_transaction_in_queue(&transfer.hash().unwrap()).is_some());
+            transfer.hash().unwrap()
+        };
+
+        let in_queue_on_a = |transfer_id: Hash, timeout: u64|
+        {
+            let mut timer = 0;
+            while timer < timeout
+            {
+                if connection_a.handler().node().chain().find_transaction_in_queue(&transfer_id).is_some() {
+                    return true;
+                }
+
+                std::thread::sleep(Duration::from_millis(100));
+                timer += 100;
+            }
+            false
+        };
+
+        // Kept, but not passed on
+        connection_b.handler().node().set_relay_transactions(false);
+        let kept_id = receive_transfer(&mut connection_b, &wallet);
+        assert!(!in_queue_on_a(kept_id, 1500));
+
+        connection_b.handler().node().set_relay_transactions(true);
+        let relayed_id = receive_transfer(&mut connection_b, &other);
+        assert!(in_queue_on_a(relayed_id, 5000));
+
+        // Only transactions that could never be valid count against the peer
+        let mut manager = connection_b.manager().clone();
+        let mut node = connection_b.handler().node();
+        let peer = "127.0.0.1:9999";
+        let transfer = node.chain().new_transfer(vec![(&other, 1.25)], 
+            vec![(wallet.get_address(), 1.0)], 0.25).unwrap();
+        let same_fee = node.chain().new_transfer(vec![(&other, 1.25)], 
+            vec![(other.get_address(), 1.0)], 0.25).unwrap();
+        node.handle_transfer(&mut manager, peer, transfer.clone()).unwrap();
+        let score = manager.score(peer);
+        node.handle_transfer(&mut manager, peer, same_fee.clone()).unwrap();
+        assert!(node.rejection(&same_fee.hash().unwrap()).is_some());
+        assert_eq!(manager.score(peer), score);
+
+        let mut forged = node.chain().new_transfer(vec![(&wallet, 1.25)], 
+            vec![(wallet.get_address(), 1.0)], 0.25).unwrap();
+        forged.header.content.outputs[0].to = other.get_address();
+        node.handle_transfer(&mut manager, peer, forged.clone()).unwrap();
+        assert!(node.rejection(&forged.hash().unwrap()).is_some());
+        assert!(manager.score(peer) < score);
+    }
+
+    #[test]
+    fn test_insert_into_branch_at_differing_heights()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut chain_a = BlockChain::open_in_memory();
+        let mut chain_b = BlockChain::open_in_memory();
+        let mine = |chain: &mut BlockChain, wallet: &PrivateWallet|
+        {
+            let block = miner::mine_block(Block::new_blank(chain, wallet).unwrap());
+            chain.add(&block).unwrap();
+            block
+        };
+
+        // Two forks sharing the first two blocks
+        let mut a = Vec::new();
+        for _ in 0..2
+        {
+            let block = mine(&mut chain_a, &wallet);
+            chain_b.add(&block).unwrap();
+            a.push(block);
+        }
+        for _ in 2..8 {
+            a.push(mine(&mut chain_a, &wallet));
+        }
+        let b = (2..5).map(|_| mine(&mut chain_b, &other)).collect::<Vec<_>>();
+
+        let mut branch = vec![a[3].clone(), a[4].clone()];
+        assert!(Node::try_insert_block_into_branch(&mut branch, a[2].clone()));
+        assert!(Node::try_insert_block_into_branch(&mut branch, a[5].clone()));
+
+        // Blocks that don't connect at either end are left out
+        assert!(!Node::try_insert_block_into_branch(&mut branch, a[7].clone()));
+        assert!(!Node::try_insert_block_into_branch(&mut branch, b[2].clone()));
+        assert_eq!(branch, a[2..6]);
+
+        // Already in the branch, or at a height it has a different block for
+        assert!(Node::try_insert_block_into_branch(&mut branch, a[3].clone()));
+        assert!(!Node::try_insert_block_into_branch(&mut branch, b[1].clone()));
+        assert!(!Node::try_insert_block_into_branch(&mut branch, b[0].clone()));
+        assert_eq!(branch, a[2..6]);
+    }
+
 }
 
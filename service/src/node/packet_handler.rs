@@ -8,19 +8,19 @@ use super::Node;
 use crate::network::packet::{Packet, PacketHandler};
 use crate::network::client_manager::ClientManager;
 
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::error::Error;
 
 #[derive(Clone)]
 pub struct NodePacketHandler
 {
-    node: Arc<Mutex<Node>>,
+    node: Arc<RwLock<Node>>,
 }
 
 impl NodePacketHandler
 {
 
-    pub fn new(node: Arc<Mutex<Node>>) -> Self
+    pub fn new(node: Arc<RwLock<Node>>) -> Self
     {
         Self
         {
@@ -28,9 +28,16 @@ impl NodePacketHandler
         }
     }
 
-    pub fn node(&self) -> MutexGuard<Node>
+    pub fn node(&self) -> RwLockWriteGuard<Node>
     {
-        self.node.lock().unwrap()
+        self.node.write().unwrap()
+    }
+
+    // NOTE: Any number of readers can hold the node at once, so 
+    //       queries don't have to wait on each other.
+    pub fn node_reader(&self) -> RwLockReadGuard<Node>
+    {
+        self.node.read().unwrap()
     }
 
 }
@@ -41,42 +48,55 @@ impl PacketHandler for NodePacketHandler
     fn handle(&self, from: &str, packet: Packet, manager: &mut ClientManager)
         -> Result<(), Box<dyn Error>>
     {
-        let mut node = self.node.lock().unwrap();
         match packet
         {
             Packet::OnConnected => 
             {
-                if let Some(top) = node.chain.top()
-                {
-                    manager.send_to(Packet::Block(top.clone()),
-                        |addr| addr == from)?;
-                }
-
+                let node = self.node_reader();
+                node.handle_top_request(manager, from)?;
                 manager.send_to(Packet::Report(None, node.our_report()?),
                     |addr| addr == from)?;
             },
 
+            Packet::TopRequest =>
+                self.node_reader().handle_top_request(manager, from)?,
+
+            Packet::Height(height) =>
+                self.node().handle_height_from_top(manager, from, height)?,
+
             Packet::Block(block) => 
-                node.handle_block(manager, from, block)?,
+                self.node().handle_block(manager, from, block)?,
 
             Packet::BlockRequest(id) =>
-                node.handle_block_request(manager, from, id)?,
+                self.node_reader().handle_block_request(manager, from, id)?,
+
+            Packet::BlockNotFound(id) =>
+                self.node().handle_block_not_found(from, id),
 
             Packet::Transfer(transfer) =>
-                node.handle_transfer(manager, from, transfer)?,
+                self.node().handle_transfer(manager, from, transfer)?,
 
             Packet::Page(page, data) =>
-                node.handle_page(manager, from, page, data)?,
+                self.node().handle_page(manager, from, page, data)?,
 
             Packet::Report(address, report) =>
                 match address
                 {
-                    Some(addr) => node.handle_report(manager, &addr, report)?,
-                    None => node.handle_report(manager, from, report)?,
+                    Some(addr) => self.node().handle_report(manager, &addr, report)?,
+                    None => self.node().handle_report(manager, from, report)?,
                 },
 
             Packet::Ping(time_sent) =>
                 manager.report_ping_time(from, time_sent),
+
+            Packet::Reject(hash, reason) =>
+                self.node().handle_reject(from, hash, reason),
+
+            Packet::GetHeaders(locator) =>
+                self.node_reader().handle_get_headers(manager, from, locator)?,
+
+            Packet::Headers(headers) =>
+                self.node().handle_headers(manager, from, headers)?,
         }
 
         Ok(())
@@ -84,9 +104,14 @@ impl PacketHandler for NodePacketHandler
 
     fn update_reports(&self, manager: &mut ClientManager)
     {
-        let mut node = self.node.lock().unwrap();
+        let mut node = self.node();
         node.update_reports(manager);
     }
 
+    fn handle_disconnect(&self, from: &str)
+    {
+        self.node().handle_disconnect(from);
+    }
+
 }
 
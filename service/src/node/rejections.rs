@@ -0,0 +1,151 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use libhyperchain::hash::Hash;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// Items that were rejected, and why. Each is forgotten after a while,
+// so one that failed for a reason that's since changed can be retried.
+pub struct Rejections
+{
+    entries: HashMap<Hash, (String, Instant)>,
+    by_age: VecDeque<(Instant, Hash)>,
+    max_size: usize,
+    expiry: Duration,
+}
+
+impl Rejections
+{
+
+    pub fn new(max_size: usize, expiry: Duration) -> Self
+    {
+        Self
+        {
+            entries: HashMap::new(),
+            by_age: VecDeque::new(),
+            max_size,
+            expiry,
+        }
+    }
+
+    pub fn insert(&mut self, hash: Hash, reason: String)
+    {
+        self.prune();
+
+        // NOTE: Still full, so make room by forgetting the oldest
+        if !self.entries.contains_key(&hash)
+        {
+            while self.entries.len() >= self.max_size
+            {
+                match self.by_age.pop_front()
+                {
+                    Some(oldest) => self.forget(oldest),
+                    None => break,
+                }
+            }
+        }
+
+        let rejected_at = Instant::now();
+        self.entries.insert(hash, (reason, rejected_at));
+        self.by_age.push_back((rejected_at, hash));
+    }
+
+    fn forget(&mut self, (rejected_at, hash): (Instant, Hash))
+    {
+        // NOTE: Unless it's been rejected again since
+        if matches!(self.entries.get(&hash), Some((_, x)) if *x == rejected_at) {
+            self.entries.remove(&hash);
+        }
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&String>
+    {
+        match self.entries.get(hash)
+        {
+            Some((reason, rejected_at)) if rejected_at.elapsed() < self.expiry => Some(reason),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool
+    {
+        self.get(hash).is_some()
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize
+    {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.entries.is_empty()
+    }
+
+    pub fn prune(&mut self)
+    {
+        while let Some((rejected_at, _)) = self.by_age.front()
+        {
+            if rejected_at.elapsed() < self.expiry {
+                break;
+            }
+
+            let oldest = self.by_age.pop_front().unwrap();
+            self.forget(oldest);
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+
+    fn hash(n: u8) -> Hash
+    {
+        let mut bytes = [0u8; 32];
+        bytes[0] = n;
+        Hash::from(&bytes)
+    }
+
+    #[test]
+    fn test_rejections()
+    {
+        let mut rejections = Rejections::new(2, Duration::from_secs(60));
+        rejections.insert(hash(0), "a".to_owned());
+        std::thread::sleep(Duration::from_millis(10));
+        rejections.insert(hash(1), "b".to_owned());
+        assert_eq!(rejections.get(&hash(0)), Some(&"a".to_owned()));
+
+        // The oldest is forgotten to make room
+        rejections.insert(hash(2), "c".to_owned());
+        assert_eq!(rejections.len(), 2);
+        assert!(!rejections.contains(&hash(0)));
+        assert!(rejections.contains(&hash(1)));
+        assert!(rejections.contains(&hash(2)));
+
+        // Rejecting one again makes it the newest
+        rejections.insert(hash(1), "b".to_owned());
+        rejections.insert(hash(3), "d".to_owned());
+        assert_eq!(rejections.len(), 2);
+        assert!(!rejections.contains(&hash(2)));
+        assert!(rejections.contains(&hash(1)));
+
+        // And all of them once they expire
+        let mut rejections = Rejections::new(2, Duration::from_millis(10));
+        rejections.insert(hash(0), "a".to_owned());
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!rejections.contains(&hash(0)));
+        rejections.prune();
+        assert!(rejections.is_empty());
+    }
+
+}
+
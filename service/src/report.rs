@@ -196,7 +196,7 @@ mod test
             let page = chain.new_page(wallet, &data_unit, 1.0)
                 .expect("Error creating page");
 
-            node.data_store().store_data_unit(&data_unit)
+            node.data_store().store_data_unit(&data_unit, page.header.content.chunk_size as usize)
                 .expect("Error storing page data");
 
             let new_report = node.our_report()
@@ -271,6 +271,10 @@ mod test
         wait_for(|| get_storage_usage(&connection_c).len() == 1, 1000)
             .expect("Node C did not receive reports");
 
+        // NOTE: A's report only arrives once C has discovered and connected to it
+        wait_for(|| get_storage_usage(&connection_c).values().next() == Some(&2), 3000)
+            .expect("Node C did not receive A's report");
+
         let storage_usage_c = get_storage_usage(&connection_c);
         assert_eq!(storage_usage_c.values().next(), Some(&2));
     }
@@ -10,7 +10,13 @@ use crate::node::packet_handler::NodePacketHandler;
 
 use libhyperchain::service::command::Response;
 use libhyperchain::wallet::private_wallet::PrivateWallet;
+use libhyperchain::chain::BlockChain;
+use libhyperchain::transaction::Transaction;
+use libhyperchain::transaction::transfer::Transfer;
+use libhyperchain::config::HASH_LEN;
+use libhyperchain::error::ErrorMessage;
 use libhyperchain::hash::Hash;
+use std::error::Error;
 
 fn deserialize_inputs(serialized_inputs: Vec<(Vec<u8>, f32)>) 
     -> Option<Vec<(PrivateWallet, f32)>>
@@ -85,9 +91,112 @@ pub fn send(connection: &mut NetworkConnection<NodePacketHandler>,
             warn!("Error in send: {}", result.unwrap_err());
             return Response::Failed;
         }
+        node.track_own_transaction(transfer_id);
     }
 
     connection.manager().send(Packet::Transfer(transfer)).unwrap();
     Response::Sent(transfer_id.data().to_vec())
 }
 
+fn queue_batch_transfer(chain: &mut BlockChain, from: Vec<u8>, 
+                        to: Vec<u8>, amount: f32, fee: f32)
+    -> Result<Transaction<Transfer>, Box<dyn Error>>
+{
+    if to.len() != HASH_LEN {
+        return Err(ErrorMessage::new("Invalid address"));
+    }
+
+    let wallet = PrivateWallet::deserialize(from)?;
+    let to = Hash::from(&to);
+    let transfer = chain.new_transfer(vec![(&wallet, amount + fee)], vec![(to, amount)], fee)?;
+    chain.push_transfer_queue(transfer.clone())?;
+    Ok(transfer)
+}
+
+pub fn send_batch(connection: &mut NetworkConnection<NodePacketHandler>,
+                  transfers: Vec<(Vec<u8>, Vec<u8>, f32, f32)>)
+    -> Response
+{
+    let mut results = Vec::new();
+    let mut queued = Vec::new();
+
+    {
+        // NOTE: Each transfer is queued before the next is created, so 
+        //       they see the balance left by the ones before them.
+        let mut node = connection.handler().node();
+        for (from, to, amount, fee) in transfers
+        {
+            match queue_batch_transfer(node.chain(), from, to, amount, fee)
+                .and_then(|transfer| Ok((transfer.hash()?, transfer)))
+            {
+                Ok((transfer_id, transfer)) =>
+                {
+                    node.track_own_transaction(transfer_id);
+                    results.push(Ok(transfer_id.data().to_vec()));
+                    queued.push(transfer);
+                },
+
+                Err(err) =>
+                {
+                    warn!("Error in batch send: {}", err);
+                    results.push(Err(err.to_string()));
+                },
+            }
+        }
+    }
+
+    for transfer in queued {
+        connection.manager().send(Packet::Transfer(transfer)).unwrap();
+    }
+    Response::SentBatch(results)
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::node::tests::{create_node, mine_block};
+    use libhyperchain::wallet::Wallet;
+
+    #[test]
+    fn test_send_batch()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut connection = create_node(8080);
+        mine_block(&mut connection, &wallet);
+
+        let to = other.get_address().data().to_vec();
+        let transfer = |amount: f32| (wallet.serialize(), to.clone(), amount, 0.25);
+        let response = send_batch(&mut connection, vec![
+            transfer(1.0),
+            transfer(1000.0),
+            transfer(2.0),
+            (wallet.serialize(), vec![0u8; 3], 1.0, 0.25),
+        ]);
+
+        let results = match response
+        {
+            Response::SentBatch(results) => results,
+            _ => panic!("Expected batch response"),
+        };
+
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert!(results[2].is_ok());
+        assert!(results[3].is_err());
+
+        let mut node = connection.handler().node();
+        let chain = node.chain();
+        assert_eq!(chain.transaction_queue_size(), 2);
+        for transfer_id in results.iter().filter_map(|x| x.as_ref().ok()) {
+            assert!(chain.find_transaction_in_queue(&Hash::from(transfer_id)).is_some());
+        }
+    }
+
+}
+
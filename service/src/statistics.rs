@@ -15,8 +15,10 @@ pub fn statistics(connection: &mut NetworkConnection<NodePacketHandler>)
 {
     let mut node = connection.handler().node();
 
-    let (sample_start, sample_end) = node.chain().take_sample();
-    let hash_rate = hash_rate(difficulty(&calculate_target(sample_start, sample_end)), 1);
+    let chain = node.chain();
+    let (sample_start, sample_end) = chain.take_sample();
+    let target = calculate_target(chain.config(), sample_start, sample_end);
+    let hash_rate = hash_rate(difficulty(&target), chain.config().block_sample_size, 1);
 
     // TODO: Handle errors.
     let usage = node.storage_usage().unwrap();
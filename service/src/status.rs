@@ -0,0 +1,113 @@
+/*
+ * Copyright (c) 2022, Ben Jilks <benjyjilks@gmail.com>
+ *
+ * SPDX-License-Identifier: BSD-2-Clause
+ */
+
+use crate::network::NetworkConnection;
+use crate::node::packet_handler::NodePacketHandler;
+
+use libhyperchain::service::command::{Response, NodeStatus};
+
+pub fn status(connection: &mut NetworkConnection<NodePacketHandler>)
+    -> Response
+{
+    let peer_count = connection.manager().peer_count();
+    let packets_sent = connection.manager().packets_sent();
+    let packets_received = connection.manager().packets_received();
+
+    let mut node = connection.handler().node();
+    let branch_count = node.branch_count();
+    let mining = node.is_mining();
+    let best_peer_height = node.best_peer_height();
+    let sync_progress = node.sync_progress();
+
+    let chain = node.chain();
+    let chain_height = chain.top_ref().map(|block| block.header.block_id);
+    let mempool_size = chain.transaction_queue_size();
+
+    Response::Status(NodeStatus
+    {
+        chain_height,
+        best_peer_height,
+        sync_progress,
+        branch_count,
+        peer_count,
+        mempool_size,
+        mining,
+        packets_sent,
+        packets_received,
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::node::tests::{create_node, mine_block, wait_for_block};
+    use libhyperchain::wallet::private_wallet::PrivateWallet;
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    fn node_status(connection: &mut NetworkConnection<NodePacketHandler>) -> NodeStatus
+    {
+        match status(connection)
+        {
+            Response::Status(node_status) => node_status,
+            _ => panic!("Expected status response"),
+        }
+    }
+
+    #[test]
+    fn test_status()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let mut connection_a = create_node(8060);
+        assert_eq!(node_status(&mut connection_a), NodeStatus
+        {
+            chain_height: None,
+            best_peer_height: None,
+            sync_progress: 100.0,
+            branch_count: 0,
+            peer_count: 0,
+            mempool_size: 0,
+            mining: false,
+            packets_sent: BTreeMap::new(),
+            packets_received: BTreeMap::new(),
+        });
+
+        let mut connection_b = create_node(8061);
+        connection_b.manager().register_node("127.0.0.1:8060");
+
+        // Ensure nodes are connected.
+        mine_block(&mut connection_a, &wallet);
+        wait_for_block(&connection_b, 0);
+
+        mine_block(&mut connection_a, &wallet);
+        mine_block(&mut connection_a, &wallet);
+        wait_for_block(&connection_b, 2);
+
+        let mut timer = 0;
+        while node_status(&mut connection_a).peer_count == 0 && timer < 1000
+        {
+            std::thread::sleep(Duration::from_millis(100));
+            timer += 100;
+        }
+
+        let status_a = node_status(&mut connection_a);
+        assert_eq!(status_a.chain_height, Some(2));
+        assert_eq!(status_a.peer_count, 1);
+        assert_eq!(status_a.mining, false);
+
+        let status_b = node_status(&mut connection_b);
+        assert_eq!(status_b.chain_height, Some(2));
+        assert_eq!(status_b.peer_count, 1);
+        assert!(status_a.packets_sent["Block"] >= 3);
+        assert!(status_b.packets_received["Block"] >= 3);
+    }
+
+}
+
@@ -8,24 +8,88 @@ use crate::network::NetworkConnection;
 use crate::node::packet_handler::NodePacketHandler;
 
 use libhyperchain::service::command::Response;
+use libhyperchain::config::HASH_LEN;
 use libhyperchain::hash::Hash;
 
 pub fn transaction_info(connection: &mut NetworkConnection<NodePacketHandler>,
                         transaction_id: Vec<u8>) 
     -> Response
 {
-    // TODO: Varify this is a valid hash
-    let transaction_id_hash = Hash::from(&transaction_id);
+    if transaction_id.len() != HASH_LEN
+    {
+        warn!("Invalid transaction id of length {}", transaction_id.len());
+        return Response::Failed;
+    }
 
+    let transaction_id_hash = Hash::from(&transaction_id);
     let mut node = connection.handler().node();
     let chain = node.chain();
 
-    let transaction_or_none = chain.find_transaction(&transaction_id_hash);
-    if transaction_or_none.is_none() {
-        return Response::Failed;
+    // NOTE: A transaction still in the mempool is returned with no block
+    match chain.find_transaction(&transaction_id_hash)
+    {
+        Some((transaction, block)) => Response::TransactionInfo(transaction, block),
+        None => Response::NotFound,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+
+    use super::*;
+    use crate::node::tests::{create_node, mine_block};
+    use libhyperchain::wallet::Wallet;
+    use libhyperchain::wallet::private_wallet::PrivateWallet;
+    use libhyperchain::transaction::TransactionVariant;
+    use libhyperchain::block::builder::BlockBuilder;
+    use libhyperchain::miner;
+
+    #[test]
+    fn test_transaction_info()
+    {
+        let _ = pretty_env_logger::try_init();
+
+        let wallet = PrivateWallet::open_temp(0).unwrap();
+        let other = PrivateWallet::open_temp(1).unwrap();
+        let mut connection = create_node(8076);
+        mine_block(&mut connection, &wallet);
+
+        // Pending in the mempool
+        let transfer = 
+        {
+            let mut node = connection.handler().node();
+            let chain = node.chain();
+            let transfer = chain.new_transfer(vec![(&wallet, 1.25)], 
+                vec![(other.get_address(), 1.0)], 0.25).unwrap();
+            chain.push_transfer_queue(transfer.clone()).unwrap();
+            transfer
+        };
+
+        let transfer_id = transfer.hash().unwrap();
+        assert_eq!(transaction_info(&mut connection, transfer_id.data().to_vec()), 
+                   Response::TransactionInfo(TransactionVariant::Transfer(transfer.clone()), None));
+
+        // Mined into a block
+        let block = 
+        {
+            let mut node = connection.handler().node();
+            let chain = node.chain();
+            let block = miner::mine_block(BlockBuilder::new(&wallet)
+                .add_transfer(transfer.clone())
+                .build(chain)
+                .unwrap());
+            chain.add(&block).unwrap();
+            block
+        };
+
+        assert_eq!(transaction_info(&mut connection, transfer_id.data().to_vec()), 
+                   Response::TransactionInfo(TransactionVariant::Transfer(transfer), Some(block)));
+
+        // Unknown and malformed ids
+        assert_eq!(transaction_info(&mut connection, vec![0u8; HASH_LEN]), Response::NotFound);
+        assert_eq!(transaction_info(&mut connection, vec![0u8; 3]), Response::Failed);
     }
 
-    let (transaction, block) = transaction_or_none.unwrap();
-    Response::TransactionInfo(transaction, block)
 }
 
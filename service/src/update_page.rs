@@ -43,6 +43,7 @@ fn add_page(connection: &mut NetworkConnection<NodePacketHandler>,
     }
     
     let page_id = page.hash().unwrap();
+    node.track_own_transaction(page_id);
     Some((page, page_id.data().to_vec()))
 }
 
@@ -59,7 +60,8 @@ pub fn update_page(connection: &mut NetworkConnection<NodePacketHandler>,
     // TODO: Handle errors.
 
     let (page, page_id) = page_or_none.unwrap();
-    connection.handler().node().data_store().store_data_unit(&data_unit).unwrap();
+    let chunk_size = page.header.content.chunk_size as usize;
+    connection.handler().node().data_store().store_data_unit(&data_unit, chunk_size).unwrap();
     connection.manager().send(Packet::Page(page, data_unit)).unwrap();
 
     let report = connection.handler().node().our_report().unwrap();